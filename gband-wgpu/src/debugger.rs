@@ -84,7 +84,7 @@ impl EmulatorState {
                 let mut infinite_loop_counter = 0u8;
                 while {
                     if let Some(step_frame) = self.emulator.clock() {
-                        self.update_frame(step_frame.as_slice());
+                        self.update_frame(&step_frame);
                     }
                     self.emulator.cpu().cycles > 1 || self.emulator.cpu().pc == current_pc
                 } {
@@ -62,7 +62,7 @@ impl EmulatorState {
                     }
                 };
 
-                self.update_frame(frame.as_slice());
+                self.update_frame(&frame);
 
                 self.loop_helper.loop_sleep();
             }
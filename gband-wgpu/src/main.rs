@@ -114,7 +114,6 @@ impl Into<wgpu::PowerPreference> for PowerAdapter {
 
 mod debugger;
 mod emulation_thread;
-mod socket_serial_transport;
 
 // This maps the keyboard input to a controller input
 fn winit_to_gband_input(keycode: &VirtualKeyCode) -> Result<JoypadState, ()> {
@@ -748,12 +747,10 @@ fn main() {
 
     // Create serial link
     let serial_transport: Box<dyn gband::SerialTransport> = match (opt.client, opt.server) {
-        (Some(addr), _) => Box::new(socket_serial_transport::SocketSerialTransport::new(
-            addr, false,
-        )),
-        (_, Some(addr)) => Box::new(socket_serial_transport::SocketSerialTransport::new(
-            addr, true,
-        )),
+        (Some(addr), _) => Box::new(gband::TcpLink::client(addr)),
+        (_, Some(addr)) => {
+            Box::new(gband::TcpLink::server(addr).expect("Could not listen on the serial address"))
+        }
         _ => Box::new(gband::NullSerialTransport),
     };
 
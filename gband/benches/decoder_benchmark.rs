@@ -1,7 +1,8 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use gband::{
-    borrow_cpu_bus, Cartridge, CgbDoubleSpeed, Cpu, HDma, InterruptState, JoypadState, OamDma, Ppu,
-    RomParserError, SerialPort, TimerRegisters,
+    borrow_cpu_bus, Accuracy, Apu, Cartridge, CgbDoubleSpeed, Cpu, HDma, InfraredPort,
+    InterruptState, JoypadState, MappedPeripheral, MemWatch, OamDma, Ppu, RomParserError,
+    SerialPort, TimerRegisters,
 };
 use std::time::Duration;
 
@@ -17,10 +18,15 @@ struct MockEmulator {
     pub hdma: HDma,
     pub timer_registers: TimerRegisters,
     pub serial_port: SerialPort,
+    pub infrared_port: InfraredPort,
     pub joypad_state: JoypadState,
     pub joypad_register: u8,
     pub ppu: Ppu,
+    pub apu: Apu,
     pub cgb_mode: bool,
+    pub accuracy: Accuracy,
+    pub mem_watch: Option<MemWatch>,
+    pub peripherals: Vec<MappedPeripheral>,
 }
 
 impl MockEmulator {
@@ -41,10 +47,15 @@ impl MockEmulator {
             hdma: Default::default(),
             timer_registers: Default::default(),
             serial_port: Default::default(),
+            infrared_port: Default::default(),
             joypad_state: Default::default(),
             joypad_register: 0,
             ppu: Default::default(),
+            apu: Default::default(),
             cgb_mode: false,
+            accuracy: Default::default(),
+            mem_watch: None,
+            peripherals: Vec::new(),
         };
 
         Ok(emulator)
@@ -82,6 +93,7 @@ fn criterion_benchmark(c: &mut Criterion) {
 
             b.iter(|| {
                 let mut cpu_bus = borrow_cpu_bus!(emulator);
+                emulator.cpu.step = 0;
                 emulator.cpu.execute(&mut cpu_bus);
             })
         });
@@ -0,0 +1,15 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Opt-in emulation of hardware quirks that games usually avoid.
+    /// They are all disabled by default.
+    #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Accuracy: u8 {
+        /// DMG OAM corruption when 16 bits operations target OAM during mode 2
+        const OAM_CORRUPTION = 0x01;
+        /// Wave RAM accesses hitting the byte channel 3 is playing. On DMG, wave RAM is also
+        /// inaccessible while it's not being read, and corrupted when channel 3 is retriggered while reading it
+        const WAVE_RAM_ACCESS = 0x02;
+    }
+}
@@ -0,0 +1,177 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+use super::high_pass::HighPass;
+use super::resampler::{BandLimited, BoxFilter, Resampler};
+
+/// Rate at which the APU is sampled, in Hz
+pub const APU_SAMPLE_RATE: u32 = 1 << 20;
+
+/// Number of stereo samples kept until drained, the oldest ones are dropped past that
+const BUFFER_CAPACITY: usize = 8192;
+
+/// Resampling used for the audio output, trading accuracy for speed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AudioQuality {
+    /// Averages the samples, which aliases high frequencies
+    Low,
+    /// Band-limited synthesis
+    #[default]
+    High,
+}
+
+/// Resamples the output of the APU and buffers it for the host.
+/// It isn't part of the emulated state, so the resampler and the filter keep going
+/// when a state is loaded and the output stays continuous.
+pub struct AudioOutput {
+    // No audio is produced until a sample rate is set
+    sample_rate: Option<u32>,
+    quality: AudioQuality,
+    resampler: Box<dyn Resampler>,
+    high_pass: HighPass,
+    high_pass_enabled: bool,
+    // Interleaved left and right samples
+    buffer: VecDeque<i16>,
+    // Stereo samples produced since the creation, including the dropped ones
+    produced: u64,
+}
+
+impl AudioOutput {
+    pub fn new(cgb_mode: bool) -> Self {
+        Self {
+            sample_rate: None,
+            quality: Default::default(),
+            resampler: Box::new(BandLimited::default()),
+            high_pass: HighPass::new(cgb_mode),
+            high_pass_enabled: true,
+            buffer: VecDeque::new(),
+            produced: 0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate == 0 {
+            self.sample_rate = None;
+        } else {
+            self.sample_rate = Some(sample_rate);
+            self.resampler.set_rates(APU_SAMPLE_RATE, sample_rate);
+        }
+
+        self.buffer.clear();
+    }
+
+    pub fn set_quality(&mut self, quality: AudioQuality) {
+        if quality == self.quality {
+            return;
+        }
+
+        self.quality = quality;
+        self.resampler = match quality {
+            AudioQuality::Low => Box::new(BoxFilter::default()),
+            AudioQuality::High => Box::new(BandLimited::default()),
+        };
+
+        if let Some(sample_rate) = self.sample_rate {
+            self.resampler.set_rates(APU_SAMPLE_RATE, sample_rate);
+        }
+    }
+
+    /// Whether a sample rate is set, so samples are produced
+    pub fn is_enabled(&self) -> bool {
+        self.sample_rate.is_some()
+    }
+
+    /// Number of stereo samples produced so far, even if they were dropped or drained
+    pub fn produced(&self) -> u64 {
+        self.produced
+    }
+
+    /// Enables the high-pass filter of the hardware, which is on by default
+    pub fn set_high_pass(&mut self, enabled: bool) {
+        self.high_pass_enabled = enabled;
+    }
+
+    /// Takes a sample of the APU, at `APU_SAMPLE_RATE`
+    pub fn push(&mut self, sample: (i16, i16)) {
+        if self.sample_rate.is_none() {
+            return;
+        }
+
+        // The capacitors keep charging even if the filter is disabled, so it can be enabled back without a pop
+        let filtered = self.high_pass.apply(sample);
+        let sample = if self.high_pass_enabled {
+            filtered
+        } else {
+            sample
+        };
+
+        if let Some((left, right)) = self.resampler.push(sample) {
+            if self.buffer.len() == BUFFER_CAPACITY * 2 {
+                self.buffer.drain(..2);
+            }
+
+            self.buffer.push_back(left);
+            self.buffer.push_back(right);
+            self.produced += 1;
+        }
+    }
+
+    /// Moves the buffered samples to `output`, as interleaved left and right samples.
+    /// Returns the number of values written, which is always even.
+    pub fn drain(&mut self, output: &mut [i16]) -> usize {
+        let len = self.buffer.len().min(output.len() & !1);
+
+        for (out, sample) in output.iter_mut().zip(self.buffer.drain(..len)) {
+            *out = sample;
+        }
+
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_output() {
+        let mut output = AudioOutput::new(false);
+        output.set_quality(AudioQuality::Low);
+        output.set_high_pass(false);
+
+        // Nothing is buffered without a sample rate
+        output.push((100, -100));
+        assert_eq!(output.drain(&mut [0; 16]), 0);
+
+        output.set_sample_rate(APU_SAMPLE_RATE / 4);
+        for _ in 0..8 {
+            output.push((100, -100));
+        }
+
+        // Only whole stereo samples are drained
+        let mut samples = [0i16; 3];
+        assert_eq!(output.drain(&mut samples), 2);
+        assert_eq!(samples, [100, -100, 0]);
+
+        let mut samples = [0i16; 8];
+        assert_eq!(output.drain(&mut samples), 2);
+        assert_eq!(output.drain(&mut samples), 0);
+    }
+
+    #[test]
+    fn test_audio_output_overflow() {
+        let mut output = AudioOutput::new(false);
+        output.set_quality(AudioQuality::Low);
+        output.set_high_pass(false);
+        output.set_sample_rate(APU_SAMPLE_RATE);
+
+        for i in 0..BUFFER_CAPACITY as i16 + 10 {
+            output.push((i, i));
+        }
+
+        // The oldest samples are dropped
+        let mut samples = alloc::vec![0i16; BUFFER_CAPACITY * 2 + 2];
+        assert_eq!(output.drain(&mut samples), BUFFER_CAPACITY * 2);
+        assert_eq!(samples[0], 10);
+    }
+}
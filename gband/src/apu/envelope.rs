@@ -0,0 +1,115 @@
+// See: https://gbdev.io/pandocs/Audio_Registers.html#ff12--nr12-channel-1-volume--envelope
+
+/// Volume envelope of the pulse and noise channels, set through NRx2
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, data: u8) {
+        self.initial_volume = data >> 4;
+        self.increase = data & 0x08 != 0;
+        self.period = data & 0x07;
+    }
+
+    pub fn read(&self) -> u8 {
+        (self.initial_volume << 4) | if self.increase { 0x08 } else { 0x00 } | self.period
+    }
+
+    /// The DAC is off when the upper 5 bits of NRx2 are cleared, which also disables the channel
+    pub fn is_dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.reload_value();
+    }
+
+    /// Clocked by the frame sequencer at 64 Hz
+    pub fn clock(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return;
+        }
+
+        self.timer = self.reload_value();
+
+        // The timer still runs with a period of 0, but the volume never changes
+        if self.period != 0 {
+            if self.increase && self.volume < 0xF {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn reload_value(&self) -> u8 {
+        // A period of 0 is treated as 8
+        if self.period == 0 {
+            8
+        } else {
+            self.period
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        let mut envelope = Envelope::default();
+
+        // Start at 2, increase every 3 ticks
+        envelope.write(0x2B);
+        assert_eq!(envelope.read(), 0x2B);
+        envelope.trigger();
+        assert_eq!(envelope.volume, 2);
+
+        for _ in 0..2 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume, 2);
+        envelope.clock();
+        assert_eq!(envelope.volume, 3);
+
+        // Stops at 15
+        for _ in 0..100 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume, 15);
+
+        // Start at 1, decrease every tick and stop at 0
+        envelope.write(0x11);
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume, 0);
+        envelope.clock();
+        assert_eq!(envelope.volume, 0);
+    }
+
+    #[test]
+    fn test_dac_enabled() {
+        let mut envelope = Envelope::default();
+        assert!(!envelope.is_dac_enabled());
+
+        envelope.write(0x07);
+        assert!(!envelope.is_dac_enabled());
+
+        envelope.write(0x08);
+        assert!(envelope.is_dac_enabled());
+
+        envelope.write(0x10);
+        assert!(envelope.is_dac_enabled());
+    }
+}
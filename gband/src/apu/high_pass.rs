@@ -0,0 +1,78 @@
+// See: https://gbdev.io/pandocs/Audio_details.html#mixer
+
+/// Charge factor of the output capacitors per 4 MiHz clock, on DMG and CGB
+const DMG_CHARGE: f64 = 0.999958;
+const CGB_CHARGE: f64 = 0.998943;
+
+/// Clocks between two samples of the APU
+const CLOCKS_PER_SAMPLE: u32 = 4;
+
+/// Capacitors of the output stage, which remove the DC offset of the channels
+pub struct HighPass {
+    // Charge factor for each sample
+    charge: f32,
+    capacitor: (f32, f32),
+}
+
+impl HighPass {
+    pub fn new(cgb_mode: bool) -> Self {
+        let charge = if cgb_mode { CGB_CHARGE } else { DMG_CHARGE };
+
+        Self {
+            charge: (0..CLOCKS_PER_SAMPLE).fold(1.0, |total, _| total * charge) as f32,
+            capacitor: (0.0, 0.0),
+        }
+    }
+
+    pub fn apply(&mut self, sample: (i16, i16)) -> (i16, i16) {
+        let (left, right) = (sample.0 as f32, sample.1 as f32);
+
+        let output = (left - self.capacitor.0, right - self.capacitor.1);
+        self.capacitor = (
+            left - output.0 * self.charge,
+            right - output.1 * self.charge,
+        );
+
+        (output.0 as i16, output.1 as i16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_decay() {
+        let mut high_pass = HighPass::new(false);
+        assert_eq!(high_pass.apply((0, 0)), (0, 0));
+
+        // A step goes through, then decays toward 0
+        let (first, _) = high_pass.apply((10000, -10000));
+        assert_eq!(first, 10000);
+
+        let mut previous = first;
+        let mut expected = 10000.0;
+        for _ in 0..40000 {
+            let (left, right) = high_pass.apply((10000, -10000));
+            assert!(left <= previous);
+            assert_eq!(left, -right);
+
+            expected *= high_pass.charge;
+            assert!((left as f32 - expected).abs() <= 2.0);
+            previous = left;
+        }
+        assert!(previous < 100);
+    }
+
+    #[test]
+    fn test_cgb_charge() {
+        // The capacitors discharge faster on CGB
+        let mut dmg = HighPass::new(false);
+        let mut cgb = HighPass::new(true);
+        for _ in 0..100 {
+            dmg.apply((10000, 10000));
+            cgb.apply((10000, 10000));
+        }
+        assert!(cgb.apply((10000, 10000)).0 < dmg.apply((10000, 10000)).0);
+    }
+}
@@ -0,0 +1,104 @@
+// See: https://gbdev.io/pandocs/Audio_details.html#length-timer
+
+/// Silences a channel after a number of 256 Hz ticks, if enabled
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+    pub enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    /// `max` is 64 for every channel except the wave channel, which counts to 256
+    pub fn new(max: u16) -> Self {
+        Self {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    /// Loads the length written in NRx1
+    pub fn load(&mut self, length: u16) {
+        self.counter = self.max - length;
+    }
+
+    /// Clocked by the frame sequencer. Returns true if the channel must be disabled.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    /// Handles a write to NRx4. `extra_clock` is set when the next step of the frame sequencer
+    /// doesn't clock the length counters, in which case enabling the counter clocks it right away.
+    /// Returns true if the channel must be disabled.
+    pub fn write_control(&mut self, enabled: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+
+        let mut expired = false;
+        if extra_clock && !was_enabled {
+            expired = self.clock() && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+
+            // The reloaded counter also gets the extra clock
+            if extra_clock {
+                self.clock();
+            }
+        }
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_counter() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+
+        // Disabled, so the counter doesn't move
+        assert!(!length.clock());
+        assert!(!length.write_control(true, false, false));
+        assert!(!length.clock());
+        assert!(length.clock());
+
+        // Stays at 0 once expired, until triggered
+        assert!(!length.clock());
+        assert!(!length.write_control(true, true, false));
+        for _ in 0..63 {
+            assert!(!length.clock());
+        }
+        assert!(length.clock());
+    }
+
+    #[test]
+    fn test_length_counter_extra_clock() {
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+
+        // Enabling the counter in the first half of the frame sequencer period clocks it
+        assert!(length.write_control(true, false, true));
+
+        // Unless the channel is triggered at the same time, which reloads it
+        let mut length = LengthCounter::new(64);
+        length.load(63);
+        assert!(!length.write_control(true, true, true));
+        assert_eq!(length.counter, 63);
+
+        // Nothing happens if it was already enabled
+        assert!(!length.write_control(true, false, true));
+        assert_eq!(length.counter, 63);
+    }
+}
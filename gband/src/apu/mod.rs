@@ -0,0 +1,629 @@
+// See: https://gbdev.io/pandocs/Audio.html
+
+mod audio_output;
+mod envelope;
+mod high_pass;
+mod length_counter;
+mod noise_channel;
+mod resampler;
+mod square_channel;
+mod sweep;
+mod wave_channel;
+
+pub use audio_output::{AudioOutput, AudioQuality};
+use noise_channel::NoiseChannel;
+use square_channel::SquareChannel;
+use wave_channel::WaveChannel;
+
+/// Bit of the internal timer counter clocking the frame sequencer on its falling edge, which is DIV bit 4.
+/// The next one is used in double speed, so the frame sequencer always runs at 512 Hz.
+const FRAME_SEQUENCER_DIV_BIT: u16 = 1 << 12;
+
+/// Sound channels, in the order of their registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioChannel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    channel1: SquareChannel,
+    channel2: SquareChannel,
+    channel3: WaveChannel,
+    channel4: NoiseChannel,
+
+    // Last state of the DIV bit clocking the frame sequencer
+    frame_sequencer_div_bit: bool,
+    // Next step to be executed by the frame sequencer
+    frame_sequencer_step: u8,
+
+    // NR52 bit 7
+    power: bool,
+    // NR50, with the Vin bits
+    master_volume: u8,
+    // NR51
+    panning: u8,
+
+    cgb_mode: bool,
+
+    // Channels left out of the mix, as a bitmask in the order of `AudioChannel`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    muted_channels: u8,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl Apu {
+    pub fn new(cgb_mode: bool) -> Self {
+        Self {
+            channel1: SquareChannel::new(true),
+            channel2: SquareChannel::new(false),
+            channel3: Default::default(),
+            channel4: Default::default(),
+
+            frame_sequencer_div_bit: false,
+            frame_sequencer_step: 0,
+
+            power: true,
+            master_volume: 0,
+            panning: 0,
+
+            cgb_mode,
+
+            muted_channels: 0,
+        }
+    }
+
+    /// Restores a saved state, keeping the muted channels
+    pub(crate) fn load_state(&mut self, state: Self) {
+        let muted_channels = self.muted_channels;
+        *self = state;
+        self.muted_channels = muted_channels;
+    }
+
+    /// Leaves a channel out of the mix. This is only a setting of the output,
+    /// so the emulated hardware still sees it, like in NR52 and PCM12/PCM34.
+    pub fn set_channel_enabled(&mut self, channel: AudioChannel, enabled: bool) {
+        let mask = 1 << channel as u8;
+        if enabled {
+            self.muted_channels &= !mask;
+        } else {
+            self.muted_channels |= mask;
+        }
+    }
+
+    /// Clocked at ~4MHz
+    pub fn clock(&mut self) {
+        if !self.power {
+            return;
+        }
+
+        self.channel1.clock();
+        self.channel2.clock();
+        self.channel3.clock();
+        self.channel4.clock();
+    }
+
+    /// Clocked with the internal timer counter on each M-cycle.
+    /// The frame sequencer steps when its DIV bit falls, so writing DIV while it's set adds a step.
+    pub fn clock_div(&mut self, div: u16, double_speed: bool) {
+        let mask = if double_speed {
+            FRAME_SEQUENCER_DIV_BIT << 1
+        } else {
+            FRAME_SEQUENCER_DIV_BIT
+        };
+
+        let div_bit = div & mask != 0;
+        let falling_edge = self.frame_sequencer_div_bit && !div_bit;
+        self.frame_sequencer_div_bit = div_bit;
+
+        if falling_edge && self.power {
+            self.clock_frame_sequencer();
+        }
+    }
+
+    /// Current digital output of each channel, from 0 to 15
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [
+            self.channel1.output(),
+            self.channel2.output(),
+            self.channel3.output(),
+            self.channel4.output(),
+        ]
+    }
+
+    /// Mixes the channels into a stereo pair of samples, as (left, right)
+    pub fn sample(&self) -> (i16, i16) {
+        let outputs = self.channel_outputs();
+        let dacs = [
+            self.channel1.is_dac_enabled(),
+            self.channel2.is_dac_enabled(),
+            self.channel3.is_dac_enabled(),
+            self.channel4.is_dac_enabled(),
+        ];
+
+        // Each DAC converts the output to a value from -15 to 15, and a disabled DAC outputs 0
+        let mut left = 0i16;
+        let mut right = 0i16;
+        for (i, (output, dac)) in outputs.iter().zip(dacs).enumerate() {
+            if !dac || self.muted_channels & (1 << i) != 0 {
+                continue;
+            }
+
+            let analog = *output as i16 * 2 - 15;
+            if self.panning & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if self.panning & (0x01 << i) != 0 {
+                right += analog;
+            }
+        }
+
+        // The master volume goes from 1 to 8, which gives a range of +-480 scaled to fit an i16
+        let left_volume = ((self.master_volume >> 4) & 0x07) as i16 + 1;
+        let right_volume = (self.master_volume & 0x07) as i16 + 1;
+        (left * left_volume * 64, right * right_volume * 64)
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        // Only NR52 and wave RAM are writable while the APU is off
+        if !self.power && !matches!(addr, 0xFF26 | 0xFF30..=0xFF3F) {
+            // Except for the length counters on DMG
+            if !self.cgb_mode {
+                match addr {
+                    0xFF11 => self.channel1.write_length(data),
+                    0xFF16 => self.channel2.write_length(data),
+                    0xFF1B => self.channel3.write_length(data),
+                    0xFF20 => self.channel4.write_length(data),
+                    _ => {}
+                }
+            }
+
+            return;
+        }
+
+        match addr {
+            0xFF10 => self.channel1.write_sweep(data),
+            0xFF11 => self.channel1.write_length_duty(data),
+            0xFF12 => self.channel1.write_envelope(data),
+            0xFF13 => self.channel1.write_frequency_low(data),
+            0xFF14 => self
+                .channel1
+                .write_control(data, self.is_extra_length_clock()),
+            0xFF16 => self.channel2.write_length_duty(data),
+            0xFF17 => self.channel2.write_envelope(data),
+            0xFF18 => self.channel2.write_frequency_low(data),
+            0xFF19 => self
+                .channel2
+                .write_control(data, self.is_extra_length_clock()),
+            0xFF1A => self.channel3.write_dac(data),
+            0xFF1B => self.channel3.write_length(data),
+            0xFF1C => self.channel3.write_volume(data),
+            0xFF1D => self.channel3.write_frequency_low(data),
+            0xFF1E => self
+                .channel3
+                .write_control(data, self.is_extra_length_clock()),
+            0xFF20 => self.channel4.write_length(data),
+            0xFF21 => self.channel4.write_envelope(data),
+            0xFF22 => self.channel4.write_frequency(data),
+            0xFF23 => self
+                .channel4
+                .write_control(data, self.is_extra_length_clock()),
+            0xFF24 => self.master_volume = data,
+            0xFF25 => self.panning = data,
+            0xFF26 => self.set_power(data & 0x80 != 0),
+            0xFF30..=0xFF3F => self.channel3.write_wave_ram(addr - 0xFF30, data),
+            _ => {}
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF10 => self.channel1.read_sweep(),
+            0xFF11 => self.channel1.read_length_duty(),
+            0xFF12 => self.channel1.read_envelope(),
+            0xFF14 => self.channel1.read_control(),
+            0xFF16 => self.channel2.read_length_duty(),
+            0xFF17 => self.channel2.read_envelope(),
+            0xFF19 => self.channel2.read_control(),
+            0xFF1A => self.channel3.read_dac(),
+            0xFF1C => self.channel3.read_volume(),
+            0xFF1E => self.channel3.read_control(),
+            0xFF21 => self.channel4.read_envelope(),
+            0xFF22 => self.channel4.read_frequency(),
+            0xFF23 => self.channel4.read_control(),
+            0xFF24 => self.master_volume,
+            0xFF25 => self.panning,
+            0xFF26 => self.read_status(),
+            0xFF30..=0xFF3F => self.channel3.read_wave_ram(addr - 0xFF30),
+            // PCM12 and PCM34, the digital outputs of the channels on CGB
+            0xFF76 | 0xFF77 if self.cgb_mode => {
+                let outputs = self.channel_outputs();
+                let channel = ((addr & 1) << 1) as usize;
+                outputs[channel] | (outputs[channel + 1] << 4)
+            }
+            // Write-only registers
+            _ => 0xFF,
+        }
+    }
+
+    fn set_power(&mut self, power: bool) {
+        if self.power && !power {
+            // Every register is cleared, except the length counters on DMG
+            let keep_length = !self.cgb_mode;
+            self.channel1.power_off(keep_length);
+            self.channel2.power_off(keep_length);
+            self.channel3.power_off(keep_length);
+            self.channel4.power_off(keep_length);
+
+            self.master_volume = 0;
+            self.panning = 0;
+        } else if !self.power && power {
+            // The frame sequencer restarts from the first step
+            self.frame_sequencer_step = 0;
+        }
+
+        self.power = power;
+    }
+
+    /// Wave RAM write with the quirks of channel 3, see `WaveChannel::write_wave_ram_playing`
+    pub fn write_wave_ram_playing(&mut self, addr: u16, data: u8, blocking: bool) {
+        self.channel3
+            .write_wave_ram_playing(addr - 0xFF30, data, blocking)
+    }
+
+    /// Wave RAM read with the quirks of channel 3, see `WaveChannel::read_wave_ram_playing`
+    pub fn read_wave_ram_playing(&self, addr: u16, blocking: bool) -> u8 {
+        self.channel3.read_wave_ram_playing(addr - 0xFF30, blocking)
+    }
+
+    /// See `WaveChannel::corrupt_wave_ram`
+    pub fn corrupt_wave_ram(&mut self) {
+        self.channel3.corrupt_wave_ram()
+    }
+
+    /// NR52, with the status of each channel in the lower bits
+    fn read_status(&self) -> u8 {
+        let mut status = 0x70;
+
+        if self.power {
+            status |= 0x80;
+        }
+
+        for (i, enabled) in [
+            self.channel1.enabled,
+            self.channel2.enabled,
+            self.channel3.enabled,
+            self.channel4.enabled,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if *enabled {
+                status |= 1 << i;
+            }
+        }
+
+        status
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.channel1.clock_sweep();
+            }
+            7 => {
+                self.channel1.clock_envelope();
+                self.channel2.clock_envelope();
+                self.channel4.clock_envelope();
+            }
+            _ => {}
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) & 0x7;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.channel1.clock_length();
+        self.channel2.clock_length();
+        self.channel3.clock_length();
+        self.channel4.clock_length();
+    }
+
+    /// Enabling a length counter clocks it once if the next step doesn't
+    fn is_extra_length_clock(&self) -> bool {
+        self.frame_sequencer_step & 1 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::TimerRegisters;
+
+    /// Dots between two steps of the frame sequencer
+    const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+    /// Clocks the APU along with the timer, like the emulator does
+    fn run(apu: &mut Apu, timer: &mut TimerRegisters, dots: u32) {
+        for dot in 1..=dots {
+            apu.clock();
+            if dot % 4 == 0 {
+                timer.clock();
+                apu.clock_div(timer.div(), false);
+            }
+        }
+    }
+
+    #[test]
+    fn test_channel1_length() {
+        let mut apu = Apu::default();
+        let mut timer = TimerRegisters::default();
+
+        // 2 ticks of length, so it expires on the second length step
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 62);
+        apu.write(0xFF14, 0xC0);
+        assert_eq!(apu.read(0xFF14), 0xFF);
+
+        run(&mut apu, &mut timer, FRAME_SEQUENCER_PERIOD * 2);
+        assert!(apu.channel1.enabled);
+
+        run(&mut apu, &mut timer, FRAME_SEQUENCER_PERIOD * 2);
+        assert!(!apu.channel1.enabled);
+    }
+
+    #[test]
+    fn test_div_write_length() {
+        let mut apu = Apu::default();
+        let mut timer = TimerRegisters::default();
+
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 62);
+        apu.write(0xFF14, 0xC0);
+
+        // Resetting DIV while bit 4 is set makes it fall, which steps the frame sequencer.
+        // The third step is the second length clock.
+        let mut dots = 0;
+        for _ in 0..3 {
+            while timer.div() & FRAME_SEQUENCER_DIV_BIT == 0 {
+                run(&mut apu, &mut timer, 4);
+                dots += 4;
+            }
+            assert!(apu.channel1.enabled);
+            timer.write(0xFF04, 0);
+        }
+
+        run(&mut apu, &mut timer, 4);
+        dots += 4;
+        assert!(!apu.channel1.enabled);
+
+        // The length would otherwise expire on the third step
+        assert!(dots < FRAME_SEQUENCER_PERIOD * 2);
+    }
+
+    #[test]
+    fn test_channel2_registers() {
+        let mut apu = Apu::default();
+
+        // There is no sweep on channel 2
+        apu.write(0xFF15, 0x77);
+        assert_eq!(apu.read(0xFF15), 0xFF);
+
+        apu.write(0xFF16, 0xBF);
+        apu.write(0xFF17, 0xA5);
+        apu.write(0xFF18, 0x12);
+        apu.write(0xFF19, 0x47);
+        assert_eq!(apu.read(0xFF16), 0xBF);
+        assert_eq!(apu.read(0xFF17), 0xA5);
+        assert_eq!(apu.read(0xFF18), 0xFF);
+        assert_eq!(apu.read(0xFF19), 0xFF);
+        assert!(!apu.channel2.enabled);
+
+        // Only channel 2 is triggered
+        apu.write(0xFF19, 0x87);
+        assert!(apu.channel2.enabled);
+        assert!(!apu.channel1.enabled);
+    }
+
+    #[test]
+    fn test_power_off() {
+        let mut apu = Apu::default();
+
+        apu.write(0xFF10, 0x7F);
+        apu.write(0xFF11, 0xFF);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0xC0);
+        apu.write(0xFF16, 0xFF);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1C, 0x60);
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF22, 0xFF);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xFF);
+        apu.write(0xFF30, 0x12);
+        assert_eq!(apu.read(0xFF26), 0xF1);
+
+        apu.write(0xFF26, 0x00);
+        let expected = [
+            (0xFF10, 0x80),
+            (0xFF11, 0x3F),
+            (0xFF12, 0x00),
+            (0xFF14, 0xBF),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF19, 0xBF),
+            (0xFF1A, 0x7F),
+            (0xFF1C, 0x9F),
+            (0xFF1E, 0xBF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0xBF),
+            (0xFF24, 0x00),
+            (0xFF25, 0x00),
+            (0xFF26, 0x70),
+        ];
+        for (addr, value) in expected {
+            assert_eq!(apu.read(addr), value, "{:04X}", addr);
+        }
+
+        // The registers are read-only, but wave RAM isn't cleared and can still be written
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF31, 0x34);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF30), 0x12);
+        assert_eq!(apu.read(0xFF31), 0x34);
+
+        // The length counters can still be loaded on DMG
+        apu.write(0xFF11, 0xBF);
+        apu.write(0xFF26, 0x80);
+        assert_eq!(apu.read(0xFF11), 0x3F);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0xC0);
+        run(
+            &mut apu,
+            &mut TimerRegisters::default(),
+            FRAME_SEQUENCER_PERIOD,
+        );
+        assert_eq!(apu.read(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn test_panning() {
+        let mut apu = Apu::default();
+        apu.write(0xFF24, 0x77);
+
+        // Channel 2 only on the left
+        apu.write(0xFF25, 0x20);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x87);
+
+        let mut left_levels = Vec::new();
+        for _ in 0..0x1000 {
+            apu.clock();
+
+            let (left, right) = apu.sample();
+            assert_eq!(right, 0);
+            left_levels.push(left);
+        }
+
+        // The square wave goes from the lowest to the highest level
+        assert_eq!(left_levels.iter().min(), Some(&(-15 * 8 * 64)));
+        assert_eq!(left_levels.iter().max(), Some(&(15 * 8 * 64)));
+
+        // Routing it to both sides
+        apu.write(0xFF25, 0x22);
+        assert_eq!(apu.sample().0, apu.sample().1);
+    }
+
+    #[test]
+    fn test_muted_channel() {
+        let mut apu = Apu::new(true);
+        apu.write(0xFF25, 0x11);
+        apu.set_channel_enabled(AudioChannel::Square1, false);
+
+        apu.write(0xFF11, 0x80);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x87);
+
+        // The game still sees the channel playing
+        let mut max_pcm = 0;
+        for _ in 0..0x1000 {
+            apu.clock();
+            assert_eq!(apu.sample(), (0, 0));
+            max_pcm = max_pcm.max(apu.read(0xFF76));
+        }
+        assert_eq!(apu.read(0xFF26), 0xF1);
+        assert_eq!(max_pcm, 0x0F);
+
+        // The setting is kept when loading a state
+        let state = apu.clone();
+        apu.set_channel_enabled(AudioChannel::Square1, true);
+        apu.load_state(state);
+        assert!(apu.sample().0.abs() > 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut apu = Apu::new(false);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xF5);
+        for i in 0..16 {
+            apu.write(0xFF30 + i, (i as u8) * 0x10 + 0x0F - i as u8);
+        }
+
+        // Every channel playing, with the sweep and the envelopes running
+        apu.write(0xFF10, 0x15);
+        apu.write(0xFF11, 0x80);
+        apu.write(0xFF12, 0xF3);
+        apu.write(0xFF14, 0x87);
+        apu.write(0xFF16, 0x40);
+        apu.write(0xFF17, 0x2A);
+        apu.write(0xFF18, 0x40);
+        apu.write(0xFF19, 0x86);
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1C, 0x20);
+        apu.write(0xFF1D, 0x80);
+        apu.write(0xFF1E, 0x87);
+        apu.write(0xFF21, 0xF1);
+        apu.write(0xFF22, 0x34);
+        apu.write(0xFF23, 0x80);
+
+        // Stop in the middle of the notes and of a frame sequencer step
+        for _ in 0..50_000 {
+            apu.clock();
+        }
+
+        let serialized = bincode::serialize(&apu).unwrap();
+        let mut restored: Apu = bincode::deserialize(&serialized).unwrap();
+
+        for _ in 0..1000 {
+            apu.clock();
+            restored.clock();
+            assert_eq!(apu.sample(), restored.sample());
+        }
+        assert_eq!(apu.read(0xFF26), restored.read(0xFF26));
+    }
+
+    #[test]
+    fn test_pcm_registers() {
+        let mut apu = Apu::new(true);
+
+        // Channel 1 at volume 10
+        apu.write(0xFF11, 0x80);
+        apu.write(0xFF12, 0xA0);
+        apu.write(0xFF14, 0x87);
+
+        let mut levels = Vec::new();
+        for _ in 0..0x1000 {
+            apu.clock();
+            levels.push(apu.read(0xFF76));
+            assert_eq!(apu.read(0xFF77), 0x00);
+        }
+
+        assert_eq!(levels.iter().min(), Some(&0x00));
+        assert_eq!(levels.iter().max(), Some(&0x0A));
+
+        // Unmapped on DMG
+        let apu = Apu::new(false);
+        assert_eq!(apu.read(0xFF76), 0xFF);
+        assert_eq!(apu.read(0xFF77), 0xFF);
+    }
+}
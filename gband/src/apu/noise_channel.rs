@@ -0,0 +1,255 @@
+// See: https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-4--noise
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+/// Dots between two LFSR clocks for each divisor code, before the shift
+const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Noise channel, playing the output of a linear feedback shift register
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoiseChannel {
+    pub enabled: bool,
+
+    clock_shift: u8,
+    short_mode: bool,
+    divisor_code: u8,
+
+    timer: u32,
+    lfsr: u16,
+
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+
+            timer: 0,
+            lfsr: 0,
+
+            length: LengthCounter::new(64),
+            envelope: Default::default(),
+        }
+    }
+}
+
+impl NoiseChannel {
+    /// Clocks the frequency timer, at 4 MiHz
+    pub fn clock(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            // The LFSR doesn't receive any clock with a shift of 14 or 15
+            if self.clock_shift < 14 {
+                self.clock_lfsr();
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clears every register when the APU is turned off. The length counter is kept on DMG.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = self.length;
+        *self = Self::default();
+
+        if keep_length {
+            length.enabled = false;
+            self.length = length;
+        }
+    }
+
+    /// Digital output of the channel, from 0 to 15
+    pub fn output(&self) -> u8 {
+        // The output is the inverse of bit 0
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    /// NR41, which is write-only
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    /// NR42
+    pub fn write_envelope(&mut self, data: u8) {
+        self.envelope.write(data);
+
+        if !self.envelope.is_dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn read_envelope(&self) -> u8 {
+        self.envelope.read()
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    /// NR43. Changing the width mid-note applies on the next LFSR clock.
+    pub fn write_frequency(&mut self, data: u8) {
+        self.clock_shift = data >> 4;
+        self.short_mode = data & 0x08 != 0;
+        self.divisor_code = data & 0x07;
+    }
+
+    pub fn read_frequency(&self) -> u8 {
+        (self.clock_shift << 4) | if self.short_mode { 0x08 } else { 0x00 } | self.divisor_code
+    }
+
+    /// NR44. See `LengthCounter::write_control` for `extra_length_clock`.
+    pub fn write_control(&mut self, data: u8, extra_length_clock: bool) {
+        let trigger = data & 0x80 != 0;
+        if self
+            .length
+            .write_control(data & 0x40 != 0, trigger, extra_length_clock)
+        {
+            self.enabled = false;
+        }
+
+        if trigger {
+            self.enabled = self.envelope.is_dac_enabled();
+            self.timer = self.period();
+            self.envelope.trigger();
+            self.lfsr = 0x7FFF;
+        }
+    }
+
+    pub fn read_control(&self) -> u8 {
+        // Only the length enable bit can be read
+        0xBF | if self.length.enabled { 0x40 } else { 0x00 }
+    }
+
+    fn clock_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+
+        // In 7 bits mode, the feedback is also written to bit 6
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+        }
+    }
+
+    /// Dots between two LFSR clocks
+    fn period(&self) -> u32 {
+        // The divisor code 0 is 8, which is half of code 1
+        (DIVISORS[self.divisor_code as usize] as u32) << self.clock_shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bit 0 of the LFSR after each of the 32 first clocks, first one in the MSB
+    fn lfsr_sequence(channel: &mut NoiseChannel) -> u32 {
+        (0..32).fold(0, |sequence, _| {
+            channel.clock_lfsr();
+            (sequence << 1) | (channel.lfsr & 1) as u32
+        })
+    }
+
+    #[test]
+    fn test_lfsr_sequence() {
+        let mut channel = NoiseChannel::default();
+        channel.write_envelope(0xF0);
+
+        channel.write_frequency(0x00);
+        channel.write_control(0x80, false);
+        assert_eq!(lfsr_sequence(&mut channel), 0xFFFC_0008);
+
+        channel.write_frequency(0x08);
+        channel.write_control(0x80, false);
+        assert_eq!(lfsr_sequence(&mut channel), 0xFC08_30A3);
+    }
+
+    #[test]
+    fn test_lfsr_period() {
+        let mut channel = NoiseChannel::default();
+        channel.write_envelope(0xF0);
+
+        channel.write_frequency(0x00);
+        channel.write_control(0x80, false);
+        for _ in 0..0x7FFF {
+            channel.clock_lfsr();
+        }
+        assert_eq!(channel.lfsr, 0x7FFF);
+
+        // The 7 bits mode repeats every 127 clocks
+        channel.write_frequency(0x08);
+        channel.write_control(0x80, false);
+        channel.clock_lfsr();
+        let start = channel.lfsr & 0x7F;
+        for _ in 0..127 {
+            channel.clock_lfsr();
+        }
+        assert_eq!(channel.lfsr & 0x7F, start);
+    }
+
+    #[test]
+    fn test_width_switch_mid_note() {
+        let mut channel = NoiseChannel::default();
+        channel.write_envelope(0xF0);
+        channel.write_control(0x80, false);
+        for _ in 0..20 {
+            channel.clock_lfsr();
+        }
+
+        // Switching to 7 bits only changes the following clocks
+        let lfsr = channel.lfsr;
+        channel.write_frequency(0x08);
+        assert_eq!(channel.lfsr, lfsr);
+
+        let feedback = (lfsr ^ (lfsr >> 1)) & 1;
+        channel.clock_lfsr();
+        assert_eq!((channel.lfsr >> 6) & 1, feedback);
+        assert_eq!((channel.lfsr >> 14) & 1, feedback);
+        assert_eq!(channel.lfsr & 0x3F, (lfsr >> 1) & 0x3F);
+    }
+
+    #[test]
+    fn test_period() {
+        let mut channel = NoiseChannel::default();
+
+        // The divisor code 0 is 8
+        channel.write_frequency(0x00);
+        assert_eq!(channel.period(), 8);
+        channel.write_frequency(0x01);
+        assert_eq!(channel.period(), 16);
+        channel.write_frequency(0x27);
+        assert_eq!(channel.period(), 112 << 2);
+
+        // No LFSR clock with a shift of 14
+        channel.write_envelope(0xF0);
+        channel.write_frequency(0xE0);
+        channel.write_control(0x80, false);
+        for _ in 0..(8 << 14) * 2 {
+            channel.clock();
+        }
+        assert_eq!(channel.lfsr, 0x7FFF);
+        assert_eq!(channel.read_frequency(), 0xE0);
+    }
+}
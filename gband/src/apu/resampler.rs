@@ -0,0 +1,302 @@
+use alloc::boxed::Box;
+use core::f64::consts::PI;
+
+/// Length of the band-limited kernel, in output samples
+const TAPS: usize = 32;
+/// Number of kernels, for each sub-sample position of an input sample
+const PHASES: usize = 64;
+/// The taps of each kernel sum up to 1 in this fixed point precision
+const KERNEL_BITS: u32 = 15;
+/// Cutoff frequency of the kernel, relative to the output rate
+const CUTOFF: f64 = 0.45;
+
+/// Converts the stereo samples of the APU to the sample rate of the host
+pub trait Resampler {
+    fn set_rates(&mut self, input_rate: u32, output_rate: u32);
+
+    /// Takes a sample at the input rate, and returns a sample when one is due at the output rate
+    fn push(&mut self, sample: (i16, i16)) -> Option<(i16, i16)>;
+}
+
+/// Averages the input samples falling in each output sample
+#[derive(Default)]
+pub struct BoxFilter {
+    input_rate: u32,
+    output_rate: u32,
+
+    // Goes up by the output rate on each input sample, an output sample is due when it reaches the input rate
+    phase: u32,
+    sum: (i32, i32),
+    count: i32,
+}
+
+impl Resampler for BoxFilter {
+    fn set_rates(&mut self, input_rate: u32, output_rate: u32) {
+        *self = Self {
+            input_rate,
+            output_rate,
+            ..Default::default()
+        };
+    }
+
+    fn push(&mut self, sample: (i16, i16)) -> Option<(i16, i16)> {
+        self.sum.0 += sample.0 as i32;
+        self.sum.1 += sample.1 as i32;
+        self.count += 1;
+
+        self.phase += self.output_rate;
+        if self.phase < self.input_rate {
+            return None;
+        }
+        self.phase -= self.input_rate;
+
+        let output = (
+            (self.sum.0 / self.count) as i16,
+            (self.sum.1 / self.count) as i16,
+        );
+        self.sum = (0, 0);
+        self.count = 0;
+
+        Some(output)
+    }
+}
+
+/// Band-limited synthesis: each change of the input is added to the output as a band-limited step,
+/// which removes the frequencies above the output Nyquist frequency instead of aliasing them
+pub struct BandLimited {
+    input_rate: u32,
+    output_rate: u32,
+    phase: u32,
+
+    kernel: Box<[[i32; TAPS]; PHASES]>,
+
+    last: (i16, i16),
+    // Ring buffer of the changes to apply to the next output samples
+    deltas: [(i64, i64); TAPS],
+    head: usize,
+    sum: (i64, i64),
+}
+
+impl Default for BandLimited {
+    fn default() -> Self {
+        Self {
+            input_rate: 0,
+            output_rate: 0,
+            phase: 0,
+
+            kernel: band_limited_kernel(),
+
+            last: (0, 0),
+            deltas: [(0, 0); TAPS],
+            head: 0,
+            sum: (0, 0),
+        }
+    }
+}
+
+impl Resampler for BandLimited {
+    fn set_rates(&mut self, input_rate: u32, output_rate: u32) {
+        self.input_rate = input_rate;
+        self.output_rate = output_rate;
+        self.phase = 0;
+
+        self.last = (0, 0);
+        self.deltas = [(0, 0); TAPS];
+        self.head = 0;
+        self.sum = (0, 0);
+    }
+
+    fn push(&mut self, sample: (i16, i16)) -> Option<(i16, i16)> {
+        if sample != self.last {
+            let delta = (
+                sample.0 as i64 - self.last.0 as i64,
+                sample.1 as i64 - self.last.1 as i64,
+            );
+            self.last = sample;
+
+            // Position of the change between the previous and the next output sample
+            let phase = (self.phase as u64 * PHASES as u64 / self.input_rate as u64) as usize;
+            for (i, tap) in self.kernel[phase.min(PHASES - 1)].iter().enumerate() {
+                let slot = &mut self.deltas[(self.head + i) % TAPS];
+                slot.0 += delta.0 * *tap as i64;
+                slot.1 += delta.1 * *tap as i64;
+            }
+        }
+
+        self.phase += self.output_rate;
+        if self.phase < self.input_rate {
+            return None;
+        }
+        self.phase -= self.input_rate;
+
+        let delta = core::mem::take(&mut self.deltas[self.head]);
+        self.head = (self.head + 1) % TAPS;
+
+        self.sum.0 += delta.0;
+        self.sum.1 += delta.1;
+
+        let to_sample =
+            |sum: i64| (sum >> KERNEL_BITS).clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+        Some((to_sample(self.sum.0), to_sample(self.sum.1)))
+    }
+}
+
+/// Windowed sinc kernels, for each sub-sample phase
+fn band_limited_kernel() -> Box<[[i32; TAPS]; PHASES]> {
+    let mut kernel = Box::new([[0i32; TAPS]; PHASES]);
+
+    for (phase, taps) in kernel.iter_mut().enumerate() {
+        let offset = phase as f64 / PHASES as f64;
+
+        let mut weights = [0f64; TAPS];
+        for (i, weight) in weights.iter_mut().enumerate() {
+            let x = i as f64 - (TAPS / 2) as f64 - offset;
+            *weight = sinc(x * CUTOFF * 2.0) * blackman(x);
+        }
+        let total: f64 = weights.iter().sum();
+
+        for (tap, weight) in taps.iter_mut().zip(weights) {
+            let value = weight / total * (1 << KERNEL_BITS) as f64;
+            *tap = if value < 0.0 {
+                value - 0.5
+            } else {
+                value + 0.5
+            } as i32;
+        }
+
+        // The rounding error goes to the center, so a step always settles on its exact value
+        let error = (1 << KERNEL_BITS) - taps.iter().sum::<i32>();
+        taps[TAPS / 2] += error;
+    }
+
+    kernel
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        sin(PI * x) / (PI * x)
+    }
+}
+
+/// Blackman window spanning all the taps
+fn blackman(x: f64) -> f64 {
+    let half = (TAPS / 2) as f64;
+    if x <= -half || x >= half {
+        0.0
+    } else {
+        let x = x / TAPS as f64;
+        0.42 + 0.5 * cos(2.0 * PI * x) + 0.08 * cos(4.0 * PI * x)
+    }
+}
+
+/// Taylor series of the sine, as core has no floating point functions
+fn sin(mut x: f64) -> f64 {
+    while x > PI {
+        x -= 2.0 * PI;
+    }
+    while x < -PI {
+        x += 2.0 * PI;
+    }
+
+    let mut term = x;
+    let mut sum = x;
+    for n in 1..12 {
+        term *= -x * x / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+    }
+
+    sum
+}
+
+fn cos(x: f64) -> f64 {
+    sin(x + PI / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Resamples a square wave from ~1MHz to 48kHz, and returns the output after the kernel settles
+    fn resample_square(resampler: &mut dyn Resampler, frequency: u64) -> Vec<i16> {
+        const INPUT_RATE: u64 = 1 << 20;
+        resampler.set_rates(INPUT_RATE as u32, 48000);
+
+        (0..INPUT_RATE / 10)
+            .filter_map(|i| {
+                let level = if (i * frequency * 2 / INPUT_RATE) & 1 == 0 {
+                    8000
+                } else {
+                    -8000
+                };
+                resampler.push((level, level))
+            })
+            .skip(TAPS)
+            .map(|(left, _)| left)
+            .collect()
+    }
+
+    /// Average distance to the mean
+    fn deviation(samples: &[i16]) -> i64 {
+        let mean = samples.iter().map(|&x| x as i64).sum::<i64>() / samples.len() as i64;
+        samples
+            .iter()
+            .map(|&x| (x as i64 - mean).abs())
+            .sum::<i64>()
+            / samples.len() as i64
+    }
+
+    #[test]
+    fn test_sin() {
+        for (x, expected) in [
+            (0.0, 0.0),
+            (PI / 6.0, 0.5),
+            (PI / 2.0, 1.0),
+            (-7.0 * PI / 2.0, 1.0),
+        ] {
+            assert!((sin(x) - expected) < 1e-9 && (expected - sin(x)) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_band_limited_alias() {
+        // A 40kHz square wave is above the Nyquist frequency, so nothing should come out.
+        // Averaging the input aliases it to 8kHz.
+        let aliased = deviation(&resample_square(&mut BoxFilter::default(), 40000));
+        let filtered = deviation(&resample_square(&mut BandLimited::default(), 40000));
+        assert!(aliased > 1000);
+        assert!(filtered < aliased / 10);
+    }
+
+    #[test]
+    fn test_band_limited_square() {
+        // A 500Hz square wave goes through, with a bit of ringing on the edges
+        let samples = resample_square(&mut BandLimited::default(), 500);
+        let max = *samples.iter().max().unwrap();
+        let min = *samples.iter().min().unwrap();
+        assert!((8000..9000).contains(&max));
+        assert!((-9000..=-8000).contains(&min));
+
+        // Between the edges, it settles on the input level
+        let settled = samples
+            .iter()
+            .filter(|&&x| (7990..8010).contains(&x) || (-8010..-7990).contains(&x))
+            .count();
+        assert!(settled > samples.len() / 2);
+    }
+
+    #[test]
+    fn test_box_filter() {
+        let mut filter = BoxFilter::default();
+        filter.set_rates(1000, 300);
+
+        // One output sample every 3.33 input samples, averaging them
+        let outputs: alloc::vec::Vec<_> = (0..10)
+            .filter_map(|i| filter.push((i * 10, -i * 10)))
+            .collect();
+        assert_eq!(outputs, [(15, -15), (50, -50), (80, -80)]);
+    }
+}
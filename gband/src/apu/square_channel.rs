@@ -0,0 +1,377 @@
+// See: https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-1--pulse-with-wavelength-sweep
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use super::sweep::Sweep;
+
+/// Waveforms for each duty cycle, played from the most significant bit
+const DUTY_PATTERNS: [u8; 4] = [
+    0b0000_0001, // 12.5%
+    0b1000_0001, // 25%
+    0b1000_0111, // 50%
+    0b0111_1110, // 75%
+];
+
+/// Pulse channel, with the sweep unit only on channel 1
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SquareChannel {
+    pub enabled: bool,
+
+    duty: u8,
+    duty_step: u8,
+
+    frequency: u16,
+    timer: u16,
+
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    pub fn new(has_sweep: bool) -> Self {
+        Self {
+            enabled: false,
+
+            duty: 0,
+            duty_step: 0,
+
+            frequency: 0,
+            timer: 0,
+
+            length: LengthCounter::new(64),
+            envelope: Default::default(),
+            sweep: if has_sweep {
+                Some(Default::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Clocks the frequency timer, at 4 MiHz
+    pub fn clock(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) & 0x7;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = &mut self.sweep {
+            match sweep.clock(self.frequency) {
+                Some(frequency) => self.frequency = frequency,
+                None => self.enabled = false,
+            }
+        }
+    }
+
+    /// Clears every register when the APU is turned off. The length counter is kept on DMG.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = self.length;
+        *self = Self::new(self.sweep.is_some());
+
+        if keep_length {
+            length.enabled = false;
+            self.length = length;
+        }
+    }
+
+    /// Digital output of the channel, from 0 to 15
+    pub fn output(&self) -> u8 {
+        let is_high = (DUTY_PATTERNS[self.duty as usize] << self.duty_step) & 0x80 != 0;
+
+        if self.enabled && is_high {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    /// NRx0, only on channel 1
+    pub fn write_sweep(&mut self, data: u8) {
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.write(data) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn read_sweep(&self) -> u8 {
+        match &self.sweep {
+            Some(sweep) => sweep.read(),
+            None => 0xFF,
+        }
+    }
+
+    /// NRx1
+    pub fn write_length_duty(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    /// Loads the length without changing the duty, for writes while the APU is off
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    pub fn read_length_duty(&self) -> u8 {
+        // The length is write-only
+        (self.duty << 6) | 0x3F
+    }
+
+    /// NRx2
+    pub fn write_envelope(&mut self, data: u8) {
+        self.envelope.write(data);
+
+        if !self.envelope.is_dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn read_envelope(&self) -> u8 {
+        self.envelope.read()
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.envelope.is_dac_enabled()
+    }
+
+    /// NRx3, which is write-only
+    pub fn write_frequency_low(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x700) | data as u16;
+    }
+
+    /// NRx4. See `LengthCounter::write_control` for `extra_length_clock`.
+    pub fn write_control(&mut self, data: u8, extra_length_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | (((data & 0x7) as u16) << 8);
+
+        let trigger = data & 0x80 != 0;
+        if self
+            .length
+            .write_control(data & 0x40 != 0, trigger, extra_length_clock)
+        {
+            self.enabled = false;
+        }
+
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    pub fn read_control(&self) -> u8 {
+        // Only the length enable bit can be read
+        0xBF | if self.length.enabled { 0x40 } else { 0x00 }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(sweep) = &mut self.sweep {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Dots between two steps of the waveform
+    fn period(&self) -> u16 {
+        (0x800 - self.frequency) * 4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays a full period of the waveform, one sample per step
+    fn waveform(channel: &mut SquareChannel) -> [u8; 8] {
+        let mut samples = [0u8; 8];
+
+        for sample in &mut samples {
+            for _ in 0..channel.period() {
+                channel.clock();
+            }
+            *sample = channel.output();
+        }
+
+        samples
+    }
+
+    #[test]
+    fn test_duty_waveforms() {
+        let expected = [
+            [0, 0, 0, 0, 0, 0, 0, 15],
+            [15, 0, 0, 0, 0, 0, 0, 15],
+            [15, 0, 0, 0, 0, 15, 15, 15],
+            [0, 15, 15, 15, 15, 15, 15, 0],
+        ];
+
+        for (duty, expected) in expected.iter().enumerate() {
+            let mut channel = SquareChannel::new(true);
+            channel.write_length_duty((duty as u8) << 6);
+            channel.write_envelope(0xF0);
+            channel.write_frequency_low(0x00);
+            channel.write_control(0x87, false);
+
+            // The first step played is 1, so start from the end of the previous period
+            channel.clock();
+            let mut samples = waveform(&mut channel);
+            samples.rotate_right(1);
+            assert_eq!(&samples, expected, "duty {}", duty);
+        }
+    }
+
+    #[test]
+    fn test_sweep_overflow_disables() {
+        let mut channel = SquareChannel::new(true);
+        channel.write_sweep(0x11);
+        channel.write_envelope(0xF0);
+        channel.write_frequency_low(0x00);
+        channel.write_control(0x85, false);
+        assert!(channel.enabled);
+
+        // 0x500 + 0x280 fits, but the second check from 0x780 overflows
+        channel.clock_sweep();
+        assert!(!channel.enabled);
+        assert_eq!(channel.output(), 0);
+
+        // Overflowing on trigger never enables it
+        channel.write_control(0x87, false);
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn test_dac_disables() {
+        let mut channel = SquareChannel::new(true);
+        channel.write_envelope(0x08);
+        channel.write_control(0x80, false);
+        assert!(channel.enabled);
+
+        channel.write_envelope(0x00);
+        assert!(!channel.enabled);
+
+        // Can't be triggered without the DAC
+        channel.write_control(0x80, false);
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn test_trigger() {
+        let mut channel = SquareChannel::new(false);
+        channel.write_length_duty(0x80);
+        channel.write_envelope(0xA0);
+        channel.write_frequency_low(0x34);
+        channel.write_control(0x06, false);
+
+        // Nothing plays until triggered
+        assert!(!channel.enabled);
+        assert_eq!(channel.output(), 0);
+
+        channel.write_control(0x86, false);
+        assert!(channel.enabled);
+        assert_eq!(channel.envelope.volume, 0xA);
+        assert_eq!(channel.timer, (0x800 - 0x634) * 4);
+
+        // Retriggering restarts the envelope, but not the waveform
+        channel.clock();
+        channel.clock_envelope();
+        channel.write_envelope(0x30);
+        channel.write_control(0x86, false);
+        assert_eq!(channel.envelope.volume, 0x3);
+        assert_eq!(channel.duty_step, 0);
+        assert_eq!(channel.timer, (0x800 - 0x634) * 4);
+    }
+
+    #[test]
+    fn test_length_enable_mid_note() {
+        let mut channel = SquareChannel::new(false);
+        channel.write_length_duty(0x3E);
+        channel.write_envelope(0xF0);
+        channel.write_control(0x80, false);
+
+        // The length doesn't count while disabled
+        for _ in 0..10 {
+            channel.clock_length();
+        }
+        assert!(channel.enabled);
+
+        // Enabling it while playing counts the remaining 2 ticks
+        channel.write_control(0x40, false);
+        channel.clock_length();
+        assert!(channel.enabled);
+        channel.clock_length();
+        assert!(!channel.enabled);
+
+        // With the extra clock, enabling it on the last tick stops the channel right away
+        channel.write_length_duty(0x3F);
+        channel.write_control(0x80, false);
+        channel.write_control(0x40, true);
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn test_envelope_period_0() {
+        let mut channel = SquareChannel::new(false);
+        channel.write_envelope(0xF0);
+        channel.write_control(0x80, false);
+
+        // The volume doesn't change
+        for _ in 0..16 {
+            channel.clock_envelope();
+        }
+        assert_eq!(channel.envelope.volume, 0xF);
+
+        // But the timer was loaded with 8, so setting a period afterward only applies 8 ticks later
+        channel.write_control(0x80, false);
+        channel.write_envelope(0xF1);
+        for _ in 0..7 {
+            channel.clock_envelope();
+        }
+        assert_eq!(channel.envelope.volume, 0xF);
+        channel.clock_envelope();
+        assert_eq!(channel.envelope.volume, 0xE);
+        channel.clock_envelope();
+        assert_eq!(channel.envelope.volume, 0xD);
+    }
+
+    #[test]
+    fn test_registers_read_back() {
+        let mut channel = SquareChannel::new(true);
+        channel.write_sweep(0xFF);
+        channel.write_length_duty(0xFF);
+        channel.write_envelope(0xFF);
+        channel.write_frequency_low(0xFF);
+        channel.write_control(0x7F, false);
+
+        assert_eq!(channel.read_sweep(), 0xFF);
+        assert_eq!(channel.read_length_duty(), 0xFF);
+        assert_eq!(channel.read_envelope(), 0xFF);
+        assert_eq!(channel.read_control(), 0xFF);
+
+        channel.write_sweep(0x00);
+        channel.write_length_duty(0x00);
+        channel.write_envelope(0x00);
+        channel.write_control(0x00, false);
+
+        assert_eq!(channel.read_sweep(), 0x80);
+        assert_eq!(channel.read_length_duty(), 0x3F);
+        assert_eq!(channel.read_envelope(), 0x00);
+        assert_eq!(channel.read_control(), 0xBF);
+    }
+}
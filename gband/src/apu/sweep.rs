@@ -0,0 +1,170 @@
+// See: https://gbdev.io/pandocs/Audio_Registers.html#ff10--nr10-channel-1-sweep
+
+/// Frequency sweep of channel 1, set through NR10
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+
+    enabled: bool,
+    timer: u8,
+    shadow_frequency: u16,
+
+    // Set when a calculation was done in negate mode since the last trigger
+    negate_used: bool,
+}
+
+impl Sweep {
+    /// Returns false if the channel must be disabled
+    pub fn write(&mut self, data: u8) -> bool {
+        self.period = (data >> 4) & 0x07;
+        self.negate = data & 0x08 != 0;
+        self.shift = data & 0x07;
+
+        // Leaving negate mode after it was used to compute a frequency disables the channel
+        self.negate || !self.negate_used
+    }
+
+    pub fn read(&self) -> u8 {
+        0x80 | (self.period << 4) | if self.negate { 0x08 } else { 0x00 } | self.shift
+    }
+
+    /// Returns false if the channel must be disabled
+    pub fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow_frequency = frequency;
+        self.timer = self.reload_value();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.negate_used = false;
+
+        // The overflow check is done immediately when there is a shift
+        self.shift == 0 || self.calculate().is_some()
+    }
+
+    /// Clocked by the frame sequencer at 128 Hz.
+    /// Returns the new frequency, or None if it overflowed and the channel must be disabled.
+    pub fn clock(&mut self, frequency: u16) -> Option<u16> {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return Some(frequency);
+        }
+
+        self.timer = self.reload_value();
+
+        if !self.enabled || self.period == 0 {
+            return Some(frequency);
+        }
+
+        let new_frequency = self.calculate()?;
+        if self.shift == 0 {
+            return Some(frequency);
+        }
+
+        self.shadow_frequency = new_frequency;
+
+        // The overflow check is done a second time with the new frequency, which isn't kept
+        self.calculate()?;
+
+        Some(new_frequency)
+    }
+
+    /// Returns None on overflow
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow_frequency >> self.shift;
+
+        let new_frequency = if self.negate {
+            self.negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+
+        if new_frequency > 0x7FF {
+            None
+        } else {
+            Some(new_frequency)
+        }
+    }
+
+    fn reload_value(&self) -> u8 {
+        // A period of 0 is treated as 8
+        if self.period == 0 {
+            8
+        } else {
+            self.period
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_up() {
+        let mut sweep = Sweep::default();
+
+        // Every tick, add a quarter of the frequency
+        assert!(sweep.write(0x12));
+        assert_eq!(sweep.read(), 0x92);
+        assert!(sweep.trigger(0x400));
+
+        assert_eq!(sweep.clock(0x400), Some(0x500));
+        assert_eq!(sweep.clock(0x500), Some(0x640));
+
+        // The second calculation from 0x7D0 overflows
+        assert_eq!(sweep.clock(0x640), None);
+    }
+
+    #[test]
+    fn test_sweep_overflow_on_trigger() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x01);
+        assert!(!sweep.trigger(0x7FF));
+
+        // Without shift, there is no calculation on trigger
+        sweep.write(0x70);
+        assert!(sweep.trigger(0x7FF));
+    }
+
+    #[test]
+    fn test_sweep_period() {
+        let mut sweep = Sweep::default();
+
+        // Every 3 ticks, remove half of the frequency
+        sweep.write(0x39);
+        assert!(sweep.trigger(0x400));
+
+        assert_eq!(sweep.clock(0x400), Some(0x400));
+        assert_eq!(sweep.clock(0x400), Some(0x400));
+        assert_eq!(sweep.clock(0x400), Some(0x200));
+
+        // A period of 0 never updates the frequency
+        sweep.write(0x01);
+        assert!(sweep.trigger(0x400));
+        for _ in 0..16 {
+            assert_eq!(sweep.clock(0x400), Some(0x400));
+        }
+    }
+
+    #[test]
+    fn test_sweep_negate_quirk() {
+        let mut sweep = Sweep::default();
+
+        // Switching from negate to addition is fine before any calculation
+        sweep.write(0x18);
+        assert!(sweep.trigger(0x400));
+        assert!(sweep.write(0x10));
+
+        // But disables the channel once a calculation was done in negate mode, like on trigger
+        sweep.write(0x19);
+        assert!(sweep.trigger(0x400));
+        assert!(!sweep.write(0x11));
+
+        // Triggering resets it
+        sweep.write(0x18);
+        sweep.trigger(0x400);
+        assert!(sweep.write(0x10));
+    }
+}
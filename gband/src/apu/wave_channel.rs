@@ -0,0 +1,493 @@
+// See: https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-3--wave-output
+
+use super::length_counter::LengthCounter;
+
+/// Number of 4 bits samples in wave RAM
+const WAVE_SAMPLES: u8 = 32;
+
+/// Extra dots before the first sample is read after a trigger
+const TRIGGER_DELAY: u16 = 6;
+
+/// Wave channel, playing the 4 bits samples stored in wave RAM
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+
+    frequency: u16,
+    timer: u16,
+
+    // Index of the sample being played, and the sample itself
+    position: u8,
+    sample_buffer: u8,
+    // Set on the clock the sample is fetched from wave RAM
+    reading_wave_ram: bool,
+
+    length: LengthCounter,
+    wave_ram: [u8; 16],
+}
+
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+
+            frequency: 0,
+            timer: 0,
+
+            position: 0,
+            sample_buffer: 0,
+            reading_wave_ram: false,
+
+            length: LengthCounter::new(256),
+            wave_ram: [0u8; 16],
+        }
+    }
+}
+
+impl WaveChannel {
+    /// Clocks the frequency timer, at 4 MiHz
+    pub fn clock(&mut self) {
+        self.reading_wave_ram = false;
+        if !self.enabled {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.position = (self.position + 1) % WAVE_SAMPLES;
+            self.sample_buffer = self.sample(self.position);
+            self.reading_wave_ram = true;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Clears every register when the APU is turned off. Wave RAM is kept, and so is the length
+    /// counter on DMG.
+    pub fn power_off(&mut self, keep_length: bool) {
+        let mut length = self.length;
+        *self = Self {
+            wave_ram: self.wave_ram,
+            ..Default::default()
+        };
+
+        if keep_length {
+            length.enabled = false;
+            self.length = length;
+        }
+    }
+
+    /// Digital output of the channel, from 0 to 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.volume_code {
+            0 => 0,
+            code => self.sample_buffer >> (code - 1),
+        }
+    }
+
+    /// NR30
+    pub fn write_dac(&mut self, data: u8) {
+        self.dac_enabled = data & 0x80 != 0;
+
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn read_dac(&self) -> u8 {
+        0x7F | if self.dac_enabled { 0x80 } else { 0x00 }
+    }
+
+    pub fn is_dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    /// NR31, which is write-only
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data as u16);
+    }
+
+    /// NR32
+    pub fn write_volume(&mut self, data: u8) {
+        self.volume_code = (data >> 5) & 0x03;
+    }
+
+    pub fn read_volume(&self) -> u8 {
+        0x9F | (self.volume_code << 5)
+    }
+
+    /// NR33, which is write-only
+    pub fn write_frequency_low(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x700) | data as u16;
+    }
+
+    /// NR34. See `LengthCounter::write_control` for `extra_length_clock`.
+    pub fn write_control(&mut self, data: u8, extra_length_clock: bool) {
+        self.frequency = (self.frequency & 0xFF) | (((data & 0x7) as u16) << 8);
+
+        let trigger = data & 0x80 != 0;
+        if self
+            .length
+            .write_control(data & 0x40 != 0, trigger, extra_length_clock)
+        {
+            self.enabled = false;
+        }
+
+        if trigger {
+            self.enabled = self.dac_enabled;
+            self.timer = self.period() + TRIGGER_DELAY;
+
+            // The sample buffer isn't refilled, so the first sample played is the second one
+            self.position = 0;
+        }
+    }
+
+    pub fn read_control(&self) -> u8 {
+        // Only the length enable bit can be read
+        0xBF | if self.length.enabled { 0x40 } else { 0x00 }
+    }
+
+    /// `index` is the offset in wave RAM, from 0x0 to 0xF
+    pub fn write_wave_ram(&mut self, index: u16, data: u8) {
+        self.wave_ram[(index & 0xF) as usize] = data;
+    }
+
+    pub fn read_wave_ram(&self, index: u16) -> u8 {
+        self.wave_ram[(index & 0xF) as usize]
+    }
+
+    /// Same as `write_wave_ram`, with the quirks of the channel while it plays.
+    /// See `wave_ram_index_playing` and `is_wave_ram_blocked` for `blocking`.
+    pub fn write_wave_ram_playing(&mut self, index: u16, data: u8, blocking: bool) {
+        if !(blocking && self.is_wave_ram_blocked()) {
+            let index = self.wave_ram_index_playing(index);
+            self.wave_ram[index] = data;
+        }
+    }
+
+    /// Same as `read_wave_ram`, with the quirks of the channel while it plays.
+    /// See `wave_ram_index_playing` and `is_wave_ram_blocked` for `blocking`.
+    pub fn read_wave_ram_playing(&self, index: u16, blocking: bool) -> u8 {
+        if blocking && self.is_wave_ram_blocked() {
+            0xFF
+        } else {
+            self.wave_ram[self.wave_ram_index_playing(index)]
+        }
+    }
+
+    /// On DMG, wave RAM can only be accessed while the channel plays on the clock it reads it.
+    /// Reads return 0xFF and writes are ignored otherwise.
+    fn is_wave_ram_blocked(&self) -> bool {
+        self.enabled && !self.reading_wave_ram
+    }
+
+    /// On DMG, retriggering the channel on the clock it reads wave RAM overwrites the first bytes
+    /// of wave RAM. Must be called before the trigger is written.
+    pub fn corrupt_wave_ram(&mut self) {
+        if !self.enabled || !self.reading_wave_ram {
+            return;
+        }
+
+        // The first byte is overwritten with the byte being read if it's one of the first four,
+        // else the first four bytes are overwritten with the aligned block containing it
+        let index = (self.position / 2) as usize;
+        if index < 4 {
+            self.wave_ram[0] = self.wave_ram[index];
+        } else {
+            let start = index & !0x3;
+            self.wave_ram.copy_within(start..start + 4, 0);
+        }
+    }
+
+    fn wave_ram_index_playing(&self, index: u16) -> usize {
+        // While the channel plays, wave RAM accesses hit the byte being played
+        if self.enabled {
+            (self.position / 2) as usize
+        } else {
+            (index & 0xF) as usize
+        }
+    }
+
+    fn sample(&self, position: u8) -> u8 {
+        let byte = self.wave_ram[(position / 2) as usize];
+
+        // The upper nibble is played first
+        if position & 1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0xF
+        }
+    }
+
+    /// Dots between two samples
+    fn period(&self) -> u16 {
+        (0x800 - self.frequency) * 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triggers the channel at its lowest frequency, until the first sample is about to be read
+    fn trigger(channel: &mut WaveChannel) {
+        channel.write_frequency_low(0x00);
+        channel.write_control(0x87, false);
+        for _ in 0..TRIGGER_DELAY {
+            channel.clock();
+        }
+    }
+
+    fn play(channel: &mut WaveChannel, count: usize) -> alloc::vec::Vec<u8> {
+        (0..count)
+            .map(|_| {
+                for _ in 0..channel.period() {
+                    channel.clock();
+                }
+                channel.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_waveform() {
+        let mut channel = WaveChannel::default();
+        for i in 0..16 {
+            channel.write_wave_ram(i, 0x01 + 0x22 * (i as u8 & 0x7));
+        }
+
+        channel.write_dac(0x80);
+        channel.write_volume(0x20);
+        trigger(&mut channel);
+        assert!(channel.enabled);
+
+        // Starts at the second sample
+        let expected: alloc::vec::Vec<u8> = (1..=32).map(|i| (i % 16) as u8).collect();
+        assert_eq!(play(&mut channel, 32), expected);
+
+        // 50% and 25% volumes shift the samples
+        channel.write_volume(0x40);
+        let expected: alloc::vec::Vec<u8> = (1..=16).map(|i| (i % 16) as u8 >> 1).collect();
+        assert_eq!(play(&mut channel, 16), expected);
+
+        channel.write_volume(0x60);
+        let expected: alloc::vec::Vec<u8> = (1..=16).map(|i| (i % 16) as u8 >> 2).collect();
+        assert_eq!(play(&mut channel, 16), expected);
+
+        // Muted
+        channel.write_volume(0x00);
+        assert_eq!(play(&mut channel, 16), [0u8; 16]);
+    }
+
+    #[test]
+    fn test_dac_disables() {
+        let mut channel = WaveChannel::default();
+        channel.write_control(0x80, false);
+        assert!(!channel.enabled);
+
+        channel.write_dac(0x80);
+        channel.write_control(0x80, false);
+        assert!(channel.enabled);
+
+        channel.write_dac(0x00);
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut channel = WaveChannel::default();
+        channel.write_dac(0x80);
+
+        // The length counts up to 256
+        channel.write_length(0x00);
+        channel.write_control(0xC0, false);
+        for _ in 0..255 {
+            channel.clock_length();
+        }
+        assert!(channel.enabled);
+        channel.clock_length();
+        assert!(!channel.enabled);
+    }
+
+    #[test]
+    fn test_wave_ram_while_playing() {
+        let mut channel = WaveChannel::default();
+        for i in 0..16 {
+            channel.write_wave_ram(i, i as u8);
+        }
+        channel.write_dac(0x80);
+        trigger(&mut channel);
+
+        // Move to the sample 6, in the byte 3
+        play(&mut channel, 6);
+        assert_eq!(channel.read_wave_ram_playing(0x0, false), 0x03);
+        channel.write_wave_ram_playing(0xA, 0xAB, false);
+        assert_eq!(channel.wave_ram[3], 0xAB);
+        assert_eq!(channel.wave_ram[0xA], 0x0A);
+
+        // Without the quirks, the address is used
+        assert_eq!(channel.read_wave_ram(0xA), 0x0A);
+
+        // Back to normal once stopped
+        channel.write_dac(0x00);
+        assert_eq!(channel.read_wave_ram_playing(0xA, false), 0x0A);
+    }
+
+    #[test]
+    fn test_wave_ram_blocked() {
+        let mut channel = WaveChannel::default();
+        channel.write_dac(0x80);
+        trigger(&mut channel);
+
+        // Only accessible on the clock the sample is fetched
+        play(&mut channel, 6);
+        assert!(!channel.is_wave_ram_blocked());
+        channel.clock();
+        assert!(channel.is_wave_ram_blocked());
+        assert_eq!(channel.read_wave_ram_playing(0x0, true), 0xFF);
+        assert_eq!(channel.read_wave_ram_playing(0x0, false), 0x00);
+
+        channel.write_dac(0x00);
+        assert!(!channel.is_wave_ram_blocked());
+    }
+
+    #[test]
+    fn test_wave_ram_corruption() {
+        let start = |channel: &mut WaveChannel| {
+            for i in 0..16 {
+                channel.write_wave_ram(i, i as u8);
+            }
+            channel.write_dac(0x80);
+            trigger(channel);
+        };
+
+        // Reading one of the first four bytes only overwrites the first one
+        let mut channel = WaveChannel::default();
+        start(&mut channel);
+        play(&mut channel, 6);
+        channel.corrupt_wave_ram();
+        trigger(&mut channel);
+        assert_eq!(channel.wave_ram[..4], [0x03, 0x01, 0x02, 0x03]);
+
+        // Else the aligned block containing it is copied
+        let mut channel = WaveChannel::default();
+        start(&mut channel);
+        play(&mut channel, 19);
+        channel.corrupt_wave_ram();
+        assert_eq!(channel.wave_ram[..4], [0x08, 0x09, 0x0A, 0x0B]);
+        assert_eq!(channel.wave_ram[4..8], [0x04, 0x05, 0x06, 0x07]);
+
+        // No corruption outside of the read
+        let mut channel = WaveChannel::default();
+        start(&mut channel);
+        play(&mut channel, 19);
+        channel.clock();
+        channel.corrupt_wave_ram();
+        assert_eq!(channel.wave_ram[..4], [0x00, 0x01, 0x02, 0x03]);
+    }
+
+    /// Triggers the channel with a period of 4 dots after `delay` dots, like blargg's dmg_sound tests
+    fn start_blargg(delay: usize) -> WaveChannel {
+        let mut channel = WaveChannel::default();
+        for i in 0..16 {
+            channel.write_wave_ram(i, 0x11 * i as u8);
+        }
+        channel.write_dac(0x80);
+        channel.write_frequency_low(0xFE);
+        channel.write_control(0x87, false);
+        for _ in 0..delay {
+            channel.clock();
+        }
+        channel
+    }
+
+    #[test]
+    fn test_wave_read_while_on() {
+        // Like in 09-wave read while on, wave RAM is read a growing number of dots after the trigger.
+        // The byte being played is only seen on the dots a sample is read: after the trigger delay
+        // and the first period, then every period
+        let reads: alloc::vec::Vec<u8> = (0..32)
+            .map(|delay| start_blargg(delay).read_wave_ram_playing(0x5, true))
+            .collect();
+
+        let mut expected = [0xFF; 32];
+        for (delay, byte) in [
+            (10, 0x00),
+            (14, 0x11),
+            (18, 0x11),
+            (22, 0x22),
+            (26, 0x22),
+            (30, 0x33),
+        ] {
+            expected[delay] = byte;
+        }
+        assert_eq!(reads, expected);
+    }
+
+    #[test]
+    fn test_wave_trigger_while_on() {
+        // Like in 10-wave trigger while on, the channel is retriggered a growing number of dots
+        // after the first trigger. Wave RAM is only corrupted on the dots a sample is read
+        let first_bytes: alloc::vec::Vec<[u8; 4]> = [9, 10, 11, 18, 42, 43]
+            .into_iter()
+            .map(|delay| {
+                let mut channel = start_blargg(delay);
+                channel.corrupt_wave_ram();
+                channel.write_control(0x87, false);
+                channel.wave_ram[..4].try_into().unwrap()
+            })
+            .collect();
+
+        assert_eq!(
+            first_bytes,
+            [
+                [0x00, 0x11, 0x22, 0x33],
+                // The first byte is read, so it's copied to itself
+                [0x00, 0x11, 0x22, 0x33],
+                [0x00, 0x11, 0x22, 0x33],
+                // Second byte
+                [0x11, 0x11, 0x22, 0x33],
+                // Fifth byte, its aligned block is copied
+                [0x44, 0x55, 0x66, 0x77],
+                [0x00, 0x11, 0x22, 0x33],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_registers_read_back() {
+        let mut channel = WaveChannel::default();
+        channel.write_dac(0xFF);
+        channel.write_length(0xFF);
+        channel.write_volume(0xFF);
+        channel.write_frequency_low(0xFF);
+        channel.write_control(0x7F, false);
+
+        assert_eq!(channel.read_dac(), 0xFF);
+        assert_eq!(channel.read_volume(), 0xFF);
+        assert_eq!(channel.read_control(), 0xFF);
+
+        channel.write_dac(0x00);
+        channel.write_volume(0x00);
+        channel.write_control(0x00, false);
+
+        assert_eq!(channel.read_dac(), 0x7F);
+        assert_eq!(channel.read_volume(), 0x9F);
+        assert_eq!(channel.read_control(), 0xBF);
+    }
+}
@@ -1,15 +1,22 @@
 use crate::dma::*;
+use crate::Accuracy;
+use crate::Apu;
 use crate::Cartridge;
 use crate::CgbDoubleSpeed;
+use crate::InfraredPort;
 use crate::InterruptReg;
 use crate::InterruptState;
 use crate::JoypadState;
+use crate::MappedPeripheral;
+use crate::MemAccess;
+use crate::MemAccessKind;
+use crate::MemWatch;
 use crate::Ppu;
 use crate::SerialPort;
 use crate::TimerRegisters;
 use crate::WRAM_BANK_SIZE;
 
-use crate::ppu::FifoMode;
+use crate::ppu::{FifoMode, OamCorruption};
 
 // TODO: Revert macro_export added for criterion
 #[macro_export]
@@ -26,14 +33,29 @@ macro_rules! borrow_cpu_bus {
             &mut $owner.timer_registers,
             &mut $owner.cartridge,
             &mut $owner.ppu,
+            &mut $owner.apu,
             &mut $owner.cgb_mode,
             &mut $owner.serial_port,
+            &mut $owner.infrared_port,
             &$owner.joypad_state,
             &mut $owner.joypad_register,
+            &$owner.accuracy,
+            &mut $owner.mem_watch,
+            &mut $owner.peripherals,
         )
     }};
 }
 
+/// Memory buses that can conflict with OAM DMA
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MemoryBus {
+    /// Cartridge, and WRAM on DMG
+    External,
+    Video,
+    /// WRAM on CGB
+    Wram,
+}
+
 pub struct CpuBus<'a> {
     wram: &'a mut [u8; WRAM_BANK_SIZE as usize * 8],
     wram_bank: &'a mut u8,
@@ -45,10 +67,15 @@ pub struct CpuBus<'a> {
     timer_registers: &'a mut TimerRegisters,
     cartridge: &'a mut Cartridge,
     ppu: &'a mut Ppu,
+    apu: &'a mut Apu,
     cgb_mode: &'a mut bool,
     serial_port: &'a mut SerialPort,
+    infrared_port: &'a mut InfraredPort,
     joypad_state: &'a JoypadState,
     joypad_register: &'a mut u8,
+    accuracy: &'a Accuracy,
+    mem_watch: &'a mut Option<MemWatch>,
+    peripherals: &'a mut alloc::vec::Vec<MappedPeripheral>,
 }
 
 impl<'a> CpuBus<'a> {
@@ -64,10 +91,15 @@ impl<'a> CpuBus<'a> {
         timer_registers: &'a mut TimerRegisters,
         cartridge: &'a mut Cartridge,
         ppu: &'a mut Ppu,
+        apu: &'a mut Apu,
         cgb_mode: &'a mut bool,
         serial_port: &'a mut SerialPort,
+        infrared_port: &'a mut InfraredPort,
         joypad_state: &'a JoypadState,
         joypad_register: &'a mut u8,
+        accuracy: &'a Accuracy,
+        mem_watch: &'a mut Option<MemWatch>,
+        peripherals: &'a mut alloc::vec::Vec<MappedPeripheral>,
     ) -> Self {
         Self {
             wram,
@@ -80,48 +112,63 @@ impl<'a> CpuBus<'a> {
             timer_registers,
             cartridge,
             ppu,
+            apu,
             cgb_mode,
             serial_port,
+            infrared_port,
             joypad_state,
             joypad_register,
+            accuracy,
+            mem_watch,
+            peripherals,
         }
     }
 }
 
 impl CpuBus<'_> {
+    /// CPU write, reported to the memory watch
     pub fn write(&mut self, addr: u16, data: u8) {
-        match self.oam_dma {
-            OamDma {
-                cycle: Some(_),
-                source,
-            } => {
-                // Wraps regular CPU writes to disallow conflicting bus access during OAM_DMA
-                if !Self::check_oam_dma_bus_conflict(*source, addr) {
-                    self.write_without_dma_check(addr, data, false)
-                }
-            }
-            _ => self.write_without_dma_check(addr, data, false),
+        self.notify_mem_watch(addr, data, MemAccessKind::Write);
+        self.write_unwatched(addr, data);
+    }
+
+    /// CPU read, reported to the memory watch
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let data = self.read_unwatched(addr);
+        self.notify_mem_watch(addr, data, MemAccessKind::Read);
+        data
+    }
+
+    fn notify_mem_watch(&mut self, addr: u16, value: u8, kind: MemAccessKind) {
+        if let Some(mem_watch) = self.mem_watch {
+            mem_watch(MemAccess { addr, value, kind });
         }
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
-        match self.oam_dma.clone() {
-            OamDma {
-                cycle: Some(_),
-                source,
-            } => {
-                // Wraps regular CPU reads to disallow conflicting bus access during OAM_DMA
-                if !Self::check_oam_dma_bus_conflict(source, addr) {
-                    self.read_without_dma_check(addr, false)
-                } else {
-                    0xFF
-                }
-            }
-            _ => self.read_without_dma_check(addr, false),
+    /// Same as `write`, for the accesses that aren't reported to the memory watch
+    pub fn write_unwatched(&mut self, addr: u16, data: u8) {
+        // Wraps regular CPU writes to disallow conflicting bus access during OAM_DMA
+        if !self.check_oam_dma_bus_conflict(addr) {
+            self.write_without_dma_check(addr, data, false)
+        }
+    }
+
+    /// Same as `read`, for the accesses that aren't reported to the memory watch
+    pub fn read_unwatched(&self, addr: u16) -> u8 {
+        // Wraps regular CPU reads to disallow conflicting bus access during OAM_DMA
+        if self.check_oam_dma_bus_conflict(addr) {
+            // The CPU sees the byte the DMA is transferring on the shared bus
+            self.oam_dma.value
+        } else {
+            self.read_without_dma_check(addr, false)
         }
     }
 
     pub fn write_without_dma_check(&mut self, addr: u16, data: u8, called_from_dma: bool) {
+        if self.write_peripheral(addr, data) {
+            return;
+        }
+
         match addr {
             0x0000..=0x7fff => {
                 // Cartridge
@@ -157,6 +204,14 @@ impl CpuBus<'_> {
             }
             0xFF04..=0xFF07 => self.timer_registers.write(addr, data),
             0xFF0F => self.interrupts.status = InterruptReg::from_bits_truncate(0xE0 | data),
+            0xFF1E if data & 0x80 != 0 && self.has_wave_ram_quirks() => {
+                self.apu.corrupt_wave_ram();
+                self.apu.write(addr, data)
+            }
+            0xFF30..=0xFF3F if self.accuracy.contains(Accuracy::WAVE_RAM_ACCESS) => self
+                .apu
+                .write_wave_ram_playing(addr, data, self.has_wave_ram_quirks()),
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.write(addr, data),
             0xFF46 => {
                 // OAM DMA
                 self.request_oam_dma(data)
@@ -176,9 +231,25 @@ impl CpuBus<'_> {
                     };
 
                     self.ppu.disable();
+                } else if !was_enabled && is_enabled {
+                    self.ppu.enable();
                 };
             }
-            0xFF40..=0xFF45 | 0xFF47..=0xFF4C | 0xFF4E..=0xFF50 | 0xFF56..=0xFF6F => {
+            0xFF45 => {
+                // LYC is compared to LY as soon as it is written
+                let request_stat = self.ppu.write_lyc(data);
+                if request_stat {
+                    self.request_interrupt(InterruptReg::LCD_STAT);
+                }
+            }
+            0xFF4D | 0xFF51..=0xFF56 | 0xFF70 if !*self.cgb_mode => {
+                // CGB registers
+            }
+            0xFF56 => {
+                // Infrared port (RP)
+                self.infrared_port.write(data)
+            }
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4C | 0xFF4E..=0xFF50 | 0xFF57..=0xFF6F => {
                 // PPU control regs
                 self.ppu.write(addr, data)
             }
@@ -201,6 +272,10 @@ impl CpuBus<'_> {
     }
 
     pub fn read_without_dma_check(&self, addr: u16, called_from_dma: bool) -> u8 {
+        if let Some(data) = self.read_peripheral(addr) {
+            return data;
+        }
+
         match addr {
             0x0000..=0x7fff => {
                 // Cartridge
@@ -236,15 +311,23 @@ impl CpuBus<'_> {
             }
             0xFF04..=0xFF07 => self.timer_registers.read(addr),
             0xFF0F => self.interrupts.status.bits(),
-            0xFF26 => {
-                // NR52, mock for now to make Zelda games work
-                0x00
-            }
+            0xFF30..=0xFF3F if self.accuracy.contains(Accuracy::WAVE_RAM_ACCESS) => self
+                .apu
+                .read_wave_ram_playing(addr, self.has_wave_ram_quirks()),
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F | 0xFF76 | 0xFF77 => self.apu.read(addr),
             0xFF46 => {
                 // OAM DMA
                 self.read_oam_dma()
             }
-            0xFF40..=0xFF45 | 0xFF47..=0xFF4C | 0xFF4E..=0xFF50 | 0xFF56..=0xFF6F => {
+            0xFF4D | 0xFF51..=0xFF56 | 0xFF70 if !*self.cgb_mode => {
+                // CGB registers
+                0xFF
+            }
+            0xFF56 => {
+                // Infrared port (RP)
+                self.infrared_port.read()
+            }
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4C | 0xFF4E..=0xFF50 | 0xFF57..=0xFF6F => {
                 // PPU control reg
                 self.ppu.read(addr)
             }
@@ -256,7 +339,7 @@ impl CpuBus<'_> {
                 // HDMA
                 self.read_hdma(addr)
             }
-            0xFF70 => *self.wram_bank,
+            0xFF70 => 0xF8 | (*self.wram_bank & 0x7),
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
             0xFFFF => self.interrupts.enable.bits(),
             _ => {
@@ -348,40 +431,30 @@ impl CpuBus<'_> {
 
     fn read_hdma(&self, addr: u16) -> u8 {
         match addr {
-            0xFF51 => (self.hdma.source >> 8) as u8,
-            0xFF52 => (self.hdma.source & 0xFF) as u8,
-            0xFF53 => (self.hdma.destination >> 8) as u8,
-            0xFF54 => (self.hdma.destination & 0xFF) as u8,
             0xFF55 => self.hdma.control,
+            // The source and destination are write-only
             _ => 0xFF,
         }
     }
 
     pub fn write_joypad_reg(&mut self, data: u8) {
-        let state: u8 = (*self.joypad_state).bits();
-
-        // Defaults to no button pressed
-        *self.joypad_register = 0;
+        let lines_before = self.joypad_state.input_lines(*self.joypad_register);
 
-        if data & 0x10 == 0 {
-            // If bit 4 is set to 0, handle D-pad
-            *self.joypad_register |= state & 0x0F;
-        };
+        // Only the select lines are writable
+        *self.joypad_register = data & 0x30;
 
-        if data & 0x20 == 0 {
-            // If bit 5 is set to 0, handle the other buttons
-            *self.joypad_register |= (state & 0xF0) >> 4;
+        // Selecting a group with a button held pulls its line low
+        let lines_after = self.joypad_state.input_lines(*self.joypad_register);
+        if JoypadState::is_falling_edge(lines_before, lines_after) {
+            self.request_interrupt(InterruptReg::JOYPAD);
         }
-
-        // Invert button presses
-        *self.joypad_register = !*self.joypad_register;
-
-        // Mask to get only the buttons + add the input bits
-        *self.joypad_register = (*self.joypad_register & 0x0F) | (data & 0x30)
     }
 
     pub fn read_joypad_reg(&self) -> u8 {
-        *self.joypad_register
+        let select = *self.joypad_register;
+
+        // The upper bits are unused
+        0xC0 | select | self.joypad_state.input_lines(select)
     }
 
     pub fn toggle_double_speed(&mut self) {
@@ -393,7 +466,7 @@ impl CpuBus<'_> {
 
     pub fn request_oam_dma(&mut self, source: u8) {
         // Mirror 0xE0-0xFF to 0xC0-0xDF by removing a specific bit
-        let mask = if source & 0xc0 == 0xc0 { !0x20 } else { 0 };
+        let mask = if source & 0xc0 == 0xc0 { !0x20 } else { 0xff };
 
         *self.oam_dma = OamDma::new(source & mask);
     }
@@ -414,6 +487,10 @@ impl CpuBus<'_> {
         *self.cgb_mode
     }
 
+    pub fn get_joypad_state(&self) -> JoypadState {
+        *self.joypad_state
+    }
+
     pub fn get_double_speed_mode(&self) -> CgbDoubleSpeed {
         *self.double_speed
     }
@@ -434,31 +511,89 @@ impl CpuBus<'_> {
         self.serial_port
     }
 
+    pub fn get_infrared_port(&mut self) -> &mut InfraredPort {
+        self.infrared_port
+    }
+
+    pub fn get_apu(&mut self) -> &mut Apu {
+        self.apu
+    }
+
+    pub fn get_interrupts(&mut self) -> &mut InterruptState {
+        self.interrupts
+    }
+
     pub fn request_interrupt(&mut self, interrupt: InterruptReg) {
         self.interrupts.status.insert(interrupt)
     }
 
-    fn check_oam_dma_bus_conflict(source: u8, addr: u16) -> bool {
-        // Bus on CGB are emulated.
-        match (source, addr) {
-            // ROM and SRAM shares the same bus
-            (0x00..=0x7F | 0xA0..=0xBF, 0x0000..=0x7FFF | 0xA000..=0xBFFF) => true,
-            // WRAM has it's own bus.
-            (0xC0..=0xFD, 0xC000..=0xFDFF) => true,
-            // VRAM has it's own bus, which is always blocked because it's the destination
-            (_, 0x8000..=0x9FFF) => true,
-            _ => false,
+    /// Signals that a 16 bits operation put `addr` on the bus on the current M-cycle.
+    /// This can corrupt OAM on DMG if it's enabled.
+    pub(crate) fn trigger_oam_corruption(&mut self, addr: u16, kind: OamCorruption) {
+        if self.accuracy.contains(Accuracy::OAM_CORRUPTION)
+            && !*self.cgb_mode
+            && (0xFE00..=0xFEFF).contains(&addr)
+        {
+            self.ppu.corrupt_oam(kind);
+        }
+    }
+
+    fn read_peripheral(&self, addr: u16) -> Option<u8> {
+        self.peripherals
+            .iter()
+            .filter(|mapped| mapped.range.contains(&addr))
+            .find_map(|mapped| mapped.peripheral.read(addr))
+    }
+
+    fn write_peripheral(&mut self, addr: u16, data: u8) -> bool {
+        self.peripherals
+            .iter_mut()
+            .filter(|mapped| mapped.range.contains(&addr))
+            .any(|mapped| mapped.peripheral.write(addr, data))
+    }
+
+    /// The DMG wave RAM quirks, see `Accuracy::WAVE_RAM_ACCESS`
+    fn has_wave_ram_quirks(&self) -> bool {
+        self.accuracy.contains(Accuracy::WAVE_RAM_ACCESS) && !*self.cgb_mode
+    }
+
+    /// Returns true if `addr` is on the bus used by the OAM DMA to read its source
+    fn check_oam_dma_bus_conflict(&self, addr: u16) -> bool {
+        if self.oam_dma.cycle.is_none() {
+            return false;
+        }
+
+        let source = (self.oam_dma.source as u16) << 8;
+        match self.memory_bus(addr) {
+            Some(bus) => self.memory_bus(source) == Some(bus),
+            None => false,
+        }
+    }
+
+    /// Bus used to access `addr`, or None if it's inside the CPU (OAM, IO registers and HRAM)
+    fn memory_bus(&self, addr: u16) -> Option<MemoryBus> {
+        match addr {
+            0x8000..=0x9FFF => Some(MemoryBus::Video),
+            // CGB has a separate bus for WRAM, it's on the external one with the cartridge on DMG
+            0xC000..=0xFDFF if *self.cgb_mode => Some(MemoryBus::Wram),
+            0x0000..=0xFDFF => Some(MemoryBus::External),
+            _ => None,
         }
     }
 
     #[cfg(feature = "debugger")]
-    pub fn get_cartridge_rom_bank(&self) -> u8 {
-        self.cartridge.get_rom_bank()
+    pub fn get_cartridge_rom_bank(&self) -> u16 {
+        self.cartridge.current_rom_bank()
     }
 
     #[cfg(feature = "debugger")]
     pub fn get_cartridge_ram_bank(&self) -> u8 {
-        self.cartridge.get_ram_bank()
+        self.cartridge.current_ram_bank()
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn get_wram_bank(&self) -> u8 {
+        *self.wram_bank
     }
 }
 
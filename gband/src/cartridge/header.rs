@@ -80,6 +80,14 @@ pub struct Header {
     pub global_checksum: [u8; 2],
 }
 
+impl Header {
+    /// The CGB boot ROM only colorizes DMG games published by Nintendo
+    pub fn is_nintendo_licensed(&self) -> bool {
+        self.old_licensee_code == 0x01
+            || (self.old_licensee_code == 0x33 && &self.licensee_code == b"01")
+    }
+}
+
 impl TryFrom<&[u8]> for Header {
     type Error = RomParserError;
 
@@ -1,6 +1,7 @@
 use super::Mapper;
 use crate::cartridge::CartridgeReadTarget;
 
+#[derive(Clone)]
 pub struct Mbc1 {
     n_rom_banks: usize,
     n_ram_banks: usize,
@@ -1,6 +1,7 @@
 use super::Mapper;
 use crate::cartridge::CartridgeReadTarget;
 
+#[derive(Clone)]
 pub struct Mbc2 {
     bank_mask: usize,
     ram_enable: bool,
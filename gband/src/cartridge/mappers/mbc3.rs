@@ -1,6 +1,7 @@
 use super::Mapper;
 use crate::cartridge::CartridgeReadTarget;
 
+#[derive(Clone)]
 pub struct Mbc3 {
     ram_rtc_enable: bool,
     ram_or_rtc_bank_number: u8,
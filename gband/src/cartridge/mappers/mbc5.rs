@@ -1,16 +1,21 @@
 use super::Mapper;
 use crate::cartridge::CartridgeReadTarget;
 
+#[derive(Clone)]
 pub struct Mbc5 {
     ram_enable: bool,
     rom_bank_number: u8,
     rom_bank_number_9th: u8,
     ram_bank_number: u8,
+
+    has_rumble: bool,
+    rumble: bool,
 }
 
 impl Mbc5 {
-    pub fn new() -> Self {
+    pub fn new(has_rumble: bool) -> Self {
         Self {
+            has_rumble,
             ..Default::default()
         }
     }
@@ -23,6 +28,9 @@ impl Default for Mbc5 {
             rom_bank_number: 0x00,
             rom_bank_number_9th: 0x00,
             ram_bank_number: 0x00,
+
+            has_rumble: false,
+            rumble: false,
         }
     }
 }
@@ -85,7 +93,13 @@ impl Mapper for Mbc5 {
             }
             0x4000..=0x5FFF => {
                 // Set RAM bank number
-                self.ram_bank_number = data & 0xF;
+                // On rumble cartridges, bit 3 drives the motor instead
+                if self.has_rumble {
+                    self.ram_bank_number = data & 0x7;
+                    self.rumble = data & 0x8 != 0;
+                } else {
+                    self.ram_bank_number = data & 0xF;
+                }
                 None
             }
             0xA000..=0xBFFF => {
@@ -108,4 +122,8 @@ impl Mapper for Mbc5 {
             }
         }
     }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
 }
@@ -0,0 +1,468 @@
+use alloc::vec::Vec;
+
+use super::Mapper;
+use crate::cartridge::CartridgeReadTarget;
+
+/// Size of the 93LC56 EEPROM, organized in 128 words of 16 bits
+pub const EEPROM_SIZE: usize = 256;
+
+/// Raw accelerometer value when the cartridge is flat
+const ACCELEROMETER_CENTER: f32 = 0x81D0 as f32;
+/// Raw accelerometer units per g
+const ACCELEROMETER_SCALE: f32 = 0x70 as f32;
+/// Raw accelerometer value after erasing the latch
+const ACCELEROMETER_ERASED: u16 = 0x8000;
+
+/// Start bit, 2 bits of opcode and 8 bits of address
+const EEPROM_COMMAND_BITS: u8 = 11;
+
+const EEPROM_CS: u8 = 0x80;
+const EEPROM_CLK: u8 = 0x40;
+const EEPROM_DI: u8 = 0x02;
+const EEPROM_DO: u8 = 0x01;
+
+#[derive(Clone, Copy)]
+enum EepromState {
+    /// Shifting in a command, waiting for its start bit if no bit was shifted yet
+    Command { command: u16, bits: u8 },
+    /// Shifting out words, starting from `addr`
+    Read { addr: u8, bit: u8 },
+    /// Shifting in the data of a write, to a word or to all of them
+    Write {
+        addr: Option<u8>,
+        data: u16,
+        bits: u8,
+    },
+    /// The command is done, until CS goes low
+    Done,
+}
+
+impl Default for EepromState {
+    fn default() -> Self {
+        Self::Command {
+            command: 0,
+            bits: 0,
+        }
+    }
+}
+
+/// 93LC56 serial EEPROM, used as the save memory
+#[derive(Clone)]
+struct Eeprom {
+    data: Vec<u8>,
+    state: EepromState,
+    write_enabled: bool,
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    data_out: bool,
+}
+
+impl Eeprom {
+    fn new(save_data: Option<&[u8]>) -> Self {
+        // An erased EEPROM reads all 1s
+        let mut data = alloc::vec![0xFF; EEPROM_SIZE];
+
+        match save_data {
+            Some(save_data) if save_data.len() == EEPROM_SIZE => data.copy_from_slice(save_data),
+            Some(save_data) => log::warn!(
+                "Couldn't load save as the size doesn't match. EEPROM: {:x}, Save: {:x}",
+                EEPROM_SIZE,
+                save_data.len()
+            ),
+            None => {}
+        }
+
+        Self {
+            data,
+            state: Default::default(),
+            write_enabled: false,
+
+            cs: false,
+            clk: false,
+            di: false,
+            data_out: true,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        let mut value = 0;
+
+        if self.cs {
+            value |= EEPROM_CS;
+        }
+        if self.clk {
+            value |= EEPROM_CLK;
+        }
+        if self.di {
+            value |= EEPROM_DI;
+        }
+        if self.data_out {
+            value |= EEPROM_DO;
+        }
+
+        value
+    }
+
+    fn write(&mut self, data: u8) {
+        let cs = data & EEPROM_CS != 0;
+        let clk = data & EEPROM_CLK != 0;
+        let di = data & EEPROM_DI != 0;
+
+        if !cs {
+            // Deselecting aborts the command, and the EEPROM is ready
+            self.state = Default::default();
+            self.data_out = true;
+        } else if clk && !self.clk {
+            // Bits are shifted on the rising edge of the clock
+            self.clock(di);
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+        self.di = di;
+    }
+
+    fn clock(&mut self, di: bool) {
+        self.state = match self.state {
+            // Waiting for the start bit
+            EepromState::Command { bits: 0, .. } if !di => self.state,
+            EepromState::Command { command, bits } => {
+                let command = (command << 1) | di as u16;
+                let bits = bits + 1;
+
+                if bits == EEPROM_COMMAND_BITS {
+                    self.execute(command)
+                } else {
+                    EepromState::Command { command, bits }
+                }
+            }
+            EepromState::Read { addr, bit } => {
+                self.data_out = self.read_word(addr) & (0x8000 >> bit) != 0;
+
+                // Words are read sequentially
+                if bit == 15 {
+                    EepromState::Read {
+                        addr: addr.wrapping_add(1),
+                        bit: 0,
+                    }
+                } else {
+                    EepromState::Read { addr, bit: bit + 1 }
+                }
+            }
+            EepromState::Write { addr, data, bits } => {
+                let data = (data << 1) | di as u16;
+                let bits = bits + 1;
+
+                if bits == 16 {
+                    match addr {
+                        Some(addr) => self.write_word(addr, data),
+                        None => {
+                            (0..EEPROM_SIZE as u8 / 2).for_each(|addr| self.write_word(addr, data))
+                        }
+                    }
+                    self.data_out = true;
+                    EepromState::Done
+                } else {
+                    EepromState::Write { addr, data, bits }
+                }
+            }
+            EepromState::Done => EepromState::Done,
+        };
+    }
+
+    fn execute(&mut self, command: u16) -> EepromState {
+        let opcode = (command >> 8) & 0x3;
+        let addr = command as u8 & 0x7F;
+
+        match opcode {
+            0b10 => {
+                // READ, which starts with a dummy 0
+                self.data_out = false;
+                EepromState::Read { addr, bit: 0 }
+            }
+            0b01 => EepromState::Write {
+                addr: Some(addr),
+                data: 0,
+                bits: 0,
+            },
+            0b11 => {
+                // ERASE
+                self.write_word(addr, 0xFFFF);
+                EepromState::Done
+            }
+            _ => match (command >> 6) & 0x3 {
+                0b11 => {
+                    // EWEN
+                    self.write_enabled = true;
+                    EepromState::Done
+                }
+                0b00 => {
+                    // EWDS
+                    self.write_enabled = false;
+                    EepromState::Done
+                }
+                0b10 => {
+                    // ERAL
+                    (0..EEPROM_SIZE as u8 / 2).for_each(|addr| self.write_word(addr, 0xFFFF));
+                    EepromState::Done
+                }
+                _ => EepromState::Write {
+                    // WRAL
+                    addr: None,
+                    data: 0,
+                    bits: 0,
+                },
+            },
+        }
+    }
+
+    fn read_word(&self, addr: u8) -> u16 {
+        let addr = (addr & 0x7F) as usize * 2;
+        u16::from_le_bytes([self.data[addr], self.data[addr + 1]])
+    }
+
+    fn write_word(&mut self, addr: u8, value: u16) {
+        if self.write_enabled {
+            let addr = (addr & 0x7F) as usize * 2;
+            self.data[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Mbc7 {
+    ram_enable_1: bool,
+    ram_enable_2: bool,
+    rom_bank_number: u8,
+
+    accelerometer: (u16, u16),
+    accelerometer_latch: (u16, u16),
+    accelerometer_latched: bool,
+
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(save_data: Option<&[u8]>) -> Self {
+        let center = ACCELEROMETER_CENTER as u16;
+
+        Self {
+            ram_enable_1: false,
+            ram_enable_2: false,
+            rom_bank_number: 0x01,
+
+            accelerometer: (center, center),
+            accelerometer_latch: (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED),
+            accelerometer_latched: false,
+
+            eeprom: Eeprom::new(save_data),
+        }
+    }
+
+    fn read_register(&self, addr: u16) -> u8 {
+        let (x, y) = self.accelerometer_latch;
+
+        match (addr >> 4) & 0xF {
+            0x2 => x as u8,
+            0x3 => (x >> 8) as u8,
+            0x4 => y as u8,
+            0x5 => (y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match (addr >> 4) & 0xF {
+            0x0 if data == 0x55 => {
+                // Erase the latched values
+                self.accelerometer_latch = (ACCELEROMETER_ERASED, ACCELEROMETER_ERASED);
+                self.accelerometer_latched = false;
+            }
+            0x1 if data == 0xAA && !self.accelerometer_latched => {
+                // Latch the accelerometer, which only works after an erase
+                self.accelerometer_latch = self.accelerometer;
+                self.accelerometer_latched = true;
+            }
+            0x8 => self.eeprom.write(data),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn map_read(&self, addr: u16) -> CartridgeReadTarget {
+        match addr {
+            0x0000..=0x3FFF => {
+                // First bank
+                // Fixed to bank 0
+                let mask = 0x3FFF;
+                CartridgeReadTarget::Rom((addr & mask) as usize)
+            }
+            0x4000..=0x7FFF => {
+                // Switchable ROM banks
+                let mask = 0x3fff;
+                let addr = (addr & mask) as usize;
+
+                let bank = (self.rom_bank_number as usize) << 14usize;
+                CartridgeReadTarget::Rom(bank | addr)
+            }
+            0xA000..=0xAFFF if self.ram_enable_1 && self.ram_enable_2 => {
+                // Accelerometer and EEPROM registers
+                CartridgeReadTarget::Value(self.read_register(addr))
+            }
+            0xA000..=0xBFFF => CartridgeReadTarget::Value(0xFF),
+            _ => {
+                log::warn!("Read on cartridge at {addr}, which isn't supposed to be mapped to the cartridge");
+                CartridgeReadTarget::Error
+            }
+        }
+    }
+
+    fn map_write(&mut self, addr: u16, data: u8) -> Option<usize> {
+        match addr {
+            0x0000..=0x1FFF => {
+                // First RAM enable
+                self.ram_enable_1 = data & 0xF == 0x0A;
+            }
+            0x2000..=0x3FFF => {
+                // Set ROM bank number
+                // Used to bank switch range 0x4000 - 0x7FFF
+                self.rom_bank_number = data;
+            }
+            0x4000..=0x5FFF => {
+                // Second RAM enable
+                self.ram_enable_2 = data == 0x40;
+            }
+            0xA000..=0xAFFF if self.ram_enable_1 && self.ram_enable_2 => {
+                self.write_register(addr, data)
+            }
+            0x6000..=0x7FFF | 0xA000..=0xBFFF => {}
+            _ => {
+                log::warn!("Write on cartridge at {addr}, which isn't supposed to be mapped to the cartridge");
+            }
+        }
+
+        // There is no RAM, the save is in the EEPROM
+        None
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        let raw = |g: f32| {
+            (ACCELEROMETER_CENTER + g * ACCELEROMETER_SCALE).clamp(0.0, u16::MAX as f32) as u16
+        };
+
+        self.accelerometer = (raw(x), raw(y));
+    }
+
+    fn save_data(&self) -> Option<&[u8]> {
+        Some(&self.eeprom.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> Mbc7 {
+        let mut mbc7 = Mbc7::new(None);
+        mbc7.map_write(0x0000, 0x0A);
+        mbc7.map_write(0x4000, 0x40);
+        mbc7
+    }
+
+    fn read(mbc7: &Mbc7, addr: u16) -> u8 {
+        match mbc7.map_read(addr) {
+            CartridgeReadTarget::Value(value) => value,
+            _ => panic!("registers should read a value"),
+        }
+    }
+
+    /// Shifts bits into the EEPROM, MSB first. Returns the DO bits read after each rising edge.
+    fn shift(mbc7: &mut Mbc7, value: u32, bits: u8) -> u32 {
+        let mut out = 0;
+
+        for bit in (0..bits).rev() {
+            let di = if value & (1 << bit) != 0 {
+                EEPROM_DI
+            } else {
+                0
+            };
+            mbc7.map_write(0xA080, EEPROM_CS | di);
+            mbc7.map_write(0xA080, EEPROM_CS | EEPROM_CLK | di);
+            out = (out << 1) | (read(mbc7, 0xA080) & EEPROM_DO) as u32;
+        }
+
+        out
+    }
+
+    fn deselect(mbc7: &mut Mbc7) {
+        mbc7.map_write(0xA080, 0x00);
+    }
+
+    #[test]
+    fn test_accelerometer() {
+        let mut mbc7 = enabled();
+        mbc7.set_accelerometer(1.0, -0.5);
+
+        // Nothing is latched before an erase and a latch
+        assert_eq!(read(&mbc7, 0xA020), 0x00);
+        assert_eq!(read(&mbc7, 0xA030), 0x80);
+
+        mbc7.map_write(0xA000, 0x55);
+        mbc7.map_write(0xA010, 0xAA);
+        assert_eq!(
+            [0xA020, 0xA030, 0xA040, 0xA050].map(|addr| read(&mbc7, addr)),
+            [0x40, 0x82, 0x98, 0x81]
+        );
+
+        // The values stay latched until the next erase
+        mbc7.set_accelerometer(0.0, 0.0);
+        mbc7.map_write(0xA010, 0xAA);
+        assert_eq!(read(&mbc7, 0xA020), 0x40);
+
+        mbc7.map_write(0xA000, 0x55);
+        mbc7.map_write(0xA010, 0xAA);
+        assert_eq!(read(&mbc7, 0xA020), 0xD0);
+
+        // Registers are only accessible when both enables are set
+        mbc7.map_write(0x4000, 0x00);
+        assert_eq!(read(&mbc7, 0xA020), 0xFF);
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut save = [0u8; EEPROM_SIZE];
+        save[4..6].copy_from_slice(&[0x34, 0x12]);
+        let mut mbc7 = Mbc7::new(Some(&save));
+        mbc7.map_write(0x0000, 0x0A);
+        mbc7.map_write(0x4000, 0x40);
+
+        // READ word 2, the dummy bit is read with the last address bit
+        let out = shift(&mut mbc7, 0b110_0000_0010, 11);
+        assert_eq!(out & 1, 0);
+        assert_eq!(shift(&mut mbc7, 0, 16), 0x1234);
+        deselect(&mut mbc7);
+
+        // WRITE is ignored until EWEN
+        shift(&mut mbc7, 0b101_0000_0011, 11);
+        shift(&mut mbc7, 0xBEEF, 16);
+        deselect(&mut mbc7);
+        assert_eq!(&mbc7.save_data().unwrap()[6..8], &[0x00, 0x00]);
+
+        shift(&mut mbc7, 0b100_1100_0000, 11);
+        deselect(&mut mbc7);
+        shift(&mut mbc7, 0b101_0000_0011, 11);
+        shift(&mut mbc7, 0xBEEF, 16);
+        deselect(&mut mbc7);
+        assert_eq!(&mbc7.save_data().unwrap()[6..8], &[0xEF, 0xBE]);
+
+        // Sequential read
+        shift(&mut mbc7, 0b110_0000_0010, 11);
+        assert_eq!(shift(&mut mbc7, 0, 32), 0x1234_BEEF);
+        deselect(&mut mbc7);
+    }
+}
@@ -1,18 +1,52 @@
+use alloc::boxed::Box;
+
 use super::CartridgeReadTarget;
 
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc7;
 mod no_mapper;
 
 pub use mbc1::Mbc1;
 pub use mbc2::Mbc2;
 pub use mbc3::Mbc3;
 pub use mbc5::Mbc5;
+pub use mbc7::Mbc7;
 pub use no_mapper::NoMapper;
 
-pub trait Mapper: Send + Sync {
+pub trait Mapper: MapperClone + Send + Sync {
     fn map_read(&self, addr: u16) -> CartridgeReadTarget;
     fn map_write(&mut self, addr: u16, data: u8) -> Option<usize>;
+
+    /// Whether the rumble motor of the cartridge is on
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Sets the acceleration measured by the cartridge, in g
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {}
+
+    /// Save data kept by the mapper instead of the cartridge RAM, like an EEPROM
+    fn save_data(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Allows cloning the boxed mapper of a cartridge, implemented for every `Clone` mapper
+pub trait MapperClone {
+    fn clone_box(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: Mapper + Clone + 'static> MapperClone for T {
+    fn clone_box(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
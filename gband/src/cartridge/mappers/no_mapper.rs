@@ -1,6 +1,7 @@
 use super::Mapper;
 use crate::cartridge::CartridgeReadTarget;
 
+#[derive(Clone)]
 pub struct NoMapper;
 
 impl Mapper for NoMapper {
@@ -9,11 +9,23 @@ pub use header::RomParserError;
 
 use self::header::CgbFlag;
 
+/// Called with the new state of the rumble motor when it changes
+pub type RumbleCallback = Box<dyn FnMut(bool)>;
+
 pub enum CartridgeReadTarget {
     Error,
     Rom(usize),
     Ram(usize),
     RamHalf(usize),
+    /// Register of the mapper
+    Value(u8),
+}
+
+/// State of the cartridge that changes while running, which excludes the ROM
+#[derive(Clone)]
+pub(crate) struct CartridgeState {
+    ram: Option<alloc::vec::Vec<u8>>,
+    mapper: Box<dyn Mapper>,
 }
 
 pub struct Cartridge {
@@ -21,6 +33,7 @@ pub struct Cartridge {
     pub rom: alloc::vec::Vec<u8>,
     pub ram: Option<alloc::vec::Vec<u8>>,
     mapper: Box<dyn Mapper>,
+    rumble_callback: Option<RumbleCallback>,
 }
 
 impl Cartridge {
@@ -77,12 +90,13 @@ impl Cartridge {
             | CartridgeType::Mbc3
             | CartridgeType::Mbc3Ram
             | CartridgeType::Mbc3RamBattery => Box::new(Mbc3::new()),
-            CartridgeType::Mbc5
-            | CartridgeType::Mbc5Ram
-            | CartridgeType::Mbc5RamBattery
-            | CartridgeType::Mbc5Rumble
+            CartridgeType::Mbc5 | CartridgeType::Mbc5Ram | CartridgeType::Mbc5RamBattery => {
+                Box::new(Mbc5::new(false))
+            }
+            CartridgeType::Mbc5Rumble
             | CartridgeType::Mbc5RumbleRam
-            | CartridgeType::Mbc5RumbleRamBattery => Box::new(Mbc5::new()),
+            | CartridgeType::Mbc5RumbleRamBattery => Box::new(Mbc5::new(true)),
+            CartridgeType::Mbc7SensorRumbleRamBattery => Box::new(Mbc7::new(save_data)),
             _ => return Err(RomParserError::MapperNotImplemented),
         };
 
@@ -91,6 +105,7 @@ impl Cartridge {
             rom,
             ram,
             mapper,
+            rumble_callback: None,
         })
     }
 
@@ -116,10 +131,13 @@ impl Cartridge {
                     0
                 }
             },
+            CartridgeReadTarget::Value(value) => value,
         }
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
+        let rumble = self.mapper.rumble();
+
         if let Some(addr) = self.mapper.map_write(addr, data) {
             match &mut self.ram {
                 Some(ram) => {
@@ -133,40 +151,232 @@ impl Cartridge {
                 }
             }
         };
+
+        self.notify_rumble(rumble);
+    }
+
+    /// Sets the acceleration measured by cartridges with an accelerometer, in g
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mapper.set_accelerometer(x, y)
+    }
+
+    pub fn set_rumble_callback(&mut self, callback: Option<RumbleCallback>) {
+        self.rumble_callback = callback;
+    }
+
+    /// Fires the rumble callback if the motor changed from `previous`
+    fn notify_rumble(&mut self, previous: bool) {
+        let rumble = self.mapper.rumble();
+        if rumble != previous {
+            if let Some(callback) = &mut self.rumble_callback {
+                callback(rumble);
+            }
+        }
     }
 
     pub fn get_save_data(&self) -> Option<&[u8]> {
+        if let Some(save_data) = self.mapper.save_data() {
+            return Some(save_data);
+        }
+
         match &self.ram {
             Some(r) => Some(r),
             None => None,
         }
     }
 
+    pub(crate) fn save_state(&self) -> CartridgeState {
+        CartridgeState {
+            ram: self.ram.clone(),
+            mapper: self.mapper.clone(),
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, state: CartridgeState) {
+        let rumble = self.mapper.rumble();
+
+        self.ram = state.ram;
+        self.mapper = state.mapper;
+
+        self.notify_rumble(rumble);
+    }
+
     pub fn is_cgb(&self) -> bool {
         !matches!(self.header.cgb_flag, CgbFlag::NoCgb)
     }
 
-    #[cfg(feature = "debugger")]
-    pub fn get_rom_bank(&self) -> u8 {
-        if let CartridgeReadTarget::Rom(addr) = self.mapper.map_read(0x4000) {
-            (addr / 0x4000) as u8
-        } else {
-            0
+    /// ROM bank visible at 0x4000-0x7FFF, wrapped to the size of the ROM like the reads
+    pub fn current_rom_bank(&self) -> u16 {
+        match self.mapper.map_read(0x4000) {
+            CartridgeReadTarget::Rom(addr) => ((addr % self.rom.len()) / 0x4000) as u16,
+            _ => 0,
         }
     }
 
-    #[cfg(feature = "debugger")]
-    pub fn get_ram_bank(&self) -> u8 {
-        match self.mapper.map_read(0xA000) {
-            CartridgeReadTarget::Ram(addr) => match &self.ram {
-                Some(ram) => (addr / ram.len()) as u8,
-                None => 0,
-            },
-            CartridgeReadTarget::RamHalf(addr) => match &self.ram {
-                Some(ram) => (addr / ram.len()) as u8,
-                None => 0,
-            },
+    /// RAM bank visible at 0xA000-0xBFFF, or 0 if no RAM is mapped there
+    pub fn current_ram_bank(&self) -> u8 {
+        match (self.mapper.map_read(0xA000), &self.ram) {
+            (CartridgeReadTarget::Ram(addr) | CartridgeReadTarget::RamHalf(addr), Some(ram)) => {
+                ((addr % ram.len()) / 0x2000) as u8
+            }
             _ => 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    /// ROM with the given cartridge type and 128KiB of RAM
+    fn rom(cartridge_type: u8) -> [u8; 0x150] {
+        let mut rom = [0u8; 0x150];
+        rom[0x147] = cartridge_type;
+        rom[0x149] = 4;
+        rom[0x14d] = 231u8.wrapping_sub(cartridge_type).wrapping_sub(4);
+        rom
+    }
+
+    /// ROM with the given cartridge type, and the ROM and RAM sizes of the header
+    fn sized_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = alloc::vec![0u8; 0x8000 << rom_size];
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_size;
+        rom[0x149] = ram_size;
+        rom[0x14d] = 231u8
+            .wrapping_sub(cartridge_type)
+            .wrapping_sub(rom_size)
+            .wrapping_sub(ram_size);
+        rom
+    }
+
+    #[test]
+    fn test_mbc5_rumble() {
+        let mut cartridge = Cartridge::load(&rom(0x1E), None).unwrap();
+
+        let rumble = Rc::new(RefCell::new(Vec::new()));
+        let callback_rumble = rumble.clone();
+        cartridge.set_rumble_callback(Some(Box::new(move |on| {
+            callback_rumble.borrow_mut().push(on)
+        })));
+
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x03);
+        cartridge.write(0xA000, 0x42);
+
+        // Bit 3 turns the motor on without changing the RAM bank
+        cartridge.write(0x4000, 0x0B);
+        assert_eq!(cartridge.read(0xA000), 0x42);
+        cartridge.write(0x4000, 0x0B);
+        cartridge.write(0x4000, 0x03);
+        cartridge.write(0x4000, 0x08);
+        assert_eq!(cartridge.read(0xA000), 0x00);
+
+        assert_eq!(*rumble.borrow(), [true, false, true]);
+        assert_eq!(cartridge.ram.as_ref().unwrap()[3 * 0x2000], 0x42);
+    }
+
+    #[test]
+    fn test_mbc5_without_rumble() {
+        let mut cartridge = Cartridge::load(&rom(0x1B), None).unwrap();
+        cartridge.set_rumble_callback(Some(Box::new(|_| panic!("the cartridge has no rumble"))));
+
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x0B);
+        cartridge.write(0xA000, 0x42);
+        assert_eq!(cartridge.ram.as_ref().unwrap()[11 * 0x2000], 0x42);
+    }
+
+    #[test]
+    fn test_mbc7() {
+        let mut rom = [0u8; 0x150];
+        rom[0x147] = 0x22;
+        rom[0x14d] = 231u8.wrapping_sub(0x22);
+        let save = [0x5Au8; 256];
+        let mut cartridge = Cartridge::load(&rom, Some(&save)).unwrap();
+
+        // The EEPROM is saved, there is no RAM
+        assert!(cartridge.ram.is_none());
+        assert_eq!(cartridge.get_save_data(), Some(&save[..]));
+
+        cartridge.set_accelerometer(0.0, 1.0);
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x40);
+        cartridge.write(0xA000, 0x55);
+        cartridge.write(0xA010, 0xAA);
+        assert_eq!(cartridge.read(0xA020), 0xD0);
+        assert_eq!(cartridge.read(0xA050), 0x82);
+    }
+
+    #[test]
+    fn test_mbc1_banks() {
+        // 32 ROM banks and 4 RAM banks
+        let mut cartridge = Cartridge::load(&sized_rom(0x03, 4, 3), None).unwrap();
+        assert_eq!(cartridge.current_rom_bank(), 1);
+        assert_eq!(cartridge.current_ram_bank(), 0);
+
+        cartridge.write(0x2000, 0x15);
+        assert_eq!(cartridge.current_rom_bank(), 0x15);
+
+        // Only 5 bits are used, and bank 0 selects bank 1
+        cartridge.write(0x2000, 0x35);
+        assert_eq!(cartridge.current_rom_bank(), 0x15);
+        cartridge.write(0x2000, 0x00);
+        assert_eq!(cartridge.current_rom_bank(), 1);
+
+        // The RAM bank only applies in advanced banking mode
+        cartridge.write(0x0000, 0x0A);
+        cartridge.write(0x4000, 0x02);
+        assert_eq!(cartridge.current_ram_bank(), 0);
+        cartridge.write(0x6000, 0x01);
+        assert_eq!(cartridge.current_ram_bank(), 2);
+
+        // Disabled RAM isn't mapped
+        cartridge.write(0x0000, 0x00);
+        assert_eq!(cartridge.current_ram_bank(), 0);
+    }
+
+    #[test]
+    fn test_mbc3_banks() {
+        // 128 ROM banks and 4 RAM banks
+        let mut cartridge = Cartridge::load(&sized_rom(0x13, 6, 3), None).unwrap();
+        cartridge.write(0x0000, 0x0A);
+
+        cartridge.write(0x2000, 0x45);
+        cartridge.write(0x4000, 0x03);
+        assert_eq!(cartridge.current_rom_bank(), 0x45);
+        assert_eq!(cartridge.current_ram_bank(), 3);
+
+        cartridge.write(0x2000, 0x00);
+        cartridge.write(0x4000, 0x01);
+        assert_eq!(cartridge.current_rom_bank(), 1);
+        assert_eq!(cartridge.current_ram_bank(), 1);
+    }
+
+    #[test]
+    fn test_mbc5_banks() {
+        // 512 ROM banks and 16 RAM banks
+        let mut cartridge = Cartridge::load(&sized_rom(0x1B, 8, 4), None).unwrap();
+        cartridge.write(0x0000, 0x0A);
+
+        // The 9th bit of the ROM bank is in its own register
+        cartridge.write(0x2000, 0x34);
+        cartridge.write(0x3000, 0x01);
+        cartridge.write(0x4000, 0x0F);
+        assert_eq!(cartridge.current_rom_bank(), 0x134);
+        assert_eq!(cartridge.current_ram_bank(), 15);
+
+        // Bank 0 can be mapped at 0x4000
+        cartridge.write(0x2000, 0x00);
+        cartridge.write(0x3000, 0x00);
+        assert_eq!(cartridge.current_rom_bank(), 0);
+
+        // Banks wrap around on smaller ROMs, 8 banks here
+        let mut cartridge = Cartridge::load(&sized_rom(0x1B, 2, 4), None).unwrap();
+        cartridge.write(0x2000, 0x0D);
+        assert_eq!(cartridge.current_rom_bank(), 5);
+    }
+}
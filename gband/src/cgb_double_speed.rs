@@ -1,6 +1,7 @@
 use bitflags::bitflags;
 
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct CgbDoubleSpeed: u8 {
         const PENDING = 0x01;
         const UNUSED = 0x7E;
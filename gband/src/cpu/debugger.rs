@@ -4,13 +4,13 @@ use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-pub fn disassemble(bus: &mut CpuBus) -> Vec<(u8, u16, String)> {
+pub fn disassemble(bus: &mut CpuBus) -> Vec<(u16, u16, String)> {
     let mut pc = 0u16;
     let mut disassembly = Vec::new();
 
     let rom_bank = bus.get_cartridge_rom_bank();
-    let ram_bank = bus.get_cartridge_ram_bank();
-    let wram_bank = bus.read_without_dma_check(0xFF70, false);
+    let ram_bank = bus.get_cartridge_ram_bank() as u16;
+    let wram_bank = bus.get_wram_bank() as u16;
 
     // Read the entire memory space
     while pc < 0xFFFF {
@@ -1,6 +1,7 @@
 use num_enum::TryFromPrimitive;
 
 #[derive(TryFromPrimitive, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Register {
     B = 0,
@@ -14,6 +15,7 @@ pub enum Register {
 }
 
 #[derive(TryFromPrimitive, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum RegisterPair {
     BC = 0,
@@ -24,6 +26,7 @@ pub enum RegisterPair {
 }
 
 #[derive(TryFromPrimitive, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Alu {
     Add = 0,
@@ -37,6 +40,7 @@ pub enum Alu {
 }
 
 #[derive(TryFromPrimitive, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Condition {
     NonZero = 0,
@@ -61,6 +65,7 @@ impl core::fmt::Debug for Condition {
 }
 
 #[derive(TryFromPrimitive, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Rot {
     Rlc = 0,
@@ -74,6 +79,7 @@ pub enum Rot {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpMemAddress16 {
     Register(RegisterPair),
     RegisterIncrease(RegisterPair),
@@ -82,12 +88,14 @@ pub enum OpMemAddress16 {
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpMemAddress8 {
     Register(Register),
     Immediate,
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Opcode {
     Unknown,
     CBPrefix,
@@ -155,10 +163,18 @@ pub enum Opcode {
     Ei,
 }
 
+// A corrupt ROM must not crash the emulator, so malformed encodings decode as `Unknown`
 impl From<u8> for Opcode {
     fn from(op: u8) -> Self {
+        Self::decode(op).unwrap_or(Self::Unknown)
+    }
+}
+
+impl Opcode {
+    /// Returns None if a field of the encoding is invalid
+    fn decode(op: u8) -> Option<Self> {
         // Typical binary encodings are xx,yyy,zzz and xx,ppq,zzz
-        match &op {
+        let opcode = match &op {
             0x40..=0x45
             | 0x47..=0x4D
             | 0x4F..=0x55
@@ -169,28 +185,23 @@ impl From<u8> for Opcode {
             | 0x78..=0x7D
             | 0x7F => {
                 // Encoding: 01,yyy,zzz y: target reg8 z: source reg8
-                let target = Register::try_from((op & 0o070) >> 3)
-                    .expect("LD r,r: Unexpected target register");
-                let source =
-                    Register::try_from(op & 0o007).expect("LD r,r: Unexpected source register");
+                let target = Register::try_from((op & 0o070) >> 3).ok()?;
+                let source = Register::try_from(op & 0o007).ok()?;
                 Self::LdRR(target, source)
             }
             0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x3E => {
                 // Encoding: 00,yyy,110 y: target reg8
-                let target = Register::try_from((op & 0o070) >> 3)
-                    .expect("LD r,n: Unexpected target register");
+                let target = Register::try_from((op & 0o070) >> 3).ok()?;
                 Self::LdRImm(target)
             }
             0x46 | 0x4E | 0x56 | 0x5E | 0x66 | 0x6E | 0x7E => {
                 // Encoding: 01,yyy,110 y: target reg8
-                let target = Register::try_from((op & 0o070) >> 3)
-                    .expect("LD r,(HL): Unexpected target register");
+                let target = Register::try_from((op & 0o070) >> 3).ok()?;
                 Self::LdRMem(target, OpMemAddress16::Register(RegisterPair::HL))
             }
             0x0A | 0x1A => {
                 // Encoding: 00,pp1,010 p: source reg16 (BC and DE only)
-                let source = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("LD A,(rr): Unexpected source register");
+                let source = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::LdRMem(Register::A, OpMemAddress16::Register(source))
             }
             0x2A => {
@@ -213,14 +224,12 @@ impl From<u8> for Opcode {
             }
             0x70..=0x75 | 0x77 => {
                 // Encoding: 01,110,zzz z: source reg8
-                let source =
-                    Register::try_from(op & 0o007).expect("LD (HL),r: Unexpected source register");
+                let source = Register::try_from(op & 0o007).ok()?;
                 Self::LdMemR(OpMemAddress16::Register(RegisterPair::HL), source)
             }
             0x02 | 0x12 => {
                 // Encoding: 00,pp0,010 p: target reg16 (BC and DE only)
-                let target = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("LD (rr),A: Unexpected target register");
+                let target = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::LdMemR(OpMemAddress16::Register(target), Register::A)
             }
             0x22 => {
@@ -263,8 +272,7 @@ impl From<u8> for Opcode {
             }
             0x01 | 0x11 | 0x21 | 0x31 => {
                 // Encoding: 00,pp0,001 p: target reg16
-                let target = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("LD rr,nn: Unexpected target register");
+                let target = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::Ld16RImm(target)
             }
             0x08 => {
@@ -278,8 +286,7 @@ impl From<u8> for Opcode {
             0xC5 | 0xD5 | 0xE5 | 0xF5 => {
                 // Encoding: 11,pp0,101 p: source reg16
                 // This uses AF for 3, not SP
-                let source = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("PUSH rr: Unexpected source register");
+                let source = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::Push(if let RegisterPair::SP = source {
                     RegisterPair::AF
                 } else {
@@ -289,8 +296,7 @@ impl From<u8> for Opcode {
             0xC1 | 0xD1 | 0xE1 | 0xF1 => {
                 // Encoding: 11,pp0,001 p: target reg16
                 // This uses AF for 3, not SP
-                let target = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("POP rr: Unexpected target register");
+                let target = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::Pop(if let RegisterPair::SP = target {
                     RegisterPair::AF
                 } else {
@@ -307,28 +313,23 @@ impl From<u8> for Opcode {
             | 0xB7..=0xBD
             | 0xBF => {
                 // Encoding: 10,yyy,zzz y: alu op z: source reg8
-                let alu_op =
-                    Alu::try_from((op & 0o070) >> 3).expect("Alu r: Unexpected alu operation");
-                let source =
-                    Register::try_from(op & 0o007).expect("Alu r: Unexpected source register");
+                let alu_op = Alu::try_from((op & 0o070) >> 3).ok()?;
+                let source = Register::try_from(op & 0o007).ok()?;
                 Self::AluR(alu_op, source)
             }
             0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
                 // Encoding: 11,yyy,110 y: alu op
-                let alu_op =
-                    Alu::try_from((op & 0o070) >> 3).expect("Alu n: Unexpected alu operation");
+                let alu_op = Alu::try_from((op & 0o070) >> 3).ok()?;
                 Self::AluImm(alu_op)
             }
             0x86 | 0x8E | 0x96 | 0x9E | 0xA6 | 0xAE | 0xB6 | 0xBE => {
                 // Encoding: 10,yyy,110 y: alu op
-                let alu_op =
-                    Alu::try_from((op & 0o070) >> 3).expect("Alu (HL): Unexpected alu operation");
+                let alu_op = Alu::try_from((op & 0o070) >> 3).ok()?;
                 Self::AluMem(alu_op)
             }
             0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x3C => {
                 // Encoding: 00,yyy,100 y: source reg8
-                let source = Register::try_from((op & 0o070) >> 3)
-                    .expect("INC r: Unexpected source register");
+                let source = Register::try_from((op & 0o070) >> 3).ok()?;
                 Self::IncR(source)
             }
             0x34 => {
@@ -337,8 +338,7 @@ impl From<u8> for Opcode {
             }
             0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x3D => {
                 // Encoding: 00,yyy,101 y: source reg8
-                let source = Register::try_from((op & 0o070) >> 3)
-                    .expect("DEC r: Unexpected source register");
+                let source = Register::try_from((op & 0o070) >> 3).ok()?;
                 Self::DecR(source)
             }
             0x35 => {
@@ -355,8 +355,7 @@ impl From<u8> for Opcode {
             }
             0x09 | 0x19 | 0x29 | 0x39 => {
                 // Encoding: 00,pp1,001 p: source reg16
-                let source = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("ADD HL,rr: Unexpected source register");
+                let source = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::Add16HL(source)
             }
             0xE8 => {
@@ -365,14 +364,12 @@ impl From<u8> for Opcode {
             }
             0x03 | 0x13 | 0x23 | 0x33 => {
                 // Encoding: 00,pp0,011 p: source reg16
-                let source = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("INC rr: Unexpected source register");
+                let source = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::Inc16R(source)
             }
             0x0B | 0x1B | 0x2B | 0x3B => {
                 // Encoding: 00,pp1,011 p: source reg16
-                let source = RegisterPair::try_from((op & 0b00110000) >> 4)
-                    .expect("DEC rr: Unexpected source register");
+                let source = RegisterPair::try_from((op & 0b00110000) >> 4).ok()?;
                 Self::Dec16R(source)
             }
             0xF8 => {
@@ -405,8 +402,7 @@ impl From<u8> for Opcode {
             }
             0xC2 | 0xCA | 0xD2 | 0xDA => {
                 // Encoding: 11,0yy,010 y: flag condition
-                let cond = Condition::try_from((op & 0b00011000) >> 3)
-                    .expect("JP f,nn: Unexpected condition");
+                let cond = Condition::try_from((op & 0b00011000) >> 3).ok()?;
                 Self::JpCond(cond)
             }
             0x18 => {
@@ -415,8 +411,7 @@ impl From<u8> for Opcode {
             }
             0x20 | 0x28 | 0x30 | 0x38 => {
                 // Encoding: 00,yyy,000 y: flag condition (must substract 4)
-                let cond = Condition::try_from(((op & 0o070) >> 3) - 4)
-                    .expect("JR f,dd: Unexpected condition");
+                let cond = Condition::try_from(((op & 0o070) >> 3) - 4).ok()?;
                 Self::JpRelCond(cond)
             }
             0xCD => {
@@ -425,8 +420,7 @@ impl From<u8> for Opcode {
             }
             0xC4 | 0xCC | 0xD4 | 0xDC => {
                 // Encoding: 11,0yy,100 y: flag condition
-                let cond = Condition::try_from((op & 0b00011000) >> 3)
-                    .expect("CALL f,nn: Unexpected condition");
+                let cond = Condition::try_from((op & 0b00011000) >> 3).ok()?;
                 Self::CallCond(cond)
             }
             0xC9 => {
@@ -435,8 +429,7 @@ impl From<u8> for Opcode {
             }
             0xC0 | 0xC8 | 0xD0 | 0xD8 => {
                 // Encoding: 11,0yy,000 y: flag condition
-                let cond = Condition::try_from((op & 0b00011000) >> 3)
-                    .expect("RET f: Unexpected condition");
+                let cond = Condition::try_from((op & 0b00011000) >> 3).ok()?;
                 Self::RetCond(cond)
             }
             0xD9 => {
@@ -481,7 +474,9 @@ impl From<u8> for Opcode {
                 Self::CBPrefix
             }
             _ => Self::Unknown,
-        }
+        };
+
+        Some(opcode)
     }
 }
 
@@ -552,9 +547,36 @@ impl Opcode {
             Self::Ei => 1,
         }
     }
+
+    /// Length of the instruction in bytes, including its operands
+    pub fn length(&self) -> u8 {
+        match self {
+            Self::CBPrefix
+            | Self::LdRImm(_)
+            | Self::LdMemImm(_)
+            | Self::LdhRead(_, OpMemAddress8::Immediate)
+            | Self::LdhWrite(OpMemAddress8::Immediate, _)
+            | Self::AluImm(_)
+            | Self::Add16SPSigned
+            | Self::Ld16HLSPSigned
+            | Self::JpRel
+            | Self::JpRelCond(_)
+            | Self::Stop => 2,
+            Self::LdRMem(_, OpMemAddress16::Immediate)
+            | Self::LdMemR(OpMemAddress16::Immediate, _)
+            | Self::Ld16RImm(_)
+            | Self::Ld16MemSp
+            | Self::JpImm
+            | Self::JpCond(_)
+            | Self::Call
+            | Self::CallCond(_) => 3,
+            _ => 1,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpcodeCB {
     RotateR(Rot, Register),
     RotateMem(Rot),
@@ -566,6 +588,8 @@ pub enum OpcodeCB {
     SetMem(u8),
 }
 
+// The `expect`s below can't fire: register fields are only decoded for the opcodes where they
+// aren't 0b110, which encodes (HL). Every input is decoded by `test_decode_exhaustive`.
 impl From<u8> for OpcodeCB {
     fn from(op: u8) -> Self {
         match op {
@@ -693,3 +717,54 @@ fn test_all_instructions_implemented() {
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_decode_exhaustive() {
+    // Decoding must never panic, whatever the ROM contains
+    for i in 0u8..=255u8 {
+        let opcode = Opcode::from(i);
+        assert!(opcode.cycles() > 0, "{:#04X} has no cycles", i);
+
+        let opcode = OpcodeCB::from(i);
+        assert!(opcode.cycles() > 0, "CB {:#04X} has no cycles", i);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_decode_valid_opcodes() {
+    use alloc::format;
+
+    // One opcode of each encoding that extracts a field
+    let cases = [
+        (0x41, "LdRR(B, C)"),
+        (0x0E, "LdRImm(C)"),
+        (0x7E, "LdRMem(A, Register(HL))"),
+        (0x1A, "LdRMem(A, Register(DE))"),
+        (0x77, "LdMemR(Register(HL), A)"),
+        (0x12, "LdMemR(Register(DE), A)"),
+        (0x31, "Ld16RImm(SP)"),
+        (0xC5, "Push(BC)"),
+        (0xF5, "Push(AF)"),
+        (0xF1, "Pop(AF)"),
+        (0xA8, "AluR(Xor, B)"),
+        (0xFE, "AluImm(Cp)"),
+        (0x9E, "AluMem(Sbc)"),
+        (0x3C, "IncR(A)"),
+        (0x2D, "DecR(L)"),
+        (0x29, "Add16HL(HL)"),
+        (0x13, "Inc16R(DE)"),
+        (0x3B, "Dec16R(SP)"),
+        (0xDA, "JpCond(c)"),
+        (0x20, "JpRelCond(nz)"),
+        (0xCC, "CallCond(z)"),
+        (0xD0, "RetCond(nc)"),
+        (0xCB, "CBPrefix"),
+        (0xD3, "Unknown"),
+    ];
+
+    for (op, expected) in cases {
+        assert_eq!(format!("{:?}", Opcode::from(op)), expected, "{:#04X}", op);
+    }
+}
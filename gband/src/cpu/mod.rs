@@ -1,16 +1,21 @@
 mod decoder;
+mod trace;
 
 #[cfg(feature = "debugger")]
 pub mod debugger;
 
 use bitflags::bitflags;
 
-use crate::{bus::CpuBus, CgbDoubleSpeed, InterruptReg, OamDma};
+use crate::{bus::CpuBus, ppu::OamCorruption, CgbDoubleSpeed, InterruptReg, OamDma};
 use decoder::{
-    Alu, Condition, OpMemAddress16, OpMemAddress8, Opcode, OpcodeCB, Register, RegisterPair, Rot,
+    Alu, Condition, OpMemAddress16, OpMemAddress8, OpcodeCB, Register, RegisterPair, Rot,
 };
 
+pub use decoder::Opcode;
+pub use trace::{TraceCallback, TraceEntry};
+
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct FlagRegister: u8 {
         const UNUSED = 0x0F;
         const C = 0x10;
@@ -20,6 +25,7 @@ bitflags! {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     pub b: u8,
     pub c: u8,
@@ -34,10 +40,36 @@ pub struct Cpu {
 
     pub cycles: u8,
     pub opcode_latch: Opcode,
+    /// CB-prefixed opcode being executed
+    pub cb_opcode_latch: Option<OpcodeCB>,
+    /// M-cycles of the latched instruction executed since its fetch
+    pub step: u8,
+    /// Immediate or popped value kept between the M-cycles of an instruction
+    pub operand: u16,
     pub interrupt_master_enable: bool,
     pub ime_pending: Option<bool>,
     pub halted: bool,
     pub halt_bug_active: bool,
+    /// Set by STOP, until a button is pressed
+    pub stopped: bool,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    trace_callback: Option<TraceCallback>,
+
+    #[cfg(feature = "debugger")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    breakpoints: alloc::vec::Vec<u16>,
+    #[cfg(feature = "debugger")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    breakpoint_hit: Option<u16>,
+    /// Set while stopped before fetching the instruction on a breakpoint
+    #[cfg(feature = "debugger")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    at_breakpoint: bool,
+    /// Set while stepping, otherwise breakpoints are only reported so the timing doesn't change
+    #[cfg(feature = "debugger")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stop_on_breakpoint: bool,
 }
 
 impl Default for Cpu {
@@ -58,36 +90,129 @@ impl Default for Cpu {
 
             cycles: 0,
             opcode_latch: Opcode::Unknown,
+            cb_opcode_latch: None,
+            step: 0,
+            operand: 0,
             interrupt_master_enable: false,
             ime_pending: None,
             halted: false,
             halt_bug_active: false,
+            stopped: false,
+
+            trace_callback: None,
+
+            #[cfg(feature = "debugger")]
+            breakpoints: Default::default(),
+            #[cfg(feature = "debugger")]
+            breakpoint_hit: None,
+            #[cfg(feature = "debugger")]
+            at_breakpoint: false,
+            #[cfg(feature = "debugger")]
+            stop_on_breakpoint: false,
+        }
+    }
+}
+
+/// The trace callback isn't cloned
+impl Clone for Cpu {
+    fn clone(&self) -> Self {
+        Self {
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            a: self.a,
+            f: self.f,
+
+            sp: self.sp,
+            pc: self.pc,
+
+            cycles: self.cycles,
+            opcode_latch: self.opcode_latch,
+            cb_opcode_latch: self.cb_opcode_latch,
+            step: self.step,
+            operand: self.operand,
+            interrupt_master_enable: self.interrupt_master_enable,
+            ime_pending: self.ime_pending,
+            halted: self.halted,
+            halt_bug_active: self.halt_bug_active,
+            stopped: self.stopped,
+
+            trace_callback: None,
+
+            #[cfg(feature = "debugger")]
+            breakpoints: self.breakpoints.clone(),
+            #[cfg(feature = "debugger")]
+            breakpoint_hit: self.breakpoint_hit,
+            #[cfg(feature = "debugger")]
+            at_breakpoint: self.at_breakpoint,
+            #[cfg(feature = "debugger")]
+            stop_on_breakpoint: self.stop_on_breakpoint,
         }
     }
 }
 
 impl Cpu {
+    /// Restores a saved state, keeping the trace callback and the breakpoints
+    pub(crate) fn load_state(&mut self, state: Self) {
+        let trace_callback = self.trace_callback.take();
+        #[cfg(feature = "debugger")]
+        let breakpoints = core::mem::take(&mut self.breakpoints);
+
+        *self = state;
+
+        self.trace_callback = trace_callback;
+        #[cfg(feature = "debugger")]
+        {
+            self.breakpoints = breakpoints;
+            self.breakpoint_hit = None;
+            self.at_breakpoint = false;
+        }
+    }
+
     pub fn clock(&mut self, bus: &mut CpuBus) {
         if self.handle_dma(bus) {
             // CPU is hanged while executing HDMA
             return;
         };
 
-        // TODO: Don't clock if the CPU is STOPped
+        if self.stopped {
+            // Exits STOP mode when a button selected in P1 is pressed
+            if bus.read_joypad_reg() & 0x0F == 0x0F {
+                return;
+            }
+
+            self.stopped = false;
+        }
+
         if bus.get_timer_registers().clock() {
             bus.request_interrupt(InterruptReg::TIMER);
         }
 
-        if bus.get_serial_port().clock() {
+        let div = bus.get_timer_registers().div();
+        if bus.get_serial_port().clock(div) {
             bus.request_interrupt(InterruptReg::SERIAL);
         }
+        bus.get_infrared_port().clock();
+
+        let double_speed = bus
+            .get_double_speed_mode()
+            .contains(CgbDoubleSpeed::ENABLED);
+        bus.get_apu().clock_div(div, double_speed);
 
         // Fetch/Execute overlap, last cycle of execute runs at the same time as the next fetch
         if !self.halted && self.cycles != 0 {
-            self.execute(bus);
-
-            // We are not emulating cycle-accurate yet, so just reset the latch to unknown to noop the remaining cycles
-            self.opcode_latch = Opcode::Unknown;
+            // Each M-cycle runs one step of the instruction, so its memory accesses stay in sync
+            // with the PPU and the timer. The last M-cycle is the next fetch, which only
+            // single cycle instructions execute on.
+            if self.step == 0 || self.cycles > 1 {
+                match self.cb_opcode_latch {
+                    Some(op) => self.execute_cb(bus, op),
+                    None => self.execute(bus),
+                }
+            }
 
             self.cycles -= 1;
         }
@@ -102,46 +227,110 @@ impl Cpu {
                 None => {}
             }
 
-            self.handle_interrupt(bus);
-
-            if !self.halted {
+            // Dispatching an interrupt takes the place of the fetch
+            if !self.handle_interrupt(bus) && !self.halted {
                 self.fetch(bus);
             }
         }
     }
 
-    fn handle_interrupt(&mut self, bus: &mut CpuBus) {
-        let interrupts_status = bus.read(0xFF0F);
-        let interrupts_enable = bus.read(0xFFFF);
-
-        // Get the highest priority interrupt requested, bit 0 is higher priority
-        let pending = interrupts_enable & interrupts_status & 0x1F;
-        let pending_index = pending.trailing_zeros() as u16;
-
-        if pending != 0 {
+    /// Returns true if an interrupt is being dispatched
+    fn handle_interrupt(&mut self, bus: &mut CpuBus) -> bool {
+        // Get the highest priority interrupt requested
+        if let Some(pending) = bus.get_interrupts().pending() {
             // Wake up from halt, even if ime is not set
             self.halted = false;
 
             if self.interrupt_master_enable {
                 // Unset ime and request flag
                 self.interrupt_master_enable = false;
-                bus.write(0xFF0F, interrupts_status & !(1 << pending_index));
-
-                // Save pc and run ISR
-                self.push_stack(bus, self.pc);
-                self.pc = 0x0040 + 0x0008 * pending_index;
+                bus.get_interrupts().acknowledge(pending);
 
-                // The ISR takes 5 cycles
+                // The dispatch runs like a RST to the vector with an extra wait M-cycle,
+                // so it takes 5 M-cycles before the handler is fetched
+                self.opcode_latch = Opcode::Rst(pending.vector() as u8);
+                self.cb_opcode_latch = None;
+                self.step = 0;
                 self.cycles = 5;
+                return true;
             }
         }
+
+        false
+    }
+
+    pub fn set_trace_callback(&mut self, callback: Option<TraceCallback>) {
+        self.trace_callback = callback;
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|&x| x != addr);
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    /// Returns the address of the breakpoint reached since the last call, if any
+    #[cfg(feature = "debugger")]
+    pub fn take_breakpoint_hit(&mut self) -> Option<u16> {
+        self.breakpoint_hit.take()
+    }
+
+    /// Selects whether reaching a breakpoint stops before the fetch, or only reports it
+    #[cfg(feature = "debugger")]
+    pub(crate) fn set_stop_on_breakpoint(&mut self, stop: bool) {
+        self.stop_on_breakpoint = stop;
+    }
+
+    /// Returns true if the CPU stopped on a breakpoint, before fetching its instruction
+    #[cfg(feature = "debugger")]
+    pub fn at_breakpoint(&self) -> bool {
+        self.at_breakpoint
     }
 
     // TODO: Remove pub added for criterion
     pub fn fetch(&mut self, bus: &mut CpuBus) {
+        let pc = self.pc;
+
+        #[cfg(feature = "debugger")]
+        {
+            if self.breakpoints.contains(&pc) && !self.at_breakpoint {
+                self.breakpoint_hit = Some(pc);
+
+                // Stepping stops at the instruction boundary, the fetch is done when resuming
+                if self.stop_on_breakpoint {
+                    self.at_breakpoint = true;
+                    return;
+                }
+            }
+            self.at_breakpoint = false;
+        }
+
         self.opcode_latch = Opcode::from(self.read_immediate(bus));
+        self.cb_opcode_latch = None;
+        self.step = 0;
         self.cycles = self.opcode_latch.cycles();
 
+        if let Some(mut callback) = self.trace_callback.take() {
+            let mut raw = [0u8; 3];
+            for (i, byte) in raw.iter_mut().enumerate() {
+                *byte = bus.read_without_dma_check(pc.wrapping_add(i as u16), false);
+            }
+
+            callback(TraceEntry::new(self, pc, self.opcode_latch, raw));
+            self.trace_callback = Some(callback);
+        }
+
         if self.halt_bug_active {
             // Revert pc increment here, instead of running this on every read_immediate
             self.pc = self.pc.wrapping_sub(1);
@@ -149,8 +338,13 @@ impl Cpu {
         }
     }
 
+    /// Runs the current M-cycle of the latched instruction.
+    /// Each M-cycle after the fetch does at most one memory access, in the order of the hardware.
     // TODO: Remove pub added for criterion
     pub fn execute(&mut self, bus: &mut CpuBus) {
+        let step = self.step;
+        self.step += 1;
+
         // In Z80 / GB, unknown instructions are just noop
         match self.opcode_latch {
             Opcode::Unknown | Opcode::Nop => {
@@ -167,24 +361,28 @@ impl Cpu {
                 self.set_register(target, immediate);
             }
             Opcode::LdRMem(target, source) => {
-                let val = match source {
-                    OpMemAddress16::Register(source) => bus.read(self.get_register_pair(source)),
+                let addr = match source {
+                    OpMemAddress16::Register(source) => self.get_register_pair(source),
                     OpMemAddress16::RegisterIncrease(source) => {
                         let reg = self.get_register_pair(source);
                         self.set_register_pair(source, reg.wrapping_add(1));
-                        bus.read(reg)
+                        bus.trigger_oam_corruption(reg, OamCorruption::ReadDuringIncDec);
+                        reg
                     }
                     OpMemAddress16::RegisterDecrease(source) => {
                         let reg = self.get_register_pair(source);
                         self.set_register_pair(source, reg.wrapping_sub(1));
-                        bus.read(reg)
+                        bus.trigger_oam_corruption(reg, OamCorruption::ReadDuringIncDec);
+                        reg
                     }
-                    OpMemAddress16::Immediate => {
-                        let addr = self.read_immediate16(bus);
-                        bus.read(addr)
+                    OpMemAddress16::Immediate if step < 2 => {
+                        self.read_operand16(bus, step);
+                        return;
                     }
+                    OpMemAddress16::Immediate => self.operand,
                 };
 
+                let val = bus.read(addr);
                 self.set_register(target, val);
             }
             Opcode::LdMemR(target, source) => {
@@ -193,60 +391,95 @@ impl Cpu {
                     OpMemAddress16::RegisterIncrease(target) => {
                         let reg = self.get_register_pair(target);
                         self.set_register_pair(target, reg.wrapping_add(1));
+
+                        // The write and the increase happen on the same cycle, so it's only one corruption
+                        bus.trigger_oam_corruption(reg, OamCorruption::Write);
                         reg
                     }
                     OpMemAddress16::RegisterDecrease(target) => {
                         let reg = self.get_register_pair(target);
                         self.set_register_pair(target, reg.wrapping_sub(1));
+                        bus.trigger_oam_corruption(reg, OamCorruption::Write);
                         reg
                     }
-                    OpMemAddress16::Immediate => self.read_immediate16(bus),
+                    OpMemAddress16::Immediate if step < 2 => {
+                        self.read_operand16(bus, step);
+                        return;
+                    }
+                    OpMemAddress16::Immediate => self.operand,
                 };
 
                 bus.write(addr, self.get_register(source));
             }
-            Opcode::LdMemImm(target) => {
-                let immediate = self.read_immediate(bus);
-                bus.write(self.get_register_pair(target), immediate);
-            }
+            Opcode::LdMemImm(target) => match step {
+                0 => self.operand = self.read_immediate(bus) as u16,
+                _ => bus.write(self.get_register_pair(target), self.operand as u8),
+            },
             Opcode::LdhRead(target, source) => {
-                let addr = 0xFF00
-                    | match source {
-                        OpMemAddress8::Register(source) => self.get_register(source),
-                        OpMemAddress8::Immediate => self.read_immediate(bus),
-                    } as u16;
+                let offset = match source {
+                    OpMemAddress8::Register(source) => self.get_register(source),
+                    OpMemAddress8::Immediate if step == 0 => {
+                        self.operand = self.read_immediate(bus) as u16;
+                        return;
+                    }
+                    OpMemAddress8::Immediate => self.operand as u8,
+                };
 
-                self.set_register(target, bus.read(addr));
+                self.set_register(target, bus.read(0xFF00 | offset as u16));
             }
             Opcode::LdhWrite(target, source) => {
-                let addr = 0xFF00
-                    | match target {
-                        OpMemAddress8::Register(target) => self.get_register(target),
-                        OpMemAddress8::Immediate => self.read_immediate(bus),
-                    } as u16;
+                let offset = match target {
+                    OpMemAddress8::Register(target) => self.get_register(target),
+                    OpMemAddress8::Immediate if step == 0 => {
+                        self.operand = self.read_immediate(bus) as u16;
+                        return;
+                    }
+                    OpMemAddress8::Immediate => self.operand as u8,
+                };
 
-                bus.write(addr, self.get_register(source));
+                bus.write(0xFF00 | offset as u16, self.get_register(source));
             }
             Opcode::Ld16RImm(target) => {
-                let immediate = self.read_immediate16(bus);
-                self.set_register_pair(target, immediate);
-            }
-            Opcode::Ld16MemSp => {
-                let addr = self.read_immediate16(bus);
-                bus.write(addr, (self.sp & 0x00FF) as u8);
-                bus.write(addr + 1, (self.sp >> 8) as u8);
+                self.read_operand16(bus, step);
+                if step == 1 {
+                    self.set_register_pair(target, self.operand);
+                }
             }
+            Opcode::Ld16MemSp => match step {
+                0 | 1 => self.read_operand16(bus, step),
+                2 => bus.write(self.operand, (self.sp & 0x00FF) as u8),
+                _ => bus.write(self.operand.wrapping_add(1), (self.sp >> 8) as u8),
+            },
             Opcode::Ld16SpHL => {
                 self.sp = self.get_register_pair(RegisterPair::HL);
             }
             Opcode::Push(source) => {
-                let source = self.get_register_pair(source);
-                self.push_stack(bus, source);
-            }
-            Opcode::Pop(target) => {
-                let val = self.pop_stack(bus);
-                self.set_register_pair(target, val);
+                // SP is decremented on the first M-cycle, then before each write.
+                // The extra decrease cycle and both writes can each corrupt OAM
+                let val = self.get_register_pair(source);
+                match step {
+                    0 => bus.trigger_oam_corruption(self.sp, OamCorruption::Write),
+                    1 => {
+                        bus.trigger_oam_corruption(self.sp.wrapping_sub(1), OamCorruption::Write);
+                        self.push_byte(bus, (val >> 8) as u8);
+                    }
+                    _ => {
+                        bus.trigger_oam_corruption(self.sp.wrapping_sub(1), OamCorruption::Write);
+                        self.push_byte(bus, (val & 0x00FF) as u8);
+                    }
+                }
             }
+            Opcode::Pop(target) => match step {
+                0 => {
+                    bus.trigger_oam_corruption(self.sp, OamCorruption::ReadDuringIncDec);
+                    self.operand = self.pop_byte(bus) as u16;
+                }
+                _ => {
+                    bus.trigger_oam_corruption(self.sp, OamCorruption::Read);
+                    let msb = self.pop_byte(bus) as u16;
+                    self.set_register_pair(target, (msb << 8) | self.operand);
+                }
+            },
             Opcode::AluR(alu_op, source) => {
                 let val = self.get_register(source);
                 self.run_alu(alu_op, val);
@@ -270,7 +503,12 @@ impl Cpu {
             }
             Opcode::IncMem => {
                 let addr = self.get_register_pair(RegisterPair::HL);
-                let val = bus.read(addr);
+                if step == 0 {
+                    self.operand = bus.read(addr) as u16;
+                    return;
+                }
+
+                let val = self.operand as u8;
                 let result = val.wrapping_add(1);
 
                 self.f.set(FlagRegister::H, (val & 0x0F) + 1 > 0x0F);
@@ -289,7 +527,12 @@ impl Cpu {
             }
             Opcode::DecMem => {
                 let addr = self.get_register_pair(RegisterPair::HL);
-                let val = bus.read(addr);
+                if step == 0 {
+                    self.operand = bus.read(addr) as u16;
+                    return;
+                }
+
+                let val = self.operand as u8;
                 let result = val.wrapping_sub(1);
 
                 self.f.set(FlagRegister::H, (val & 0x0F) == 0);
@@ -343,34 +586,28 @@ impl Cpu {
                 self.f.set(FlagRegister::N, false);
             }
             Opcode::Add16SPSigned => {
-                // Reinterpret the immediate as signed, then convert to unsigned u16 equivalent
-                let immediate = self.read_immediate(bus) as i8 as u16;
-                let carry = (self.sp & 0x00FF) + (immediate & 0x00FF) > 0x00FF;
-                let half_carry = (self.sp & 0x000F) + (immediate & 0x000F) > 0x000F;
-
-                self.sp = self.sp.wrapping_add(immediate);
-                self.f.set(FlagRegister::C, carry);
-                self.f.set(FlagRegister::H, half_carry);
-                self.f.set(FlagRegister::N, false);
-                self.f.set(FlagRegister::Z, false);
+                // The next M-cycles are internal
+                if step == 0 {
+                    let immediate = self.read_immediate(bus);
+                    self.sp = self.add_sp_signed(immediate);
+                }
             }
             Opcode::Inc16R(source) => {
-                self.set_register_pair(source, self.get_register_pair(source).wrapping_add(1));
+                let reg = self.get_register_pair(source);
+                bus.trigger_oam_corruption(reg, OamCorruption::Write);
+                self.set_register_pair(source, reg.wrapping_add(1));
             }
             Opcode::Dec16R(source) => {
-                self.set_register_pair(source, self.get_register_pair(source).wrapping_sub(1));
+                let reg = self.get_register_pair(source);
+                bus.trigger_oam_corruption(reg, OamCorruption::Write);
+                self.set_register_pair(source, reg.wrapping_sub(1));
             }
             Opcode::Ld16HLSPSigned => {
-                // Two's complement conversion
-                let immediate = self.read_immediate(bus) as i8 as u16;
-                let carry = (self.sp & 0x00FF) + (immediate & 0x00FF) > 0x00FF;
-                let half_carry = (self.sp & 0x000F) + (immediate & 0x000F) > 0x000F;
-
-                self.set_register_pair(RegisterPair::HL, self.sp.wrapping_add(immediate));
-                self.f.set(FlagRegister::C, carry);
-                self.f.set(FlagRegister::H, half_carry);
-                self.f.set(FlagRegister::N, false);
-                self.f.set(FlagRegister::Z, false);
+                if step == 0 {
+                    let immediate = self.read_immediate(bus);
+                    let result = self.add_sp_signed(immediate);
+                    self.set_register_pair(RegisterPair::HL, result);
+                }
             }
             Opcode::RlcA => {
                 let val = self.get_register(Register::A);
@@ -392,65 +629,72 @@ impl Cpu {
                 let result = self.run_rot(Rot::Rr, val, true);
                 self.set_register(Register::A, result);
             }
-            Opcode::JpImm => {
-                let addr = self.read_immediate16(bus);
-                self.pc = addr;
-            }
+            Opcode::JpImm => match step {
+                0 | 1 => self.read_operand16(bus, step),
+                _ => self.pc = self.operand,
+            },
             Opcode::JpHL => {
                 self.pc = self.get_register_pair(RegisterPair::HL);
             }
-            Opcode::JpCond(condition) => {
-                let addr = self.read_immediate16(bus);
-                if self.check_conditional(condition) {
-                    self.cycles += 1;
-                    self.pc = addr;
+            Opcode::JpCond(condition) => match step {
+                0 => self.read_operand16(bus, step),
+                1 => {
+                    self.read_operand16(bus, step);
+                    if self.check_conditional(condition) {
+                        self.cycles += 1;
+                    }
                 }
-            }
-            Opcode::JpRel => {
-                let offset = self.read_immediate(bus) as i8;
-                self.pc = self.pc.wrapping_add(offset as u16);
-            }
-            Opcode::JpRelCond(condition) => {
-                let offset = self.read_immediate(bus) as i8;
-                if self.check_conditional(condition) {
-                    self.cycles += 1;
-                    self.pc = self.pc.wrapping_add(offset as u16)
+                _ => self.pc = self.operand,
+            },
+            Opcode::JpRel => match step {
+                0 => self.operand = self.read_immediate(bus) as i8 as u16,
+                _ => self.pc = self.pc.wrapping_add(self.operand),
+            },
+            Opcode::JpRelCond(condition) => match step {
+                0 => {
+                    self.operand = self.read_immediate(bus) as i8 as u16;
+                    if self.check_conditional(condition) {
+                        self.cycles += 1;
+                    }
                 }
-            }
-            Opcode::Call => {
-                let addr = self.read_immediate16(bus);
-                self.push_stack(bus, self.pc);
-                self.pc = addr;
-            }
-            Opcode::CallCond(condition) => {
-                let addr = self.read_immediate16(bus);
-                if self.check_conditional(condition) {
-                    self.cycles += 3;
-                    self.push_stack(bus, self.pc);
-                    self.pc = addr;
+                _ => self.pc = self.pc.wrapping_add(self.operand),
+            },
+            Opcode::Call => match step {
+                0 | 1 => self.read_operand16(bus, step),
+                _ => self.call(bus, step - 2, self.operand),
+            },
+            Opcode::CallCond(condition) => match step {
+                0 => self.read_operand16(bus, step),
+                1 => {
+                    self.read_operand16(bus, step);
+                    if self.check_conditional(condition) {
+                        self.cycles += 3;
+                    }
                 }
-            }
+                _ => self.call(bus, step - 2, self.operand),
+            },
             Opcode::Ret => {
-                let addr = self.pop_stack(bus);
-                self.pc = addr;
+                self.ret(bus, step);
             }
-            Opcode::RetCond(condition) => {
-                if self.check_conditional(condition) {
-                    self.cycles += 3;
-                    let addr = self.pop_stack(bus);
-                    self.pc = addr;
+            Opcode::RetCond(condition) => match step {
+                // The condition is checked on an internal M-cycle
+                0 => {
+                    if self.check_conditional(condition) {
+                        self.cycles += 3;
+                    }
                 }
-            }
+                _ => self.ret(bus, step - 1),
+            },
             Opcode::Reti => {
-                let addr = self.pop_stack(bus);
-                self.pc = addr;
+                self.ret(bus, step);
 
                 // IME enable is NOT delayed to the next instruction.
-                self.interrupt_master_enable = true;
+                if step == 2 {
+                    self.interrupt_master_enable = true;
+                }
             }
             Opcode::Rst(addr) => {
-                self.push_stack(bus, self.pc);
-                self.pc = addr as u16;
+                self.call(bus, step, addr as u16);
             }
             Opcode::Ccf => {
                 self.f
@@ -464,21 +708,30 @@ impl Cpu {
                 self.f.remove(FlagRegister::H);
             }
             Opcode::Halt => {
-                let interrupts_status = bus.read(0xFF0F);
-                let interrupts_enable = bus.read(0xFFFF);
-                let pending = interrupts_enable & interrupts_status & 0x1F;
+                let pending = bus.get_interrupts().pending();
 
-                // If there is already an interrupt pending AND IME is false, skip halt completely
-                if !self.interrupt_master_enable && pending != 0 {
+                // If there is already an interrupt pending AND IME is false, skip halt completely.
+                // This is the HALT bug: PC isn't incremented on the next fetch, so the next byte is read twice
+                if !self.interrupt_master_enable && pending.is_some() {
                     self.halt_bug_active = true;
                 } else {
                     self.halted = true;
                 }
             }
             Opcode::Stop => {
-                // TODO: Completely implement stop (sleep portion...?)
+                // The byte after STOP is skipped
+                self.pc = self.pc.wrapping_add(1);
                 bus.get_timer_registers().reset_div();
-                bus.toggle_double_speed();
+
+                // STOP is only used to switch speed when a switch is armed
+                if bus
+                    .get_double_speed_mode()
+                    .contains(CgbDoubleSpeed::PENDING)
+                {
+                    bus.toggle_double_speed();
+                } else {
+                    self.stopped = true;
+                }
             }
             Opcode::Di => {
                 self.interrupt_master_enable = false;
@@ -491,48 +744,82 @@ impl Cpu {
     }
 
     fn read_immediate(&mut self, bus: &mut CpuBus) -> u8 {
-        let immediate = bus.read(self.pc);
+        let immediate = bus.read_unwatched(self.pc);
         self.pc = self.pc.wrapping_add(1);
         immediate
     }
 
-    fn read_immediate16(&mut self, bus: &mut CpuBus) -> u16 {
-        let lsb = self.read_immediate(bus) as u16;
-        let msb = self.read_immediate(bus) as u16;
-        (msb << 8) | lsb
+    /// Reads a 16 bits immediate into the operand, the low byte on step 0 and the high byte on step 1
+    fn read_operand16(&mut self, bus: &mut CpuBus, step: u8) {
+        let immediate = self.read_immediate(bus) as u16;
+        if step == 0 {
+            self.operand = immediate;
+        } else {
+            self.operand |= immediate << 8;
+        }
     }
 
-    fn pop_stack(&mut self, bus: &mut CpuBus) -> u16 {
-        let lsb = bus.read(self.sp) as u16;
-        self.sp = self.sp.wrapping_add(1);
-        let msb = bus.read(self.sp) as u16;
+    fn pop_byte(&mut self, bus: &mut CpuBus) -> u8 {
+        let val = bus.read(self.sp);
         self.sp = self.sp.wrapping_add(1);
-
-        (msb << 8) | lsb
+        val
     }
 
-    fn push_stack(&mut self, bus: &mut CpuBus, val: u16) {
-        self.sp = self.sp.wrapping_sub(1);
-        bus.write(self.sp, (val >> 8) as u8);
+    fn push_byte(&mut self, bus: &mut CpuBus, val: u8) {
         self.sp = self.sp.wrapping_sub(1);
-        bus.write(self.sp, (val & 0x00FF) as u8);
+        bus.write(self.sp, val);
+    }
+
+    /// Pushes PC and jumps to `addr` over 3 M-cycles, starting with an internal one
+    fn call(&mut self, bus: &mut CpuBus, step: u8, addr: u16) {
+        match step {
+            0 => {}
+            1 => self.push_byte(bus, (self.pc >> 8) as u8),
+            2 => {
+                self.push_byte(bus, (self.pc & 0x00FF) as u8);
+                self.pc = addr;
+            }
+            _ => {}
+        }
+    }
+
+    /// Pops PC over 3 M-cycles, the jump taking an internal one
+    fn ret(&mut self, bus: &mut CpuBus, step: u8) {
+        match step {
+            0 => self.operand = self.pop_byte(bus) as u16,
+            1 => self.operand |= (self.pop_byte(bus) as u16) << 8,
+            _ => self.pc = self.operand,
+        }
     }
 
     fn run_cb(&mut self, bus: &mut CpuBus) {
         let op = OpcodeCB::from(self.read_immediate(bus));
-        self.cycles += op.cycles();
 
+        // The cycles of the CB opcode include the prefix
+        self.cycles += op.cycles() - 1;
+        self.cb_opcode_latch = Some(op);
+        self.step = 0;
+    }
+
+    fn execute_cb(&mut self, bus: &mut CpuBus, op: OpcodeCB) {
+        let step = self.step;
+        self.step += 1;
+
+        // Read-modify-write opcodes read on the first M-cycle and write on the next one
+        let addr = self.get_register_pair(RegisterPair::HL);
         match op {
             OpcodeCB::RotateR(rot_op, source) => {
                 let val = self.get_register(source);
                 let result = self.run_rot(rot_op, val, false);
                 self.set_register(source, result);
             }
-            OpcodeCB::RotateMem(rot_op) => {
-                let val = bus.read(self.get_register_pair(RegisterPair::HL));
-                let result = self.run_rot(rot_op, val, false);
-                bus.write(self.get_register_pair(RegisterPair::HL), result);
-            }
+            OpcodeCB::RotateMem(rot_op) => match step {
+                0 => self.operand = bus.read(addr) as u16,
+                _ => {
+                    let result = self.run_rot(rot_op, self.operand as u8, false);
+                    bus.write(addr, result);
+                }
+            },
             OpcodeCB::BitR(index, source) => {
                 let val = self.get_register(source);
                 let mask = 1u8 << index;
@@ -542,7 +829,7 @@ impl Cpu {
                 self.f.set(FlagRegister::Z, (val & mask) == 0);
             }
             OpcodeCB::BitMem(index) => {
-                let val = bus.read(self.get_register_pair(RegisterPair::HL));
+                let val = bus.read(addr);
                 let mask = 1u8 << index;
 
                 self.f.set(FlagRegister::N, false);
@@ -554,21 +841,25 @@ impl Cpu {
                 let mask = !(1u8 << index);
                 self.set_register(source, val & mask);
             }
-            OpcodeCB::ResMem(index) => {
-                let val = bus.read(self.get_register_pair(RegisterPair::HL));
-                let mask = !(1u8 << index);
-                bus.write(self.get_register_pair(RegisterPair::HL), val & mask);
-            }
+            OpcodeCB::ResMem(index) => match step {
+                0 => self.operand = bus.read(addr) as u16,
+                _ => {
+                    let mask = !(1u8 << index);
+                    bus.write(addr, self.operand as u8 & mask);
+                }
+            },
             OpcodeCB::SetR(index, source) => {
                 let val = self.get_register(source);
                 let mask = 1u8 << index;
                 self.set_register(source, val | mask);
             }
-            OpcodeCB::SetMem(index) => {
-                let val = bus.read(self.get_register_pair(RegisterPair::HL));
-                let mask = 1u8 << index;
-                bus.write(self.get_register_pair(RegisterPair::HL), val | mask);
-            }
+            OpcodeCB::SetMem(index) => match step {
+                0 => self.operand = bus.read(addr) as u16,
+                _ => {
+                    let mask = 1u8 << index;
+                    bus.write(addr, self.operand as u8 | mask);
+                }
+            },
         }
     }
 
@@ -657,6 +948,22 @@ impl Cpu {
         }
     }
 
+    /// Computes SP + e for ADD SP,e and LD HL,SP+e.
+    /// The result uses the signed offset, but H and C come from the unsigned addition of the low bytes.
+    fn add_sp_signed(&mut self, offset: u8) -> u16 {
+        let low = self.sp as u8;
+        let carry = low as u16 + offset as u16 > 0x00FF;
+        let half_carry = (low & 0x0F) + (offset & 0x0F) > 0x0F;
+
+        self.f.set(FlagRegister::C, carry);
+        self.f.set(FlagRegister::H, half_carry);
+        self.f.set(FlagRegister::N, false);
+        self.f.set(FlagRegister::Z, false);
+
+        // Two's complement conversion
+        self.sp.wrapping_add(offset as i8 as u16)
+    }
+
     fn run_rot(&mut self, rot_op: Rot, val: u8, force_zero: bool) -> u8 {
         let (result, carry) = match rot_op {
             Rot::Rlc => {
@@ -792,10 +1099,12 @@ impl Cpu {
             OamDma {
                 cycle: Some(c),
                 source,
+                value,
             } => {
                 // Each cycle, DMA reads and write one byte from source to destination
                 let data = bus.read_without_dma_check(((*source as u16) << 8) | (*c as u16), true);
                 bus.write_without_dma_check(0xFE00 | ((*c & 0xFF) as u16), data, true);
+                *value = data;
                 *c += 1;
 
                 (true, *c >= 0xA0)
@@ -842,11 +1151,13 @@ impl Cpu {
                     let destination = ((hdma.destination) & 0x1FF0) | 0x8000;
 
                     let data = match source {
-                        0x0000..=0x7FF0 | 0xA000..=0xDFF0 => bus.read(source | (hdma.cycle as u16)),
+                        0x0000..=0x7FF0 | 0xA000..=0xDFF0 => {
+                            bus.read_unwatched(source | (hdma.cycle as u16))
+                        }
                         _ => 0xFF,
                     };
 
-                    bus.write(destination | (hdma.cycle as u16), data);
+                    bus.write_unwatched(destination | (hdma.cycle as u16), data);
 
                     hdma.cycle += 1;
 
@@ -881,18 +1192,23 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Accuracy;
+    use crate::Apu;
     use crate::Cartridge;
     use crate::CgbDoubleSpeed;
     use crate::HDma;
+    use crate::InfraredPort;
     use crate::InterruptState;
     use crate::JoypadState;
+    use crate::MappedPeripheral;
+    use crate::MemWatch;
     use crate::OamDma;
     use crate::Ppu;
     use crate::RomParserError;
     use crate::SerialPort;
     use crate::TimerRegisters;
     use crate::WRAM_BANK_SIZE;
-    use alloc::vec;
+    use alloc::vec::Vec;
 
     struct MockEmulator {
         pub cartridge: Cartridge,
@@ -906,17 +1222,20 @@ mod tests {
         pub hdma: HDma,
         pub timer_registers: TimerRegisters,
         pub serial_port: SerialPort,
+        pub infrared_port: InfraredPort,
         pub joypad_state: JoypadState,
         pub joypad_register: u8,
         pub ppu: Ppu,
+        pub apu: Apu,
         pub cgb_mode: bool,
+        pub accuracy: Accuracy,
+        pub mem_watch: Option<MemWatch>,
+        pub peripherals: Vec<MappedPeripheral>,
     }
 
     impl MockEmulator {
         pub fn new() -> Result<Self, RomParserError> {
-            let mut rom = vec![0; 0x200];
-            rom[0x14d] = 231;
-            let cartridge = Cartridge::load(&rom, None)?;
+            let cartridge = Cartridge::load(&crate::test_rom(&[], false), None)?;
 
             let emulator = Self {
                 cartridge,
@@ -930,10 +1249,15 @@ mod tests {
                 hdma: Default::default(),
                 timer_registers: Default::default(),
                 serial_port: Default::default(),
+                infrared_port: Default::default(),
                 joypad_state: Default::default(),
                 joypad_register: 0,
                 ppu: Default::default(),
+                apu: Default::default(),
                 cgb_mode: false,
+                accuracy: Default::default(),
+                mem_watch: None,
+                peripherals: Vec::new(),
             };
 
             Ok(emulator)
@@ -957,6 +1281,400 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_trace_callback() {
+        use alloc::boxed::Box;
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        let mut emu = MockEmulator::new().unwrap();
+
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let callback_trace = trace.clone();
+        emu.cpu.set_trace_callback(Some(Box::new(move |entry| {
+            callback_trace.borrow_mut().push(entry)
+        })));
+
+        emu.cpu.pc = 0xC000;
+        emu.wram[0..6].copy_from_slice(&[
+            0x3E, 0x12, // ld a, 0x12
+            0x47, // ld b, a
+            0xC3, 0x10, 0xC0, // jp 0xc010
+        ]);
+        emu.wram[0x10] = 0x3C; // inc a
+
+        execute_n(&mut emu, 4);
+
+        // Because of the fetch-execute overlap, the next instruction is already fetched
+        let trace = trace.borrow();
+        assert_eq!(
+            trace.iter().map(|entry| entry.pc).collect::<Vec<_>>(),
+            [0xC000, 0xC002, 0xC003, 0xC010, 0xC011]
+        );
+
+        assert!(matches!(trace[0].opcode, Opcode::LdRImm(Register::A)));
+        assert_eq!(trace[0].bytes(), [0x3E, 0x12]);
+
+        assert!(matches!(
+            trace[1].opcode,
+            Opcode::LdRR(Register::B, Register::A)
+        ));
+        assert_eq!(trace[1].bytes(), [0x47]);
+        assert_eq!(trace[1].a, 0x12);
+        assert_eq!(trace[1].b, 0x00);
+
+        assert!(matches!(trace[2].opcode, Opcode::JpImm));
+        assert_eq!(trace[2].bytes(), [0xC3, 0x10, 0xC0]);
+        assert_eq!(trace[2].b, 0x12);
+
+        // Registers are captured before the execution
+        assert!(matches!(trace[3].opcode, Opcode::IncR(Register::A)));
+        assert_eq!(trace[3].a, 0x12);
+        assert_eq!(emu.cpu.a, 0x13);
+    }
+
+    #[test]
+    fn test_mem_watch() {
+        use crate::{MemAccess, MemAccessKind};
+        use alloc::boxed::Box;
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        let mut emu = MockEmulator::new().unwrap();
+
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let watch_accesses = accesses.clone();
+        emu.mem_watch = Some(Box::new(move |access| {
+            watch_accesses.borrow_mut().push(access)
+        }));
+
+        emu.cpu.pc = 0xC000;
+        emu.wram[0..8].copy_from_slice(&[
+            0x3E, 0x42, // ld a, 0x42
+            0xEA, 0x00, 0xD0, // ld (0xd000), a
+            0xFA, 0x00, 0xD0, // ld a, (0xd000)
+        ]);
+
+        execute_n(&mut emu, 3);
+        assert_eq!(emu.wram[0x1000], 0x42);
+
+        // Instruction fetches aren't reported
+        assert_eq!(
+            *accesses.borrow(),
+            [
+                MemAccess {
+                    addr: 0xD000,
+                    value: 0x42,
+                    kind: MemAccessKind::Write
+                },
+                MemAccess {
+                    addr: 0xD000,
+                    value: 0x42,
+                    kind: MemAccessKind::Read
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        let mut emu = MockEmulator::new().unwrap();
+
+        emu.cpu.pc = 0xC000;
+        emu.cpu.sp = 0xD000;
+        emu.cpu.interrupt_master_enable = true;
+
+        // VBlank is already requested on boot
+        emu.interrupts.enable = InterruptReg::from_bits_truncate(0x1F);
+        emu.interrupts
+            .status
+            .insert(InterruptReg::TIMER | InterruptReg::JOYPAD);
+
+        // VBlank is serviced first, the other requests are kept
+        execute_n(&mut emu, 1);
+        assert_eq!(emu.cpu.pc & 0xFFF8, 0x0040);
+        assert_eq!(&emu.wram[0x0FFE..0x1000], &[0x00, 0xC0]);
+        assert!(!emu.cpu.interrupt_master_enable);
+        assert_eq!(
+            emu.interrupts.status,
+            InterruptReg::UNUSED | InterruptReg::TIMER | InterruptReg::JOYPAD
+        );
+
+        // Then the timer, after the instruction at the VBlank vector
+        emu.cpu.interrupt_master_enable = true;
+        execute_n(&mut emu, 2);
+        assert_eq!(emu.cpu.pc & 0xFFF8, 0x0050);
+        assert_eq!(
+            emu.interrupts.status,
+            InterruptReg::UNUSED | InterruptReg::JOYPAD
+        );
+    }
+
+    /// Runs DAA on the specified A and F registers
+    fn run_daa(emu: &mut MockEmulator, a: u8, f: FlagRegister) -> (u8, FlagRegister) {
+        let mut bus = borrow_cpu_bus!(emu);
+        emu.cpu.a = a;
+        emu.cpu.f = f;
+        emu.cpu.opcode_latch = Opcode::Daa;
+        emu.cpu.step = 0;
+        emu.cpu.execute(&mut bus);
+
+        (emu.cpu.a, emu.cpu.f)
+    }
+
+    #[test]
+    fn test_daa() {
+        let mut emu = MockEmulator::new().unwrap();
+
+        let n = FlagRegister::N;
+        let h = FlagRegister::H;
+        let c = FlagRegister::C;
+        let z = FlagRegister::Z;
+        let none = FlagRegister::empty();
+
+        // (A, flags before) => (A, flags after)
+        let cases = [
+            // After an addition
+            ((0x0A, none), (0x10, none)),
+            ((0x12, h), (0x18, none)),
+            ((0x9A, none), (0x00, z | c)),
+            ((0xA0, none), (0x00, z | c)),
+            ((0x00, c), (0x60, c)),
+            ((0x3C, h | c), (0xA2, c)),
+            ((0x99, none), (0x99, none)),
+            ((0x00, none), (0x00, z)),
+            // After a subtraction
+            ((0x45, n), (0x45, n)),
+            ((0x0F, n | h), (0x09, n)),
+            ((0x90, n | c), (0x30, n | c)),
+            ((0x00, n | h | c), (0x9A, n | c)),
+            ((0x66, n | h | c), (0x00, n | z | c)),
+        ];
+
+        for ((a, f), expected) in cases {
+            assert_eq!(run_daa(&mut emu, a, f), expected, "A={:02x}", a);
+        }
+    }
+
+    #[test]
+    fn test_daa_bcd_arithmetic() {
+        let mut emu = MockEmulator::new().unwrap();
+
+        let to_bcd = |x: u8| ((x / 10) << 4) | (x % 10);
+
+        for x in 0..100u8 {
+            for y in 0..100u8 {
+                let (a, b) = (to_bcd(x), to_bcd(y));
+
+                // ADD A,B then DAA
+                let (result, carry) = a.overflowing_add(b);
+                let mut f = FlagRegister::empty();
+                f.set(FlagRegister::H, (a & 0x0F) + (b & 0x0F) > 0x0F);
+                f.set(FlagRegister::C, carry);
+
+                let (result, f) = run_daa(&mut emu, result, f);
+                assert_eq!(result, to_bcd((x + y) % 100), "{} + {}", x, y);
+                assert_eq!(f.contains(FlagRegister::C), x + y >= 100, "{} + {}", x, y);
+                assert_eq!(f.contains(FlagRegister::Z), (x + y) % 100 == 0);
+
+                // SUB A,B then DAA
+                let (result, carry) = a.overflowing_sub(b);
+                let mut f = FlagRegister::N;
+                f.set(FlagRegister::H, (a & 0x0F) < (b & 0x0F));
+                f.set(FlagRegister::C, carry);
+
+                let (result, f) = run_daa(&mut emu, result, f);
+                assert_eq!(result, to_bcd((100 + x - y) % 100), "{} - {}", x, y);
+                assert_eq!(f.contains(FlagRegister::C), x < y, "{} - {}", x, y);
+                assert!(!f.contains(FlagRegister::H));
+            }
+        }
+    }
+
+    /// Runs an SP+e opcode with Z and N set beforehand, to make sure they get cleared
+    fn run_sp_signed(opcode: u8, sp: u16, e: u8) -> (Cpu, u16) {
+        let mut emu = MockEmulator::new().unwrap();
+
+        emu.cpu.pc = 0xC000;
+        emu.cpu.sp = sp;
+        emu.cpu.f = FlagRegister::Z | FlagRegister::N;
+        emu.wram[0] = opcode;
+        emu.wram[1] = e;
+        execute_n(&mut emu, 1);
+
+        let hl = emu.cpu.get_register_pair(RegisterPair::HL);
+        (emu.cpu, hl)
+    }
+
+    #[test]
+    fn test_sp_signed() {
+        let h = FlagRegister::H;
+        let c = FlagRegister::C;
+        let none = FlagRegister::empty();
+
+        // (SP, e) => (result, flags)
+        let cases = [
+            ((0x0000, 0x01), (0x0001, none)),
+            ((0x000F, 0x01), (0x0010, h)),
+            ((0x00F0, 0x10), (0x0100, c)),
+            ((0x00FF, 0x01), (0x0100, h | c)),
+            ((0xFFFF, 0x01), (0x0000, h | c)),
+            // Negative offsets still compute the flags from the unsigned low byte
+            ((0x0000, 0xFF), (0xFFFF, none)),
+            ((0x0001, 0xFF), (0x0000, h | c)),
+            ((0x1000, 0x80), (0x0F80, none)),
+            ((0x1080, 0x80), (0x1000, c)),
+            ((0x1008, 0xF8), (0x1000, h | c)),
+            ((0x1007, 0xF8), (0x0FFF, none)),
+        ];
+
+        for ((sp, e), (result, flags)) in cases {
+            // ADD SP,e
+            let (cpu, _) = run_sp_signed(0xE8, sp, e);
+            assert_eq!(cpu.sp, result, "ADD SP={:04x} e={:02x}", sp, e);
+            assert_eq!(cpu.f, flags, "ADD SP={:04x} e={:02x}", sp, e);
+
+            // LD HL,SP+e
+            let (cpu, hl) = run_sp_signed(0xF8, sp, e);
+            assert_eq!(cpu.sp, sp);
+            assert_eq!(hl, result, "LD HL,SP={:04x} e={:02x}", sp, e);
+            assert_eq!(cpu.f, flags, "LD HL,SP={:04x} e={:02x}", sp, e);
+        }
+    }
+
+    #[test]
+    fn test_halt_bug() {
+        let mut emu = MockEmulator::new().unwrap();
+
+        // IME is disabled and VBlank is already pending
+        emu.cpu.pc = 0xC000;
+        emu.cpu.a = 0;
+        emu.interrupts.enable = InterruptReg::VBLANK;
+        emu.wram[0] = 0x76; // HALT
+        emu.wram[1] = 0x3C; // INC A
+        emu.wram[2] = 0x06; // LD B,n
+        emu.wram[3] = 0x42;
+
+        // The CPU doesn't halt, and PC isn't incremented when fetching the byte after HALT
+        execute_n(&mut emu, 1);
+        assert!(!emu.cpu.halted);
+        assert_eq!(emu.cpu.pc, 0xC001);
+
+        execute_n(&mut emu, 2);
+        assert_eq!(emu.cpu.a, 2);
+        assert_eq!(emu.cpu.pc, 0xC003);
+
+        // Execution continues normally afterward
+        execute_n(&mut emu, 1);
+        assert_eq!(emu.cpu.b, 0x42);
+        assert_eq!(emu.cpu.pc, 0xC005);
+    }
+
+    #[test]
+    fn test_halt_bug_operand() {
+        let mut emu = MockEmulator::new().unwrap();
+
+        emu.cpu.pc = 0xC000;
+        emu.interrupts.enable = InterruptReg::VBLANK;
+        emu.wram[0] = 0x76; // HALT
+        emu.wram[1] = 0x3E; // LD A,n
+        emu.wram[2] = 0x14; // INC D
+
+        // The opcode is read again as the operand of LD A,n
+        emu.cpu.d = 0;
+        execute_n(&mut emu, 3);
+        assert_eq!(emu.cpu.a, 0x3E);
+        assert_eq!(emu.cpu.d, 1);
+    }
+
+    #[test]
+    fn test_halt_without_bug() {
+        let mut emu = MockEmulator::new().unwrap();
+
+        // Nothing pending, so the CPU halts normally
+        emu.cpu.pc = 0xC000;
+        emu.interrupts.status = InterruptReg::UNUSED;
+        emu.interrupts.enable = InterruptReg::VBLANK;
+        emu.wram[0] = 0x76; // HALT
+        emu.wram[1] = 0x3C; // INC A
+        emu.cpu.a = 0;
+
+        execute_n(&mut emu, 1);
+        assert!(emu.cpu.halted);
+
+        // Waking up without IME resumes after HALT without any duplicated byte
+        emu.interrupts.status.insert(InterruptReg::VBLANK);
+        let mut bus = borrow_cpu_bus!(emu);
+        while emu.cpu.pc != 0xC003 {
+            emu.cpu.clock(&mut bus);
+        }
+        assert!(!emu.cpu.halted);
+        assert_eq!(emu.cpu.a, 1);
+    }
+
+    #[test]
+    fn test_oam_corruption() {
+        let run_op = |opcode: u8, hl: u16, accuracy: Accuracy, cgb_mode: bool| {
+            let mut emu = MockEmulator::new().unwrap();
+            emu.accuracy = accuracy;
+            emu.cgb_mode = cgb_mode;
+
+            for i in 0..0xA0u16 {
+                emu.ppu.write_oam(0xFE00 + i, ((i * 37) as u8) ^ 0x5A, true);
+            }
+
+            // Go to the middle of the OAM scan, on row 10
+            let mut interrupts = InterruptState::default();
+            let mut hdma = HDma::default();
+            let mut ppu_bus = crate::bus::PpuBus::borrow(&mut interrupts, &mut hdma);
+            for _ in 0..40 {
+                emu.ppu.clock(&mut ppu_bus);
+            }
+
+            emu.cpu.pc = 0xC000;
+            emu.wram[0] = opcode;
+            emu.cpu.h = (hl >> 8) as u8;
+            emu.cpu.l = hl as u8;
+            execute_n(&mut emu, 1);
+
+            (0..0xA0u16)
+                .map(|i| emu.ppu.read_oam(0xFE00 + i, true))
+                .collect::<Vec<u8>>()
+        };
+
+        // INC HL
+        let run = |accuracy: Accuracy, cgb_mode: bool| run_op(0x23, 0xFE10, accuracy, cgb_mode);
+
+        let untouched = run(Accuracy::empty(), false);
+        assert_eq!(
+            &untouched[80..88],
+            &[0xCA, 0xEF, 0x80, 0xA5, 0x7E, 0x13, 0x34, 0xC9]
+        );
+
+        let corrupted = run(Accuracy::OAM_CORRUPTION, false);
+        assert_eq!(
+            &corrupted[80..88],
+            &[0xA2, 0xFF, 0xE8, 0x8D, 0xA6, 0x7B, 0x1C, 0x31]
+        );
+        assert_eq!(&corrupted[..80], &untouched[..80]);
+        assert_eq!(&corrupted[88..], &untouched[88..]);
+
+        // CGB doesn't have the bug
+        assert_eq!(run(Accuracy::OAM_CORRUPTION, true), untouched);
+
+        // DEC HL corrupts the same way
+        assert_eq!(
+            run_op(0x2B, 0xFE10, Accuracy::OAM_CORRUPTION, false),
+            corrupted
+        );
+
+        // Only addresses in OAM trigger it
+        assert_eq!(
+            run_op(0x2B, 0xFD10, Accuracy::OAM_CORRUPTION, false),
+            untouched
+        );
+    }
+
     #[test]
     fn test_ld_rr() {
         let mut emu = MockEmulator::new().unwrap();
@@ -1166,4 +1884,62 @@ mod tests {
         execute_n(&mut emu, 1);
         assert_eq!(emu.cpu.pc, 0xD060 + 1);
     }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut emu = MockEmulator::new().unwrap();
+        let mut bus = borrow_cpu_bus!(emu);
+
+        // IF bit 2 can be set and cleared manually
+        bus.write(0xFF0F, 0x04);
+        assert_eq!(bus.read(0xFF0F), 0xE4);
+        bus.write(0xFF0F, 0x00);
+        assert_eq!(bus.read(0xFF0F), 0xE0);
+
+        // At the fastest rate, TIMA overflows after 256 * 4 M-cycles and requests the interrupt
+        // on the next one
+        bus.write(0xFF07, 0x05);
+        let mut m_cycles = 0;
+        while !bus.get_interrupts().status.contains(InterruptReg::TIMER) {
+            emu.cpu.clock(&mut bus);
+            m_cycles += 1;
+        }
+        assert_eq!(m_cycles, 256 * 4 + 1);
+
+        // It is serviced after LCD STAT
+        bus.write(0xFF0F, 0x06);
+        bus.write(0xFFFF, 0x06);
+        emu.cpu.interrupt_master_enable = true;
+        while emu.cpu.pc < 0x48 || emu.cpu.pc >= 0x100 {
+            emu.cpu.clock(&mut bus);
+        }
+        assert_eq!(emu.cpu.pc, 0x48);
+        assert_eq!(bus.read(0xFF0F), 0xE4);
+    }
+
+    #[test]
+    fn test_stop_div_reset() {
+        let mut emu = MockEmulator::new().unwrap();
+        emu.cpu.pc = 0xC000;
+        emu.wram[0..2].copy_from_slice(&[
+            0x10, 0x00, // stop
+        ]);
+
+        // Run long enough for DIV to count
+        for _ in 0..1000 {
+            emu.timer_registers.clock();
+        }
+        assert_ne!(emu.timer_registers.read(0xFF04), 0);
+
+        execute_n(&mut emu, 1);
+        assert!(emu.cpu.stopped);
+        assert_eq!(emu.timer_registers.read(0xFF04), 0);
+
+        // DIV doesn't count while stopped
+        let mut bus = borrow_cpu_bus!(emu);
+        for _ in 0..1000 {
+            emu.cpu.clock(&mut bus);
+        }
+        assert_eq!(bus.get_timer_registers().div(), 0);
+    }
 }
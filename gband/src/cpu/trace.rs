@@ -0,0 +1,49 @@
+use super::{decoder::Opcode, FlagRegister};
+
+/// Instruction about to be executed, with the CPU state before its execution
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: Opcode,
+    raw: [u8; 3],
+    length: u8,
+
+    pub a: u8,
+    pub f: FlagRegister,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+}
+
+impl TraceEntry {
+    pub(super) fn new(cpu: &super::Cpu, pc: u16, opcode: Opcode, raw: [u8; 3]) -> Self {
+        Self {
+            pc,
+            opcode,
+            raw,
+            length: opcode.length(),
+
+            a: cpu.a,
+            f: cpu.f,
+            b: cpu.b,
+            c: cpu.c,
+            d: cpu.d,
+            e: cpu.e,
+            h: cpu.h,
+            l: cpu.l,
+            sp: cpu.sp,
+        }
+    }
+
+    /// Raw bytes of the instruction, including its operands
+    pub fn bytes(&self) -> &[u8] {
+        &self.raw[..self.length as usize]
+    }
+}
+
+/// Callback fired before each executed instruction
+pub type TraceCallback = alloc::boxed::Box<dyn FnMut(TraceEntry)>;
@@ -1,7 +1,10 @@
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OamDma {
     pub cycle: Option<u8>,
     pub source: u8,
+    /// Last byte transferred, which the CPU reads on the bus used by the DMA
+    pub value: u8,
 }
 
 impl OamDma {
@@ -9,11 +12,13 @@ impl OamDma {
         Self {
             source,
             cycle: Some(0),
+            value: 0xFF,
         }
     }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HDma {
     pub source: u16,
     pub destination: u16,
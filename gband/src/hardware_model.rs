@@ -0,0 +1,9 @@
+/// Console running the game. CGB games run in DMG mode on a DMG.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HardwareModel {
+    /// Original Game Boy, with its 4 shades of grey
+    Dmg,
+    /// Game Boy Color, which colorizes DMG games with palettes picked by its boot ROM
+    #[default]
+    Cgb,
+}
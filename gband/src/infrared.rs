@@ -0,0 +1,111 @@
+use alloc::boxed::Box;
+
+use crate::{IrTransceiver, NullIrTransceiver};
+
+const LED_ON: u8 = 0x01;
+const NO_LIGHT_RECEIVED: u8 = 0x02;
+const UNUSED: u8 = 0x3C;
+const READ_ENABLE: u8 = 0xC0;
+
+/// CGB infrared communication port (RP)
+pub struct InfraredPort {
+    control: u8,
+    light_received: bool,
+
+    transceiver: Box<dyn IrTransceiver>,
+    // Light simulated by the frontend, received along with the transceiver's
+    external_light: bool,
+}
+
+impl Default for InfraredPort {
+    fn default() -> Self {
+        Self {
+            control: 0,
+            light_received: false,
+
+            transceiver: Box::new(NullIrTransceiver),
+            external_light: false,
+        }
+    }
+}
+
+/// The transceiver isn't cloned, the clone has nothing in front of it
+impl Clone for InfraredPort {
+    fn clone(&self) -> Self {
+        Self {
+            control: self.control,
+            light_received: self.light_received,
+
+            transceiver: Box::new(NullIrTransceiver),
+            external_light: self.external_light,
+        }
+    }
+}
+
+impl InfraredPort {
+    /// Restores the registers from a snapshot, keeping the current transceiver and simulated light
+    pub(crate) fn load_state(&mut self, mut state: Self) {
+        core::mem::swap(&mut state.transceiver, &mut self.transceiver);
+        state.external_light = self.external_light;
+        *self = state;
+    }
+
+    /// Samples the photodiode, which is only powered when reading is enabled
+    pub fn clock(&mut self) {
+        self.light_received = self.control & READ_ENABLE == READ_ENABLE
+            && (self.transceiver.sense() || self.external_light);
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.control = data & (READ_ENABLE | LED_ON);
+        self.transceiver.led(data & LED_ON != 0);
+    }
+
+    pub fn read(&self) -> u8 {
+        // The light received bit is active-low
+        let light = if self.light_received {
+            0
+        } else {
+            NO_LIGHT_RECEIVED
+        };
+
+        self.control | UNUSED | light
+    }
+
+    pub fn set_transceiver(&mut self, transceiver: Box<dyn IrTransceiver>) {
+        self.transceiver = transceiver;
+        self.light_received = false;
+    }
+
+    /// Simulates light shining on the port, whatever the transceiver senses
+    pub fn set_external_light(&mut self, light: bool) {
+        self.external_light = light;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LoopbackIr;
+
+    #[test]
+    fn test_loopback() {
+        let mut port = InfraredPort::default();
+        port.set_transceiver(Box::new(LoopbackIr::default()));
+        port.clock();
+        assert_eq!(port.read(), 0x3E);
+
+        // The LED is on, but reading is disabled
+        port.write(0x01);
+        port.clock();
+        assert_eq!(port.read(), 0x3F);
+
+        port.write(0xC1);
+        port.clock();
+        assert_eq!(port.read(), 0xFD);
+
+        port.write(0xC0);
+        port.clock();
+        assert_eq!(port.read(), 0xFE);
+    }
+}
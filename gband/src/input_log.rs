@@ -0,0 +1,284 @@
+use alloc::vec::Vec;
+
+use crate::{Emulator, JoypadState};
+
+const INPUT_LOG_MAGIC: &[u8; 4] = b"GBIL";
+const EVENT_SIZE: usize = 5;
+
+/// Joypad changes of a run, with the number of the frame they were latched on.
+/// Frame 0 is the frame during which the recording started.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputLog {
+    events: Vec<(u32, JoypadState)>,
+}
+
+impl InputLog {
+    pub fn events(&self) -> &[(u32, JoypadState)] {
+        &self.events
+    }
+
+    /// Serializes the log as a magic number followed by the events,
+    /// each one being the frame number in little endian and the buttons
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(INPUT_LOG_MAGIC.len() + self.events.len() * EVENT_SIZE);
+        data.extend_from_slice(INPUT_LOG_MAGIC);
+
+        for (frame, state) in &self.events {
+            data.extend_from_slice(&frame.to_le_bytes());
+            data.push(state.bits());
+        }
+
+        data
+    }
+
+    /// Parses a log produced by `to_bytes`. Returns None if it's malformed.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let events = data.strip_prefix(INPUT_LOG_MAGIC)?;
+        if events.len() % EVENT_SIZE != 0 {
+            return None;
+        }
+
+        let events: Vec<(u32, JoypadState)> = events
+            .chunks_exact(EVENT_SIZE)
+            .map(|event| {
+                let frame = u32::from_le_bytes([event[0], event[1], event[2], event[3]]);
+                (frame, JoypadState::from_bits_truncate(event[4]))
+            })
+            .collect();
+
+        // Events are in the order they are played back
+        if events.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+            return None;
+        }
+
+        Some(Self { events })
+    }
+}
+
+pub(crate) enum InputLogMode {
+    /// Joypad changes are latched at the next frame boundary and logged
+    Recording {
+        log: InputLog,
+        pending: Option<JoypadState>,
+    },
+    /// Joypad changes come from the log, the frontend's are ignored
+    Playing { log: InputLog, next_event: usize },
+}
+
+pub(crate) struct InputLogState {
+    frame: u32,
+    mode: InputLogMode,
+}
+
+impl Emulator {
+    /// Starts logging the joypad changes. While recording, `set_joypad` takes effect
+    /// on the next frame boundary, which is when `clock` returns a frame, so the log can be replayed exactly.
+    pub fn start_input_recording(&mut self) {
+        let log = InputLog {
+            events: alloc::vec![(0, self.joypad_state)],
+        };
+
+        self.input_log = Some(InputLogState {
+            frame: 0,
+            mode: InputLogMode::Recording { log, pending: None },
+        });
+    }
+
+    /// Stops the recording and returns its log, or None if nothing was being recorded.
+    /// A joypad change that wasn't latched yet is applied.
+    pub fn stop_input_recording(&mut self) -> Option<InputLog> {
+        match self.input_log.take() {
+            Some(InputLogState {
+                mode: InputLogMode::Recording { log, pending },
+                ..
+            }) => {
+                if let Some(state) = pending {
+                    self.latch_joypad(state);
+                }
+                Some(log)
+            }
+            other => {
+                self.input_log = other;
+                None
+            }
+        }
+    }
+
+    /// Replays the joypad changes of a log, starting now. The joypad is controlled by the log
+    /// until its last event is played back, and `set_joypad` is ignored in the meantime.
+    pub fn play_input_log(&mut self, log: &InputLog) {
+        self.input_log = Some(InputLogState {
+            frame: 0,
+            mode: InputLogMode::Playing {
+                log: log.clone(),
+                next_event: 0,
+            },
+        });
+
+        self.play_input_events();
+    }
+
+    pub fn is_playing_input_log(&self) -> bool {
+        matches!(
+            self.input_log,
+            Some(InputLogState {
+                mode: InputLogMode::Playing { .. },
+                ..
+            })
+        )
+    }
+
+    /// Handles a joypad change from the frontend. Returns false if it must not be applied right away.
+    pub(crate) fn on_input_log_joypad(&mut self, state: JoypadState) -> bool {
+        match &mut self.input_log {
+            Some(InputLogState {
+                mode: InputLogMode::Recording { pending, .. },
+                ..
+            }) => {
+                *pending = Some(state);
+                false
+            }
+            Some(InputLogState {
+                mode: InputLogMode::Playing { .. },
+                ..
+            }) => false,
+            None => true,
+        }
+    }
+
+    /// Called when a frame is done, which is when the joypad changes are latched
+    pub(crate) fn on_input_log_frame(&mut self) {
+        let input_log = match &mut self.input_log {
+            Some(input_log) => input_log,
+            None => return,
+        };
+        input_log.frame += 1;
+
+        let latched = match &mut input_log.mode {
+            InputLogMode::Recording { log, pending } => match pending.take() {
+                Some(state) if state != self.joypad_state => {
+                    log.events.push((input_log.frame, state));
+                    Some(state)
+                }
+                _ => None,
+            },
+            InputLogMode::Playing { .. } => return self.play_input_events(),
+        };
+
+        if let Some(state) = latched {
+            self.latch_joypad(state);
+        }
+    }
+
+    /// Applies the events of the current frame, and stops the playback after the last one
+    fn play_input_events(&mut self) {
+        let (frame, log, next_event) = match &mut self.input_log {
+            Some(InputLogState {
+                frame,
+                mode: InputLogMode::Playing { log, next_event },
+            }) => (*frame, log, next_event),
+            _ => return,
+        };
+
+        let mut latched = None;
+        while let Some(&(event_frame, state)) = log.events.get(*next_event) {
+            if event_frame > frame {
+                break;
+            }
+
+            latched = Some(state);
+            *next_event += 1;
+        }
+
+        if *next_event == log.events.len() {
+            self.input_log = None;
+        }
+
+        if let Some(state) = latched {
+            self.latch_joypad(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frame;
+
+    /// Copies the buttons to BGP in a loop, so the frames show when the inputs changed
+    fn emulator() -> Emulator {
+        let rom = crate::test_rom(
+            &[
+                0x3E, 0x10, // ld a, $10
+                0xE0, 0x00, // ldh [rP1], a
+                0xF0, 0x00, // ldh a, [rP1]
+                0xE0, 0x47, // ldh [rBGP], a
+                0x18, 0xFA, // jr -6
+            ],
+            false,
+        );
+        Emulator::new(&rom, None).unwrap()
+    }
+
+    #[test]
+    fn test_record_playback() {
+        let inputs = [
+            JoypadState::A,
+            JoypadState::A,
+            JoypadState::empty(),
+            JoypadState::START | JoypadState::B,
+            JoypadState::SELECT,
+        ];
+
+        let mut emu = emulator();
+        emu.run_frame();
+        emu.start_input_recording();
+
+        // Inputs change in the middle of the frames
+        let mut expected: Vec<Frame> = Vec::new();
+        for (i, &state) in inputs.iter().enumerate() {
+            for _ in 0..i * 10000 {
+                emu.clock();
+            }
+            emu.set_joypad(state);
+            expected.push(emu.run_frame());
+        }
+        expected.push(emu.run_frame());
+
+        let log = emu.stop_input_recording().unwrap();
+        assert_eq!(
+            log.events(),
+            &[
+                (0, JoypadState::empty()),
+                (1, JoypadState::A),
+                (3, JoypadState::empty()),
+                (4, JoypadState::START | JoypadState::B),
+                (5, JoypadState::SELECT),
+            ]
+        );
+
+        let log = InputLog::from_bytes(&log.to_bytes()).unwrap();
+
+        let mut emu = emulator();
+        emu.run_frame();
+        emu.play_input_log(&log);
+
+        // Inputs from the frontend are ignored
+        emu.set_joypad(JoypadState::DOWN);
+        let frames: Vec<Frame> = (0..expected.len()).map(|_| emu.run_frame()).collect();
+        assert!(frames == expected);
+        assert!(!emu.is_playing_input_log());
+    }
+
+    #[test]
+    fn test_malformed_log() {
+        assert_eq!(InputLog::from_bytes(b"GBIL"), Some(InputLog::default()));
+        assert_eq!(InputLog::from_bytes(b"GBIM"), None);
+        assert_eq!(InputLog::from_bytes(b"GBIL\x01\x00\x00\x00"), None);
+        assert_eq!(
+            InputLog::from_bytes(b"GBIL\x02\x00\x00\x00\x10\x01\x00\x00\x00\x00"),
+            None
+        );
+    }
+}
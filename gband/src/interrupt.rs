@@ -1,6 +1,7 @@
 use bitflags::bitflags;
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InterruptState {
     pub enable: InterruptReg,
     pub status: InterruptReg,
@@ -15,8 +16,31 @@ impl Default for InterruptState {
     }
 }
 
+impl InterruptState {
+    /// Returns the highest priority interrupt that is both enabled and requested.
+    /// Lower bits have higher priority, so VBlank is serviced first and Joypad last.
+    pub fn pending(&self) -> Option<InterruptReg> {
+        let pending = (self.enable & self.status).bits() & 0x1F;
+
+        if pending == 0 {
+            None
+        } else {
+            // Isolate the lowest bit set
+            Some(InterruptReg::from_bits_truncate(
+                pending & pending.wrapping_neg(),
+            ))
+        }
+    }
+
+    /// Clears the request flag of an interrupt when it gets serviced
+    pub fn acknowledge(&mut self, interrupt: InterruptReg) {
+        self.status.remove(interrupt);
+    }
+}
+
 bitflags! {
     #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct InterruptReg: u8 {
         const VBLANK = 0x01;
         const LCD_STAT = 0x02;
@@ -26,3 +50,76 @@ bitflags! {
         const UNUSED = 0xE0;
     }
 }
+
+impl InterruptReg {
+    /// Address of the interrupt handler. Only meaningful for a single interrupt flag.
+    pub fn vector(self) -> u16 {
+        0x0040 + 0x0008 * self.bits().trailing_zeros() as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        assert_eq!(InterruptReg::VBLANK.vector(), 0x40);
+        assert_eq!(InterruptReg::LCD_STAT.vector(), 0x48);
+        assert_eq!(InterruptReg::TIMER.vector(), 0x50);
+        assert_eq!(InterruptReg::SERIAL.vector(), 0x58);
+        assert_eq!(InterruptReg::JOYPAD.vector(), 0x60);
+    }
+
+    #[test]
+    fn test_pending_priority() {
+        let mut interrupts = InterruptState {
+            enable: InterruptReg::from_bits_truncate(0x1F),
+            status: InterruptReg::UNUSED,
+        };
+        assert_eq!(interrupts.pending(), None);
+
+        interrupts.status.insert(InterruptReg::JOYPAD);
+        interrupts.status.insert(InterruptReg::TIMER);
+        interrupts.status.insert(InterruptReg::VBLANK);
+
+        // Interrupts are serviced in priority order
+        let pending = interrupts.pending().unwrap();
+        assert_eq!(pending, InterruptReg::VBLANK);
+        assert_eq!(pending.vector(), 0x40);
+        interrupts.acknowledge(pending);
+
+        let pending = interrupts.pending().unwrap();
+        assert_eq!(pending, InterruptReg::TIMER);
+        assert_eq!(pending.vector(), 0x50);
+        interrupts.acknowledge(pending);
+
+        let pending = interrupts.pending().unwrap();
+        assert_eq!(pending, InterruptReg::JOYPAD);
+        assert_eq!(pending.vector(), 0x60);
+        interrupts.acknowledge(pending);
+
+        assert_eq!(interrupts.pending(), None);
+
+        // Unused bits are left untouched
+        assert_eq!(interrupts.status.bits(), 0xE0);
+    }
+
+    #[test]
+    fn test_pending_disabled() {
+        let mut interrupts = InterruptState {
+            enable: InterruptReg::SERIAL,
+            ..Default::default()
+        };
+
+        // VBlank is requested on boot, but not enabled
+        assert_eq!(interrupts.pending(), None);
+
+        interrupts.status.insert(InterruptReg::SERIAL);
+        assert_eq!(interrupts.pending(), Some(InterruptReg::SERIAL));
+
+        // All unused bits set in IE don't matter
+        interrupts.enable = InterruptReg::from_bits_truncate(0xE0);
+        assert_eq!(interrupts.pending(), None);
+    }
+}
@@ -0,0 +1,105 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Emulator;
+
+/// What is in front of the CGB infrared port
+pub trait IrTransceiver: Sync + Send {
+    /// Turns the LED of the port on or off
+    fn led(&mut self, on: bool);
+
+    /// Returns if the photodiode of the port receives light
+    fn sense(&mut self) -> bool;
+}
+
+/// Nothing in front of the port, so no light is ever received
+pub struct NullIrTransceiver;
+
+impl IrTransceiver for NullIrTransceiver {
+    fn led(&mut self, _on: bool) {}
+
+    fn sense(&mut self) -> bool {
+        false
+    }
+}
+
+/// Reflects the LED of the port back to it, like a mirror in front of the console
+#[derive(Default)]
+pub struct LoopbackIr {
+    led: bool,
+}
+
+impl IrTransceiver for LoopbackIr {
+    fn led(&mut self, on: bool) {
+        self.led = on
+    }
+
+    fn sense(&mut self) -> bool {
+        self.led
+    }
+}
+
+/// Two emulators of the same process facing each other, each one sensing the LED of the other.
+/// Both emulators must be clocked by the caller, for example alternately.
+pub struct PairedIr {
+    outgoing: Arc<AtomicBool>,
+    incoming: Arc<AtomicBool>,
+}
+
+impl PairedIr {
+    /// Creates both ends of the pair
+    pub fn pair() -> (Self, Self) {
+        let a_led = Arc::new(AtomicBool::new(false));
+        let b_led = Arc::new(AtomicBool::new(false));
+
+        (
+            Self {
+                outgoing: a_led.clone(),
+                incoming: b_led.clone(),
+            },
+            Self {
+                outgoing: b_led,
+                incoming: a_led,
+            },
+        )
+    }
+
+    /// Points the infrared ports of two emulators at each other, replacing their transceivers
+    pub fn connect(a: &mut Emulator, b: &mut Emulator) {
+        let (ir_a, ir_b) = Self::pair();
+        a.set_ir_transceiver(Box::new(ir_a));
+        b.set_ir_transceiver(Box::new(ir_b));
+    }
+}
+
+impl IrTransceiver for PairedIr {
+    fn led(&mut self, on: bool) {
+        self.outgoing.store(on, Ordering::Release)
+    }
+
+    fn sense(&mut self) -> bool {
+        self.incoming.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paired_ir() {
+        let (mut a, mut b) = PairedIr::pair();
+        assert!(!a.sense());
+        assert!(!b.sense());
+
+        a.led(true);
+        assert!(!a.sense());
+        assert!(b.sense());
+
+        a.led(false);
+        b.led(true);
+        assert!(a.sense());
+        assert!(!b.sense());
+    }
+}
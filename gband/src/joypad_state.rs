@@ -2,6 +2,7 @@ use bitflags::bitflags;
 
 bitflags! {
     #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct JoypadState: u8 {
         const START = 0x80;
         const SELECT = 0x40;
@@ -13,3 +14,71 @@ bitflags! {
         const RIGHT = 0x01;
     }
 }
+
+impl JoypadState {
+    /// State of the P1 input lines with the given select bits. Like on hardware, the lines are
+    /// active-low and only the selected groups can pull them low.
+    pub(crate) fn input_lines(self, select: u8) -> u8 {
+        let state = self.bits();
+        let mut lines = 0;
+
+        if select & 0x10 == 0 {
+            // If bit 4 is set to 0, handle D-pad
+            lines |= state & 0x0F;
+        };
+
+        if select & 0x20 == 0 {
+            // If bit 5 is set to 0, handle the other buttons
+            lines |= (state & 0xF0) >> 4;
+        }
+
+        !lines & 0x0F
+    }
+
+    /// The joypad interrupt is requested when any input line goes from high to low
+    pub(crate) fn is_falling_edge(lines_before: u8, lines_after: u8) -> bool {
+        lines_before & !lines_after != 0
+    }
+
+    /// Releases both directions of an axis when they are pressed at the same time.
+    /// The hardware allows it, but some games glitch when it happens.
+    pub fn without_opposing_directions(self) -> Self {
+        let mut state = self;
+
+        if state.contains(Self::UP | Self::DOWN) {
+            state.remove(Self::UP | Self::DOWN);
+        }
+
+        if state.contains(Self::LEFT | Self::RIGHT) {
+            state.remove(Self::LEFT | Self::RIGHT);
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_lines() {
+        let state = JoypadState::A | JoypadState::START | JoypadState::LEFT;
+        assert_eq!(state.input_lines(0x10), 0x06);
+        assert_eq!(state.input_lines(0x20), 0x0D);
+        assert_eq!(state.input_lines(0x00), 0x04);
+        assert_eq!(state.input_lines(0x30), 0x0F);
+    }
+
+    #[test]
+    fn test_without_opposing_directions() {
+        let state = JoypadState::UP | JoypadState::DOWN | JoypadState::LEFT | JoypadState::A;
+        assert_eq!(
+            state.without_opposing_directions(),
+            JoypadState::LEFT | JoypadState::A
+        );
+
+        let state = JoypadState::LEFT | JoypadState::RIGHT | JoypadState::UP;
+        assert_eq!(state.without_opposing_directions(), JoypadState::UP);
+    }
+}
@@ -1,39 +1,87 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 #[macro_use]
 pub mod bus; // TODO: Revert pub added for criterion
 
+mod accuracy;
+mod apu;
 mod cartridge;
 mod cgb_double_speed;
 mod cpu;
 mod dma;
+mod hardware_model;
+mod infrared;
+mod input_log;
 mod interrupt;
+mod ir_transceiver;
 mod joypad_state;
+mod mem_watch;
+mod peripheral;
 mod ppu;
+mod rewind;
 mod rgb_palette;
+#[cfg(feature = "serde")]
+mod serde_arrays;
 mod serial;
 mod serial_transport;
+#[cfg(feature = "std")]
+mod tcp_link;
 mod timer_regs;
 pub mod utils;
 
-pub use cartridge::RomParserError;
+pub use accuracy::Accuracy;
+use apu::AudioOutput;
+pub use apu::{Apu, AudioChannel, AudioQuality};
+pub use cartridge::{RomParserError, RumbleCallback};
 pub use cgb_double_speed::CgbDoubleSpeed;
-pub use cpu::Cpu;
+pub use cpu::{Cpu, Opcode, TraceEntry};
+pub use hardware_model::HardwareModel;
+pub use input_log::InputLog;
 pub use interrupt::{InterruptReg, InterruptState};
+pub use ir_transceiver::{IrTransceiver, LoopbackIr, NullIrTransceiver, PairedIr};
 pub use joypad_state::JoypadState;
-pub use ppu::{Frame, Ppu, FRAME_HEIGHT, FRAME_WIDTH};
+pub use mem_watch::{MemAccess, MemAccessKind, MemWatch};
+pub use peripheral::{MappedPeripheral, Peripheral};
+pub use ppu::{
+    ColorCorrection, CompatPalette, Frame, GraphicsSnapshot, Layer, LcdControl, LcdStatus,
+    PixelFormat, Ppu, RenderMode, ScanlineCallback, ScanlineHook, ScanlineInfo,
+    DEFAULT_FRAME_BLEND, FRAME_HEIGHT, FRAME_WIDTH,
+};
+pub use rewind::REWIND_CAPACITY;
+
+#[cfg(feature = "debug-ppu")]
+pub use ppu::{FetcherState, FifoMode, FifoPixel, PixelFetcherState};
+#[cfg(feature = "debugger")]
+pub use ppu::{OamEntry, TileMapSelect, Viewport};
 pub use serial_transport::*;
+#[cfg(feature = "std")]
+pub use tcp_link::{TcpLink, DEFAULT_LINK_TIMEOUT};
 
 // TODO: Revert pub added for criterion
 pub use cartridge::Cartridge;
 pub use dma::*;
-pub use serial::SerialPort;
+pub use infrared::InfraredPort;
+pub use serial::{SerialCallback, SerialPort};
 pub use timer_regs::TimerRegisters;
 
+/// Result of a single emulator clock when running under a debugger
+#[cfg(feature = "debugger")]
+pub enum StepResult {
+    Continue,
+    Frame(Frame),
+    /// The instruction at this address is about to be executed
+    BreakpointHit(u16),
+}
+
 const WRAM_BANK_SIZE: u16 = 0x1000; // 4KiB
 
+/// Rate of `Emulator::clock` calls on hardware, in Hz. It doesn't change in CGB double speed mode.
+pub const CLOCK_RATE: u64 = 1 << 22;
+
 pub struct Emulator {
     // == Cartridge Related Hardware== //
     cartridge: Cartridge,
@@ -55,23 +103,51 @@ pub struct Emulator {
     ppu: Ppu,
     cgb_mode: bool,
 
+    // == APU Related Hardware == //
+    apu: Apu,
+    audio_output: AudioOutput,
+
     // == IP Related Hardware == //
     serial_port: SerialPort,
+    infrared_port: InfraredPort,
 
     // == IO Hardware ==
     joypad_state: JoypadState,
     joypad_register: u8,
+    filter_opposing_directions: bool,
 
     // == Emulation Specific Data == //
     clock_count: u8,
+    /// Fraction of a clock left by `run_for_duration`, in millionths
+    duration_remainder: u64,
+    accuracy: Accuracy,
+    mem_watch: Option<MemWatch>,
+    peripherals: alloc::vec::Vec<MappedPeripheral>,
+    rewind_buffer: Option<rewind::RewindBuffer>,
+    input_log: Option<input_log::InputLogState>,
 }
 
 impl Emulator {
     pub fn new(rom: &[u8], save_data: Option<&[u8]>) -> Result<Self, RomParserError> {
+        Self::with_hardware_model(rom, save_data, Default::default())
+    }
+
+    /// Like `new`, but runs the game on the given console instead of a CGB
+    pub fn with_hardware_model(
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        hardware_model: HardwareModel,
+    ) -> Result<Self, RomParserError> {
         let cartridge = Cartridge::load(rom, save_data)?;
-        let cgb_mode = cartridge.is_cgb();
+        let cgb_mode = cartridge.is_cgb() && hardware_model == HardwareModel::Cgb;
         let mut ppu = Ppu::new(cgb_mode);
-        ppu.set_dmg_colorized_palette(&cartridge.header.title);
+        if hardware_model == HardwareModel::Dmg {
+            ppu.set_greyscale_palette();
+        } else if cartridge.header.is_nintendo_licensed() {
+            ppu.set_dmg_colorized_palette(&cartridge.header.title);
+        } else {
+            ppu.set_compat_palette(Default::default());
+        }
 
         let emulator = Self {
             cartridge,
@@ -89,12 +165,23 @@ impl Emulator {
             ppu,
             cgb_mode,
 
+            apu: Apu::new(cgb_mode),
+            audio_output: AudioOutput::new(cgb_mode),
+
             serial_port: Default::default(),
+            infrared_port: Default::default(),
 
             joypad_state: Default::default(),
             joypad_register: Default::default(),
+            filter_opposing_directions: false,
 
             clock_count: 0,
+            duration_remainder: 0,
+            accuracy: Default::default(),
+            mem_watch: None,
+            peripherals: alloc::vec::Vec::new(),
+            rewind_buffer: None,
+            input_log: None,
         };
 
         Ok(emulator)
@@ -105,9 +192,17 @@ impl Emulator {
 
         // clock_count is at ~4MHz
         // PPU is clocked at ~4MHz
+        self.ppu.set_stopped(self.cpu.stopped);
+
         let mut ppu_bus = borrow_ppu_bus!(self);
         self.ppu.clock(&mut ppu_bus);
 
+        // APU is clocked at ~4MHz, and sampled at ~1MHz
+        self.apu.clock();
+        if self.clock_count == 4 {
+            self.audio_output.push(self.apu.sample());
+        }
+
         // We clock CPU on M-cycles, at ~1MHz on regular mode and ~2MHz on CGB double speed mode
         // This means we clock it every 2 or 4 cycles
         let double_speed = self.double_speed.contains(CgbDoubleSpeed::ENABLED);
@@ -121,27 +216,283 @@ impl Emulator {
         };
 
         // Return a frame if available
-        self.ppu.ready_frame()
+        let frame = self.ppu.ready_frame();
+        if frame.is_some() {
+            self.on_input_log_frame();
+            self.on_rewind_frame();
+        }
+
+        frame
+    }
+
+    /// Runs the emulator until the next frame is done
+    pub fn run_frame(&mut self) -> Frame {
+        loop {
+            if let Some(frame) = self.clock() {
+                break frame;
+            }
+        }
+    }
+
+    /// Runs the emulator until `samples` stereo samples of audio are produced, so the emulation can be
+    /// paced by the audio device. Returns the last frame done in the meantime, if any.
+    /// The audio is produced at the same pace in CGB double speed mode.
+    /// Returns immediately if no audio sample rate is set.
+    pub fn run_until_audio(&mut self, samples: usize) -> Option<Frame> {
+        if !self.audio_output.is_enabled() {
+            return None;
+        }
+
+        let target = self.audio_output.produced() + samples as u64;
+        let mut frame = None;
+        while self.audio_output.produced() < target {
+            if let Some(done) = self.clock() {
+                frame = Some(done);
+            }
+        }
+
+        frame
+    }
+
+    /// Runs the emulator for the time `micros` microseconds take on hardware, so the emulation can be paced
+    /// by a fixed timestep. The fraction of a clock left is carried to the next call, so it doesn't drift.
+    /// Returns the frames done in the meantime, oldest first.
+    pub fn run_for_duration(&mut self, micros: u64) -> alloc::vec::Vec<Frame> {
+        let total = micros * CLOCK_RATE + self.duration_remainder;
+        self.duration_remainder = total % 1_000_000;
+
+        let mut frames = alloc::vec::Vec::new();
+        for _ in 0..total / 1_000_000 {
+            if let Some(frame) = self.clock() {
+                frames.push(frame);
+            }
+        }
+
+        frames
+    }
+
+    /// Frame being drawn. Once a frame is returned by `clock`, the next one is drawn in a new buffer.
+    pub fn frame(&self) -> &[u8] {
+        self.ppu.frame()
     }
 
     pub fn set_serial(&mut self, serial: alloc::boxed::Box<dyn SerialTransport>) {
         self.serial_port.set_serial(serial)
     }
 
+    /// Sets a callback fired with each byte the game transmits on the serial port,
+    /// whether something is connected or not. Test ROMs print their results this way.
+    pub fn set_serial_callback(&mut self, callback: SerialCallback) {
+        self.serial_port.set_serial_callback(Some(callback))
+    }
+
+    pub fn clear_serial_callback(&mut self) {
+        self.serial_port.set_serial_callback(None)
+    }
+
+    /// Sets the tilt of cartridges with an accelerometer, like MBC7 ones, in g on each axis
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.cartridge.set_accelerometer(x, y)
+    }
+
+    /// Sets a callback fired when the rumble motor of the cartridge turns on or off
+    pub fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.cartridge.set_rumble_callback(Some(callback))
+    }
+
+    pub fn clear_rumble_callback(&mut self) {
+        self.cartridge.set_rumble_callback(None)
+    }
+
+    /// Sets what is in front of the infrared port, which is only used in CGB mode
+    pub fn set_ir_transceiver(&mut self, transceiver: alloc::boxed::Box<dyn IrTransceiver>) {
+        self.infrared_port.set_transceiver(transceiver)
+    }
+
+    /// Simulates an infrared signal received by the CGB infrared port, for frontends without a transceiver.
+    /// It's only seen when the game enables reading in RP.
+    pub fn set_infrared_received(&mut self, received: bool) {
+        self.infrared_port.set_external_light(received)
+    }
+
+    /// Sets the buttons pressed. While an input log is recorded, this takes effect on the next frame boundary,
+    /// and it's ignored while one is played back.
     pub fn set_joypad(&mut self, state: JoypadState) {
-        self.joypad_state = state
+        if self.on_input_log_joypad(state) {
+            self.latch_joypad(state);
+        }
+    }
+
+    fn latch_joypad(&mut self, state: JoypadState) {
+        let lines_before = self.joypad_state.input_lines(self.joypad_register);
+
+        self.joypad_state = if self.filter_opposing_directions {
+            state.without_opposing_directions()
+        } else {
+            state
+        };
+
+        // Only presses in the selected groups reach the input lines
+        let lines_after = self.joypad_state.input_lines(self.joypad_register);
+        if JoypadState::is_falling_edge(lines_before, lines_after) {
+            self.interrupts.status.insert(InterruptReg::JOYPAD);
+        }
+    }
+
+    /// Releases opposing D-pad directions pressed together, instead of passing them to the game
+    pub fn set_filter_opposing_directions(&mut self, filter: bool) {
+        self.filter_opposing_directions = filter
+    }
+
+    /// Sets a callback fired before each executed instruction
+    pub fn set_trace_callback(&mut self, callback: impl FnMut(TraceEntry) + 'static) {
+        self.cpu
+            .set_trace_callback(Some(alloc::boxed::Box::new(callback)))
+    }
+
+    pub fn clear_trace_callback(&mut self) {
+        self.cpu.set_trace_callback(None)
+    }
+
+    /// Overrides the colorization used when running DMG games
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        self.ppu.set_compat_palette(palette)
+    }
+
+    /// Sets a callback fired on every memory access done by the CPU
+    pub fn set_mem_watch(&mut self, mem_watch: impl FnMut(MemAccess) + 'static) {
+        self.mem_watch = Some(alloc::boxed::Box::new(mem_watch))
+    }
+
+    pub fn clear_mem_watch(&mut self) {
+        self.mem_watch = None
+    }
+
+    /// Maps custom hardware over `range` on the CPU bus, see `Peripheral` for the precedence
+    pub fn add_peripheral(
+        &mut self,
+        range: core::ops::RangeInclusive<u16>,
+        peripheral: alloc::boxed::Box<dyn Peripheral>,
+    ) {
+        self.peripherals
+            .push(MappedPeripheral::new(range, peripheral))
+    }
+
+    pub fn clear_peripherals(&mut self) {
+        self.peripherals.clear()
+    }
+
+    /// Blends each frame with the previous one, see `Ppu::set_frame_blend`
+    pub fn set_frame_blend(&mut self, factor: u8) {
+        self.ppu.set_frame_blend(factor)
+    }
+
+    /// Sets a callback fired with the registers latched for each scanline when it is done drawing
+    pub fn set_scanline_hook(&mut self, hook: impl FnMut(u8, &ScanlineInfo) + 'static) {
+        self.ppu.set_scanline_hook(hook)
+    }
+
+    pub fn clear_scanline_hook(&mut self) {
+        self.ppu.clear_scanline_hook()
+    }
+
+    /// Sets a callback fired with the pixels of each scanline when it is done drawing
+    pub fn set_scanline_callback(&mut self, callback: impl FnMut(u8, &[u8]) + 'static) {
+        self.ppu.set_scanline_callback(callback)
+    }
+
+    pub fn clear_scanline_callback(&mut self) {
+        self.ppu.clear_scanline_callback()
+    }
+
+    /// Selects the byte layout of the returned frames
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        self.ppu.set_pixel_format(pixel_format)
+    }
+
+    /// Selects how the CGB colors are converted to RGB. Defaults to `ColorCorrection::Simple`
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.ppu.set_color_correction(color_correction)
+    }
+
+    /// Skips the drawing of the frames, for when they aren't displayed. See `Ppu::set_rendering_enabled`
+    pub fn set_rendering_enabled(&mut self, enabled: bool) {
+        self.ppu.set_rendering_enabled(enabled)
+    }
+
+    /// Selects the PPU renderer, trading accuracy for speed
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.ppu.set_render_mode(render_mode)
+    }
+
+    /// Sets the rate of the audio samples returned by `drain_audio`. 0 disables the audio output.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.audio_output.set_sample_rate(sample_rate)
+    }
+
+    /// Selects the resampling of the audio output, trading accuracy for speed
+    pub fn set_audio_quality(&mut self, quality: AudioQuality) {
+        self.audio_output.set_quality(quality)
+    }
+
+    /// Enables the high-pass filter of the hardware output, which removes the DC offset.
+    /// It is enabled by default.
+    pub fn set_audio_high_pass(&mut self, enabled: bool) {
+        self.audio_output.set_high_pass(enabled)
+    }
+
+    /// Hides a layer of the frames for debugging, without changing what the game sees
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        self.ppu.set_layer_enabled(layer, enabled)
+    }
+
+    /// Mutes a channel of the audio output, without changing what the game sees
+    pub fn set_channel_enabled(&mut self, channel: AudioChannel, enabled: bool) {
+        self.apu.set_channel_enabled(channel, enabled)
+    }
+
+    /// Moves the produced audio to `samples`, as interleaved left and right samples.
+    /// Returns the number of values written.
+    pub fn drain_audio(&mut self, samples: &mut [i16]) -> usize {
+        self.audio_output.drain(samples)
     }
 
     pub fn get_save_data(&self) -> Option<&[u8]> {
         self.cartridge.get_save_data()
     }
 
+    /// See `Cartridge::current_rom_bank`
+    pub fn current_rom_bank(&self) -> u16 {
+        self.cartridge.current_rom_bank()
+    }
+
+    /// See `Cartridge::current_ram_bank`
+    pub fn current_ram_bank(&self) -> u8 {
+        self.cartridge.current_ram_bank()
+    }
+
+    /// Enables the emulation of hardware quirks that are disabled by default
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy
+    }
+
+    pub fn get_accuracy(&self) -> Accuracy {
+        self.accuracy
+    }
+
+    /// Reads memory as seen by the CPU, without reporting it to the memory watch.
+    /// This is meant for tools and test harnesses reading results left in memory.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        let bus = borrow_cpu_bus!(self);
+        bus.read_without_dma_check(addr, false)
+    }
+
     #[cfg(feature = "debugger")]
     pub fn disassemble(
         &mut self,
         _start: u16,
         _end: u16,
-    ) -> alloc::vec::Vec<(u8, u16, alloc::string::String)> {
+    ) -> alloc::vec::Vec<(u16, u16, alloc::string::String)> {
         let mut bus = borrow_cpu_bus!(self);
         crate::cpu::debugger::disassemble(&mut bus)
     }
@@ -151,26 +502,1004 @@ impl Emulator {
         let mut data = alloc::vec::Vec::new();
 
         for addr in start..=end {
-            let mut bus = borrow_cpu_bus!(self);
-            data.push(bus.read(addr));
+            let bus = borrow_cpu_bus!(self);
+            data.push(bus.read_unwatched(addr));
         }
 
         data
     }
 
+    /// Clocks the emulator, stopping before the fetch of an instruction on a breakpoint
+    #[cfg(feature = "debugger")]
+    pub fn step(&mut self) -> StepResult {
+        // A breakpoint can be reached on the same clock as a frame is completed
+        if let Some(addr) = self.cpu.take_breakpoint_hit() {
+            return StepResult::BreakpointHit(addr);
+        }
+
+        // Resuming finishes the M-cycle stopped by the breakpoint with its fetch
+        if self.cpu.at_breakpoint() {
+            let mut cpu_bus = borrow_cpu_bus!(self);
+            self.cpu.fetch(&mut cpu_bus);
+        }
+
+        self.cpu.set_stop_on_breakpoint(true);
+        let frame = self.clock();
+        self.cpu.set_stop_on_breakpoint(false);
+
+        if let Some(frame) = frame {
+            return StepResult::Frame(frame);
+        }
+
+        match self.cpu.take_breakpoint_hit() {
+            Some(addr) => StepResult::BreakpointHit(addr),
+            None => StepResult::Continue,
+        }
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.cpu.add_breakpoint(addr)
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.cpu.remove_breakpoint(addr)
+    }
+
+    /// Returns the address of the last breakpoint reached since the last call, if any.
+    /// `clock` and `run_frame` don't stop on breakpoints, they are only reported here.
+    #[cfg(feature = "debugger")]
+    pub fn take_breakpoint_hit(&mut self) -> Option<u16> {
+        self.cpu.take_breakpoint_hit()
+    }
+
     #[cfg(feature = "debugger")]
     pub fn cpu(&self) -> &Cpu {
         &self.cpu
     }
+
+    #[cfg(feature = "debugger")]
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// Captures VRAM, OAM and the palettes, without the rest of the state
+    pub fn snapshot_graphics(&self) -> GraphicsSnapshot {
+        self.ppu.snapshot_graphics()
+    }
+
+    /// Puts back graphics memory taken with `snapshot_graphics`
+    pub fn restore_graphics(&mut self, snapshot: &GraphicsSnapshot) {
+        self.ppu.restore_graphics(snapshot)
+    }
+}
+
+/// Fills in the header checksum of a test ROM, so it can be loaded
+#[cfg(test)]
+pub(crate) fn set_header_checksum(rom: &mut [u8]) {
+    rom[0x14d] = rom[0x134..0x14d]
+        .iter()
+        .fold(0u8, |checksum, b| checksum.wrapping_sub(*b).wrapping_sub(1));
+}
+
+/// Test ROM without a mapper, running `program` from the entry point
+#[cfg(test)]
+pub(crate) fn test_rom(program: &[u8], cgb: bool) -> alloc::vec::Vec<u8> {
+    let mut rom = alloc::vec![0u8; 0x150];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    if cgb {
+        rom[0x143] = 0x80;
+    }
+
+    set_header_checksum(&mut rom);
+    rom
 }
 
 #[test]
 fn test() {
-    let mut rom = [0u8; 0x150];
-    rom[0x14d] = 231;
+    let rom = test_rom(&[], false);
     let mut emu = Emulator::new(&rom, None).unwrap();
 
     for _ in 0..10 {
         emu.clock();
     }
 }
+
+#[test]
+fn test_stop() {
+    let rom = test_rom(
+        &[
+            0x3E, 0xFF, // ld a, 0xFF
+            0xE0, 0x47, // ldh [rBGP], a
+            0x3E, 0x91, // ld a, 0x91
+            0xE0, 0x40, // ldh [rLCDC], a
+            0x3E, 0x10, // ld a, 0x10
+            0xE0, 0x00, // ldh [rP1], a
+            0x10, 0x3C, // stop, followed by inc a which is skipped
+            0x18, 0xFE, // jr -2
+        ],
+        false,
+    );
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    let run_frame = |emu: &mut Emulator| loop {
+        if let Some(frame) = emu.clock() {
+            break frame;
+        }
+    };
+
+    while !emu.cpu.stopped {
+        emu.clock();
+    }
+
+    // The LCD is white while stopped, and the CPU doesn't run
+    for _ in 0..2 {
+        assert!(run_frame(&mut emu).iter().all(|&x| x == 0xFF));
+    }
+    assert!(emu.cpu.stopped);
+
+    // Only the buttons are selected, so the D-pad can't wake the CPU
+    emu.set_joypad(JoypadState::UP);
+    run_frame(&mut emu);
+    assert!(emu.cpu.stopped);
+
+    // A button press resumes the execution and the rendering of the black background
+    emu.set_joypad(JoypadState::A);
+    for _ in 0..4 {
+        emu.clock();
+    }
+    assert!(!emu.cpu.stopped);
+
+    emu.set_joypad(JoypadState::empty());
+    for _ in 0..4 * 4 {
+        emu.clock();
+    }
+    assert_eq!(emu.cpu.a, 0x10);
+    assert_eq!(emu.cpu.pc & 0xFFFE, 0x10E);
+
+    for _ in 0..2 {
+        run_frame(&mut emu);
+    }
+    assert!(run_frame(&mut emu)
+        .chunks_exact(4)
+        .all(|x| x[0..3] != [0xFF; 3]));
+}
+
+#[test]
+fn test_run_frame() {
+    let rom = test_rom(
+        &[
+            0xAF, // xor a
+            0xE0, 0x40, // ldh [rLCDC], a
+            0x3E, 0xFF, // ld a, 0xFF
+            0x21, 0x00, 0x80, // ld hl, 0x8000
+            0x22, // ld [hl+], a
+            0x22, // ld [hl+], a
+            0x3E, 0xE4, // ld a, 0xE4
+            0xE0, 0x47, // ldh [rBGP], a
+            0x3E, 0x91, // ld a, 0x91
+            0xE0, 0x40, // ldh [rLCDC], a
+            0x18, 0xFE, // jr -2
+        ],
+        false,
+    );
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    emu.run_frame();
+    let frame = emu.run_frame();
+    assert_eq!(frame.len(), FRAME_WIDTH * FRAME_HEIGHT * 4);
+
+    // The first row of tile 0 is drawn at the top of every tile of the map
+    let pixel =
+        |x: usize, y: usize| &frame[(y * FRAME_WIDTH + x) * 4..(y * FRAME_WIDTH + x) * 4 + 4];
+    assert_ne!(pixel(0, 0), pixel(0, 1));
+    for x in 0..FRAME_WIDTH {
+        assert_eq!(pixel(x, 0), pixel(0, 0));
+        assert_eq!(pixel(x, 8), pixel(0, 0));
+        assert_eq!(pixel(x, 1), pixel(0, 1));
+    }
+
+    // The next frame is drawn in a new buffer
+    assert_eq!(emu.frame().len(), frame.len());
+    assert_ne!(emu.frame().as_ptr(), frame.as_ptr());
+}
+
+#[test]
+fn test_hardware_model() {
+    let rom = test_rom(
+        &[
+            0xAF, // xor a
+            0xE0, 0x40, // ldh [rLCDC], a
+            0x3E, 0xFF, // ld a, 0xFF
+            0x21, 0x00, 0x80, // ld hl, 0x8000
+            0x22, // ld [hl+], a
+            0xAF, // xor a
+            0x22, // ld [hl+], a
+            0x3E, 0xE4, // ld a, 0xE4
+            0xE0, 0x47, // ldh [rBGP], a
+            0x3E, 0x91, // ld a, 0x91
+            0xE0, 0x40, // ldh [rLCDC], a
+            0x18, 0xFE, // jr -2
+        ],
+        false,
+    );
+
+    // The first row of tile 0 is color 1
+    let top_left_pixel = |model| {
+        let mut emu = Emulator::with_hardware_model(&rom, None, model).unwrap();
+        emu.run_frame();
+        let frame = emu.run_frame();
+        [frame[0], frame[1], frame[2]]
+    };
+
+    assert_eq!(top_left_pixel(HardwareModel::Dmg), [0xAA; 3]);
+
+    let [r, g, b] = top_left_pixel(HardwareModel::Cgb);
+    assert!(r != g || g != b);
+}
+
+#[test]
+fn test_peek() {
+    let rom = test_rom(
+        &[
+            0x3E, 0x42, // ld a, 0x42
+            0xEA, 0x00, 0xC0, // ld [0xC000], a
+        ],
+        false,
+    );
+    let mut emu = Emulator::new(&rom, None).unwrap();
+    for _ in 0..100 {
+        emu.clock();
+    }
+
+    assert_eq!(emu.peek(0x0101), 0x42);
+    assert_eq!(emu.peek(0xC000), 0x42);
+}
+
+#[test]
+fn test_peripheral() {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    /// Register at 0xFF7F, only handling the writes of even values
+    struct DebugPort(Rc<Cell<u8>>);
+
+    impl Peripheral for DebugPort {
+        fn read(&self, _addr: u16) -> Option<u8> {
+            Some(self.0.get())
+        }
+
+        fn write(&mut self, _addr: u16, value: u8) -> bool {
+            if value & 1 == 0 {
+                self.0.set(value);
+            }
+            value & 1 == 0
+        }
+    }
+
+    let rom = test_rom(&[], false);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    let value = Rc::new(Cell::new(0x42));
+    emu.add_peripheral(
+        0xFF7F..=0xFF7F,
+        alloc::boxed::Box::new(DebugPort(value.clone())),
+    );
+
+    let mut bus = borrow_cpu_bus!(emu);
+    assert_eq!(bus.read_without_dma_check(0xFF7F, false), 0x42);
+    assert_eq!(bus.read_without_dma_check(0xFF7E, false), 0xFF);
+
+    bus.write_without_dma_check(0xFF7F, 0x10, false);
+    assert_eq!(value.get(), 0x10);
+
+    // The write is let through to the built-in hardware
+    bus.write_without_dma_check(0xFF7F, 0x11, false);
+    assert_eq!(value.get(), 0x10);
+
+    // Peripherals take precedence over the built-in hardware
+    emu.add_peripheral(0xC000..=0xC0FF, alloc::boxed::Box::new(DebugPort(value)));
+    let mut bus = borrow_cpu_bus!(emu);
+    bus.write_without_dma_check(0xC000, 0x20, false);
+    assert_eq!(bus.read_without_dma_check(0xC000, false), 0x20);
+    assert_eq!(emu.wram[0], 0x00);
+}
+
+#[test]
+fn test_audio_sample_rate() {
+    let rom = test_rom(&[], false);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+    emu.set_audio_sample_rate(48000);
+
+    // 10 frames of 70224 dots, at ~4MHz
+    for _ in 0..70224 * 10 {
+        emu.clock();
+    }
+
+    let mut samples = [0i16; 20000];
+    let len = emu.drain_audio(&mut samples);
+    let expected = 48000 * 70224 * 10 / (4 << 20);
+    assert!((len / 2).abs_diff(expected) <= 1);
+}
+
+#[test]
+fn test_run_until_audio() {
+    let rom = test_rom(&[], false);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    // Nothing to wait for without audio
+    assert!(emu.run_until_audio(100).is_none());
+    assert_eq!(emu.clock_count, 0);
+
+    emu.set_audio_sample_rate(48000);
+    assert!(emu.run_until_audio(100).is_none());
+    let mut samples = [0i16; 1000];
+    assert_eq!(emu.drain_audio(&mut samples), 200);
+
+    // A tenth of a second spans a few frames
+    assert!(emu.run_until_audio(4800).is_some());
+    let mut samples = [0i16; 20000];
+    assert_eq!(emu.drain_audio(&mut samples), 9600);
+}
+
+#[test]
+fn test_unused_bits_read_back() {
+    let mut emu = Emulator::new(&test_rom(&[], false), None).unwrap();
+
+    // CGB registers are unmapped on DMG
+    let bus = borrow_cpu_bus!(emu);
+    for addr in [
+        0xFF4D, 0xFF4F, 0xFF50, 0xFF51, 0xFF55, 0xFF56, 0xFF68, 0xFF69, 0xFF6A, 0xFF6B, 0xFF6C,
+        0xFF70, 0xFF76, 0xFF77,
+    ] {
+        assert_eq!(bus.read_without_dma_check(addr, false), 0xFF, "{addr:04X}");
+    }
+
+    let mut emu = Emulator::new(&test_rom(&[], true), None).unwrap();
+
+    let mut bus = borrow_cpu_bus!(emu);
+    bus.write_without_dma_check(0xFF4F, 0x00, false);
+    bus.write_without_dma_check(0xFF68, 0x00, false);
+    bus.write_without_dma_check(0xFF70, 0x02, false);
+    for (addr, expected) in [
+        (0xFF4D, 0x7E),
+        (0xFF4F, 0xFE),
+        (0xFF50, 0xFF),
+        (0xFF51, 0xFF),
+        (0xFF54, 0xFF),
+        (0xFF68, 0x40),
+        (0xFF6C, 0xFE),
+        (0xFF70, 0xFA),
+    ] {
+        assert_eq!(
+            bus.read_without_dma_check(addr, false),
+            expected,
+            "{addr:04X}"
+        );
+    }
+}
+
+#[cfg(feature = "debugger")]
+#[test]
+fn test_breakpoint() {
+    let rom = test_rom(
+        &[
+            0x3E, 0x42, // ld a, 0x42
+            0x3C, // inc a
+            0x3C, // inc a
+            0x3C, // inc a
+        ],
+        false,
+    );
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    let run_until_breakpoint = |emu: &mut Emulator| loop {
+        if let StepResult::BreakpointHit(addr) = emu.step() {
+            break addr;
+        }
+    };
+
+    emu.add_breakpoint(0x103);
+    assert_eq!(run_until_breakpoint(&mut emu), 0x103);
+
+    // Only the first increment is executed, the one on the breakpoint isn't fetched yet
+    assert_eq!(emu.cpu().a, 0x43);
+    assert_eq!(emu.cpu().pc, 0x103);
+
+    // Resuming executes the instruction without hitting the breakpoint again
+    emu.add_breakpoint(0x104);
+    assert_eq!(run_until_breakpoint(&mut emu), 0x104);
+    assert_eq!(emu.cpu().a, 0x44);
+    assert_eq!(emu.cpu().pc, 0x104);
+}
+
+#[cfg(feature = "debugger")]
+#[test]
+fn test_breakpoint_timing() {
+    let rom = test_rom(
+        &[
+            0x3C, // inc a
+            0x18, 0xFD, // jr -3
+        ],
+        false,
+    );
+
+    // Counts the clocks until A wraps around to 0
+    let run = |emu: &mut Emulator| {
+        let mut clocks = 0;
+        while emu.cpu().a != 0 {
+            emu.clock();
+            clocks += 1;
+        }
+        clocks
+    };
+
+    let mut emu = Emulator::new(&rom, None).unwrap();
+    let expected = run(&mut emu);
+
+    // Breakpoints are only reported when the emulator isn't stepped
+    let mut emu = Emulator::new(&rom, None).unwrap();
+    emu.add_breakpoint(0x100);
+    assert_eq!(run(&mut emu), expected);
+    assert_eq!(emu.take_breakpoint_hit(), Some(0x100));
+    assert!(!emu.cpu().at_breakpoint());
+}
+
+#[cfg(feature = "debugger")]
+#[test]
+fn test_disassemble_rom_bank() {
+    // 8MB MBC5 cartridge, the largest one, to map a bank above 0xFF
+    let mut rom = alloc::vec![0u8; 0x8000 << 8];
+    rom[0x147] = 0x19;
+    rom[0x148] = 8;
+    set_header_checksum(&mut rom);
+    rom[0x100..0x10C].copy_from_slice(&[
+        0x3E, 0x34, // ld a, 0x34
+        0xEA, 0x00, 0x20, // ld [0x2000], a
+        0x3E, 0x01, // ld a, 0x01
+        0xEA, 0x00, 0x30, // ld [0x3000], a
+        0x18, 0xFE, // jr -2
+    ]);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    emu.add_breakpoint(0x10A);
+    while !matches!(emu.step(), StepResult::BreakpointHit(0x10A)) {}
+
+    assert_eq!(emu.current_rom_bank(), 0x134);
+
+    let disassembly = emu.disassemble(0, 0);
+    let (bank, _, _) = disassembly
+        .iter()
+        .find(|(_, addr, _)| *addr == 0x4000)
+        .unwrap();
+    assert_eq!(*bank, 0x134);
+}
+
+#[test]
+fn test_write_m_cycle() {
+    let rom = test_rom(
+        &[
+            0x21, 0x00, 0xC0, // ld hl, 0xC000
+            0x3E, 0x42, // ld a, 0x42
+            0x77, // ld [hl], a
+            0xEA, 0x01, 0xC0, // ld [0xC001], a
+            0x18, 0xFE, // jr -2
+        ],
+        false,
+    );
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    // PPU dot at which each byte was written
+    let mut written = [None; 2];
+    for dot in 0..200 {
+        emu.clock();
+
+        for (i, written) in written.iter_mut().enumerate() {
+            if written.is_none() && emu.peek(0xC000 + i as u16) == 0x42 {
+                *written = Some(dot);
+            }
+        }
+    }
+
+    // `ld [hl], a` writes on its 2nd and last M-cycle, then `ld [a16], a` writes on its 4th one
+    let (first, second) = (written[0].unwrap(), written[1].unwrap());
+    assert_eq!(second - first, 4 * 4);
+}
+
+#[test]
+fn test_vram_write_timing() {
+    /// Runs `code` from WRAM with its opcode fetched `m_cycles` M-cycles before the first one
+    /// to see mode 3 on line 2, then returns the first VRAM bytes once it's done
+    fn run_before_mode_3(code: &[u8], m_cycles: u32) -> [u8; 2] {
+        fn mode(emu: &mut Emulator) -> (u8, u8) {
+            (emu.peek(0xFF44), emu.peek(0xFF41) & 0x03)
+        }
+
+        fn m_cycle(emu: &mut Emulator) {
+            for _ in 0..4 {
+                emu.clock();
+            }
+        }
+
+        // Line 2 is used to skip the shorter first line after power on
+        fn line_2(code: &[u8]) -> Emulator {
+            let rom = test_rom(&[0x18, 0xFE], false); // jr -2
+            let mut emu = Emulator::new(&rom, None).unwrap();
+
+            let mut bus = borrow_cpu_bus!(emu);
+            for (i, &byte) in code.iter().chain(&[0x18, 0xFE]).enumerate() {
+                bus.write(0xC000 + i as u16, byte);
+            }
+
+            while emu.clock_count != 0 || mode(&mut emu) != (2, 2) {
+                emu.clock();
+            }
+            emu
+        }
+
+        // Mode 3 is found on another run first, so the CPU can be started before it
+        let mut probe = line_2(code);
+        let mut to_mode_3 = 0;
+        while mode(&mut probe) != (2, 3) {
+            m_cycle(&mut probe);
+            to_mode_3 += 1;
+        }
+
+        let mut emu = line_2(code);
+        for _ in 0..to_mode_3 - m_cycles - 1 {
+            m_cycle(&mut emu);
+        }
+
+        emu.cpu.pc = 0xC000;
+        emu.cpu.sp = 0x8002;
+        emu.cpu.cycles = 0;
+        (emu.cpu.b, emu.cpu.c) = (0x12, 0x34);
+        (emu.cpu.h, emu.cpu.l) = (0x80, 0x00);
+        emu.cpu.a = 0x42;
+        for _ in 0..8 {
+            m_cycle(&mut emu);
+        }
+
+        let vram = emu.snapshot_graphics().vram;
+        [vram[0], vram[1]]
+    }
+
+    // `ld [hl], a` is fetched, then writes on the next M-cycle, which is blocked in mode 3
+    let ld = [0x77];
+    assert_eq!(run_before_mode_3(&ld, 2), [0x42, 0x00]);
+    assert_eq!(run_before_mode_3(&ld, 1), [0x00, 0x00]);
+
+    // `push bc` writes each byte on its own M-cycle, after an internal one.
+    // Only the high byte lands if mode 3 starts in between
+    let push = [0xC5];
+    assert_eq!(run_before_mode_3(&push, 4), [0x34, 0x12]);
+    assert_eq!(run_before_mode_3(&push, 3), [0x00, 0x12]);
+    assert_eq!(run_before_mode_3(&push, 2), [0x00, 0x00]);
+}
+
+#[test]
+fn test_interrupt_dispatch_length() {
+    let mut rom = test_rom(&[], false);
+    rom[0x40..0x43].copy_from_slice(&[
+        0x00, // nop
+        0x18, 0xFE, // jr -2
+    ]);
+    rom[0x100..0x109].copy_from_slice(&[
+        0xAF, // xor a
+        0xE0, 0x0F, // ldh [rIF], a
+        0x3C, // inc a
+        0xE0, 0xFF, // ldh [rIE], a
+        0xFB, // ei
+        0x18, 0xFE, // jr -2
+    ]);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    let mut dispatch = None;
+    let mut handler = None;
+    for dot in 0..2 * 70224 {
+        let ime = emu.cpu.interrupt_master_enable;
+        emu.clock();
+
+        if dispatch.is_none() && ime && !emu.cpu.interrupt_master_enable {
+            // The interrupt is dispatched at the start of VBlank
+            assert_eq!(emu.peek(0xFF41) & 0x03, 1);
+            dispatch = Some(dot);
+        }
+
+        // PC points after the first opcode of the handler once it is fetched
+        if emu.cpu.pc == 0x41 {
+            handler = Some(dot);
+            break;
+        }
+    }
+
+    // 5 M-cycles between the dispatch and the fetch of the handler
+    assert_eq!(handler.unwrap() - dispatch.unwrap(), 5 * 4);
+}
+
+#[test]
+fn test_local_link() {
+    fn rom(data: u8, control: u8) -> alloc::vec::Vec<u8> {
+        test_rom(
+            &[
+                0x3E, data, // ld a, data
+                0xE0, 0x01, // ldh [rSB], a
+                0x3E, control, // ld a, control
+                0xE0, 0x02, // ldh [rSC], a
+                0x18, 0xFE, // jr -2
+            ],
+            false,
+        )
+    }
+
+    // The master uses the internal clock and the slave waits for it, in both directions
+    for master_first in [true, false] {
+        let mut master = Emulator::new(&rom(0x42, 0x81), None).unwrap();
+        let mut slave = Emulator::new(&rom(0x99, 0x80), None).unwrap();
+        LocalLink::connect(&mut master, &mut slave);
+
+        for _ in 0..70224 {
+            if master_first {
+                master.clock();
+                slave.clock();
+            } else {
+                slave.clock();
+                master.clock();
+            }
+        }
+
+        // The bytes were exchanged and both ends got the serial interrupt
+        assert_eq!(master.peek(0xFF01), 0x99);
+        assert_eq!(slave.peek(0xFF01), 0x42);
+        assert_eq!(master.peek(0xFF02) & 0x80, 0);
+        assert_eq!(slave.peek(0xFF02) & 0x80, 0);
+        assert_ne!(master.peek(0xFF0F) & 0x08, 0);
+        assert_ne!(slave.peek(0xFF0F) & 0x08, 0);
+    }
+
+    // Without a master, the transfer on the external clock never completes
+    let mut slave = Emulator::new(&rom(0x99, 0x80), None).unwrap();
+    let mut other = Emulator::new(&rom(0x42, 0x00), None).unwrap();
+    LocalLink::connect(&mut slave, &mut other);
+    for _ in 0..70224 {
+        slave.clock();
+        other.clock();
+    }
+    assert_eq!(slave.peek(0xFF01), 0x99);
+    assert_eq!(slave.peek(0xFF02) & 0x80, 0x80);
+    assert_eq!(slave.peek(0xFF0F) & 0x08, 0);
+}
+
+#[test]
+fn test_joypad_register() {
+    let rom = test_rom(&[], false);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    let select = |emu: &mut Emulator, data: u8| {
+        let mut bus = borrow_cpu_bus!(emu);
+        bus.write(0xFF00, data);
+    };
+
+    emu.set_joypad(JoypadState::A | JoypadState::START | JoypadState::LEFT);
+
+    // Buttons selected
+    select(&mut emu, 0x10);
+    assert_eq!(emu.peek(0xFF00), 0xD6);
+
+    // D-pad selected
+    select(&mut emu, 0x20);
+    assert_eq!(emu.peek(0xFF00), 0xED);
+
+    // Both groups selected
+    select(&mut emu, 0x00);
+    assert_eq!(emu.peek(0xFF00), 0xC4);
+
+    // Nothing selected
+    select(&mut emu, 0x30);
+    assert_eq!(emu.peek(0xFF00), 0xFF);
+
+    // Reads follow the buttons pressed after the selection
+    select(&mut emu, 0x20);
+    emu.set_joypad(JoypadState::UP | JoypadState::DOWN);
+    assert_eq!(emu.peek(0xFF00), 0xE3);
+
+    emu.set_filter_opposing_directions(true);
+    emu.set_joypad(JoypadState::UP | JoypadState::DOWN);
+    assert_eq!(emu.peek(0xFF00), 0xEF);
+}
+
+#[test]
+fn test_joypad_interrupt() {
+    let rom = test_rom(&[], false);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+    emu.interrupts.status = InterruptReg::empty();
+
+    let select = |emu: &mut Emulator, data: u8| {
+        let mut bus = borrow_cpu_bus!(emu);
+        bus.write(0xFF00, data);
+    };
+    let take_interrupt = |emu: &mut Emulator| {
+        let requested = emu.interrupts.status.contains(InterruptReg::JOYPAD);
+        emu.interrupts.status = InterruptReg::empty();
+        requested
+    };
+
+    // Only the D-pad is selected, so pressing A doesn't change the lines
+    select(&mut emu, 0x20);
+    emu.set_joypad(JoypadState::A);
+    assert!(!take_interrupt(&mut emu));
+
+    emu.set_joypad(JoypadState::A | JoypadState::UP);
+    assert!(take_interrupt(&mut emu));
+
+    // Releasing is a rising edge
+    emu.set_joypad(JoypadState::A);
+    assert!(!take_interrupt(&mut emu));
+
+    // Selecting the buttons while A is held pulls its line low
+    select(&mut emu, 0x10);
+    assert!(take_interrupt(&mut emu));
+
+    // B has its own line, but setting the same state again changes nothing
+    emu.set_joypad(JoypadState::A | JoypadState::B);
+    assert!(take_interrupt(&mut emu));
+    emu.set_joypad(JoypadState::A | JoypadState::B);
+    assert!(!take_interrupt(&mut emu));
+}
+
+#[test]
+fn test_infrared_port() {
+    let rom = test_rom(&[], true);
+
+    let mut a = Emulator::new(&rom, None).unwrap();
+    let mut b = Emulator::new(&rom, None).unwrap();
+    PairedIr::connect(&mut a, &mut b);
+
+    let write_rp = |emu: &mut Emulator, data: u8| {
+        let mut bus = borrow_cpu_bus!(emu);
+        bus.write(0xFF56, data);
+    };
+    let clock = |emu: &mut Emulator| {
+        for _ in 0..4 {
+            emu.clock();
+        }
+    };
+
+    // Nothing received while the LED of the other end is off
+    write_rp(&mut b, 0xC0);
+    clock(&mut b);
+    assert_eq!(b.peek(0xFF56), 0xFE);
+
+    write_rp(&mut a, 0x01);
+    clock(&mut b);
+    assert_eq!(b.peek(0xFF56), 0xFC);
+    assert_eq!(a.peek(0xFF56), 0x3F);
+
+    // DMG doesn't have the port
+    let mut dmg = Emulator::with_hardware_model(&rom, None, HardwareModel::Dmg).unwrap();
+    dmg.set_ir_transceiver(alloc::boxed::Box::new(LoopbackIr::default()));
+    write_rp(&mut dmg, 0xC1);
+    clock(&mut dmg);
+    assert_eq!(dmg.peek(0xFF56), 0xFF);
+}
+
+#[test]
+fn test_run_for_duration() {
+    let rom = test_rom(&[0x18, 0xFE], false); // jr -2
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    // A frame is 70224 clocks, or about 16742.7µs
+    emu.run_frame();
+    assert!(emu.run_for_duration(16_000).is_empty());
+    assert_eq!(emu.run_for_duration(743).len(), 1);
+
+    // Every frame done is returned
+    assert_eq!(emu.run_for_duration(3 * 16_743).len(), 3);
+
+    // The fractions of clocks add up, so a second is about 59.7 frames
+    let mut frames = 0;
+    for _ in 0..1_000_000 {
+        frames += emu.run_for_duration(1).len();
+    }
+    assert_eq!(frames, 59);
+}
+
+#[test]
+fn test_oam_dma_sources() {
+    use alloc::vec::Vec;
+
+    /// Copies a page to OAM with a DMA, and returns OAM afterward
+    fn run_dma(emu: &mut Emulator, source: u8) -> Vec<u8> {
+        borrow_cpu_bus!(emu).write(0xFF46, source);
+        for _ in 0..4 * 0xA2 {
+            emu.clock();
+        }
+
+        (0xFE00..0xFEA0)
+            .map(|addr| emu.ppu.read_oam(addr, true))
+            .collect()
+    }
+
+    // CGB MBC1 cartridge with 4 ROM banks, each one filled with its number
+    let mut rom = alloc::vec![0u8; 0x10000];
+    for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+        data.fill(bank as u8);
+    }
+    rom[0x100..0x150].fill(0);
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x80, 0xFF]); // jp 0xFF80
+    rom[0x143] = 0x80;
+    rom[0x147] = 0x01;
+    rom[0x148] = 0x01;
+    set_header_checksum(&mut rom);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    // The CPU waits in HRAM, which it can still read during the DMA
+    let mut bus = borrow_cpu_bus!(emu);
+    bus.write(0xFF80, 0x18); // jr -2
+    bus.write(0xFF81, 0xFE);
+    for _ in 0..100 {
+        emu.clock();
+    }
+
+    // The switched ROM bank is read through the mapper
+    borrow_cpu_bus!(emu).write(0x2000, 2);
+    assert_eq!(run_dma(&mut emu, 0x40), [2; 0xA0]);
+    borrow_cpu_bus!(emu).write(0x2000, 3);
+    assert_eq!(run_dma(&mut emu, 0x40), [3; 0xA0]);
+
+    // Different data in WRAM banks 1 and 3
+    let mut bus = borrow_cpu_bus!(emu);
+    for bank in [1, 3] {
+        bus.write(0xFF70, bank);
+        for addr in 0xD000..0xD0A0 {
+            bus.write(addr, bank * 0x10 + (addr & 0x0F) as u8);
+        }
+    }
+
+    // The selected WRAM bank is used, including through echo RAM
+    let bank_3: Vec<u8> = (0..0xA0).map(|i| 0x30 + (i & 0x0F)).collect();
+    assert_eq!(run_dma(&mut emu, 0xD0), bank_3);
+    assert_eq!(run_dma(&mut emu, 0xF0), bank_3);
+
+    borrow_cpu_bus!(emu).write(0xFF70, 1);
+    let bank_1: Vec<u8> = (0..0xA0).map(|i| 0x10 + (i & 0x0F)).collect();
+    assert_eq!(run_dma(&mut emu, 0xF0), bank_1);
+}
+
+#[test]
+fn test_oam_dma_bus_conflict() {
+    use alloc::vec::Vec;
+
+    // The CPU runs from HRAM, which is never blocked by the DMA
+    let dma_from_wram = |cgb: bool| {
+        let mut rom = test_rom(&[], cgb);
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x80, 0xFF]); // jp 0xFF80
+        rom[0x120] = 0x24;
+        let mut emu = Emulator::new(&rom, None).unwrap();
+
+        // The LCD is off so VRAM is never blocked by the PPU
+        let mut bus = borrow_cpu_bus!(emu);
+        bus.write(0xFF40, 0x00);
+        for i in 0..0xA0 {
+            bus.write(0xC100 + i, (i as u8).wrapping_mul(7) ^ 0x5A);
+        }
+        bus.write(0xC000, 0x42);
+        bus.write(0x8000, 0x81);
+        bus.write(0xFF80, 0x18); // jr -2
+        bus.write(0xFF81, 0xFE);
+        for _ in 0..4 * 4 {
+            emu.clock();
+        }
+        assert_eq!(emu.cpu.pc & 0xFF80, 0xFF80);
+
+        borrow_cpu_bus!(emu).write(0xFF46, 0xC1);
+        emu
+    };
+
+    // On DMG, WRAM is on the external bus with the cartridge.
+    // Reads on it return the byte being transferred, on each M-cycle
+    let mut emu = dma_from_wram(false);
+    let mut values = Vec::new();
+    for i in 0..0x9F {
+        for _ in 0..4 {
+            emu.clock();
+        }
+        let addr = if i % 2 == 0 { 0xC000 } else { 0x0120 };
+        values.push(borrow_cpu_bus!(emu).read(addr));
+    }
+
+    let expected: Vec<u8> = (0..0x9Fu8).map(|i| i.wrapping_mul(7) ^ 0x5A).collect();
+    assert_eq!(values, expected);
+
+    // VRAM has its own bus, and HRAM is inside the CPU
+    let mut bus = borrow_cpu_bus!(emu);
+    assert_eq!(bus.read(0x8000), 0x81);
+    assert_eq!(bus.read(0xFF80), 0x18);
+
+    // The conflict is over once the last byte is transferred
+    for _ in 0..4 {
+        emu.clock();
+    }
+    let mut bus = borrow_cpu_bus!(emu);
+    assert_eq!(bus.read(0xC000), 0x42);
+    assert_eq!(bus.read(0x0120), 0x24);
+
+    // CGB has a separate bus for WRAM, so the cartridge can still be read
+    let mut emu = dma_from_wram(true);
+    for _ in 0..4 {
+        emu.clock();
+    }
+    let mut bus = borrow_cpu_bus!(emu);
+    assert_eq!(bus.read(0xC000), 0x5A);
+    assert_eq!(bus.read(0x0120), 0x24);
+    assert_eq!(bus.read(0x8000), 0x81);
+}
+
+#[test]
+fn test_infrared_received() {
+    let rom = test_rom(&[], true);
+    let mut emu = Emulator::new(&rom, None).unwrap();
+
+    let read_rp = |emu: &mut Emulator, data: u8| {
+        borrow_cpu_bus!(emu).write(0xFF56, data);
+        for _ in 0..4 {
+            emu.clock();
+        }
+        emu.peek(0xFF56)
+    };
+
+    // No light is received by default
+    assert_eq!(read_rp(&mut emu, 0xC0), 0xFE);
+
+    emu.set_infrared_received(true);
+    assert_eq!(read_rp(&mut emu, 0xC0), 0xFC);
+    assert_eq!(read_rp(&mut emu, 0xC1), 0xFD);
+
+    // Nothing is received while reading is disabled
+    assert_eq!(read_rp(&mut emu, 0x00), 0x3E);
+
+    emu.set_infrared_received(false);
+    assert_eq!(read_rp(&mut emu, 0xC0), 0xFE);
+}
+
+#[test]
+fn test_wave_ram_access() {
+    // Plays the sample 4 of channel 3, in the byte 2 of wave RAM, and stops on the dot it's read
+    let play = |cgb: bool, accuracy: Accuracy| {
+        let rom = test_rom(&[], cgb);
+        let mut emu = Emulator::new(&rom, None).unwrap();
+        emu.set_accuracy(accuracy);
+
+        let mut bus = borrow_cpu_bus!(emu);
+        bus.write(0xFF26, 0x80);
+        for i in 0..16 {
+            bus.write(0xFF30 + i, 0x11 * i as u8);
+        }
+        bus.write(0xFF1A, 0x80);
+        bus.write(0xFF1D, 0x00);
+        bus.write(0xFF1E, 0x87);
+
+        // 6 dots of trigger delay, then 512 dots per sample
+        for _ in 0..6 + 512 * 4 {
+            emu.clock();
+        }
+        emu
+    };
+
+    // Without the quirk, the address is used
+    let mut emu = play(false, Accuracy::empty());
+    assert_eq!(borrow_cpu_bus!(emu).read(0xFF31), 0x11);
+
+    // With it, the byte being played is accessed
+    let mut emu = play(true, Accuracy::WAVE_RAM_ACCESS);
+    assert_eq!(borrow_cpu_bus!(emu).read(0xFF31), 0x22);
+    emu.clock();
+    assert_eq!(borrow_cpu_bus!(emu).read(0xFF31), 0x22);
+
+    // Only on the dot it's read on DMG
+    let mut emu = play(false, Accuracy::WAVE_RAM_ACCESS);
+    assert_eq!(borrow_cpu_bus!(emu).read(0xFF31), 0x22);
+    emu.clock();
+    assert_eq!(borrow_cpu_bus!(emu).read(0xFF31), 0xFF);
+}
@@ -0,0 +1,20 @@
+use alloc::boxed::Box;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemAccessKind {
+    Read,
+    Write,
+}
+
+/// Memory access done by the CPU, reported to the memory watch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: MemAccessKind,
+}
+
+/// Callback fired on CPU data accesses.
+/// Instruction fetches, including their operands, and DMA transfers are not reported
+/// since they would drown the accesses done by the program itself.
+pub type MemWatch = Box<dyn FnMut(MemAccess)>;
@@ -0,0 +1,25 @@
+use alloc::boxed::Box;
+use core::ops::RangeInclusive;
+
+/// Custom hardware mapped on the CPU bus, like a homebrew mapper or a debug port.
+/// Mapped peripherals are consulted before the built-in hardware, in the order they were added,
+/// and can let an access through to the next peripheral and then to the built-in hardware.
+pub trait Peripheral {
+    /// Returns `None` to let the access through
+    fn read(&self, addr: u16) -> Option<u8>;
+
+    /// Returns false to let the access through
+    fn write(&mut self, addr: u16, value: u8) -> bool;
+}
+
+/// Peripheral with the range of addresses it is consulted for
+pub struct MappedPeripheral {
+    pub(crate) range: RangeInclusive<u16>,
+    pub(crate) peripheral: Box<dyn Peripheral>,
+}
+
+impl MappedPeripheral {
+    pub fn new(range: RangeInclusive<u16>, peripheral: Box<dyn Peripheral>) -> Self {
+        Self { range, peripheral }
+    }
+}
@@ -1,6 +1,10 @@
+use super::color_correction::ColorCorrection;
 use super::fifo_mode::FifoMode;
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CgbPalette {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays"))]
     pub data: [u8; 0x40],
     pub autoincrement: bool,
     pub index: usize,
@@ -22,62 +26,86 @@ impl CgbPalette {
         self.index = (data & 0x3F) as usize;
     }
 
-    pub fn write_data(&mut self, data: u8, _mode: FifoMode) {
-        // TODO: Reimplement write lock
-        // match mode {
-        //     FifoMode::Drawing(_) => {
-        //         // Write blocked during rendering
-        //     }
-        //     _ => {
-        //         self.data[self.index] = data;
-        //     }
-        // };
-
-        self.data[self.index] = data;
-        if self.autoincrement {
-            // Note: Autoincrement happens even if write is blocked, this is not a bug
-            self.index += 1;
-            self.index &= 0x3F;
-        }
+    pub fn write_data(&mut self, data: u8, mode: FifoMode) {
+        match mode {
+            FifoMode::Drawing(_) => {
+                // Write blocked during rendering
+            }
+            _ => {
+                self.data[self.index] = data;
+
+                // Note: A blocked write doesn't increment the index
+                if self.autoincrement {
+                    self.index += 1;
+                    self.index &= 0x3F;
+                }
+            }
+        };
     }
 
     pub fn read_spec(&self) -> u8 {
-        (self.index as u8) | if self.autoincrement { 0x80 } else { 0x00 }
+        // Bit 6 is unused and reads as 1
+        0x40 | (self.index as u8) | if self.autoincrement { 0x80 } else { 0x00 }
     }
 
-    pub fn read_data(&self, _mode: FifoMode) -> u8 {
-        // TODO: Reimplement write lock
-        // match mode {
-        //     FifoMode::Drawing(_) => {
-        //         // Read blocked during rendering
-        //         0xFF
-        //     }
-        //     _ => self.data[self.index],
-        // }
-
-        self.data[self.index]
+    pub fn read_data(&self, mode: FifoMode) -> u8 {
         // Note: There is no autoincrement on read
+        match mode {
+            FifoMode::Drawing(_) => {
+                // Read blocked during rendering
+                0xFF
+            }
+            _ => self.data[self.index],
+        }
     }
 
-    pub fn get_rgb(&self, palette_index: usize, color_index: usize) -> [u8; 3] {
-        let mut pixel = [0u8; 3];
-
+    pub fn get_rgb(
+        &self,
+        palette_index: usize,
+        color_index: usize,
+        color_correction: ColorCorrection,
+    ) -> [u8; 3] {
         let lo = self.data[(palette_index << 3) | (color_index << 1)] as u16;
         let hi = self.data[(palette_index << 3) | (color_index << 1) | 1] as u16;
 
-        let mut color555 = (hi << 8) | lo;
+        let color555 = (hi << 8) | lo;
 
         let r555 = (color555 & 0x1f) as u8;
-        pixel[0] = (r555 << 3) | (r555 >> 2);
-        color555 >>= 5;
+        let g555 = ((color555 >> 5) & 0x1f) as u8;
+        let b555 = ((color555 >> 10) & 0x1f) as u8;
+
+        color_correction.convert(r555, g555, b555)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocked_during_drawing() {
+        let mut palette = CgbPalette::default();
+        palette.write_spec(0x82);
 
-        let g555 = (color555 & 0x1f) as u8;
-        pixel[1] = (g555 << 3) | (g555 >> 2);
-        color555 >>= 5;
+        palette.write_data(0x12, FifoMode::HBlank);
+        assert_eq!(palette.data[2], 0x12);
+        assert_eq!(palette.read_spec(), 0xC3);
 
-        let b555 = (color555 & 0x1f) as u8;
-        pixel[2] = (b555 << 3) | (b555 >> 2);
+        // The write is dropped, and the index isn't incremented
+        palette.write_data(0x34, FifoMode::Drawing(Default::default()));
+        assert_eq!(palette.data[3], 0xFF);
+        assert_eq!(palette.read_spec(), 0xC3);
 
-        pixel
+        palette.write_spec(0x02);
+        assert_eq!(
+            palette.read_data(FifoMode::Drawing(Default::default())),
+            0xFF
+        );
+        assert_eq!(
+            palette.read_data(FifoMode::OamScan(Default::default())),
+            0x12
+        );
+        assert_eq!(palette.read_data(FifoMode::VBlank), 0x12);
+        assert_eq!(palette.read_spec(), 0x42);
     }
 }
@@ -0,0 +1,63 @@
+/// Conversion of the CGB BGR555 colors to the 8 bits per channel of the frames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorCorrection {
+    /// Shifts each channel left by 3, so white is slightly grey
+    None,
+    /// Scales each channel to the full range by repeating its high bits in the low bits
+    #[default]
+    Simple,
+    /// Mixes the channels to look like the washed out colors of the GBC screen
+    GbcLcd,
+}
+
+impl ColorCorrection {
+    /// Converts the 5 bits channels of a color to RGB888
+    pub fn convert(self, r: u8, g: u8, b: u8) -> [u8; 3] {
+        match self {
+            Self::None => [r << 3, g << 3, b << 3],
+            Self::Simple => [
+                (r << 3) | (r >> 2),
+                (g << 3) | (g >> 2),
+                (b << 3) | (b >> 2),
+            ],
+            Self::GbcLcd => {
+                let (r, g, b) = (r as u16, g as u16, b as u16);
+
+                // From byuu's color emulation of the GBC screen
+                let mix = |value: u16| (value.min(960) >> 2) as u8;
+                [
+                    mix(r * 26 + g * 4 + b * 2),
+                    mix(g * 24 + b * 8),
+                    mix(r * 6 + g * 4 + b * 22),
+                ]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bright_red() {
+        assert_eq!(ColorCorrection::None.convert(31, 0, 0), [0xF8, 0x00, 0x00]);
+        assert_eq!(
+            ColorCorrection::Simple.convert(31, 0, 0),
+            [0xFF, 0x00, 0x00]
+        );
+
+        // Some red bleeds in the blue channel
+        assert_eq!(
+            ColorCorrection::GbcLcd.convert(31, 0, 0),
+            [0xC9, 0x00, 0x2E]
+        );
+
+        // White is never fully bright
+        assert_eq!(
+            ColorCorrection::GbcLcd.convert(31, 31, 31),
+            [0xF0, 0xF0, 0xF0]
+        );
+    }
+}
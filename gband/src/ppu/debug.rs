@@ -0,0 +1,487 @@
+use alloc::boxed::Box;
+use alloc::vec;
+
+use super::cgb_palette::CgbPalette;
+use super::color_correction::ColorCorrection;
+use super::tile;
+use super::{Ppu, FRAME_HEIGHT, FRAME_WIDTH};
+
+const TILE_SIZE: u16 = 16;
+const TILES_PER_BANK: u16 = 384;
+
+/// Fixed greyscale shades used by the debug views, indexed by color index.
+const DEBUG_PALETTE: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
+
+pub const TILES_VIEW_WIDTH: usize = 16 * 8;
+pub const TILES_VIEW_HEIGHT: usize = 24 * 8;
+
+pub const TILEMAP_VIEW_SIZE: usize = 32 * 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileMapSelect {
+    /// Tile map currently selected for the background by LCDC bit 3
+    Background,
+    /// Tile map currently selected for the window by LCDC bit 6
+    Window,
+}
+
+/// Area of a tile map that is visible on the screen.
+/// The background viewport wraps around the edges of the map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+/// Decoded OAM entry. Coordinates are the raw ones, so they are offset by (8, 16) from the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OamEntry {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+
+    /// BG and window colors 1-3 are drawn over the sprite
+    pub bg_priority: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    /// DMG palette, OBP0 or OBP1
+    pub dmg_palette: u8,
+    pub cgb_bank: u8,
+    pub cgb_palette: u8,
+
+    /// Whether the OAM scan would select this entry on the current scanline
+    pub selected: bool,
+}
+
+impl Ppu {
+    /// Decodes the 40 OAM entries. The selection takes the 10 sprites per scanline limit into account.
+    pub fn debug_oam(&self) -> [OamEntry; 40] {
+        let mut entries = [OamEntry::default(); 40];
+        let mut n_selected = 0;
+
+        for (entry, data) in entries.iter_mut().zip(self.oam.chunks_exact(4)) {
+            let attr = data[3];
+
+            let selected = n_selected < self.secondary_oam.len() && self.is_sprite_on_line(data[0]);
+            if selected {
+                n_selected += 1;
+            }
+
+            *entry = OamEntry {
+                y: data[0],
+                x: data[1],
+                tile: data[2],
+
+                bg_priority: attr & 0x80 > 0,
+                y_flip: attr & 0x40 > 0,
+                x_flip: attr & 0x20 > 0,
+                dmg_palette: (attr >> 4) & 1,
+                cgb_bank: (attr >> 3) & 1,
+                cgb_palette: attr & 0x7,
+
+                selected,
+            };
+        }
+
+        entries
+    }
+
+    pub fn vram(&self) -> &[u8; 0x4000] {
+        &self.vram
+    }
+
+    pub fn oam(&self) -> &[u8; 0xA0] {
+        &self.oam
+    }
+
+    /// Decodes the 8 CGB background palettes of 4 colors into RGB, with the selected color correction.
+    /// This reads the palette RAM directly, so it isn't blocked during drawing.
+    pub fn cgb_bg_colors(&self) -> [[(u8, u8, u8); 4]; 8] {
+        decode_palettes(&self.cgb_bg_palette, self.color_correction)
+    }
+
+    /// Decodes the 8 CGB sprite palettes of 4 colors into RGB, with the selected color correction.
+    /// This reads the palette RAM directly, so it isn't blocked during drawing.
+    pub fn cgb_obj_colors(&self) -> [[(u8, u8, u8); 4]; 8] {
+        decode_palettes(&self.cgb_obj_palette, self.color_correction)
+    }
+
+    /// Decodes a tile of the specified VRAM bank into its 2 bits color indices.
+    /// The tile index is the "0x8000 addressing" one, so tiles 0x100-0x17F can't be reached.
+    pub fn decode_tile(&self, bank: u8, index: u8) -> [[u8; 8]; 8] {
+        self.decode_tile_at(bank, index as u16)
+    }
+
+    /// Renders the 384 tiles of a VRAM bank in a 16x24 tiles grid, as a 128x192 RGBA buffer.
+    /// This reads VRAM directly, so it works in any mode and doesn't affect rendering.
+    pub fn debug_render_tiles(&self, bank: u8) -> Box<[u8]> {
+        let mut buffer = vec![0u8; TILES_VIEW_WIDTH * TILES_VIEW_HEIGHT * 4].into_boxed_slice();
+
+        for tile_idx in 0..TILES_PER_BANK {
+            let tile_x = (tile_idx % 16) as usize * 8;
+            let tile_y = (tile_idx / 16) as usize * 8;
+
+            for (y, row) in self.decode_tile_at(bank, tile_idx).iter().enumerate() {
+                for (x, color) in row.iter().enumerate() {
+                    let base = ((tile_y + y) * TILES_VIEW_WIDTH + tile_x + x) * 4;
+                    buffer[base..base + 3].copy_from_slice(&DEBUG_PALETTE[*color as usize]);
+
+                    // Alpha channel
+                    buffer[base + 3] = 0xFF;
+                }
+            }
+        }
+
+        buffer
+    }
+
+    /// Renders a full 256x256 tile map as an RGBA buffer using the current tile data addressing
+    /// mode, attributes and palettes, along with the area visible on the screen.
+    /// This doesn't affect the pixel fetcher or the frame being rendered.
+    pub fn debug_render_tilemap(&self, which: TileMapSelect) -> (Box<[u8]>, Viewport) {
+        let mut buffer = vec![0u8; TILEMAP_VIEW_SIZE * TILEMAP_VIEW_SIZE * 4].into_boxed_slice();
+
+        for tile_map_idx in 0..(32 * 32) {
+            let (idx, attr) = match which {
+                TileMapSelect::Background => (
+                    self.read_bg_tile_index(tile_map_idx),
+                    if self.cgb_mode {
+                        self.read_bg_tile_attributes(tile_map_idx)
+                    } else {
+                        0
+                    },
+                ),
+                TileMapSelect::Window => (
+                    self.read_win_tile_index(tile_map_idx),
+                    if self.cgb_mode {
+                        self.read_win_tile_attributes(tile_map_idx)
+                    } else {
+                        0
+                    },
+                ),
+            };
+
+            let bank = (attr >> 3) & 1;
+            let tile_x = (tile_map_idx & 0x1F) as usize * 8;
+            let tile_y = (tile_map_idx >> 5) as usize * 8;
+
+            for row in 0..8u8 {
+                // Y flip
+                let fetched_row = if attr & 0x40 > 0 { 7 - row } else { row };
+
+                let lo = self.read_bg_win_tile(bank, idx, fetched_row << 1);
+                let hi = self.read_bg_win_tile(bank, idx, (fetched_row << 1) | 1);
+                let mut pixels = tile::decode_tile_row(lo, hi);
+
+                // X flip
+                if attr & 0x20 > 0 {
+                    pixels.reverse();
+                }
+
+                for (x, color) in pixels.iter().enumerate() {
+                    let rgb = if self.cgb_mode {
+                        self.cgb_bg_palette.get_rgb(
+                            (attr & 0x7) as usize,
+                            *color as usize,
+                            self.color_correction,
+                        )
+                    } else {
+                        let index = (self.dmg_bg_palette >> (color << 1)) & 0x3;
+                        self.dmg_colorized_bg_palette[index as usize]
+                    };
+
+                    let base = ((tile_y + row as usize) * TILEMAP_VIEW_SIZE + tile_x + x) * 4;
+                    buffer[base..base + 3].copy_from_slice(&rgb);
+
+                    // Alpha channel
+                    buffer[base + 3] = 0xFF;
+                }
+            }
+        }
+
+        let viewport = match which {
+            TileMapSelect::Background => Viewport {
+                x: self.scroll_x,
+                y: self.scroll_y,
+                width: FRAME_WIDTH as u8,
+                height: FRAME_HEIGHT as u8,
+            },
+            TileMapSelect::Window => Viewport {
+                x: 0,
+                y: 0,
+                width: (FRAME_WIDTH as u8 + 7).saturating_sub(self.window_x),
+                height: (FRAME_HEIGHT as u8).saturating_sub(self.window_y),
+            },
+        };
+
+        (buffer, viewport)
+    }
+
+    fn decode_tile_at(&self, bank: u8, tile_idx: u16) -> [[u8; 8]; 8] {
+        let base = (((bank & 1) as u16) << 13) | (tile_idx * TILE_SIZE);
+        let mut tile = [[0u8; 8]; 8];
+
+        for (row, pixels) in tile.iter_mut().enumerate() {
+            let addr = base | ((row as u16) << 1);
+            let lo = self.read_vram_without_banking(addr);
+            let hi = self.read_vram_without_banking(addr | 1);
+
+            *pixels = tile::decode_tile_row(lo, hi);
+        }
+
+        tile
+    }
+}
+
+fn decode_palettes(
+    palette: &CgbPalette,
+    color_correction: ColorCorrection,
+) -> [[(u8, u8, u8); 4]; 8] {
+    let mut colors = [[(0, 0, 0); 4]; 8];
+
+    for (palette_index, palette_colors) in colors.iter_mut().enumerate() {
+        for (color_index, color) in palette_colors.iter_mut().enumerate() {
+            let [r, g, b] = palette.get_rgb(palette_index, color_index, color_correction);
+            *color = (r, g, b);
+        }
+    }
+
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(buffer: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let base = (y * TILES_VIEW_WIDTH + x) * 4;
+        buffer[base..base + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_decode_tile() {
+        let mut ppu = Ppu::new(true);
+
+        // Example tile from the pandocs
+        let data = [
+            0x3C, 0x7E, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x5E, 0x7E, 0x0A, 0x7C, 0x56,
+            0x38, 0x7C,
+        ];
+        ppu.vram[0x2000 + 0x20..0x2000 + 0x30].copy_from_slice(&data);
+
+        assert_eq!(
+            ppu.decode_tile(1, 2),
+            [
+                [0, 2, 3, 3, 3, 3, 2, 0],
+                [0, 3, 0, 0, 0, 0, 3, 0],
+                [0, 3, 0, 0, 0, 0, 3, 0],
+                [0, 3, 0, 0, 0, 0, 3, 0],
+                [0, 3, 1, 3, 3, 3, 3, 0],
+                [0, 1, 1, 1, 3, 1, 3, 0],
+                [0, 3, 1, 3, 1, 3, 2, 0],
+                [0, 2, 3, 3, 3, 2, 0, 0],
+            ]
+        );
+
+        // Same index on the other bank is untouched
+        assert_eq!(ppu.decode_tile(0, 2), [[0; 8]; 8]);
+        assert_eq!(&ppu.vram()[0x2020..0x2030], &data);
+    }
+
+    #[test]
+    fn test_debug_render_tilemap() {
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF47, 0xE4);
+        ppu.dmg_colorized_bg_palette = DEBUG_PALETTE;
+
+        // Tile 1 at 0x8010 and tile 0x81 at 0x8810 are fully color 3
+        // Tile 1 at 0x9010 is fully color 1
+        for row in 0..8 {
+            ppu.vram[0x0010 + row * 2] = 0xFF;
+            ppu.vram[0x0010 + row * 2 + 1] = 0xFF;
+            ppu.vram[0x0810 + row * 2] = 0xFF;
+            ppu.vram[0x0810 + row * 2 + 1] = 0xFF;
+            ppu.vram[0x1010 + row * 2] = 0xFF;
+        }
+
+        // Map 0x9800, tile (2, 1) uses tile 1 and tile (3, 1) uses tile 0x81
+        ppu.vram[0x1800 + 32 + 2] = 0x01;
+        ppu.vram[0x1800 + 32 + 3] = 0x81;
+
+        // Map 0x9C00, tile (0, 0) uses tile 1
+        ppu.vram[0x1C00] = 0x01;
+
+        ppu.write(0xFF42, 250);
+        ppu.write(0xFF43, 12);
+        ppu.write(0xFF4A, 100);
+        ppu.write(0xFF4B, 87);
+
+        // BG on 0x9800, window on 0x9C00, tile data on 0x8000
+        ppu.write(0xFF40, 0xF1);
+        let (map, viewport) = ppu.debug_render_tilemap(TileMapSelect::Background);
+        assert_eq!(map.len(), 256 * 256 * 4);
+        assert_eq!(&map[(8 * 256 + 16) * 4..][..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&map[(8 * 256 + 24) * 4..][..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&map[(15 * 256 + 31) * 4..][..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&map[(8 * 256 + 32) * 4..][..4], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&map[0..4], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            viewport,
+            Viewport {
+                x: 12,
+                y: 250,
+                width: 160,
+                height: 144
+            }
+        );
+
+        let (map, viewport) = ppu.debug_render_tilemap(TileMapSelect::Window);
+        assert_eq!(&map[0..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&map[(8 * 256 + 16) * 4..][..4], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            viewport,
+            Viewport {
+                x: 0,
+                y: 0,
+                width: 80,
+                height: 44
+            }
+        );
+
+        // Switch to 0x8800 addressing
+        ppu.write(0xFF40, 0xE1);
+        let (map, _) = ppu.debug_render_tilemap(TileMapSelect::Background);
+        assert_eq!(&map[(8 * 256 + 16) * 4..][..4], &[0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(&map[(8 * 256 + 24) * 4..][..4], &[0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_debug_render_tilemap_cgb_attributes() {
+        let mut ppu = Ppu::new(true);
+
+        // Palette 2: color 0 is black, color 1 is pure red
+        ppu.write(0xFF68, 0x80 | 0x10);
+        for data in [0x00, 0x00, 0x1F, 0x00] {
+            ppu.write(0xFF69, data);
+        }
+
+        // Tile 1 in bank 1 only has its top-left pixel set to color 1
+        ppu.vram[0x2010] = 0x80;
+
+        // Tile (1, 0) of map 0x9800 uses tile 1 from bank 1, palette 2, flipped on both axis
+        ppu.vram[0x1801] = 0x01;
+        ppu.vram[0x3801] = 0x08 | 0x02 | 0x20 | 0x40;
+
+        ppu.write(0xFF40, 0x91);
+        let (map, _) = ppu.debug_render_tilemap(TileMapSelect::Background);
+
+        assert_eq!(&map[(7 * 256 + 15) * 4..][..4], &[0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(&map[(7 * 256 + 14) * 4..][..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&map[8 * 4..][..4], &[0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_debug_oam() {
+        let mut ppu = Ppu::new(true);
+        ppu.y = 10;
+
+        ppu.oam[0..4].copy_from_slice(&[26, 8, 0x42, 0xFB]);
+        ppu.oam[4..8].copy_from_slice(&[100, 50, 0x01, 0x00]);
+
+        // 11 more sprites on the same line, only 9 of them fit in the 10 sprites limit
+        for i in 2..13 {
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[20, i as u8 * 8, 0, 0]);
+        }
+
+        let entries = ppu.debug_oam();
+
+        assert_eq!(
+            entries[0],
+            OamEntry {
+                y: 26,
+                x: 8,
+                tile: 0x42,
+                bg_priority: true,
+                y_flip: true,
+                x_flip: true,
+                dmg_palette: 1,
+                cgb_bank: 1,
+                cgb_palette: 3,
+                selected: true,
+            }
+        );
+        assert!(!entries[1].selected);
+        assert!(entries[2..11].iter().all(|e| e.selected));
+        assert!(!entries[11].selected);
+        assert!(!entries[12].selected);
+
+        // With 8x16 sprites, the second one now covers line 10 too and pushes another one out
+        ppu.write(0xFF40, 0x95);
+        ppu.oam[4] = 25;
+        let entries = ppu.debug_oam();
+        assert!(entries[1].selected);
+        assert!(entries[9].selected);
+        assert!(!entries[10].selected);
+    }
+
+    #[test]
+    fn test_debug_render_tiles() {
+        let mut ppu = Ppu::new(true);
+
+        // Tile 17 of bank 0, first row: 0 2 3 3 3 3 2 0
+        ppu.vram[17 * 16] = 0x3C;
+        ppu.vram[17 * 16 + 1] = 0x7E;
+
+        // Last tile of bank 1, last row: all color 1
+        ppu.vram[0x2000 + 383 * 16 + 14] = 0xFF;
+
+        let tiles = ppu.debug_render_tiles(0);
+        assert_eq!(tiles.len(), 128 * 192 * 4);
+
+        // Tile 17 is the second tile of the second tile row
+        assert_eq!(pixel(&tiles, 8, 8), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(&tiles, 9, 8), [0x55, 0x55, 0x55, 0xFF]);
+        assert_eq!(pixel(&tiles, 10, 8), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&tiles, 14, 8), [0x55, 0x55, 0x55, 0xFF]);
+        assert_eq!(pixel(&tiles, 8, 9), [0xFF, 0xFF, 0xFF, 0xFF]);
+
+        // Bank 1 data doesn't leak into bank 0
+        assert_eq!(pixel(&tiles, 127, 191), [0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let tiles = ppu.debug_render_tiles(1);
+        assert_eq!(pixel(&tiles, 120, 191), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(pixel(&tiles, 127, 191), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(pixel(&tiles, 127, 190), [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_cgb_palette_colors() {
+        let mut ppu = Ppu::new(true);
+
+        // BG palette 3, color 2: r=31, g=16, b=1
+        ppu.write(0xFF68, 0x80 | (3 << 3) | (2 << 1));
+        for data in [0x1F, 0x06] {
+            ppu.write(0xFF69, data);
+        }
+
+        // OBJ palette 7, color 1: r=0, g=31, b=8
+        ppu.write(0xFF6A, 0x80 | (7 << 3) | (1 << 1));
+        for data in [0xE0, 0x23] {
+            ppu.write(0xFF6B, data);
+        }
+
+        // The 5 bits are repeated in the low bits, so 31 gives 255
+        assert_eq!(ppu.cgb_bg_colors()[3][2], (0xFF, 0x84, 0x08));
+        assert_eq!(ppu.cgb_obj_colors()[7][1], (0x00, 0xFF, 0x42));
+
+        // The palette RAM starts white
+        assert_eq!(ppu.cgb_bg_colors()[0][0], (0xFF, 0xFF, 0xFF));
+    }
+}
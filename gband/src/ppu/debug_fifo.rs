@@ -0,0 +1,167 @@
+use alloc::vec::Vec;
+
+use super::fifo_mode::{FifoMode, PixelFetcherState};
+use super::pixel_fifo::PixelFifo;
+use super::Ppu;
+
+/// Pixel waiting in one of the pixel FIFOs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FifoPixel {
+    pub color: u8,
+    /// CGB palette, or OBP0/OBP1 for DMG sprites. Always 0 for the DMG background
+    pub palette: u8,
+    /// BG-to-OAM priority bit of the pixel
+    pub priority: bool,
+}
+
+impl FifoPixel {
+    fn decode(pixel: u16, is_sprite: bool, cgb_mode: bool) -> Self {
+        let palette = if cgb_mode {
+            pixel & 0x7
+        } else if is_sprite {
+            (pixel >> 4) & 1
+        } else {
+            0
+        };
+
+        Self {
+            color: ((pixel >> 8) & 3) as u8,
+            palette: palette as u8,
+            priority: pixel & 0x80 > 0,
+        }
+    }
+}
+
+/// State of the pixel fetcher during mode 3
+#[derive(Clone, Copy, Debug)]
+pub struct FetcherState {
+    pub step: PixelFetcherState,
+    pub fetcher_x: u8,
+    pub is_window: bool,
+    pub is_sprite: bool,
+    pub tile_idx: u8,
+    /// Pixels fetched so far, leftmost first. The attributes are only added when they are pushed
+    pub buffer: [FifoPixel; 8],
+}
+
+impl Ppu {
+    /// Returns the current mode along with its internal state
+    pub fn debug_fifo_mode(&self) -> &FifoMode {
+        &self.fifo_mode
+    }
+
+    /// Returns the state of the pixel fetcher, or `None` outside of mode 3
+    pub fn debug_fetcher(&self) -> Option<FetcherState> {
+        match &self.fifo_mode {
+            FifoMode::Drawing(state) => {
+                let mut buffer = [FifoPixel::default(); 8];
+                for (pixel, raw) in buffer.iter_mut().zip(state.buffer.iter().rev()) {
+                    *pixel = FifoPixel::decode(*raw, state.is_sprite, self.cgb_mode);
+                }
+
+                Some(FetcherState {
+                    step: state.pixel_fetcher,
+                    fetcher_x: state.fetcher_x,
+                    is_window: state.is_window,
+                    is_sprite: state.is_sprite,
+                    tile_idx: state.tile_idx,
+                    buffer,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the pixels waiting in the background FIFO, next one to be shifted out first
+    pub fn debug_bg_fifo(&self) -> Vec<FifoPixel> {
+        self.debug_fifo(&self.background_pixel_pipeline, false)
+    }
+
+    /// Returns the pixels waiting in the sprite FIFO, next one to be shifted out first
+    pub fn debug_sprite_fifo(&self) -> Vec<FifoPixel> {
+        self.debug_fifo(&self.sprite_pixel_pipeline, true)
+    }
+
+    fn debug_fifo(&self, fifo: &PixelFifo, is_sprite: bool) -> Vec<FifoPixel> {
+        // Pixels are shifted out from the end
+        fifo.fifo
+            .iter()
+            .rev()
+            .take(fifo.n_pixels as usize)
+            .map(|pixel| FifoPixel::decode(*pixel, is_sprite, self.cgb_mode))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_fifo() {
+        use crate::{HDma, InterruptState};
+
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = crate::bus::PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        // The whole background uses tile 0, whose first row is 0 0 2 2 1 1 3 3
+        let mut ppu = Ppu::new(false);
+        ppu.vram[0] = 0x0F;
+        ppu.vram[1] = 0x33;
+        let row = [0, 0, 2, 2, 1, 1, 3, 3];
+
+        assert!(ppu.debug_fetcher().is_none());
+        assert!(ppu.debug_bg_fifo().is_empty());
+
+        while ppu.debug_bg_fifo().is_empty() {
+            ppu.clock(&mut bus);
+        }
+
+        let fetcher = ppu.debug_fetcher().expect("the PPU should be in mode 3");
+        assert_eq!(fetcher.fetcher_x, 1);
+        assert!(!fetcher.is_window);
+        assert!(!fetcher.is_sprite);
+
+        // Taking a snapshot doesn't change the rendering
+        for _ in 0..3 {
+            let fifo = ppu.debug_bg_fifo();
+            assert!(fifo.len() < 8);
+            assert_eq!(
+                fifo.iter().map(|pixel| pixel.color).collect::<Vec<_>>(),
+                row[8 - fifo.len()..]
+            );
+            assert!(fifo
+                .iter()
+                .all(|pixel| pixel.palette == 0 && !pixel.priority));
+
+            ppu.clock(&mut bus);
+        }
+
+        assert!(ppu.debug_sprite_fifo().is_empty());
+    }
+
+    #[test]
+    fn test_debug_fifo_mode() {
+        use crate::{HDma, InterruptState};
+
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = crate::bus::PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut ppu = Ppu::new(false);
+        assert!(matches!(ppu.debug_fifo_mode(), FifoMode::OamScan(_)));
+
+        // The modes outside of mode 3 can be told apart
+        while !matches!(ppu.debug_fifo_mode(), FifoMode::HBlank) {
+            let drawing = matches!(ppu.debug_fifo_mode(), FifoMode::Drawing(_));
+            assert_eq!(ppu.debug_fetcher().is_some(), drawing);
+            ppu.clock(&mut bus);
+        }
+
+        while !matches!(ppu.debug_fifo_mode(), FifoMode::VBlank) {
+            ppu.clock(&mut bus);
+        }
+        assert_eq!(ppu.y, 144);
+    }
+}
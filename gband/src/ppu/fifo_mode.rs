@@ -1,4 +1,5 @@
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FifoMode {
     HBlank,
     VBlank,
@@ -7,6 +8,7 @@ pub enum FifoMode {
 }
 
 #[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OamScanState {
     pub oam_pointer: usize,
     pub secondary_oam_pointer: usize,
@@ -14,6 +16,7 @@ pub struct OamScanState {
 }
 
 #[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DrawingState {
     pub pixel_fetcher: PixelFetcherState,
     pub cycle: u8,
@@ -23,9 +26,15 @@ pub struct DrawingState {
 
     pub is_sprite: bool,
     pub sprite_idx: u8,
+    // Dots left to wait for the background fetch before fetching the sprite
+    pub sprite_penalty: u8,
+    // Last background or window tile a sprite waited on, which only happens once per tile
+    pub penalty_tile: Option<i16>,
+    pub background_fetch: BackgroundFetch,
 
     pub tile_idx: u8,
     pub tile_attr: u8,
+    pub tile_data_low: u8,
     pub buffer: [u16; 8],
 }
 
@@ -34,9 +43,51 @@ impl DrawingState {
         self.pixel_fetcher = Default::default();
         self.cycle = 0;
         self.tile_idx = 0;
+        self.tile_data_low = 0;
         self.buffer = Default::default();
     }
 
+    /// Pauses the background fetch to fetch a sprite
+    pub fn start_sprite_fetch(&mut self, sprite_idx: u8, penalty: u8) {
+        self.background_fetch = BackgroundFetch {
+            pixel_fetcher: self.pixel_fetcher,
+            cycle: self.cycle,
+            tile_idx: self.tile_idx,
+            tile_attr: self.tile_attr,
+            tile_data_low: self.tile_data_low,
+            buffer: self.buffer,
+        };
+        self.reset();
+
+        self.is_sprite = true;
+        self.sprite_idx = sprite_idx;
+        self.sprite_penalty = penalty;
+    }
+
+    /// Resumes the background fetch where it was paused
+    pub fn end_sprite_fetch(&mut self) {
+        let fetch = self.background_fetch;
+        self.pixel_fetcher = fetch.pixel_fetcher;
+        self.cycle = fetch.cycle;
+        self.tile_idx = fetch.tile_idx;
+        self.tile_attr = fetch.tile_attr;
+        self.tile_data_low = fetch.tile_data_low;
+        self.buffer = fetch.buffer;
+
+        self.is_sprite = false;
+    }
+
+    /// Applies the X flip, and adds the palette and priority bits to the fetched pixels
+    pub fn apply_tile_attributes(&mut self) {
+        if self.tile_attr & 0x20 > 0 {
+            self.buffer.reverse();
+        }
+
+        for b in &mut self.buffer {
+            *b |= self.tile_attr as u16;
+        }
+    }
+
     pub fn advance_fetcher_state(&mut self) {
         self.pixel_fetcher = match self.pixel_fetcher {
             PixelFetcherState::GetTile => PixelFetcherState::GetTileLow,
@@ -49,7 +100,21 @@ impl DrawingState {
     }
 }
 
-#[derive(Clone, Copy)]
+/// Progress of the background fetch, kept while a sprite is fetched
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackgroundFetch {
+    pub pixel_fetcher: PixelFetcherState,
+    pub cycle: u8,
+
+    pub tile_idx: u8,
+    pub tile_attr: u8,
+    pub tile_data_low: u8,
+    pub buffer: [u16; 8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PixelFetcherState {
     GetTile,
     GetTileLow,
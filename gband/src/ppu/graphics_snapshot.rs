@@ -0,0 +1,45 @@
+use alloc::boxed::Box;
+
+use super::Ppu;
+
+/// Graphics memory of the PPU at an instant: both VRAM banks, OAM and the palettes.
+/// Much lighter than a full state, for tools capturing tiles and sprites.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GraphicsSnapshot {
+    pub vram: Box<[u8; 0x4000]>,
+    pub oam: [u8; 0xa0],
+
+    pub dmg_bg_palette: u8,
+    pub dmg_obj_palette: [u8; 2],
+
+    /// Raw palette RAM, only meaningful in CGB mode
+    pub cgb_bg_palette: [u8; 0x40],
+    pub cgb_obj_palette: [u8; 0x40],
+}
+
+impl Ppu {
+    pub fn snapshot_graphics(&self) -> GraphicsSnapshot {
+        GraphicsSnapshot {
+            vram: Box::new(self.vram),
+            oam: self.oam,
+
+            dmg_bg_palette: self.dmg_bg_palette,
+            dmg_obj_palette: self.dmg_obj_palette,
+
+            cgb_bg_palette: self.cgb_bg_palette.data,
+            cgb_obj_palette: self.cgb_obj_palette.data,
+        }
+    }
+
+    /// Puts back the graphics memory of a snapshot. The rest of the PPU state is left as is.
+    pub fn restore_graphics(&mut self, snapshot: &GraphicsSnapshot) {
+        self.vram = *snapshot.vram;
+        self.oam = snapshot.oam;
+
+        self.dmg_bg_palette = snapshot.dmg_bg_palette;
+        self.dmg_obj_palette = snapshot.dmg_obj_palette;
+
+        self.cgb_bg_palette.data = snapshot.cgb_bg_palette;
+        self.cgb_obj_palette.data = snapshot.cgb_obj_palette;
+    }
+}
@@ -1,6 +1,7 @@
 use bitflags::bitflags;
 
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct LcdControl: u8 {
         const BACKGROUND_WINDOW_ENABLE_PRIORITY = 0x01;
         const OBJ_ENABLE = 0x02;
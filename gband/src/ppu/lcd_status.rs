@@ -3,6 +3,7 @@ use bitflags::bitflags;
 
 bitflags! {
     #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct LcdStatus: u8 {
         const MODE_LOW = 0x01;
         const MODE_HI = 0x02;
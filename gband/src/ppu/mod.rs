@@ -2,33 +2,86 @@ use core::num::Wrapping;
 
 use alloc::boxed::Box;
 use alloc::vec;
-use alloc::vec::Vec;
 
 mod cgb_palette;
+mod color_correction;
 mod fifo_mode;
+mod graphics_snapshot;
 mod lcd_control;
 mod lcd_status;
 mod palette_table;
 mod pixel_fifo;
+mod pixel_format;
+mod scanline_info;
+mod scanline_renderer;
+mod sprite_entry;
+mod tile;
+
+#[cfg(feature = "debugger")]
+mod debug;
+#[cfg(feature = "debug-ppu")]
+mod debug_fifo;
+
+#[cfg(feature = "debugger")]
+pub use debug::{OamEntry, TileMapSelect, Viewport};
+#[cfg(feature = "debug-ppu")]
+pub use debug_fifo::{FetcherState, FifoPixel};
 
 use cgb_palette::CgbPalette;
-pub(crate) use fifo_mode::FifoMode;
-use lcd_control::LcdControl;
-use lcd_status::LcdStatus;
+pub use color_correction::ColorCorrection;
+pub use fifo_mode::{FifoMode, PixelFetcherState};
+pub use graphics_snapshot::GraphicsSnapshot;
+pub use lcd_control::LcdControl;
+pub use lcd_status::LcdStatus;
+pub use palette_table::CompatPalette;
+pub use pixel_format::PixelFormat;
+pub use scanline_info::{ScanlineCallback, ScanlineHook, ScanlineInfo};
+pub use scanline_renderer::RenderMode;
 
 use crate::bus::PpuBus;
 use crate::InterruptReg;
 
 use self::{
-    fifo_mode::{DrawingState, OamScanState, PixelFetcherState},
+    fifo_mode::{DrawingState, OamScanState},
     pixel_fifo::PixelFifo,
+    sprite_entry::SpriteEntry,
 };
 
+/// Kind of bus access that triggers the DMG OAM corruption bug.
+/// See: https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OamCorruption {
+    Write,
+    Read,
+    ReadDuringIncDec,
+}
+
 pub const FRAME_WIDTH: usize = 160;
 pub const FRAME_HEIGHT: usize = 144;
 
-pub type Frame = Box<[u8; FRAME_WIDTH * FRAME_HEIGHT * 4]>;
+/// Frame of `FRAME_WIDTH` by `FRAME_HEIGHT` pixels, laid out in the selected `PixelFormat`
+pub type Frame = Box<[u8]>;
+
+/// Dots after which LY reads 0 during line 153
+const LAST_LINE_LY_DOTS: u16 = 4;
+
+/// Frame blending weight giving the same importance to both frames
+pub const DEFAULT_FRAME_BLEND: u8 = 128;
 
+/// Layers of the picture, which can be hidden for debugging
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
+
+// The serde traits are implemented below to check the frame once it is deserialized
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
 pub struct Ppu {
     cgb_mode: bool,
 
@@ -44,14 +97,19 @@ pub struct Ppu {
     scroll_x: u8,
     scroll_y: u8,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays"))]
     vram: [u8; 0x4000],
     vram_bank_register: bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays"))]
     oam: [u8; 0xa0],
-    secondary_oam: [u8; 40],
+    // Sprites selected for the current line, removed once they are fetched
+    secondary_oam: [Option<SpriteEntry>; 10],
 
     cgb_bg_palette: CgbPalette,
     cgb_obj_palette: CgbPalette,
 
+    // Games rewrite those between or even during scanlines for raster effects,
+    // so they must be read for each pixel when it is emitted and never cached.
     dmg_bg_palette: u8,
     dmg_obj_palette: [u8; 2],
 
@@ -67,7 +125,36 @@ pub struct Ppu {
     cycle: u16,
     paused_cycles: u32,
     fifo_mode: FifoMode,
+    // Set when a frame is done, until it is taken by `ready_frame`
+    frame_ready: bool,
+    // The first frame after the LCD is turned on is not displayed
+    hide_frame: bool,
+    // The LCD is blank while the CPU is in STOP mode
+    stopped: bool,
+    render_mode: RenderMode,
+    // Timing and interrupts are emulated, but nothing is drawn in the frames
+    #[cfg_attr(feature = "serde", serde(skip))]
+    skip_rendering: bool,
+
+    // The frame being drawn is kept so a restored state finishes it the same way
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed_slice"))]
     frame: Frame,
+    pixel_format: PixelFormat,
+    color_correction: ColorCorrection,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    scanline_hook: Option<ScanlineHook>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    scanline_callback: Option<ScanlineCallback>,
+
+    // Weight of the previous frame when blending frames, 0 disables blending
+    #[cfg_attr(feature = "serde", serde(skip))]
+    frame_blend: u8,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    previous_frame: Option<Frame>,
+    // Layers left out of the frames, one bit per `Layer`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hidden_layers: u8,
 }
 
 impl Default for Ppu {
@@ -90,10 +177,11 @@ impl Default for Ppu {
             vram: [0u8; 0x4000],
             vram_bank_register: false,
             oam: [0u8; 0xa0],
-            secondary_oam: [0u8; 40],
+            secondary_oam: [None; 10],
 
             lcd_control_reg: Default::default(),
-            lcd_status_reg: Default::default(),
+            // LY and LYC both start at 0
+            lcd_status_reg: LcdStatus::LYC_EQ_LC,
 
             // Boot ROM initializes the Background palettes to white
             cgb_bg_palette: CgbPalette {
@@ -117,7 +205,106 @@ impl Default for Ppu {
             cycle: 0,
             paused_cycles: 0,
             fifo_mode: Default::default(),
-            frame: allocate_new_frame(),
+            frame_ready: false,
+            hide_frame: false,
+            stopped: false,
+            render_mode: Default::default(),
+            skip_rendering: false,
+            frame: allocate_new_frame(Default::default()),
+            pixel_format: Default::default(),
+            color_correction: Default::default(),
+
+            scanline_hook: None,
+            scanline_callback: None,
+
+            frame_blend: 0,
+            previous_frame: None,
+            hidden_layers: 0,
+        }
+    }
+}
+
+/// The scanline callbacks aren't cloned
+#[cfg(feature = "serde")]
+impl serde::Serialize for Ppu {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Ppu::serialize(self, serializer)
+    }
+}
+
+/// A frame that doesn't match the pixel format is rejected, since pixels are written at fixed offsets
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Ppu {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let ppu = Ppu::deserialize(deserializer)?;
+        if ppu.frame.len() != FRAME_WIDTH * FRAME_HEIGHT * ppu.pixel_format.bytes_per_pixel() {
+            return Err(D::Error::invalid_length(
+                ppu.frame.len(),
+                &"a frame of the size of the pixel format",
+            ));
+        }
+
+        Ok(ppu)
+    }
+}
+
+impl Clone for Ppu {
+    fn clone(&self) -> Self {
+        Self {
+            cgb_mode: self.cgb_mode,
+
+            x: self.x,
+            y: self.y,
+            window_y_counter: self.window_y_counter,
+            window_y_flag: self.window_y_flag,
+            y_compare: self.y_compare,
+
+            window_x: self.window_x,
+            window_y: self.window_y,
+
+            scroll_x: self.scroll_x,
+            scroll_y: self.scroll_y,
+
+            vram: self.vram,
+            vram_bank_register: self.vram_bank_register,
+            oam: self.oam,
+            secondary_oam: self.secondary_oam,
+
+            lcd_control_reg: self.lcd_control_reg,
+            lcd_status_reg: self.lcd_status_reg,
+
+            cgb_bg_palette: self.cgb_bg_palette.clone(),
+            cgb_obj_palette: self.cgb_obj_palette.clone(),
+
+            dmg_bg_palette: self.dmg_bg_palette,
+            dmg_obj_palette: self.dmg_obj_palette,
+
+            dmg_colorized_bg_palette: self.dmg_colorized_bg_palette,
+            dmg_colorized_obj_palette: self.dmg_colorized_obj_palette,
+
+            background_pixel_pipeline: self.background_pixel_pipeline.clone(),
+            sprite_pixel_pipeline: self.sprite_pixel_pipeline.clone(),
+
+            cycle: self.cycle,
+            paused_cycles: self.paused_cycles,
+            fifo_mode: self.fifo_mode,
+            frame_ready: self.frame_ready,
+            hide_frame: self.hide_frame,
+            stopped: self.stopped,
+            render_mode: self.render_mode,
+            skip_rendering: self.skip_rendering,
+            frame: self.frame.clone(),
+            pixel_format: self.pixel_format,
+            color_correction: self.color_correction,
+
+            scanline_hook: None,
+            scanline_callback: None,
+
+            frame_blend: self.frame_blend,
+            previous_frame: self.previous_frame.clone(),
+            hidden_layers: self.hidden_layers,
         }
     }
 }
@@ -130,22 +317,96 @@ impl Ppu {
         }
     }
 
+    /// Restores a saved state, keeping the scanline callbacks and the hidden layers
+    pub(crate) fn load_state(&mut self, state: Self) {
+        let scanline_hook = self.scanline_hook.take();
+        let scanline_callback = self.scanline_callback.take();
+        let hidden_layers = self.hidden_layers;
+
+        *self = state;
+
+        self.scanline_hook = scanline_hook;
+        self.scanline_callback = scanline_callback;
+        self.hidden_layers = hidden_layers;
+    }
+
     pub fn set_dmg_colorized_palette(&mut self, title: &[u8; 16]) {
         let hash: Wrapping<u8> = title.iter().map(|x| Wrapping(*x)).sum();
 
         let palettes = palette_table::palette_fill_from_hash(hash.0, title[3]);
+        self.set_dmg_colorized_palettes(palettes);
+    }
 
+    /// Overrides the colorization of DMG games with one of the palettes selectable in the CGB boot ROM
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        let palettes = palette_table::palette_fill_from_compat(palette);
+        self.set_dmg_colorized_palettes(palettes);
+    }
+
+    /// Renders DMG games in greyscale, like on a DMG
+    pub fn set_greyscale_palette(&mut self) {
+        let palettes = palette_table::palette_fill_greyscale();
+        self.set_dmg_colorized_palettes(palettes);
+    }
+
+    fn set_dmg_colorized_palettes(&mut self, palettes: [[[u8; 3]; 4]; 3]) {
         self.dmg_colorized_bg_palette = palettes[0];
         self.dmg_colorized_obj_palette[0] = palettes[1];
         self.dmg_colorized_obj_palette[1] = palettes[2];
     }
 
+    /// Sets a callback fired every time a visible scanline is done drawing
+    pub fn set_scanline_hook(&mut self, hook: impl FnMut(u8, &ScanlineInfo) + 'static) {
+        self.scanline_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_scanline_hook(&mut self) {
+        self.scanline_hook = None;
+    }
+
+    /// Sets a callback fired every time a visible scanline is done drawing, with its pixels
+    pub fn set_scanline_callback(&mut self, callback: impl FnMut(u8, &[u8]) + 'static) {
+        self.scanline_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_scanline_callback(&mut self) {
+        self.scanline_callback = None;
+    }
+
+    /// Selects the renderer used to draw the scanlines, see `RenderMode`
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.render_mode = render_mode;
+    }
+
+    /// Disabling the rendering speeds up the emulation when the frames aren't displayed, like when fast-forwarding.
+    /// The timing and interrupts stay the same, but the frames are left blank.
+    pub fn set_rendering_enabled(&mut self, enabled: bool) {
+        self.skip_rendering = !enabled;
+    }
+
+    fn scanline_info(&self) -> ScanlineInfo {
+        ScanlineInfo {
+            scroll_x: self.scroll_x,
+            scroll_y: self.scroll_y,
+            window_x: self.window_x,
+            window_y: self.window_y,
+            lcd_control: self.lcd_control_reg.bits(),
+
+            dmg_bg_palette: self.dmg_bg_palette,
+            dmg_obj_palette: self.dmg_obj_palette,
+
+            cgb_bg_palette: self.cgb_bg_palette.data,
+            cgb_obj_palette: self.cgb_obj_palette.data,
+        }
+    }
+
     pub fn clock(&mut self, bus: &mut PpuBus) {
-        if !self.lcd_control_reg.contains(LcdControl::LCD_PPU_ENABLE) {
+        if !self.lcd_control_reg.contains(LcdControl::LCD_PPU_ENABLE) || self.stopped {
             // Continue cycling to push frames
             self.paused_cycles += 1;
             if self.paused_cycles >= 70224 {
                 self.paused_cycles = 0;
+                self.frame_ready = true;
             }
             // PPU is disabled, only make sure to return frames
             return;
@@ -153,6 +414,11 @@ impl Ppu {
 
         self.cycle += 1;
 
+        // LY becomes 0 early on the last line, which is compared to LYC again
+        if self.y == 153 && self.cycle == LAST_LINE_LY_DOTS && self.compare_ly() {
+            bus.request_interrupt(InterruptReg::LCD_STAT);
+        }
+
         if self.y < 144 {
             match self.cycle {
                 80 => {
@@ -193,6 +459,16 @@ impl Ppu {
                 }
                 154 => {
                     // End of the frame
+                    self.frame_ready = true;
+
+                    if self.hide_frame {
+                        // The LCD stays blank, like when it is off
+                        if !self.skip_rendering {
+                            self.frame.fill(0xFF);
+                        }
+                        self.hide_frame = false;
+                    }
+
                     self.y = 0;
                     self.window_y_counter = 0;
                     self.window_y_flag = false;
@@ -211,31 +487,143 @@ impl Ppu {
                 }
             };
 
-            if self.y == self.y_compare {
-                if self
-                    .lcd_status_reg
-                    .contains(LcdStatus::LYC_EQ_LC_INTERUPT_SOURCE)
-                {
-                    bus.request_interrupt(InterruptReg::LCD_STAT);
-                }
+            if self.compare_ly() {
+                bus.request_interrupt(InterruptReg::LCD_STAT);
             };
         };
 
         self.render(bus);
     }
 
-    pub fn ready_frame(&mut self) -> Option<Frame> {
-        let is_ready = if self.lcd_control_reg.contains(LcdControl::LCD_PPU_ENABLE) {
-            self.y == 0 && self.cycle == 0
+    /// Clocks `dots` dots, with the same result as calling `clock` that many times.
+    /// While the LCD is off or stopped, they are skipped at once, and so are the dots of HBlank and VBlank
+    /// up to the next line or LY change.
+    pub fn clock_n(&mut self, bus: &mut PpuBus, mut dots: u32) {
+        if !self.lcd_control_reg.contains(LcdControl::LCD_PPU_ENABLE) || self.stopped {
+            // Only the frames are paced, and a frame is only signaled once until it's taken
+            let paused_cycles = self.paused_cycles as u64 + dots as u64;
+            if paused_cycles >= 70224 {
+                self.frame_ready = true;
+            }
+            self.paused_cycles = (paused_cycles % 70224) as u32;
+            return;
+        }
+
+        while dots > 0 {
+            // Nothing is rendered in HBlank and VBlank, so only the dot of the next event needs to be clocked
+            if let FifoMode::HBlank | FifoMode::VBlank = self.fifo_mode {
+                let skipped = (self.dots_until_event() - 1).min(dots);
+                self.cycle += skipped as u16;
+                dots -= skipped;
+
+                if dots == 0 {
+                    break;
+                }
+            }
+
+            self.clock(bus);
+            dots -= 1;
+        }
+    }
+
+    /// Number of dots until `clock` does more than counting them: the end of the line,
+    /// LY changing early on the last line or the start of mode 3
+    fn dots_until_event(&self) -> u32 {
+        let event = if self.y == 153 && self.cycle < LAST_LINE_LY_DOTS {
+            LAST_LINE_LY_DOTS
+        } else if self.y < 144 && self.cycle < 80 {
+            80
         } else {
-            self.paused_cycles == 0
+            456
         };
 
-        if is_ready {
-            let new_frame = allocate_new_frame();
+        (event - self.cycle) as u32
+    }
+
+    /// Number of dots until the start of the next VBlank, which is a whole frame when it just started.
+    /// Returns 0 if the PPU is off or stopped, since it would never get there.
+    pub fn dots_until_vblank(&self) -> u32 {
+        if !self.lcd_control_reg.contains(LcdControl::LCD_PPU_ENABLE) || self.stopped {
+            return 0;
+        }
+
+        const FRAME_DOTS: u32 = 154 * 456;
+        const VBLANK_DOT: u32 = 144 * 456;
+
+        let dot = self.y as u32 * 456 + self.cycle as u32;
+        (VBLANK_DOT + FRAME_DOTS - dot - 1) % FRAME_DOTS + 1
+    }
+
+    /// FNV-1a hash of the frame being drawn, to compare the output of a ROM against a known-good one.
+    /// The frame is complete once VBlank starts.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn frame_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        self.frame.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    /// Clocks until the start of the next VBlank and returns the number of dots it took.
+    /// Returns 0 without clocking if the PPU is off or stopped, since it would never get there.
+    pub fn step_to_vblank(&mut self, bus: &mut PpuBus) -> u32 {
+        if !self.lcd_control_reg.contains(LcdControl::LCD_PPU_ENABLE) || self.stopped {
+            return 0;
+        }
+
+        let mut dots = 0;
+        loop {
+            self.clock(bus);
+            dots += 1;
+
+            if self.y == 144 && self.cycle == 0 {
+                return dots;
+            }
+        }
+    }
+
+    /// Pointer to the frame being drawn, so a frontend can read it without a copy,
+    /// like a WebAssembly view over the emulator's memory.
+    /// It is only valid until the next call to `ready_frame` or `set_pixel_format`,
+    /// which replace the frame buffer.
+    pub fn frame_ptr(&self) -> *const u8 {
+        self.frame.as_ptr()
+    }
+
+    /// Frame being drawn, in the current pixel format
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Length in bytes of the buffer behind `frame_ptr`
+    pub fn frame_len(&self) -> usize {
+        self.frame.len()
+    }
+
+    /// Returns the last frame if it wasn't taken yet
+    pub fn ready_frame(&mut self) -> Option<Frame> {
+        if self.frame_ready {
+            self.frame_ready = false;
+
+            let new_frame = allocate_new_frame(self.pixel_format);
 
             // Replace current frame with the newly allocated one
-            let frame = core::mem::replace(&mut self.frame, new_frame);
+            let mut frame = core::mem::replace(&mut self.frame, new_frame);
+
+            if self.skip_rendering {
+                return Some(frame);
+            }
+
+            if self.stopped {
+                // The LCD is white while stopped
+                frame.fill(0xFF);
+            }
+
+            if self.frame_blend != 0 {
+                self.blend_frame(&mut frame);
+            }
 
             Some(frame)
         } else {
@@ -243,15 +631,67 @@ impl Ppu {
         }
     }
 
+    /// Selects the byte layout of the frames. The frame being drawn is discarded.
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        if pixel_format != self.pixel_format {
+            self.pixel_format = pixel_format;
+            self.frame = allocate_new_frame(pixel_format);
+            self.previous_frame = None;
+        }
+    }
+
+    /// Selects how the CGB colors are converted to RGB
+    pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+        self.color_correction = color_correction;
+    }
+
+    /// Hides a layer of the frames for debugging, regardless of LCDC.
+    /// Only the mixing of the pixels is affected, so the timing of the PPU doesn't change.
+    /// A hidden background or window is drawn as color 0 of the first palette.
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        let mask = 1 << layer as u8;
+        if enabled {
+            self.hidden_layers &= !mask;
+        } else {
+            self.hidden_layers |= mask;
+        }
+    }
+
+    fn is_layer_hidden(&self, layer: Layer) -> bool {
+        self.hidden_layers & (1 << layer as u8) != 0
+    }
+
+    /// Blends each frame with the previous one to emulate the slow response of the LCD,
+    /// which games rely on for transparency effects by flickering sprites.
+    /// `factor` is the weight of the previous frame out of 256, and 0 disables blending.
+    pub fn set_frame_blend(&mut self, factor: u8) {
+        self.frame_blend = factor;
+
+        if factor == 0 {
+            self.previous_frame = None;
+        }
+    }
+
+    fn blend_frame(&mut self, frame: &mut Frame) {
+        let previous = self.previous_frame.get_or_insert_with(|| frame.clone());
+
+        let factor = self.frame_blend as u16;
+
+        // Every channel is a byte, so this doesn't depend on their order
+        for (current, previous) in frame.iter_mut().zip(previous.iter_mut()) {
+            let raw = *current;
+            *current = ((raw as u16 * (256 - factor) + *previous as u16 * factor) >> 8) as u8;
+
+            // Blend with the frame as it was rendered, so the effect doesn't accumulate
+            *previous = raw;
+        }
+    }
+
     pub fn write_vram(&mut self, addr: u16, data: u8) {
         match self.fifo_mode {
             FifoMode::Drawing(_) => {
                 // Calls are blocked during this mode
                 // Do nothing
-                // TODO: There are timing issues right now so the write block breaks rendering right now.
-                // Delete those lines when the timing issues are fixed
-                let addr = addr & 0x1FFF | self.get_current_vram_bank();
-                self.vram[addr as usize] = data;
             }
             _ => {
                 let addr = addr & 0x1FFF | self.get_current_vram_bank();
@@ -279,17 +719,101 @@ impl Ppu {
         &self.fifo_mode
     }
 
+    /// LCDC register
+    pub fn lcdc(&self) -> LcdControl {
+        self.lcd_control_reg
+    }
+
+    /// STAT register, with the current mode
+    pub fn stat(&self) -> LcdStatus {
+        LcdStatus::from_bits_truncate(self.read_lcd_status())
+    }
+
+    /// SCX and SCY registers, in that order
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scroll_x, self.scroll_y)
+    }
+
+    /// WX and WY registers, in that order
+    pub fn window(&self) -> (u8, u8) {
+        (self.window_x, self.window_y)
+    }
+
+    /// Starts the first line after the LCD is turned on. It skips the OAM scan,
+    /// so STAT reports mode 0 until the drawing starts, and the frame is not displayed.
+    pub fn enable(&mut self) {
+        self.fifo_mode = FifoMode::HBlank;
+        self.hide_frame = true;
+    }
+
+    /// Halts the PPU and blanks the LCD while the CPU is in STOP mode
+    pub fn set_stopped(&mut self, stopped: bool) {
+        self.stopped = stopped;
+    }
+
     pub fn disable(&mut self) {
         self.cycle = 0;
         self.window_y_flag = false;
         self.window_y_counter = 0;
         self.x = 0;
         self.y = 0;
+        self.compare_ly();
         self.fifo_mode = Default::default();
         self.background_pixel_pipeline = Default::default();
         self.sprite_pixel_pipeline = Default::default();
     }
 
+    /// Applies the OAM corruption bug to the row currently accessed by the OAM scan
+    pub(crate) fn corrupt_oam(&mut self, kind: OamCorruption) {
+        let row = match self.fifo_mode {
+            FifoMode::OamScan(OamScanState { oam_pointer, .. }) => oam_pointer >> 3,
+            _ => return,
+        };
+
+        // The first row is never corrupted
+        if !(1..20).contains(&row) {
+            return;
+        }
+
+        // The formulas work on 16 bits words, but they are bitwise so we can apply them to each byte
+        let current = row * 8;
+        let previous = current - 8;
+
+        // This doesn't happen in the first 4 rows and the last one.
+        // A regular read corruption happens afterward.
+        if kind == OamCorruption::ReadDuringIncDec && (4..19).contains(&row) {
+            let before = previous - 8;
+
+            // The first word of the previous row gets corrupted, then the whole row is copied
+            // to the current one and to the one before it
+            for i in 0..2 {
+                let a = self.oam[before + i];
+                let b = self.oam[previous + i];
+                let c = self.oam[current + i];
+                let d = self.oam[previous + 4 + i];
+
+                self.oam[previous + i] = (b & (a | c | d)) | (a & c & d);
+            }
+
+            self.oam.copy_within(previous..current, current);
+            self.oam.copy_within(previous..current, before);
+        }
+
+        for i in 0..2 {
+            let a = self.oam[current + i];
+            let b = self.oam[previous + i];
+            let c = self.oam[previous + 4 + i];
+
+            self.oam[current + i] = match kind {
+                OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+                OamCorruption::Read | OamCorruption::ReadDuringIncDec => b | (a & c),
+            };
+        }
+
+        // The last 3 words are copied from the previous row
+        self.oam.copy_within(previous + 2..current, current + 2);
+    }
+
     fn read_vram_unblocked(&self, addr: u16) -> u8 {
         let addr = addr & 0x1FFF | self.get_current_vram_bank();
         self.vram[addr as usize]
@@ -340,6 +864,9 @@ impl Ppu {
 
     pub fn write(&mut self, addr: u16, data: u8) {
         match addr {
+            0xFF4F | 0xFF68..=0xFF6C if !self.cgb_mode => {
+                // CGB registers
+            }
             0xFF40 => self.write_lcd_control(data),
             0xFF41 => self.write_lcd_status(data),
             0xFF42 => self.scroll_y = data,
@@ -347,7 +874,9 @@ impl Ppu {
             0xFF44 => {
                 // ly is Read-Only
             }
-            0xFF45 => self.y_compare = data,
+            0xFF45 => {
+                self.write_lyc(data);
+            }
             0xFF47 => self.dmg_bg_palette = data,
             0xFF48 | 0xFF49 => self.dmg_obj_palette[(addr & 1) as usize] = data,
             0xFF4A => self.window_y = data,
@@ -368,11 +897,15 @@ impl Ppu {
 
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
+            0xFF4F | 0xFF68..=0xFF6C if !self.cgb_mode => {
+                // CGB registers
+                0xFF
+            }
             0xFF40 => self.read_lcd_control(),
             0xFF41 => self.read_lcd_status(),
             0xFF42 => self.scroll_y,
             0xFF43 => self.scroll_x,
-            0xFF44 => self.y,
+            0xFF44 => self.ly(),
             0xFF45 => self.y_compare,
             0xFF47 => self.dmg_bg_palette,
             0xFF48 | 0xFF49 => self.dmg_obj_palette[(addr & 1) as usize],
@@ -390,9 +923,13 @@ impl Ppu {
             0xFF69 => self.cgb_bg_palette.read_data(self.fifo_mode),
             0xFF6A => self.cgb_obj_palette.read_spec(),
             0xFF6B => self.cgb_obj_palette.read_data(self.fifo_mode),
+            0xFF6C => {
+                // OPRI, only the OAM order priority used by CGB games is emulated
+                0xFE
+            }
             _ => {
-                // Address not recognised, do nothing
-                0
+                // Unused registers read as 0xFF
+                0xFF
             }
         }
     }
@@ -402,6 +939,37 @@ impl Ppu {
             LcdControl::from_bits(data).expect("any data should be valid for LCDC bitflags")
     }
 
+    /// Writes LYC, which is compared to LY right away.
+    /// Returns true if the STAT interrupt should be requested.
+    pub fn write_lyc(&mut self, data: u8) -> bool {
+        self.y_compare = data;
+        self.compare_ly() && self.is_enabled()
+    }
+
+    /// Updates the LY=LYC flag, which must be done whenever one of them changes.
+    /// Returns true if the STAT interrupt should be requested.
+    fn compare_ly(&mut self) -> bool {
+        let was_equal = self.lcd_status_reg.contains(LcdStatus::LYC_EQ_LC);
+        let is_equal = self.ly() == self.y_compare;
+        self.lcd_status_reg.set(LcdStatus::LYC_EQ_LC, is_equal);
+
+        // The interrupt is only requested when they become equal
+        is_equal
+            && !was_equal
+            && self
+                .lcd_status_reg
+                .contains(LcdStatus::LYC_EQ_LC_INTERUPT_SOURCE)
+    }
+
+    /// LY register, which differs from the line being drawn on line 153
+    fn ly(&self) -> u8 {
+        if self.y == 153 && self.cycle >= LAST_LINE_LY_DOTS {
+            0
+        } else {
+            self.y
+        }
+    }
+
     fn read_lcd_control(&self) -> u8 {
         self.lcd_control_reg.bits()
     }
@@ -419,8 +987,7 @@ impl Ppu {
     fn read_lcd_status(&self) -> u8 {
         let mut status_reg = self.lcd_status_reg;
 
-        // Those bits are constantly changed, so might as well update them only when needed
-        status_reg.set(LcdStatus::LYC_EQ_LC, self.y == self.y_compare);
+        // The mode is constantly changed, so might as well update it only when needed
         status_reg.set_mode(self.fifo_mode);
 
         status_reg.bits()
@@ -438,72 +1005,75 @@ impl Ppu {
             }) => {
                 if self.cycle & 1 == 0 {
                     // On even cycle, fetch the y value and check if it's visible
-                    let y = self.oam[*oam_pointer];
-
-                    let sprite_size = if self.lcd_control_reg.contains(LcdControl::OBJ_SIZE) {
-                        16
-                    } else {
-                        8
-                    };
-
-                    // The index is y + 16, so the sprite can be hidden off at 0. This is why we add 16 here
-                    let y_remainder = self.y.wrapping_sub(y).wrapping_add(16);
-
-                    *is_visible = (y_remainder < sprite_size) && (self.oam[*oam_pointer + 1] > 0);
+                    *is_visible = self.is_sprite_on_line(self.oam[*oam_pointer]);
                 } else {
                     // On odd cycle, copy it to the secondary OAM
                     if *is_visible {
                         // Line is visible
-                        if *secondary_oam_pointer < self.secondary_oam.len() {
-                            self.secondary_oam[*secondary_oam_pointer..*secondary_oam_pointer + 4]
-                                .copy_from_slice(&self.oam[*oam_pointer..*oam_pointer + 4]);
-                            *secondary_oam_pointer += 4;
+                        // Sprites past the 10th one are ignored
+                        if let Some(entry) = self.secondary_oam.get_mut(*secondary_oam_pointer) {
+                            let data =
+                                <&[u8; 4]>::try_from(&self.oam[*oam_pointer..*oam_pointer + 4])
+                                    .expect("OAM entries should always be 4 bytes");
+                            *entry = Some(data.into());
+                            *secondary_oam_pointer += 1;
                         }
                     }
 
                     *oam_pointer += 4
                 }
             }
+            FifoMode::Drawing(_) if self.render_mode == RenderMode::Scanline => {
+                self.clock_scanline_renderer(bus, &mut fifo_mode);
+            }
             FifoMode::Drawing(state) => {
-                // if self
-                //     .lcd_control_reg
-                //     .contains(LcdControl::BACKGROUND_WINDOW_ENABLE_PRIORITY)
-                // {
-                // NOTE: assuming non-GBC mode only for now
+                // The background and the window are always fetched,
+                // LCDC.0 is applied when mixing the pixels
 
                 // Check for window
                 if self.y == self.window_y {
                     self.window_y_flag = true;
                 }
 
-                if !state.is_window && self.lcd_control_reg.contains(LcdControl::WINDOW_ENABLE) {
-                    if self.window_y_flag && self.x.wrapping_add(7) >= self.window_x {
-                        // We start rendering the window
-                        // We flush the entire state and signal that we start to render the window
-                        state.reset();
+                if !state.is_window
+                    && self.lcd_control_reg.contains(LcdControl::WINDOW_ENABLE)
+                    && self.window_y_flag
+                    && self.x.wrapping_add(7) >= self.window_x
+                {
+                    // We start rendering the window
+                    // We flush the entire state and signal that we start to render the window.
+                    // No pixel is emitted until the window tile is fetched, which is the 6 dots penalty.
+                    state.reset();
+
+                    state.is_window = true;
+                    state.fetcher_x = 0;
+                    state.background_fetch = Default::default();
+                    state.penalty_tile = None;
 
-                        state.is_window = true;
-                        state.fetcher_x = 0;
+                    self.background_pixel_pipeline.empty();
+                }
 
-                        self.background_pixel_pipeline.empty();
-                    }
+                if state.is_sprite && state.pixel_fetcher == PixelFetcherState::Push {
+                    // The next sprite or the background fetch resumes on the same dot
+                    self.push_sprite(state);
                 }
 
                 // Check for sprites
                 if !state.is_sprite && self.lcd_control_reg.contains(LcdControl::OBJ_ENABLE) {
                     // This condition is only for when on DMG!
-                    for (index, sprite) in self.secondary_oam.chunks_exact(4).enumerate() {
-                        let sprite = <&[u8; 4]>::try_from(sprite)
-                            .expect("secondary OAM should always be chunks of 4");
+                    for (index, sprite) in self.secondary_oam.iter().enumerate() {
+                        let sprite = match sprite {
+                            Some(sprite) => sprite,
+                            None => continue,
+                        };
 
                         // The sprite address is x + 8, so it can be hidden if set at 0
-                        let x_remainder = self.x.wrapping_sub(sprite[1]).wrapping_add(8);
+                        let x_remainder = self.x.wrapping_sub(sprite.x).wrapping_add(8);
                         if x_remainder < 8 {
-                            // Start a sprite fetch
-                            state.reset();
-
-                            state.is_sprite = true;
-                            state.sprite_idx = (index << 2) as u8;
+                            // Start a sprite fetch, which takes 6 dots after waiting for the background fetch.
+                            // Sprites at X = 0 are never fetched, so their penalty isn't applied.
+                            let penalty = self.sprite_alignment_penalty(state, sprite.x);
+                            state.start_sprite_fetch(index as u8, penalty);
 
                             break;
                         }
@@ -511,6 +1081,10 @@ impl Ppu {
                 }
 
                 match state.pixel_fetcher {
+                    _ if state.is_sprite && state.sprite_penalty > 0 => {
+                        // Waiting for the background fetch to be done
+                        state.sprite_penalty -= 1;
+                    }
                     PixelFetcherState::GetTile => {
                         // Get the tile used in this part of the map
                         // Here we use a specific fetcher indexing
@@ -518,10 +1092,8 @@ impl Ppu {
                         if state.cycle == 0 {
                             (state.tile_idx, state.tile_attr) = if state.is_sprite {
                                 // For sprites, we simply fetch it from the OAM entry
-                                (
-                                    self.secondary_oam[(state.sprite_idx + 2) as usize],
-                                    self.secondary_oam[(state.sprite_idx + 3) as usize],
-                                )
+                                let sprite = self.fetched_sprite(state);
+                                (sprite.tile, sprite.attr)
                             } else if state.is_window {
                                 // For window, we use the internal window Y counter and the X fetch counter
                                 let x_index = (state.fetcher_x) & 0x1F;
@@ -574,53 +1146,23 @@ impl Ppu {
                         }
                     }
                     PixelFetcherState::Push => {
-                        // X flip
-                        if state.tile_attr & 0x20 > 0 {
-                            state.buffer.reverse();
-                        }
-
-                        // Add palette and priority bits
-                        for b in &mut state.buffer {
-                            *b |= state.tile_attr as u16;
-                        }
-
-                        if state.is_sprite {
-                            // Add sprite index
-                            for b in &mut state.buffer {
-                                *b |= (state.sprite_idx as u16) << 12;
-                            }
-
-                            self.sprite_pixel_pipeline.load(state.buffer, self.cgb_mode);
-
-                            if self.x == 0 {
-                                self.sprite_pixel_pipeline
-                                    .drain(8 - self.secondary_oam[(state.sprite_idx + 1) as usize]);
+                        // Background pushes are retried every dot until the pipeline is empty to load it
+                        if self.background_pixel_pipeline.is_empty() {
+                            state.apply_tile_attributes();
+                            self.background_pixel_pipeline.load(state.buffer, false);
+
+                            if !state.is_window && state.fetcher_x == 0 {
+                                // The fine scroll is only applied to the first tile of the scanline.
+                                // Changing it afterward doesn't move the line.
+                                self.background_pixel_pipeline.drain(self.scroll_x & 0x7);
+                            } else if state.is_window && self.x == 0 {
+                                self.background_pixel_pipeline
+                                    .drain(7u8.wrapping_sub(self.window_x) & 0x7);
                             }
 
-                            state.is_sprite = false;
-
-                            // Remove the sprite
-                            self.secondary_oam[(state.sprite_idx + 1) as usize] = 0;
-                        } else {
-                            if self.background_pixel_pipeline.is_empty() {
-                                // Hang until pipeline is empty to load it
-                                self.background_pixel_pipeline.load(state.buffer, false);
-
-                                if !state.is_window {
-                                    self.background_pixel_pipeline
-                                        .drain((self.scroll_x.wrapping_add(self.x)) & 0x7);
-                                } else {
-                                    if self.x == 0 {
-                                        self.background_pixel_pipeline
-                                            .drain(7u8.wrapping_sub(self.window_x) & 0x7);
-                                    }
-                                }
-
-                                state.fetcher_x += 1;
-                            }
+                            state.fetcher_x += 1;
+                            state.advance_fetcher_state()
                         }
-
-                        state.advance_fetcher_state()
                     }
                 }
 
@@ -629,105 +1171,16 @@ impl Ppu {
                     let background_pixel = self.background_pixel_pipeline.pop();
                     let sprite_pixel = self.sprite_pixel_pipeline.pop();
 
-                    let sprite_palette = (sprite_pixel as usize & 0x10) >> 4;
+                    let pixel = self.mix_pixels(background_pixel, sprite_pixel, state.is_window);
 
-                    let background_priority = if self.cgb_mode {
-                        if !self
-                            .lcd_control_reg
-                            .contains(LcdControl::BACKGROUND_WINDOW_ENABLE_PRIORITY)
-                        {
-                            // If LCDC.0 is on, sprite always have priority
-                            false
-                        } else if (background_pixel & 0x80) == 0x80 {
-                            // If the background specifies priority, it has priority
-                            true
-                        } else {
-                            // Else, the priority is determined from the sprite attibutes
-                            (sprite_pixel & 0x80) == 0x80
-                        }
-                    } else {
-                        (sprite_pixel & 0x80) == 0x80
-                    };
+                    self.write_pixel(self.x as usize, pixel);
 
-                    let pixel = if self.cgb_mode {
-                        if (sprite_pixel & 0x300 == 0)
-                            || (background_priority && (background_pixel & 0x300 != 0))
-                        {
-                            // Render the background pixel
-                            self.cgb_bg_palette.get_rgb(
-                                background_pixel as usize & 0x7,
-                                (background_pixel as usize >> 8) & 3,
-                            )
-                        } else {
-                            // Rendering the sprite pixel
-                            self.cgb_obj_palette.get_rgb(
-                                sprite_pixel as usize & 0x7,
-                                (sprite_pixel as usize >> 8) & 3,
-                            )
-                        }
-                    } else {
-                        if (sprite_pixel & 0x300 == 0)
-                            || (background_priority && (background_pixel & 0x300 != 0))
-                        {
-                            // Pixel is transparent or under the background. Rendering background instead
-                            // Index the pixel in the palette
-                            if self
-                                .lcd_control_reg
-                                .contains(LcdControl::BACKGROUND_WINDOW_ENABLE_PRIORITY)
-                            {
-                                let index = (self.dmg_bg_palette
-                                    >> (((background_pixel >> 8) as u8 & 3) << 1))
-                                    & 0x3;
-                                self.dmg_colorized_bg_palette[index as usize]
-                            } else {
-                                // Renders white if background rendering is disabled
-                                [0xFF, 0xFF, 0xFF]
-                            }
-                        } else {
-                            // Rendering the sprite pixel
-                            // Index the pixel in the palette
-                            let index = (self.dmg_obj_palette[sprite_palette]
-                                >> (((sprite_pixel >> 8) as u8 & 3) << 1))
-                                & 0x3;
+                    self.x += 1;
 
-                            self.dmg_colorized_obj_palette[sprite_palette][index as usize]
-                        }
+                    if self.x >= FRAME_WIDTH as u8 {
+                        self.enter_hblank(bus, state.is_window);
+                        fifo_mode = FifoMode::HBlank;
                     };
-
-                    let base = ((self.y as usize) * FRAME_WIDTH + (self.x as usize)) * 4;
-                    if base + 3 < self.frame.len() {
-                        self.frame[base..base + 3].copy_from_slice(&pixel);
-
-                        // Alpha channel
-                        self.frame[base + 3] = 0xff;
-
-                        self.x += 1;
-
-                        if self.x >= FRAME_WIDTH as u8 {
-                            // We enter HBlank here
-
-                            // Reset some buffers
-                            self.background_pixel_pipeline = Default::default();
-                            self.sprite_pixel_pipeline = Default::default();
-                            self.secondary_oam = [0u8; 40];
-
-                            if state.is_window {
-                                self.window_y_counter += 1;
-                            };
-
-                            fifo_mode = FifoMode::HBlank;
-
-                            if self
-                                .lcd_status_reg
-                                .contains(LcdStatus::HBANLK_INTERUPT_SOURCE)
-                            {
-                                bus.request_interrupt(InterruptReg::LCD_STAT);
-                            }
-
-                            // Signal to the CPU we are in HBlank for HDMA transfer
-                            bus.set_hdma_hblank(true);
-                        };
-                    }
                 }
             }
             _ => {
@@ -738,25 +1191,183 @@ impl Ppu {
         self.fifo_mode = fifo_mode;
     }
 
-    fn read_bg_win_tile(&self, bank: u8, id: u8, offset: u8) -> u8 {
-        // See: https://gbdev.io/pandocs/Tile_Data.html
-        if self
-            .lcd_control_reg
-            .contains(LcdControl::BACKGROUND_WINDOW_TILE_DATA_AREA)
-        {
-            self.read_obj_tile(bank, id, offset)
+    /// Mixes the background and sprite pixels into the final color,
+    /// using the palettes and the priority rules of the current mode
+    fn mix_pixels(&self, background_pixel: u16, sprite_pixel: u16, is_window: bool) -> [u8; 3] {
+        let background_layer = if is_window {
+            Layer::Window
         } else {
-            let is_id_negative = id & 0x80 == 0x80;
+            Layer::Background
+        };
+        let background_pixel = if self.is_layer_hidden(background_layer) {
+            0
+        } else {
+            background_pixel
+        };
+        let sprite_pixel = if self.is_layer_hidden(Layer::Sprites) {
+            0
+        } else {
+            sprite_pixel
+        };
 
-            let addr_to_read = if !is_id_negative {
-                (0x9000 | ((id as u16) << 4) | (offset as u16)) & 0x1FFF | ((bank as u16) << 13)
+        let background_priority = if self.cgb_mode {
+            if !self
+                .lcd_control_reg
+                .contains(LcdControl::BACKGROUND_WINDOW_ENABLE_PRIORITY)
+            {
+                // If LCDC.0 is on, sprite always have priority
+                false
+            } else if (background_pixel & 0x80) == 0x80 {
+                // If the background specifies priority, it has priority
+                true
             } else {
-                0x8800
-                    | (((id as u16) << 4) & 0x7FF)
-                    | (offset as u16) & 0x1FFF
-                    | ((bank as u16) << 13)
+                // Else, the priority is determined from the sprite attibutes
+                (sprite_pixel & 0x80) == 0x80
+            }
+        } else {
+            (sprite_pixel & 0x80) == 0x80
+        };
+
+        if self.cgb_mode {
+            if (sprite_pixel & 0x300 == 0)
+                || (background_priority && (background_pixel & 0x300 != 0))
+            {
+                // Render the background pixel
+                self.cgb_bg_palette.get_rgb(
+                    background_pixel as usize & 0x7,
+                    (background_pixel as usize >> 8) & 3,
+                    self.color_correction,
+                )
+            } else {
+                // Rendering the sprite pixel, with one of the 8 OBJ palettes from attributes bits 0-2
+                self.cgb_obj_palette.get_rgb(
+                    sprite_pixel as usize & 0x7,
+                    (sprite_pixel as usize >> 8) & 3,
+                    self.color_correction,
+                )
+            }
+        } else {
+            // On DMG, LCDC.0 blanks the background and the window, which then behave as color 0
+            let background_enabled = self
+                .lcd_control_reg
+                .contains(LcdControl::BACKGROUND_WINDOW_ENABLE_PRIORITY);
+            let background_color = if background_enabled {
+                (background_pixel >> 8) as u8 & 3
+            } else {
+                0
             };
-            self.read_vram_without_banking(addr_to_read)
+
+            if (sprite_pixel & 0x300 == 0) || (background_priority && background_color != 0) {
+                // Pixel is transparent or under the background. Rendering background instead.
+                // Sprite color 0 is transparent before OBP0/OBP1 is looked up, so their bits 0-1 are unused
+                // Index the pixel in the palette
+                if background_enabled {
+                    let index = (self.dmg_bg_palette >> (background_color << 1)) & 0x3;
+                    self.dmg_colorized_bg_palette[index as usize]
+                } else {
+                    // Renders white if background rendering is disabled
+                    [0xFF, 0xFF, 0xFF]
+                }
+            } else {
+                // Rendering the sprite pixel, with OBP0 or OBP1 from attributes bit 4
+                let sprite_palette = (sprite_pixel as usize & 0x10) >> 4;
+
+                // Index the pixel in the palette
+                let index = (self.dmg_obj_palette[sprite_palette]
+                    >> (((sprite_pixel >> 8) as u8 & 3) << 1))
+                    & 0x3;
+
+                self.dmg_colorized_obj_palette[sprite_palette][index as usize]
+            }
+        }
+    }
+
+    /// Writes a pixel of the current scanline in the frame
+    fn write_pixel(&mut self, x: usize, pixel: [u8; 3]) {
+        if self.skip_rendering {
+            return;
+        }
+
+        let size = self.pixel_format.bytes_per_pixel();
+        let base = ((self.y as usize) * FRAME_WIDTH + x) * size;
+
+        self.pixel_format
+            .write_pixel(&mut self.frame[base..base + size], pixel);
+    }
+
+    fn enter_hblank(&mut self, bus: &mut PpuBus, window_drawn: bool) {
+        // Reset some buffers
+        self.background_pixel_pipeline = Default::default();
+        self.sprite_pixel_pipeline = Default::default();
+        self.secondary_oam = [None; 10];
+
+        // Only lines showing the window advance it, so disabling the window mid-frame pauses it
+        if window_drawn {
+            self.window_y_counter += 1;
+        };
+
+        if self
+            .lcd_status_reg
+            .contains(LcdStatus::HBANLK_INTERUPT_SOURCE)
+        {
+            bus.request_interrupt(InterruptReg::LCD_STAT);
+        }
+
+        // Signal to the CPU we are in HBlank for HDMA transfer
+        bus.set_hdma_hblank(true);
+
+        if let Some(mut hook) = self.scanline_hook.take() {
+            hook(self.y, &self.scanline_info());
+            self.scanline_hook = Some(hook);
+        }
+
+        if self.skip_rendering {
+            return;
+        }
+
+        if let Some(mut callback) = self.scanline_callback.take() {
+            let line_size = FRAME_WIDTH * self.pixel_format.bytes_per_pixel();
+            let base = self.y as usize * line_size;
+
+            callback(self.y, &self.frame[base..base + line_size]);
+            self.scanline_callback = Some(callback);
+        }
+    }
+
+    /// Checks if the OAM scan selects a sprite at this Y position on the current scanline.
+    /// The X position is ignored, so sprites hidden at X = 0 still count toward the 10 sprites limit.
+    fn is_sprite_on_line(&self, y: u8) -> bool {
+        let sprite_size = if self.lcd_control_reg.contains(LcdControl::OBJ_SIZE) {
+            16
+        } else {
+            8
+        };
+
+        // The index is y + 16, so the sprite can be hidden off at 0. This is why we add 16 here
+        let y_remainder = self.y.wrapping_sub(y).wrapping_add(16);
+
+        y_remainder < sprite_size
+    }
+
+    fn read_bg_win_tile(&self, bank: u8, id: u8, offset: u8) -> u8 {
+        // See: https://gbdev.io/pandocs/Tile_Data.html
+        if self
+            .lcd_control_reg
+            .contains(LcdControl::BACKGROUND_WINDOW_TILE_DATA_AREA)
+        {
+            self.read_obj_tile(bank, id, offset)
+        } else {
+            let is_id_negative = id & 0x80 == 0x80;
+
+            let addr_to_read = if !is_id_negative {
+                (0x9000 | ((id as u16) << 4) | (offset as u16)) & 0x1FFF | ((bank as u16) << 13)
+            } else {
+                0x8800
+                    | (((id as u16) << 4) & 0x7FF)
+                    | (offset as u16) & 0x1FFF
+                    | ((bank as u16) << 13)
+            };
+            self.read_vram_without_banking(addr_to_read)
         }
     }
 
@@ -831,6 +1442,80 @@ impl Ppu {
         }
     }
 
+    /// Returns the tile and the row inside of it to draw for a sprite on the current line
+    fn sprite_tile_row(&self, sprite_y: u8, tile_idx: u8, attr: u8) -> (u8, u8) {
+        let sprite_size = if self.lcd_control_reg.contains(LcdControl::OBJ_SIZE) {
+            15
+        } else {
+            7
+        };
+
+        // The index is y + 16, so the sprite can be hidden off at 0. This is why we add 16 here
+        let mut row = self.y.wrapping_sub(sprite_y).wrapping_add(16) & sprite_size;
+
+        // Y flip, which also swaps the two tiles of 8x16 sprites
+        if attr & 0x40 > 0 {
+            row = sprite_size - row;
+        }
+
+        // For 8x16 sprites, bit 0 of the index is ignored and the bottom half is the next tile
+        let tile_id = if self.lcd_control_reg.contains(LcdControl::OBJ_SIZE) {
+            (tile_idx & 0xFE) | (row >> 3)
+        } else {
+            tile_idx
+        };
+
+        (tile_id, row & 0x7)
+    }
+
+    /// Loads the fetched sprite in the sprite pipeline
+    fn push_sprite(&mut self, state: &mut DrawingState) {
+        state.apply_tile_attributes();
+
+        // Add the offset of the sprite in the secondary OAM
+        for b in &mut state.buffer {
+            *b |= ((state.sprite_idx as u16) * 4) << 12;
+        }
+
+        self.sprite_pixel_pipeline.load(state.buffer, self.cgb_mode);
+
+        if self.x == 0 {
+            self.sprite_pixel_pipeline
+                .drain(8 - self.fetched_sprite(state).x);
+        }
+
+        // Remove the sprite
+        self.secondary_oam[state.sprite_idx as usize] = None;
+
+        state.end_sprite_fetch();
+    }
+
+    /// Sprite being fetched, which is empty if the secondary OAM was cleared during the fetch
+    fn fetched_sprite(&self, state: &DrawingState) -> SpriteEntry {
+        self.secondary_oam[state.sprite_idx as usize].unwrap_or_default()
+    }
+
+    /// Dots a sprite fetch waits for the background fetch, on top of the 6 dots of the fetch itself.
+    /// Only the first sprite over a background or window tile waits.
+    /// See: https://gbdev.io/pandocs/Rendering.html#mode-3-length
+    fn sprite_alignment_penalty(&self, state: &mut DrawingState, sprite_x: u8) -> u8 {
+        // Position of the leftmost pixel of the sprite in the background or the window
+        let pixel = if state.is_window {
+            sprite_x as i16 - self.window_x as i16 - 1
+        } else {
+            sprite_x as i16 - 8 + (self.scroll_x & 0x7) as i16
+        };
+
+        let tile = pixel.div_euclid(8);
+        if state.penalty_tile == Some(tile) {
+            return 0;
+        }
+        state.penalty_tile = Some(tile);
+
+        // Pixels of the tile right of the sprite, minus 2
+        5u8.saturating_sub(pixel.rem_euclid(8) as u8)
+    }
+
     fn fetcher_get_tile(&self, state: &mut DrawingState, hi: bool) {
         // Decides if we load the lower or higher bits
         let plane = if hi { 1 } else { 0 };
@@ -840,30 +1525,12 @@ impl Ppu {
             0
         };
 
-        let mut tile_data = if state.is_sprite {
-            let sprite_size = if self.lcd_control_reg.contains(LcdControl::OBJ_SIZE) {
-                15
-            } else {
-                7
-            };
-
-            let mut row = self
-                .y
-                .wrapping_sub(self.secondary_oam[state.sprite_idx as usize])
-                .wrapping_add(16)
-                & sprite_size;
-
-            // Y flip
-            if state.tile_attr & 0x40 > 0 {
-                row = sprite_size - row;
-            }
-
-            // For 8x16 sprites, get the right index
-            let tile_id = if self.lcd_control_reg.contains(LcdControl::OBJ_SIZE) {
-                (state.tile_idx & 0xFE) | ((row & 0x08) >> 3)
-            } else {
-                state.tile_idx
-            };
+        let tile_data = if state.is_sprite {
+            let (tile_id, row) = self.sprite_tile_row(
+                self.fetched_sprite(state).y,
+                state.tile_idx,
+                state.tile_attr,
+            );
 
             self.read_obj_tile(bank, tile_id, (row << 1) | plane)
         } else {
@@ -882,26 +1549,1611 @@ impl Ppu {
             self.read_bg_win_tile(bank, state.tile_idx, (row << 1) | plane)
         };
 
-        // Put the tile data where it belongs in the buffer
-        for val in &mut state.buffer {
-            *val |= (tile_data as u16 & 1) << (8 | plane);
-            tile_data >>= 1;
+        if hi {
+            // Put the tile data where it belongs in the buffer. The leftmost pixel goes last.
+            let pixels = tile::decode_tile_row(state.tile_data_low, tile_data);
+            for (val, color) in state.buffer.iter_mut().rev().zip(pixels) {
+                *val |= (color as u16) << 8;
+            }
+        } else {
+            // Keep the low bits until the high bits are fetched
+            state.tile_data_low = tile_data;
         }
 
         state.cycle += 1;
     }
 }
 
-fn allocate_new_frame() -> Frame {
-    //   Hackish way to create fixed size boxed array.
-    // I don't know of any way to do it without
-    // having the data allocated on the stack at some point or using unsafe
-    unsafe {
-        // Safety: allocated vector has the right size for a frame array
-        // (that is `FRAME_WIDTH * FRAME_HEIGHT`)
-        let v: Vec<u8> = vec![0xFF; FRAME_WIDTH * FRAME_HEIGHT * 4];
-        Box::from_raw(
-            Box::into_raw(v.into_boxed_slice()) as *mut [u8; FRAME_WIDTH * FRAME_HEIGHT * 4]
-        )
+fn allocate_new_frame(pixel_format: PixelFormat) -> Frame {
+    // The frame starts white, like the LCD when it is turned off
+    vec![0xFF; FRAME_WIDTH * FRAME_HEIGHT * pixel_format.bytes_per_pixel()].into_boxed_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HDma, InterruptState};
+    use alloc::vec::Vec;
+
+    const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+    const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+
+    /// Runs the PPU until the end of the next mode 3 and returns its length in dots
+    fn run_line(ppu: &mut Ppu) -> u16 {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut drawing_start = None;
+
+        loop {
+            ppu.clock(&mut bus);
+
+            match ppu.fifo_mode {
+                FifoMode::Drawing(_) if drawing_start.is_none() => drawing_start = Some(ppu.cycle),
+                FifoMode::HBlank => {
+                    if let Some(start) = drawing_start {
+                        return ppu.cycle - start;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs the PPU from power on until the end of the mode 3 of the line `ly`.
+    /// Tests check line 1 and later because the OAM scan of the first line after power on starts a
+    /// dot late, so sprites aren't selected on line 0.
+    fn run_to_line(ppu: &mut Ppu, ly: u8) {
+        for _ in 0..=ly {
+            run_line(ppu);
+        }
+    }
+
+    /// Runs the PPU until the next frame is ready
+    fn run_frame(ppu: &mut Ppu) -> Frame {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        loop {
+            ppu.clock(&mut bus);
+
+            if let Some(frame) = ppu.ready_frame() {
+                return frame;
+            }
+        }
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> [u8; 3] {
+        let base = (y * FRAME_WIDTH + x) * 4;
+        ppu.frame[base..base + 3]
+            .try_into()
+            .expect("slice should be 3 bytes")
+    }
+
+    /// Background is white everywhere and the window is black everywhere
+    fn setup_window_scene(wx: u8, scx: u8) -> Ppu {
+        let mut ppu = Ppu::new(false);
+        ppu.dmg_colorized_bg_palette = [WHITE, [0xAA; 3], [0x55; 3], BLACK];
+        ppu.write(0xFF47, 0xE4);
+
+        // Tile 1 is fully color 3
+        for addr in 0x0010..0x0020 {
+            ppu.vram[addr] = 0xFF;
+        }
+
+        // Window uses the map at 0x9C00, filled with tile 1
+        for addr in 0x1C00..0x2000 {
+            ppu.vram[addr] = 0x01;
+        }
+
+        ppu.write(0xFF43, scx);
+        ppu.write(0xFF4A, 0);
+        ppu.write(0xFF4B, wx);
+        ppu
+    }
+
+    #[test]
+    fn test_step_to_vblank() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF40, 0x91);
+        for _ in 0..20 * 456 + 100 {
+            ppu.clock(&mut bus);
+        }
+        let (y, cycle) = (ppu.y as u32, ppu.cycle as u32);
+
+        assert_eq!(ppu.step_to_vblank(&mut bus), (144 - y) * 456 - cycle);
+        assert_eq!((ppu.y, ppu.cycle), (144, 0));
+        assert!(matches!(ppu.fifo_mode, FifoMode::VBlank));
+
+        // Already in VBlank, so it goes to the next one
+        assert_eq!(ppu.step_to_vblank(&mut bus), 154 * 456);
+
+        // Never reached while the LCD is off
+        ppu.write(0xFF40, 0x11);
+        assert_eq!(ppu.step_to_vblank(&mut bus), 0);
+    }
+
+    #[test]
+    fn test_dots_until_vblank() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF40, 0x11);
+        assert_eq!(ppu.dots_until_vblank(), 0);
+
+        // Frame start
+        ppu.write(0xFF40, 0x91);
+        assert_eq!((ppu.y, ppu.cycle), (0, 0));
+        assert_eq!(ppu.dots_until_vblank(), 144 * 456);
+
+        // Mid-frame, it matches the dots it takes to get there
+        for _ in 0..72 * 456 + 200 {
+            ppu.clock(&mut bus);
+        }
+        assert_eq!(ppu.dots_until_vblank(), 72 * 456 - 200);
+        let expected = ppu.dots_until_vblank();
+        assert_eq!(ppu.step_to_vblank(&mut bus), expected);
+
+        // At the start of VBlank and during it, the next frame's VBlank is next
+        assert_eq!(ppu.dots_until_vblank(), 154 * 456);
+        for _ in 0..5 * 456 + 10 {
+            ppu.clock(&mut bus);
+        }
+        assert_eq!(ppu.y, 149);
+        assert_eq!(ppu.dots_until_vblank(), 149 * 456 - 10);
+        let expected = ppu.dots_until_vblank();
+        assert_eq!(ppu.step_to_vblank(&mut bus), expected);
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let blank_hash = Ppu::new(false).frame_hash();
+
+        // The right half of the screen is covered by the window
+        let mut ppu = setup_window_scene(87, 0);
+        ppu.write(0xFF40, 0xF1);
+        ppu.step_to_vblank(&mut bus);
+        assert_eq!(pixel(&ppu, 79, 143), WHITE);
+        assert_eq!(pixel(&ppu, 80, 143), BLACK);
+        assert_eq!(ppu.frame_hash(), 14207772072962527013);
+        assert_ne!(ppu.frame_hash(), blank_hash);
+    }
+
+    #[test]
+    fn test_graphics_snapshot() {
+        let mut ppu = Ppu::new(true);
+        ppu.vram[0x0010] = 0x42;
+        ppu.vram[0x2010] = 0x99;
+        ppu.oam[4] = 0x17;
+        ppu.write(0xFF47, 0xE4);
+        ppu.cgb_obj_palette.data[6] = 0x1F;
+
+        let snapshot = ppu.snapshot_graphics();
+        assert_eq!(snapshot.vram[0x2010], 0x99);
+
+        ppu.vram.fill(0);
+        ppu.oam.fill(0);
+        ppu.write(0xFF47, 0x1B);
+        ppu.cgb_obj_palette.data.fill(0);
+        ppu.write(0xFF43, 0x08);
+        assert!(ppu.snapshot_graphics() != snapshot);
+
+        ppu.restore_graphics(&snapshot);
+        assert!(ppu.snapshot_graphics() == snapshot);
+        assert_eq!(ppu.vram[0x0010], 0x42);
+        assert_eq!(ppu.vram[0x2010], 0x99);
+        assert_eq!(ppu.oam[4], 0x17);
+        assert_eq!(ppu.read(0xFF47), 0xE4);
+        assert_eq!(ppu.cgb_obj_palette.data[6], 0x1F);
+
+        // The registers are not part of it
+        assert_eq!(ppu.read(0xFF43), 0x08);
+    }
+
+    #[test]
+    fn test_clock_n() {
+        // Clocks `start` dots one by one, then runs `clock`
+        let run = |lcdc: u8, start: u32, clock: &dyn Fn(&mut Ppu, &mut PpuBus)| {
+            let mut interrupts = InterruptState {
+                status: InterruptReg::empty(),
+                ..Default::default()
+            };
+            let mut hdma = HDma::default();
+            let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+            let mut ppu = setup_window_scene(50, 3);
+            ppu.write(0xFF40, lcdc);
+            ppu.write(0xFF41, 0x78);
+            for _ in 0..start {
+                ppu.clock(&mut bus);
+            }
+            clock(&mut ppu, &mut bus);
+
+            let state = (
+                ppu.y,
+                ppu.cycle,
+                ppu.read(0xFF41),
+                ppu.read(0xFF44),
+                ppu.paused_cycles,
+                ppu.frame_ready,
+                ppu.frame.clone(),
+            );
+            (state, interrupts.status)
+        };
+
+        // Starts in mode 2, in HBlank, in VBlank and right before LY changes on the last line
+        for start in [0, 300, 144 * 456 + 100, 153 * 456 + 1] {
+            for lcdc in [0x11, 0xF1] {
+                for dots in [1, 79, 80, 200, 456, 70224, 70224 * 2 + 1000] {
+                    let expected = run(lcdc, start, &|ppu, bus| {
+                        for _ in 0..dots {
+                            ppu.clock(bus);
+                        }
+                    });
+                    let state = run(lcdc, start, &|ppu, bus| ppu.clock_n(bus, dots));
+
+                    assert!(
+                        state == expected,
+                        "lcdc={:x} start={} dots={}",
+                        lcdc,
+                        start,
+                        dots
+                    );
+                }
+            }
+        }
+
+        // Skipping dots while the LCD is off doesn't overflow
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+        let mut ppu = setup_window_scene(50, 3);
+        ppu.write(0xFF40, 0x11);
+        ppu.clock_n(&mut bus, 70000);
+        ppu.clock_n(&mut bus, u32::MAX);
+        assert_eq!(
+            ppu.paused_cycles,
+            ((70000 + u32::MAX as u64) % 70224) as u32
+        );
+        assert!(ppu.frame_ready);
+    }
+
+    #[test]
+    fn test_allocate_new_frame() {
+        let frame = allocate_new_frame(PixelFormat::Rgba8888);
+        assert_eq!(frame.len(), FRAME_WIDTH * FRAME_HEIGHT * 4);
+        assert!(frame.iter().all(|&x| x == 0xFF));
+
+        let frame = allocate_new_frame(PixelFormat::Rgb888);
+        assert_eq!(frame.len(), FRAME_WIDTH * FRAME_HEIGHT * 3);
+        assert!(frame.iter().all(|&x| x == 0xFF));
+    }
+
+    #[test]
+    fn test_frame_ptr() {
+        let mut ppu = setup_window_scene(7, 0);
+        ppu.write(0xFF40, 0xF1);
+        assert_eq!(ppu.frame_len(), FRAME_WIDTH * FRAME_HEIGHT * 4);
+
+        let ptr = ppu.frame_ptr();
+
+        // The frame is drawn in place
+        let frame = run_frame(&mut ppu);
+        assert_eq!(frame.as_ptr(), ptr);
+        assert_eq!(frame[0..4], [0x00, 0x00, 0x00, 0xFF]);
+
+        // A new frame is then drawn in another buffer
+        assert_eq!(ppu.frame_ptr(), ppu.frame.as_ptr());
+        assert_ne!(ppu.frame_ptr(), ptr);
+
+        ppu.set_pixel_format(PixelFormat::Rgb888);
+        assert_eq!(ppu.frame_ptr(), ppu.frame.as_ptr());
+        assert_eq!(ppu.frame_len(), FRAME_WIDTH * FRAME_HEIGHT * 3);
+    }
+
+    #[test]
+    fn test_pixel_format() {
+        for (pixel_format, expected) in [
+            (PixelFormat::Rgba8888, &[0x12, 0x34, 0x56, 0xFF][..]),
+            (PixelFormat::Bgra8888, &[0x56, 0x34, 0x12, 0xFF][..]),
+            (PixelFormat::Rgb888, &[0x12, 0x34, 0x56][..]),
+        ] {
+            let mut ppu = setup_window_scene(7, 0);
+            ppu.set_pixel_format(pixel_format);
+            ppu.dmg_colorized_bg_palette[3] = [0x12, 0x34, 0x56];
+            ppu.write(0xFF40, 0xF1);
+            run_line(&mut ppu);
+
+            // Second pixel of the line, so a wrong size of pixel would be caught
+            let size = expected.len();
+            assert_eq!(&ppu.frame[size..size * 2], expected, "{:?}", pixel_format);
+            assert_eq!(ppu.frame.len(), FRAME_WIDTH * FRAME_HEIGHT * size);
+        }
+    }
+
+    #[test]
+    fn test_register_getters() {
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF40, 0xE3);
+        ppu.write(0xFF41, 0x48);
+        ppu.write(0xFF42, 0x12);
+        ppu.write(0xFF43, 0x34);
+        ppu.write(0xFF4A, 0x56);
+        ppu.write(0xFF4B, 0x78);
+
+        assert_eq!(
+            ppu.lcdc(),
+            LcdControl::LCD_PPU_ENABLE
+                | LcdControl::WINDOW_TILE_MAP_AREA
+                | LcdControl::WINDOW_ENABLE
+                | LcdControl::OBJ_ENABLE
+                | LcdControl::BACKGROUND_WINDOW_ENABLE_PRIORITY
+        );
+        assert_eq!(ppu.lcdc().bits(), ppu.read(0xFF40));
+
+        let stat = ppu.stat();
+        assert!(stat.contains(LcdStatus::HBANLK_INTERUPT_SOURCE));
+        assert!(stat.contains(LcdStatus::LYC_EQ_LC_INTERUPT_SOURCE));
+        assert_eq!(stat.bits(), ppu.read(0xFF41));
+
+        assert_eq!(ppu.scroll(), (0x34, 0x12));
+        assert_eq!(ppu.window(), (0x78, 0x56));
+    }
+
+    #[test]
+    fn test_lyc_write() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF40, 0x91);
+        ppu.write(0xFF41, 0x40);
+        ppu.write(0xFF45, 0x10);
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0);
+
+        // Middle of line 5
+        while ppu.y != 5 || ppu.cycle != 200 {
+            ppu.clock(&mut PpuBus::borrow(&mut interrupts, &mut hdma));
+        }
+        assert!(!interrupts.status.contains(InterruptReg::LCD_STAT));
+
+        // The comparison is done as soon as LYC is written
+        assert!(ppu.write_lyc(5));
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+
+        // But the interrupt is only requested when they become equal
+        assert!(!ppu.write_lyc(5));
+        assert!(!ppu.write_lyc(6));
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0);
+
+        // Changing LY still works
+        while ppu.y != 6 {
+            ppu.clock(&mut PpuBus::borrow(&mut interrupts, &mut hdma));
+        }
+        assert!(interrupts.status.contains(InterruptReg::LCD_STAT));
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+
+        // No interrupt with the LCD off
+        ppu.write(0xFF40, 0x11);
+        ppu.disable();
+        assert!(!ppu.write_lyc(1));
+        assert!(!ppu.write_lyc(0));
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_last_line_ly() {
+        let mut interrupts = InterruptState::default();
+
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF40, 0x91);
+        ppu.write(0xFF41, 0x40);
+        ppu.write(0xFF45, 153);
+
+        fn clock_to(ppu: &mut Ppu, interrupts: &mut InterruptState, y: u8, cycle: u16) {
+            let mut hdma = HDma::default();
+            while ppu.y != y || ppu.cycle != cycle {
+                ppu.clock(&mut PpuBus::borrow(interrupts, &mut hdma));
+            }
+        }
+
+        // LY is 153 for the first dots of the line
+        clock_to(&mut ppu, &mut interrupts, 153, 0);
+        assert_eq!(ppu.read(0xFF44), 153);
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+
+        // Then 0 for the rest of the line
+        clock_to(&mut ppu, &mut interrupts, 153, 8);
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0);
+
+        // LYC=0 matches on line 153
+        clock_to(&mut ppu, &mut interrupts, 100, 0);
+        ppu.write_lyc(0);
+        clock_to(&mut ppu, &mut interrupts, 153, 2);
+        interrupts.status.remove(InterruptReg::LCD_STAT);
+
+        clock_to(&mut ppu, &mut interrupts, 153, 8);
+        assert!(interrupts.status.contains(InterruptReg::LCD_STAT));
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+
+        // And the interrupt isn't requested again on line 0
+        interrupts.status.remove(InterruptReg::LCD_STAT);
+        clock_to(&mut ppu, &mut interrupts, 0, 8);
+        assert!(!interrupts.status.contains(InterruptReg::LCD_STAT));
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_frame_ready() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut ppu = Ppu::new(false);
+        ppu.write(0xFF40, 0x91);
+        assert!(ppu.ready_frame().is_none());
+
+        // Run until the end of line 153
+        while ppu.y != 0 || ppu.cycle != 0 || !ppu.frame_ready {
+            ppu.clock(&mut bus);
+            assert!(!ppu.frame_ready || ppu.y == 0);
+        }
+
+        // The frame can be taken later in the line, but only once
+        for _ in 0..10 {
+            ppu.clock(&mut bus);
+        }
+        assert!(ppu.ready_frame().is_some());
+        assert!(ppu.ready_frame().is_none());
+
+        ppu.clock(&mut bus);
+        assert!(ppu.ready_frame().is_none());
+
+        // Same thing with the LCD off
+        ppu.write(0xFF40, 0x11);
+        for _ in 0..70224 + 10 {
+            ppu.clock(&mut bus);
+        }
+        assert!(ppu.ready_frame().is_some());
+        assert!(ppu.ready_frame().is_none());
+    }
+
+    #[test]
+    fn test_frame_blend() {
+        let mut ppu = Ppu::new(false);
+
+        let next_frame = |ppu: &mut Ppu, value: u8| {
+            ppu.frame.fill(value);
+            ppu.frame_ready = true;
+            ppu.ready_frame().expect("the frame should be ready")
+        };
+
+        // Disabled by default
+        assert!(next_frame(&mut ppu, 0x00).iter().all(|&x| x == 0x00));
+        assert!(next_frame(&mut ppu, 0xFF).iter().all(|&x| x == 0xFF));
+
+        // The first frame has nothing to be blended with
+        ppu.set_frame_blend(DEFAULT_FRAME_BLEND);
+        assert!(next_frame(&mut ppu, 0x00).iter().all(|&x| x == 0x00));
+        assert!(next_frame(&mut ppu, 0xFF).iter().all(|&x| x == 0x7F));
+        assert!(next_frame(&mut ppu, 0x00).iter().all(|&x| x == 0x7F));
+
+        // The blending doesn't accumulate over multiple frames
+        assert!(next_frame(&mut ppu, 0x00).iter().all(|&x| x == 0x00));
+
+        ppu.set_frame_blend(64);
+        assert!(next_frame(&mut ppu, 0xFF).iter().all(|&x| x == 0xBF));
+
+        ppu.set_frame_blend(0);
+        assert!(next_frame(&mut ppu, 0x00).iter().all(|&x| x == 0x00));
+        assert!(ppu.previous_frame.is_none());
+    }
+
+    #[test]
+    fn test_frame_blend_rendered() {
+        let mut ppu = setup_window_scene(7, 0);
+        ppu.set_frame_blend(DEFAULT_FRAME_BLEND);
+
+        // The window covers the screen in black
+        ppu.write(0xFF40, 0xF1);
+        let frame = run_frame(&mut ppu);
+        assert_eq!(frame[0..4], [0x00, 0x00, 0x00, 0xFF]);
+
+        // Without the window, the pixel turns white but fades in over one frame
+        ppu.write(0xFF40, 0xD1);
+        let frame = run_frame(&mut ppu);
+        assert_eq!(frame[0..4], [0x7F, 0x7F, 0x7F, 0xFF]);
+
+        let frame = run_frame(&mut ppu);
+        assert_eq!(frame[0..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_sprite_on_line() {
+        let mut ppu = Ppu::new(false);
+        ppu.y = 20;
+
+        // 8x8 sprites cover Y - 16 to Y - 9
+        ppu.write(0xFF40, 0x91);
+        assert!(!ppu.is_sprite_on_line(20));
+        assert!(ppu.is_sprite_on_line(29));
+        assert!(ppu.is_sprite_on_line(36));
+        assert!(!ppu.is_sprite_on_line(37));
+        assert!(!ppu.is_sprite_on_line(40));
+
+        // 8x16 sprites cover Y - 16 to Y - 1
+        ppu.write(0xFF40, 0x95);
+        assert!(ppu.is_sprite_on_line(21));
+        assert!(ppu.is_sprite_on_line(36));
+        assert!(!ppu.is_sprite_on_line(20));
+        assert!(!ppu.is_sprite_on_line(37));
+
+        // Sprites wrap around the top of the screen
+        ppu.y = 0;
+        assert!(ppu.is_sprite_on_line(8));
+        assert!(!ppu.is_sprite_on_line(0));
+    }
+
+    #[test]
+    fn test_hidden_sprite_selected() {
+        for hidden_on_line in [true, false] {
+            let mut ppu = setup_window_scene(0, 0);
+            ppu.dmg_colorized_obj_palette[0] = [WHITE, [0xAA; 3], [0x55; 3], BLACK];
+            ppu.write(0xFF48, 0xE4);
+
+            // The first sprite is hidden at X = 0, and 10 black sprites follow it
+            let hidden_y = if hidden_on_line { 17 } else { 0 };
+            ppu.oam[0..4].copy_from_slice(&[hidden_y, 0, 0x01, 0x00]);
+            for i in 1..=10 {
+                ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[17, 8 * i as u8, 0x01, 0x00]);
+            }
+
+            ppu.write(0xFF40, 0x93);
+            run_to_line(&mut ppu, 1);
+
+            for x in 0..72 {
+                assert_eq!(pixel(&ppu, x, 1), BLACK, "x={}", x);
+            }
+
+            // The hidden sprite takes the place of the last one
+            let expected = if hidden_on_line { WHITE } else { BLACK };
+            for x in 72..80 {
+                assert_eq!(pixel(&ppu, x, 1), expected, "x={}", x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cgb_palette_blocking() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut ppu = Ppu::new(true);
+        ppu.write(0xFF40, 0x91);
+
+        // Writes the value in the first color and reads it back from BCPD
+        let write_color = |ppu: &mut Ppu, value: u8| {
+            ppu.write(0xFF68, 0x80);
+            ppu.write(0xFF69, value);
+            ppu.write(0xFF68, 0x00);
+            (ppu.cgb_bg_palette.data[0], ppu.read(0xFF69))
+        };
+
+        // Skip the first line after power on
+        while ppu.y == 0 {
+            ppu.clock(&mut bus);
+        }
+
+        // Last dot of the OAM scan
+        while ppu.cycle < 79 {
+            ppu.clock(&mut bus);
+        }
+        assert_eq!(write_color(&mut ppu, 0x01), (0x01, 0x01));
+
+        // Blocked for the whole mode 3
+        ppu.clock(&mut bus);
+        assert_eq!(ppu.cycle, 80);
+        assert_eq!(write_color(&mut ppu, 0x02), (0x01, 0xFF));
+
+        while matches!(ppu.fifo_mode, FifoMode::Drawing(_)) {
+            assert_eq!(
+                write_color(&mut ppu, 0x03),
+                (0x01, 0xFF),
+                "dot {}",
+                ppu.cycle
+            );
+            ppu.clock(&mut bus);
+        }
+
+        // First dot of HBlank
+        assert_eq!(write_color(&mut ppu, 0x04), (0x04, 0x04));
+
+        // Auto-increment doesn't apply to blocked writes
+        while !matches!(ppu.fifo_mode, FifoMode::Drawing(_)) {
+            ppu.clock(&mut bus);
+        }
+        ppu.write(0xFF68, 0x80);
+        ppu.write(0xFF69, 0x05);
+        assert_eq!(ppu.read(0xFF68), 0xC0);
+        assert_eq!(ppu.cgb_bg_palette.data[0], 0x04);
+    }
+
+    #[test]
+    fn test_lcd_enable_first_line() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut ppu = setup_window_scene(7, 0);
+        ppu.write(0xFF40, 0xF1);
+        ppu.enable();
+
+        // Mode 2 is skipped on the first line, which stays in mode 0 until the drawing starts
+        assert_eq!(ppu.read(0xFF41) & 0x3, 0);
+        for dot in 1..80 {
+            ppu.clock(&mut bus);
+            assert_eq!(ppu.read(0xFF41) & 0x3, 0, "dot {}", dot);
+        }
+
+        ppu.clock(&mut bus);
+        assert_eq!(ppu.read(0xFF41) & 0x3, 3);
+
+        while ppu.cycle < 455 {
+            ppu.clock(&mut bus);
+        }
+        ppu.clock(&mut bus);
+
+        // The next line is back to normal
+        assert_eq!(ppu.y, 1);
+        assert_eq!(ppu.read(0xFF41) & 0x3, 2);
+        for _ in 0..79 {
+            ppu.clock(&mut bus);
+            assert_eq!(ppu.read(0xFF41) & 0x3, 2);
+        }
+    }
+
+    #[test]
+    fn test_lcd_enable_first_frame() {
+        let mut ppu = setup_window_scene(7, 0);
+        ppu.write(0xFF40, 0xF1);
+        ppu.enable();
+
+        // The window covers the screen in black, but the first frame is not displayed
+        let frame = run_frame(&mut ppu);
+        assert!(frame.iter().all(|&x| x == 0xFF));
+
+        let frame = run_frame(&mut ppu);
+        assert!(frame.chunks_exact(4).all(|x| x == [0x00, 0x00, 0x00, 0xFF]));
+    }
+
+    #[test]
+    fn test_scanline_hook() {
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let hook_lines = lines.clone();
+
+        let mut ppu = setup_window_scene(0, 0);
+        ppu.write(0xFF40, 0x91);
+        ppu.set_scanline_hook(move |line, info| hook_lines.borrow_mut().push((line, *info)));
+
+        for y in 0..FRAME_HEIGHT as u8 {
+            // Change the registers during HBlank, which applies to the next line
+            ppu.write(0xFF43, y);
+            ppu.write(0xFF47, !y);
+            run_line(&mut ppu);
+        }
+
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), FRAME_HEIGHT);
+
+        for (y, (line, info)) in lines.iter().enumerate() {
+            assert_eq!(*line as usize, y);
+            assert_eq!(info.scroll_x as usize, y);
+            assert_eq!(info.dmg_bg_palette, !(y as u8));
+            assert_eq!(info.lcd_control, 0x91);
+            assert_eq!(info.window_x, 0);
+        }
+    }
+
+    #[test]
+    fn test_scanline_callback() {
+        use alloc::rc::Rc;
+        use core::cell::RefCell;
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let callback_lines = lines.clone();
+
+        // The window is drawn from the middle of the screen
+        let mut ppu = setup_window_scene(87, 0);
+        ppu.write(0xFF40, 0xF1);
+        ppu.set_scanline_callback(move |line, pixels| {
+            callback_lines.borrow_mut().push((line, pixels.to_vec()))
+        });
+
+        let frame = run_frame(&mut ppu);
+        run_frame(&mut ppu);
+
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), FRAME_HEIGHT * 2);
+
+        for (i, (line, pixels)) in lines.iter().enumerate() {
+            assert_eq!(*line as usize, i % FRAME_HEIGHT);
+            assert_eq!(pixels.len(), FRAME_WIDTH * 4);
+            assert_eq!(pixels[0..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+            assert_eq!(pixels[80 * 4..81 * 4], [0x00, 0x00, 0x00, 0xFF]);
+        }
+
+        // The lines are the same as in the frame
+        for (line, pixels) in lines.iter().take(FRAME_HEIGHT) {
+            let base = *line as usize * FRAME_WIDTH * 4;
+            assert_eq!(frame[base..base + FRAME_WIDTH * 4], pixels[..]);
+        }
+    }
+
+    /// Background is fully color 1 and a sprite of color 3 covers the first 8 columns of line 1
+    fn run_priority_scene(cgb_mode: bool, lcdc: u8, bg_attr: u8, obj_attr: u8) -> Ppu {
+        let mut ppu = setup_window_scene(0, 0);
+        ppu.cgb_mode = cgb_mode;
+        ppu.dmg_colorized_obj_palette[0] = [WHITE, [0xAA; 3], [0x55; 3], BLACK];
+        ppu.write(0xFF48, 0xE4);
+
+        // BG color 1 is red and OBJ color 3 is black on CGB
+        ppu.cgb_bg_palette.data[2..4].copy_from_slice(&[0x1F, 0x00]);
+        ppu.cgb_obj_palette.data[6..8].copy_from_slice(&[0x00, 0x00]);
+
+        // Tile 2 is fully color 1
+        for addr in (0x0020..0x0030).step_by(2) {
+            ppu.vram[addr] = 0xFF;
+        }
+        for addr in 0x1800..0x1C00 {
+            ppu.vram[addr] = 0x02;
+            ppu.vram[addr + 0x2000] = bg_attr;
+        }
+
+        ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, obj_attr]);
+        ppu.write(0xFF40, lcdc);
+        run_to_line(&mut ppu, 1);
+        ppu
+    }
+
+    #[test]
+    fn test_cgb_obj_palette() {
+        let mut ppu = setup_window_scene(0, 0);
+        ppu.cgb_mode = true;
+
+        // OBJ color 3 has a different blue in every palette
+        for palette in 0..8 {
+            let color = ((palette as u16) << 10) | 0x1F;
+            let base = (palette << 3) | 6;
+            ppu.cgb_obj_palette.data[base..base + 2].copy_from_slice(&color.to_le_bytes());
+        }
+
+        // The DMG palette bit is ignored
+        ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, 0x05]);
+        ppu.oam[4..8].copy_from_slice(&[17, 16, 0x01, 0x12]);
+        ppu.write(0xFF40, 0x93);
+        run_to_line(&mut ppu, 1);
+
+        assert_eq!(pixel(&ppu, 0, 1), [0xFF, 0x00, 0x29]);
+        assert_eq!(pixel(&ppu, 8, 1), [0xFF, 0x00, 0x10]);
+    }
+
+    #[test]
+    fn test_obj_vram_bank() {
+        const RED: [u8; 3] = [0xFF, 0x00, 0x00];
+        const GREEN: [u8; 3] = [0x00, 0xFF, 0x00];
+
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            for cgb_mode in [false, true] {
+                let mut ppu = setup_window_scene(0, 0);
+                ppu.cgb_mode = cgb_mode;
+                ppu.set_render_mode(render_mode);
+                ppu.dmg_colorized_obj_palette[0] = [WHITE, RED, GREEN, BLACK];
+                ppu.write(0xFF48, 0xE4);
+                ppu.cgb_obj_palette.data[2..6].copy_from_slice(&[0x1F, 0x00, 0xE0, 0x03]);
+
+                // Tiles 4 and 5 are color 1 in bank 0 and color 2 in bank 1
+                for addr in (0x0040..0x0060).step_by(2) {
+                    ppu.vram[addr] = 0xFF;
+                    ppu.vram[0x2000 + addr + 1] = 0xFF;
+                }
+
+                // 8x16 sprites, the last one showing its bottom tile
+                ppu.oam[0..4].copy_from_slice(&[17, 8, 0x04, 0x00]);
+                ppu.oam[4..8].copy_from_slice(&[17, 16, 0x04, 0x08]);
+                ppu.oam[8..12].copy_from_slice(&[9, 24, 0x04, 0x08]);
+                ppu.write(0xFF40, 0x97);
+                run_to_line(&mut ppu, 1);
+
+                // The bank bit is ignored on DMG
+                let bank_1 = if cgb_mode { GREEN } else { RED };
+                assert_eq!(pixel(&ppu, 0, 1), RED);
+                assert_eq!(pixel(&ppu, 8, 1), bank_1);
+                assert_eq!(pixel(&ppu, 16, 1), bank_1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bg_priority_dmg() {
+        const BG_COLOR_1: [u8; 3] = [0xAA; 3];
+
+        // OBJ-to-BG priority hides the sprite behind the non-zero background colors
+        let ppu = run_priority_scene(false, 0x93, 0, 0x80);
+        assert_eq!(pixel(&ppu, 0, 1), BG_COLOR_1);
+        assert_eq!(pixel(&ppu, 8, 1), BG_COLOR_1);
+
+        let ppu = run_priority_scene(false, 0x93, 0, 0x00);
+        assert_eq!(pixel(&ppu, 0, 1), BLACK);
+
+        // LCDC.0 blanks the background, so the sprite is visible even when behind it
+        let ppu = run_priority_scene(false, 0x92, 0, 0x80);
+        assert_eq!(pixel(&ppu, 0, 1), BLACK);
+        assert_eq!(pixel(&ppu, 8, 1), WHITE);
+    }
+
+    #[test]
+    fn test_bg_priority_cgb() {
+        const RED: [u8; 3] = [0xFF, 0x00, 0x00];
+
+        // The priority bit of the BG attributes wins over the sprite
+        let ppu = run_priority_scene(true, 0x93, 0x80, 0x00);
+        assert_eq!(pixel(&ppu, 0, 1), RED);
+
+        // So does the priority bit of the sprite
+        let ppu = run_priority_scene(true, 0x93, 0x00, 0x80);
+        assert_eq!(pixel(&ppu, 0, 1), RED);
+
+        let ppu = run_priority_scene(true, 0x93, 0x00, 0x00);
+        assert_eq!(pixel(&ppu, 0, 1), BLACK);
+
+        // LCDC.0 removes every priority, but the background is still drawn
+        for (bg_attr, obj_attr) in [(0x80, 0x00), (0x00, 0x80), (0x80, 0x80)] {
+            let ppu = run_priority_scene(true, 0x92, bg_attr, obj_attr);
+            assert_eq!(pixel(&ppu, 0, 1), BLACK);
+            assert_eq!(pixel(&ppu, 8, 1), RED);
+        }
+    }
+
+    #[test]
+    fn test_bg_enable_toggle() {
+        const BG_COLOR_1: [u8; 3] = [0xAA; 3];
+        const RED: [u8; 3] = [0xFF, 0x00, 0x00];
+
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            for cgb_mode in [false, true] {
+                let mut ppu = setup_window_scene(0, 0);
+                ppu.cgb_mode = cgb_mode;
+                ppu.set_render_mode(render_mode);
+                ppu.dmg_colorized_obj_palette[0] = [WHITE, [0xAA; 3], [0x55; 3], BLACK];
+                ppu.write(0xFF48, 0xE4);
+                ppu.cgb_bg_palette.data[2..4].copy_from_slice(&[0x1F, 0x00]);
+                ppu.cgb_obj_palette.data[6..8].copy_from_slice(&[0x00, 0x00]);
+
+                // The background is fully color 1, with the priority bit on CGB
+                for addr in (0x0020..0x0030).step_by(2) {
+                    ppu.vram[addr] = 0xFF;
+                }
+                for addr in 0x1800..0x1C00 {
+                    ppu.vram[addr] = 0x02;
+                    ppu.vram[addr + 0x2000] = 0x80;
+                }
+
+                // Sprite behind the background
+                ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, 0x80]);
+                ppu.write(0xFF40, 0x93);
+                run_to_line(&mut ppu, 1);
+                ppu.write(0xFF40, 0x92);
+                run_line(&mut ppu);
+
+                let bg_color = if cgb_mode { RED } else { BG_COLOR_1 };
+                assert_eq!(pixel(&ppu, 0, 1), bg_color);
+
+                // The sprite is drawn over the background on both, but the DMG blanks the background
+                assert_eq!(pixel(&ppu, 0, 2), BLACK);
+                let bg_color = if cgb_mode { RED } else { WHITE };
+                assert_eq!(pixel(&ppu, 8, 2), bg_color);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hidden_layers() {
+        const BG_COLOR_1: [u8; 3] = [0xAA; 3];
+        const RED: [u8; 3] = [0xFF, 0x00, 0x00];
+
+        // (hidden layer, colors at x=0 over the sprite, x=8 over the background, x=80 over the window)
+        let cases = [
+            (None, [RED, BG_COLOR_1, BLACK]),
+            (Some(Layer::Sprites), [BG_COLOR_1, BG_COLOR_1, BLACK]),
+            (Some(Layer::Background), [RED, WHITE, BLACK]),
+            (Some(Layer::Window), [RED, BG_COLOR_1, WHITE]),
+        ];
+
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            for (hidden, expected) in cases {
+                let mut ppu = setup_window_scene(87, 0);
+                ppu.set_render_mode(render_mode);
+                ppu.dmg_colorized_obj_palette[0] = [WHITE, [0xAA; 3], [0x55; 3], RED];
+                ppu.write(0xFF48, 0xE4);
+
+                // The background is fully color 1
+                for addr in (0x0020..0x0030).step_by(2) {
+                    ppu.vram[addr] = 0xFF;
+                }
+                for addr in 0x1800..0x1C00 {
+                    ppu.vram[addr] = 0x02;
+                }
+
+                // Sprite of color 3 at the left of the line 1
+                ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, 0x00]);
+
+                if let Some(layer) = hidden {
+                    ppu.set_layer_enabled(layer, false);
+                }
+
+                ppu.write(0xFF40, 0xF3);
+                run_to_line(&mut ppu, 1);
+
+                let colors = [0, 8, 80].map(|x| pixel(&ppu, x, 1));
+                assert_eq!(colors, expected, "{:?} {:?}", render_mode, hidden);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dmg_sprite_color_0() {
+        const BG_COLOR_1: [u8; 3] = [0xAA; 3];
+
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            let mut ppu = setup_window_scene(0, 0);
+            ppu.set_render_mode(render_mode);
+            ppu.dmg_colorized_obj_palette[0] = [WHITE, [0xAA; 3], [0x55; 3], BLACK];
+
+            // OBP0 maps color 0 to black, color 3 to white
+            ppu.write(0xFF48, 0x1B);
+
+            // The background is fully color 1
+            for addr in (0x0020..0x0030).step_by(2) {
+                ppu.vram[addr] = 0xFF;
+            }
+            for addr in 0x1800..0x1C00 {
+                ppu.vram[addr] = 0x02;
+            }
+
+            // Tile 3 only has its leftmost column set to color 3
+            for addr in 0x0030..0x0040 {
+                ppu.vram[addr] = 0x80;
+            }
+            ppu.oam[0..4].copy_from_slice(&[17, 8, 0x03, 0x00]);
+
+            ppu.write(0xFF40, 0x93);
+            run_to_line(&mut ppu, 1);
+
+            // Color 0 stays transparent, even though OBP0 doesn't map it to white
+            assert_eq!(pixel(&ppu, 0, 1), WHITE, "{:?}", render_mode);
+            for x in 1..8 {
+                assert_eq!(pixel(&ppu, x, 1), BG_COLOR_1, "{:?}", render_mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sprite_tile_row() {
+        let mut ppu = Ppu::new(false);
+
+        // Sprite drawn from line 20
+        let sprite_y = 36;
+        let no_flip = 0x00;
+        let x_flip = 0x20;
+        let y_flip = 0x40;
+        let xy_flip = 0x60;
+
+        // (LCDC, LY, tile index, attributes) => (tile, row)
+        let cases = [
+            // 8x8, X flip has no effect on the row
+            ((0x91, 20, 0x11, no_flip), (0x11, 0)),
+            ((0x91, 27, 0x11, no_flip), (0x11, 7)),
+            ((0x91, 20, 0x11, x_flip), (0x11, 0)),
+            ((0x91, 27, 0x11, x_flip), (0x11, 7)),
+            ((0x91, 20, 0x11, y_flip), (0x11, 7)),
+            ((0x91, 27, 0x11, y_flip), (0x11, 0)),
+            ((0x91, 20, 0x11, xy_flip), (0x11, 7)),
+            ((0x91, 27, 0x11, xy_flip), (0x11, 0)),
+            // 8x16, bit 0 of the index is ignored
+            ((0x95, 20, 0x11, no_flip), (0x10, 0)),
+            ((0x95, 27, 0x11, no_flip), (0x10, 7)),
+            ((0x95, 28, 0x11, no_flip), (0x11, 0)),
+            ((0x95, 35, 0x11, no_flip), (0x11, 7)),
+            ((0x95, 20, 0x10, x_flip), (0x10, 0)),
+            ((0x95, 27, 0x10, x_flip), (0x10, 7)),
+            ((0x95, 28, 0x10, x_flip), (0x11, 0)),
+            ((0x95, 35, 0x10, x_flip), (0x11, 7)),
+            // 8x16 Y flip swaps the tiles
+            ((0x95, 20, 0x11, y_flip), (0x11, 7)),
+            ((0x95, 27, 0x11, y_flip), (0x11, 0)),
+            ((0x95, 28, 0x11, y_flip), (0x10, 7)),
+            ((0x95, 35, 0x11, y_flip), (0x10, 0)),
+            ((0x95, 20, 0x10, xy_flip), (0x11, 7)),
+            ((0x95, 27, 0x10, xy_flip), (0x11, 0)),
+            ((0x95, 28, 0x10, xy_flip), (0x10, 7)),
+            ((0x95, 35, 0x10, xy_flip), (0x10, 0)),
+        ];
+
+        for ((lcdc, ly, tile_idx, attr), expected) in cases {
+            ppu.write(0xFF40, lcdc);
+            ppu.y = ly;
+            assert_eq!(
+                ppu.sprite_tile_row(sprite_y, tile_idx, attr),
+                expected,
+                "LCDC={:02x} LY={} tile={:02x} attr={:02x}",
+                lcdc,
+                ly,
+                tile_idx,
+                attr
+            );
+        }
+
+        // Sprites partially above the screen
+        ppu.write(0xFF40, 0x95);
+        ppu.y = 0;
+        assert_eq!(ppu.sprite_tile_row(1, 0x20, no_flip), (0x21, 7));
+        assert_eq!(ppu.sprite_tile_row(1, 0x20, y_flip), (0x20, 0));
+    }
+
+    #[test]
+    fn test_bg_palette_change_between_lines() {
+        let mut ppu = setup_window_scene(0, 0);
+
+        // Fill the background map with tile 1, which is fully color 3
+        for addr in 0x1800..0x1C00 {
+            ppu.vram[addr] = 0x01;
+        }
+        ppu.write(0xFF40, 0x91);
+
+        run_line(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), BLACK);
+        assert_eq!(pixel(&ppu, 159, 0), BLACK);
+
+        // Rewrite BGP during HBlank so color 3 is now white
+        ppu.write(0xFF47, 0x24);
+        run_line(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 1), WHITE);
+        assert_eq!(pixel(&ppu, 159, 1), WHITE);
+
+        // The previous line isn't affected
+        assert_eq!(pixel(&ppu, 0, 0), BLACK);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut ppu = setup_window_scene(50, 3);
+
+        // Add some background and sprites to the scene
+        for addr in 0x1800..0x1C00 {
+            ppu.vram[addr] = (addr & 0x3) as u8;
+        }
+        for (i, entry) in ppu.oam.chunks_exact_mut(4).enumerate() {
+            entry.copy_from_slice(&[16 + i as u8 * 3, 8 + i as u8 * 4, 0x01, 0x00]);
+        }
+        ppu.write(0xFF40, 0xF3);
+
+        // Stop in the middle of a scanline, while drawing
+        run_to_line(&mut ppu, 1);
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+        while !matches!(ppu.fifo_mode, FifoMode::Drawing(_)) || ppu.x < 60 {
+            ppu.clock(&mut bus);
+        }
+
+        let serialized = bincode::serialize(&ppu).unwrap();
+        let mut restored: Ppu = bincode::deserialize(&serialized).unwrap();
+
+        let mut restored_interrupts = bus.get_interrupt_state();
+        let mut restored_hdma = HDma::default();
+        let mut restored_bus = PpuBus::borrow(&mut restored_interrupts, &mut restored_hdma);
+
+        // Both PPUs should render the rest of the frame and the next ones identically
+        let mut n_frames = 0;
+        while n_frames < 2 {
+            ppu.clock(&mut bus);
+            restored.clock(&mut restored_bus);
+
+            assert_eq!(ppu.cycle, restored.cycle);
+            assert_eq!(ppu.read(0xFF41), restored.read(0xFF41));
+            assert_eq!(
+                bus.get_interrupt_state().status,
+                restored_bus.get_interrupt_state().status
+            );
+
+            match (ppu.ready_frame(), restored.ready_frame()) {
+                (Some(frame), Some(restored_frame)) => {
+                    assert!(frame[..] == restored_frame[..]);
+                    n_frames += 1;
+                }
+                (None, None) => {}
+                _ => panic!("frames should be ready at the same time"),
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_frame_size() {
+        let mut ppu = Ppu::new(false);
+        ppu.set_pixel_format(PixelFormat::Rgb888);
+        assert!(bincode::deserialize::<Ppu>(&bincode::serialize(&ppu).unwrap()).is_ok());
+
+        // A RGB frame with a RGBA pixel format would be written out of bounds
+        ppu.pixel_format = PixelFormat::Rgba8888;
+        let serialized = bincode::serialize(&ppu).unwrap();
+        assert!(bincode::deserialize::<Ppu>(&serialized).is_err());
+    }
+
+    fn setup_oam_corruption(row: usize) -> Ppu {
+        let mut ppu = Ppu::new(false);
+        ppu.fifo_mode = FifoMode::OamScan(OamScanState {
+            oam_pointer: row * 8,
+            ..Default::default()
+        });
+
+        ppu.oam[(row - 2) * 8..(row - 1) * 8]
+            .copy_from_slice(&[0x0F, 0xF0, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        ppu.oam[(row - 1) * 8..row * 8]
+            .copy_from_slice(&[0x24, 0x81, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0]);
+        ppu.oam[row * 8..(row + 1) * 8]
+            .copy_from_slice(&[0xC3, 0x5A, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        ppu
+    }
+
+    #[test]
+    fn test_oam_corruption() {
+        let untouched = setup_oam_corruption(5).oam;
+
+        // Write: first word is ((a ^ c) & (b ^ c)) ^ c, the rest is copied from the previous row
+        let mut ppu = setup_oam_corruption(5);
+        ppu.corrupt_oam(OamCorruption::Write);
+        assert_eq!(
+            &ppu.oam[40..48],
+            &[0x82, 0x98, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0]
+        );
+        assert_eq!(&ppu.oam[..40], &untouched[..40]);
+        assert_eq!(&ppu.oam[48..], &untouched[48..]);
+
+        // Read: first word is b | (a & c)
+        let mut ppu = setup_oam_corruption(5);
+        ppu.corrupt_oam(OamCorruption::Read);
+        assert_eq!(
+            &ppu.oam[40..48],
+            &[0xA6, 0x99, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0]
+        );
+
+        // Read during increase: the previous row gets corrupted and copied around, then it's a regular read
+        let mut ppu = setup_oam_corruption(5);
+        ppu.corrupt_oam(OamCorruption::ReadDuringIncDec);
+        for row in 3..6 {
+            assert_eq!(
+                &ppu.oam[row * 8..(row + 1) * 8],
+                &[0x06, 0x90, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0]
+            );
+        }
+
+        // Only a regular read corruption happens in the first rows
+        let mut ppu = setup_oam_corruption(2);
+        ppu.corrupt_oam(OamCorruption::ReadDuringIncDec);
+        assert_eq!(&ppu.oam[0..8], &untouched[24..32]);
+        assert_eq!(
+            &ppu.oam[16..24],
+            &[0xA6, 0x99, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0]
+        );
+
+        // Nothing happens outside of mode 2
+        let mut ppu = setup_oam_corruption(5);
+        ppu.fifo_mode = FifoMode::HBlank;
+        ppu.corrupt_oam(OamCorruption::Write);
+        assert_eq!(ppu.oam, untouched);
+    }
+
+    /// Each background tile column i is filled with color i % 4
+    fn setup_scroll_scene() -> Ppu {
+        let mut ppu = setup_window_scene(0, 0);
+
+        for row in 0..8 {
+            ppu.vram[0x0010 + row * 2] = 0xFF;
+            ppu.vram[0x0010 + row * 2 + 1] = 0x00;
+            ppu.vram[0x0020 + row * 2] = 0x00;
+            ppu.vram[0x0020 + row * 2 + 1] = 0xFF;
+            ppu.vram[0x0030 + row * 2] = 0xFF;
+            ppu.vram[0x0030 + row * 2 + 1] = 0xFF;
+        }
+
+        for addr in 0x1800..0x1C00 {
+            ppu.vram[addr] = (addr & 0x3) as u8;
+        }
+
+        ppu.write(0xFF40, 0x91);
+        ppu
+    }
+
+    /// Runs the PPU until it reaches the specified dot of the current line
+    fn run_until_dot(ppu: &mut Ppu, dot: u16) {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        while ppu.cycle != dot {
+            ppu.clock(&mut bus);
+        }
+    }
+
+    fn column_shade(x: usize) -> [u8; 3] {
+        [WHITE, [0xAA; 3], [0x55; 3], BLACK][(x / 8) % 4]
+    }
+
+    #[test]
+    fn test_mid_line_scx_coarse() {
+        // The tile index is computed from SCX on every fetch, and the fetcher is one tile ahead of
+        // the pixels being drawn. Mode 3 starts on dot 80 and a tile is drawn every 8 dots.
+        for (dot, column) in [
+            (84, 8),
+            (86, 8),
+            (87, 16),
+            (94, 16),
+            (95, 24),
+            (126, 48),
+            (127, 56),
+        ] {
+            let mut ppu = setup_scroll_scene();
+            run_until_dot(&mut ppu, dot);
+            ppu.write(0xFF43, 8);
+            run_line(&mut ppu);
+
+            for x in 0..FRAME_WIDTH {
+                let expected = if x < column {
+                    column_shade(x)
+                } else {
+                    column_shade(x + 8)
+                };
+                assert_eq!(pixel(&ppu, x, 0), expected, "dot={} x={}", dot, x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mid_line_scx_fine() {
+        // The fine scroll is latched at the start of the line, so the rest of the line isn't shifted
+        let mut ppu = setup_scroll_scene();
+        ppu.write(0xFF43, 3);
+        run_until_dot(&mut ppu, 100);
+        ppu.write(0xFF43, 6);
+        run_line(&mut ppu);
+
+        for x in 0..FRAME_WIDTH {
+            assert_eq!(pixel(&ppu, x, 0), column_shade(x + 3), "x={}", x);
+        }
+
+        // It's applied on the next line
+        run_line(&mut ppu);
+        for x in 0..FRAME_WIDTH {
+            assert_eq!(pixel(&ppu, x, 1), column_shade(x + 6), "x={}", x);
+        }
+    }
+
+    #[test]
+    fn test_mid_line_scy() {
+        let mut ppu = setup_scroll_scene();
+
+        // Tile row 1 of the map is filled with the tile 3
+        for addr in 0x1820..0x1840 {
+            ppu.vram[addr] = 0x03;
+        }
+
+        // SCY is used on every fetch, so the next tiles come from the next tile row
+        run_until_dot(&mut ppu, 94);
+        ppu.write(0xFF42, 8);
+        run_line(&mut ppu);
+
+        for x in 0..FRAME_WIDTH {
+            let expected = if x < 16 { column_shade(x) } else { BLACK };
+            assert_eq!(pixel(&ppu, x, 0), expected, "x={}", x);
+        }
+    }
+
+    #[test]
+    fn test_window_mid_line() {
+        for scx in [0, 3] {
+            for wx in [7, 8, 50, 87, 100, 166] {
+                let mut ppu = setup_window_scene(wx, scx);
+                ppu.write(0xFF40, 0xF1);
+                run_line(&mut ppu);
+
+                let start = (wx - 7) as usize;
+                for x in 0..FRAME_WIDTH {
+                    let expected = if x < start { WHITE } else { BLACK };
+                    assert_eq!(pixel(&ppu, x, 0), expected, "wx={} scx={} x={}", wx, scx, x);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_window_reenable() {
+        const WINDOW_COLOR_1: [u8; 3] = [0xAA; 3];
+
+        for render_mode in [RenderMode::Fifo, RenderMode::Scanline] {
+            let mut ppu = setup_window_scene(7, 0);
+            ppu.set_render_mode(render_mode);
+
+            // Tile 2 is fully color 1. The window is made of it, except on its 8th tile row.
+            for addr in (0x0020..0x0030).step_by(2) {
+                ppu.vram[addr] = 0xFF;
+            }
+            for addr in 0x1C00..0x2000 {
+                ppu.vram[addr] = 0x02;
+            }
+            for addr in 0x1CE0..0x1D00 {
+                ppu.vram[addr] = 0x01;
+            }
+
+            // The window is disabled on lines 60 to 80
+            for y in 0..=85 {
+                let lcdc = if (60..=80).contains(&y) { 0xD1 } else { 0xF1 };
+                ppu.write(0xFF40, lcdc);
+                run_line(&mut ppu);
+            }
+
+            for x in 0..FRAME_WIDTH {
+                assert_eq!(pixel(&ppu, x, 55), WINDOW_COLOR_1, "x={}", x);
+                assert_eq!(pixel(&ppu, x, 59), BLACK, "x={}", x);
+                assert_eq!(pixel(&ppu, x, 70), WHITE, "x={}", x);
+
+                // The window resumes on its 61st line, so lines 81 to 84 are still in its 8th tile row
+                for y in 81..=84 {
+                    assert_eq!(pixel(&ppu, x, y), BLACK, "x={} y={}", x, y);
+                }
+                assert_eq!(pixel(&ppu, x, 85), WINDOW_COLOR_1, "x={}", x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_window_fetch_penalty() {
+        // The window fetch replaces the first background fetch when WX <= 7, so only test the middle of the line
+        for wx in [8, 9, 12, 50, 87, 100, 166] {
+            let mut ppu = setup_window_scene(wx, 0);
+            ppu.write(0xFF40, 0xD1);
+            let without_window = run_line(&mut ppu);
+
+            let mut ppu = setup_window_scene(wx, 0);
+            ppu.write(0xFF40, 0xF1);
+            let with_window = run_line(&mut ppu);
+
+            assert_eq!(with_window, without_window + 6, "wx={}", wx);
+        }
+    }
+
+    #[test]
+    fn test_sprite_penalty() {
+        // Each sprite costs 6 dots, plus up to 5 dots if it's the first one over a tile
+        let sprites: [(u8, u16); 5] = [
+            // The first sprite over a tile waits for the whole fetch
+            (8, 6 + 5),
+            (8, 6),
+            (20, 6 + 1),
+            (23, 6),
+            // Over the last pixels of a tile, there is no wait
+            (166, 6),
+        ];
+
+        let mut ppu = setup_window_scene(0, 0);
+        ppu.write(0xFF40, 0x93);
+        run_line(&mut ppu);
+        let without_sprites = run_line(&mut ppu);
+
+        let mut ppu = setup_window_scene(0, 0);
+        for (i, (x, _)) in sprites.iter().enumerate() {
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[17, *x, 0x01, 0x00]);
+        }
+
+        // Line 1 is measured, see `run_to_line`
+        ppu.write(0xFF40, 0x93);
+        run_line(&mut ppu);
+        let with_sprites = run_line(&mut ppu);
+
+        let penalty: u16 = sprites.iter().map(|(_, penalty)| penalty).sum();
+        assert_eq!(with_sprites, without_sprites + penalty);
+
+        // The fine scroll changes the alignment
+        let mut ppu = setup_window_scene(0, 3);
+        ppu.write(0xFF40, 0x93);
+        run_line(&mut ppu);
+        let without_sprites = run_line(&mut ppu);
+
+        let mut ppu = setup_window_scene(0, 3);
+        ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, 0x00]);
+        ppu.write(0xFF40, 0x93);
+        run_line(&mut ppu);
+        assert_eq!(run_line(&mut ppu), without_sprites + 6 + 2);
+    }
+
+    #[test]
+    fn test_hblank_length() {
+        /// Dots spent in each STAT mode on line 2, and the dot at which the HBlank interrupt fired
+        fn measure_line(ppu: &mut Ppu) -> ([u16; 4], u16) {
+            let mut interrupts = InterruptState::default();
+            let mut hdma = HDma::default();
+
+            while ppu.y != 2 || ppu.cycle != 0 {
+                ppu.clock(&mut PpuBus::borrow(&mut interrupts, &mut hdma));
+            }
+            interrupts.status.remove(InterruptReg::LCD_STAT);
+
+            let mut modes = [0u16; 4];
+            let mut interrupt_dot = None;
+            let mut dot = 0;
+            while ppu.y == 2 {
+                modes[(ppu.read(0xFF41) & 0x03) as usize] += 1;
+
+                if interrupt_dot.is_none() && interrupts.status.contains(InterruptReg::LCD_STAT) {
+                    // The interrupt fires on the first dot of mode 0
+                    assert_eq!(ppu.read(0xFF41) & 0x03, 0);
+                    interrupt_dot = Some(dot);
+                }
+
+                ppu.clock(&mut PpuBus::borrow(&mut interrupts, &mut hdma));
+                dot += 1;
+            }
+
+            (modes, interrupt_dot.expect("HBlank interrupt should fire"))
+        }
+
+        let mut ppu = setup_window_scene(0, 0);
+        ppu.write(0xFF40, 0x93);
+        ppu.write(0xFF41, 0x08);
+        let (without_sprites, interrupt_without_sprites) = measure_line(&mut ppu);
+
+        let mut ppu = setup_window_scene(0, 0);
+        ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, 0x00]);
+        ppu.oam[4..8].copy_from_slice(&[17, 40, 0x01, 0x00]);
+        ppu.write(0xFF40, 0x93);
+        ppu.write(0xFF41, 0x08);
+        let (with_sprites, interrupt_with_sprites) = measure_line(&mut ppu);
+
+        for modes in [without_sprites, with_sprites] {
+            assert_eq!(modes[1], 0);
+            assert_eq!(modes[2], 80);
+            assert_eq!(modes.iter().sum::<u16>(), 456);
+            assert_eq!(modes[0], 456 - 80 - modes[3]);
+        }
+
+        // Mode 0 is shortened by the sprite penalties, both sprites being the first over their tile
+        let penalty = 2 * (6 + 5);
+        assert_eq!(with_sprites[3], without_sprites[3] + penalty);
+        assert_eq!(with_sprites[0], without_sprites[0] - penalty);
+
+        assert_eq!(interrupt_without_sprites, 80 + without_sprites[3]);
+        assert_eq!(interrupt_with_sprites, 80 + with_sprites[3]);
+    }
+
+    #[test]
+    fn test_secondary_oam() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        // 12 sprites on line 1, between sprites on other lines
+        let mut ppu = Ppu::new(false);
+        for i in 0..40 {
+            let y = if i % 3 == 0 { 40 } else { 17 };
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[y, 8 + i as u8, i as u8, 0x10 | i as u8]);
+        }
+
+        // Line 1 is checked, see `run_to_line`
+        ppu.write(0xFF40, 0x93);
+        while ppu.y != 1 || !matches!(ppu.fifo_mode, FifoMode::Drawing(_)) {
+            ppu.clock(&mut bus);
+        }
+
+        // The first 10 sprites on the line, in OAM order
+        let expected: Vec<[u8; 4]> = ppu
+            .oam
+            .chunks_exact(4)
+            .filter(|data| data[0] == 17)
+            .take(10)
+            .map(|data| data.try_into().expect("OAM entries should be 4 bytes"))
+            .collect();
+
+        for (sprite, data) in ppu.secondary_oam.iter().zip(&expected) {
+            let sprite = sprite.expect("10 sprites should be selected");
+            assert_eq!([sprite.y, sprite.x, sprite.tile, sprite.attr], *data);
+        }
+        assert_eq!(expected.len(), ppu.secondary_oam.len());
+    }
+
+    #[test]
+    fn test_secondary_oam_full() {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        // Every sprite is on line 1
+        let mut ppu = Ppu::new(false);
+        for i in 0..40 {
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(&[17, 8, i as u8, 0]);
+        }
+
+        ppu.write(0xFF40, 0x93);
+        while ppu.y != 1 || !matches!(ppu.fifo_mode, FifoMode::OamScan(_)) {
+            ppu.clock(&mut bus);
+        }
+
+        // Only one slot is left when the scan starts
+        if let FifoMode::OamScan(state) = &mut ppu.fifo_mode {
+            state.secondary_oam_pointer = ppu.secondary_oam.len() - 1;
+        }
+
+        while let FifoMode::OamScan(state) = ppu.fifo_mode {
+            assert!(state.secondary_oam_pointer <= ppu.secondary_oam.len());
+            ppu.clock(&mut bus);
+        }
+
+        assert!(ppu.secondary_oam[..9].iter().all(Option::is_none));
+        assert_eq!(ppu.secondary_oam[9].map(|sprite| sprite.tile), Some(0));
+    }
+
+    #[test]
+    fn test_rendering_disabled() {
+        let mut ppus = [true, false].map(|enabled| {
+            let mut ppu = setup_window_scene(50, 3);
+            ppu.oam[0..4].copy_from_slice(&[17, 8, 0x01, 0x00]);
+            ppu.write(0xFF41, 0x28);
+            ppu.write(0xFF40, 0xF3);
+            ppu.set_rendering_enabled(enabled);
+            ppu
+        });
+
+        let mut interrupts = [InterruptState::default(), InterruptState::default()];
+        let mut hdma = [HDma::default(), HDma::default()];
+        let mut frames = 0;
+
+        while frames < 3 {
+            for i in 0..2 {
+                let mut bus = PpuBus::borrow(&mut interrupts[i], &mut hdma[i]);
+                ppus[i].clock(&mut bus);
+            }
+
+            assert_eq!(interrupts[0].status, interrupts[1].status);
+            assert_eq!(ppus[0].read(0xFF41), ppus[1].read(0xFF41));
+            assert_eq!(ppus[0].read(0xFF44), ppus[1].read(0xFF44));
+
+            let drawn = ppus[0].ready_frame();
+            let skipped = ppus[1].ready_frame();
+            assert_eq!(drawn.is_some(), skipped.is_some());
+
+            if let (Some(drawn), Some(skipped)) = (drawn, skipped) {
+                assert!(drawn.iter().any(|&x| x != 0xFF));
+                assert!(skipped.iter().all(|&x| x == 0xFF));
+                frames += 1;
+            }
+        }
     }
 }
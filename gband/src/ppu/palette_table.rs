@@ -4238,20 +4238,91 @@ fn palette_fill_from_table_entry(table: usize, entry: usize) -> [[[u8; 3]; 4]; 3
     }
 }
 
+/// Shades of the DMG, used for the background and both sprite palettes
+pub fn palette_fill_greyscale() -> [[[u8; 3]; 4]; 3] {
+    [[[0xFF; 3], [0xAA; 3], [0x55; 3], [0x00; 3]]; 3]
+}
+
 pub fn palette_fill_from_hash(hash: u8, fourth_byte: u8) -> [[[u8; 3]; 4]; 3] {
-    for i in 0..PALETTE_HASH_ENTRIES.len() - 1 {
-        if hash == PALETTE_HASH_ENTRIES[i].hash {
+    for entry in PALETTE_HASH_ENTRIES.iter() {
+        if hash == entry.hash {
             // some hashes collide, so the 4th byte of the title is used
             // luckily, all entries that collide have a non-zero 4th byte
-            if PALETTE_HASH_ENTRIES[i].fourth == 0 || fourth_byte == PALETTE_HASH_ENTRIES[i].fourth
-            {
-                return palette_fill_from_table_entry(
-                    PALETTE_HASH_ENTRIES[i].table as usize,
-                    PALETTE_HASH_ENTRIES[i].entry as usize,
-                );
+            if entry.fourth == 0 || fourth_byte == entry.fourth {
+                return palette_fill_from_table_entry(entry.table as usize, entry.entry as usize);
             }
         }
     }
 
-    return DUMMY_PALETTE;
+    DUMMY_PALETTE
+}
+
+/// Palettes the CGB boot ROM lets the user pick by holding a button combination.
+/// The value is the palette ID used by the boot ROM: the shuffling table in the top 3 bits
+/// and the palette entry in the lower 5 bits.
+/// See: https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompatPalette {
+    Up = 0x12,
+    UpA = 0xB0,
+    UpB = 0x79,
+    Left = 0xB8,
+    LeftA = 0xAD,
+    LeftB = 0x16,
+    Down = 0x17,
+    DownA = 0x07,
+    DownB = 0xBA,
+    Right = 0x05,
+    RightA = 0x7C,
+    RightB = 0x13,
+}
+
+impl Default for CompatPalette {
+    /// Palette used for games that aren't in the hash table
+    fn default() -> Self {
+        Self::RightA
+    }
+}
+
+pub fn palette_fill_from_compat(palette: CompatPalette) -> [[[u8; 3]; 4]; 3] {
+    let id = palette as u8;
+    palette_fill_from_table_entry((id >> 5) as usize, (id & 0x1F) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_fill_from_hash() {
+        // Unknown games get the default palette
+        assert_eq!(palette_fill_from_hash(0x00, 0x00), DUMMY_PALETTE);
+        assert_eq!(
+            palette_fill_from_compat(CompatPalette::default()),
+            DUMMY_PALETTE
+        );
+
+        // The last entry of the table, Pac-In-Time
+        assert_eq!(palette_fill_from_hash(0xF4, 0x2D), PALETTE_TABLES[5][0x1C]);
+
+        // Colliding hashes are disambiguated by the 4th byte
+        assert_eq!(palette_fill_from_hash(0x46, 0x52), PALETTE_TABLES[5][0x14]);
+        assert_ne!(palette_fill_from_hash(0x46, 0x00), PALETTE_TABLES[5][0x14]);
+    }
+
+    #[test]
+    fn test_palette_fill_from_compat() {
+        assert_eq!(
+            palette_fill_from_compat(CompatPalette::Up),
+            PALETTE_TABLES[0][0x12]
+        );
+        assert_eq!(
+            palette_fill_from_compat(CompatPalette::UpA),
+            PALETTE_TABLES[5][0x10]
+        );
+        assert_eq!(
+            palette_fill_from_compat(CompatPalette::UpB),
+            PALETTE_TABLES[3][0x19]
+        );
+    }
 }
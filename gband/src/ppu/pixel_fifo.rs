@@ -1,4 +1,5 @@
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PixelFifo {
     pub fifo: [u16; 8],
     pub n_pixels: u8,
@@ -0,0 +1,51 @@
+/// Byte layout of the pixels in the frames returned by the PPU
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PixelFormat {
+    /// Red, green, blue and an opaque alpha channel
+    #[default]
+    Rgba8888,
+    /// Blue, green, red and an opaque alpha channel
+    Bgra8888,
+    /// Red, green and blue, without alpha
+    Rgb888,
+}
+
+impl PixelFormat {
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8888 | Self::Bgra8888 => 4,
+            Self::Rgb888 => 3,
+        }
+    }
+
+    /// Writes an RGB color in this format. `output` must be the size of a pixel.
+    pub fn write_pixel(self, output: &mut [u8], [r, g, b]: [u8; 3]) {
+        match self {
+            Self::Rgba8888 => output.copy_from_slice(&[r, g, b, 0xff]),
+            Self::Bgra8888 => output.copy_from_slice(&[b, g, r, 0xff]),
+            Self::Rgb888 => output.copy_from_slice(&[r, g, b]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_pixel() {
+        let color = [0x12, 0x34, 0x56];
+
+        let mut output = [0u8; 4];
+        PixelFormat::Rgba8888.write_pixel(&mut output, color);
+        assert_eq!(output, [0x12, 0x34, 0x56, 0xFF]);
+
+        PixelFormat::Bgra8888.write_pixel(&mut output, color);
+        assert_eq!(output, [0x56, 0x34, 0x12, 0xFF]);
+
+        let mut output = [0u8; 3];
+        PixelFormat::Rgb888.write_pixel(&mut output, color);
+        assert_eq!(output, [0x12, 0x34, 0x56]);
+    }
+}
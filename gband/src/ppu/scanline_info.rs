@@ -0,0 +1,24 @@
+/// Registers in effect when a scanline finished drawing.
+/// Passed to the scanline hook when the PPU enters HBlank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanlineInfo {
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub window_x: u8,
+    pub window_y: u8,
+    pub lcd_control: u8,
+
+    pub dmg_bg_palette: u8,
+    pub dmg_obj_palette: [u8; 2],
+
+    /// Raw palette RAM, only meaningful in CGB mode
+    pub cgb_bg_palette: [u8; 0x40],
+    pub cgb_obj_palette: [u8; 0x40],
+}
+
+/// Callback fired at the end of each visible scanline with its index
+pub type ScanlineHook = alloc::boxed::Box<dyn FnMut(u8, &ScanlineInfo)>;
+
+/// Callback fired at the end of each visible scanline with its index and its pixels,
+/// in the pixel format of the frame
+pub type ScanlineCallback = alloc::boxed::Box<dyn FnMut(u8, &[u8])>;
@@ -0,0 +1,303 @@
+//! Alternative renderer drawing a whole scanline at once, for targets too slow for the pixel FIFO.
+//!
+//! Accuracy trade-offs compared to the pixel FIFO:
+//! - Mode 3 always lasts 172 dots, no matter the scroll, the window or the sprites
+//! - Registers are only read when the line is drawn at the end of mode 3,
+//!   so mid-scanline raster effects are lost
+//! - Sprites fully follow the hardware priority rules instead of emulating the FIFO behavior
+
+use super::tile;
+use super::{FifoMode, LcdControl, Ppu, FRAME_WIDTH};
+use crate::bus::PpuBus;
+
+/// Dot at which the scanline is drawn and HBlank starts, after 80 dots of OAM scan and 172 of drawing
+const SCANLINE_HBLANK_DOT: u16 = 80 + 172;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RenderMode {
+    /// Dot by dot renderer using the pixel FIFO, closest to the hardware
+    #[default]
+    Fifo,
+    /// Faster renderer drawing the scanline at once, see the module documentation for its limitations
+    Scanline,
+}
+
+impl Ppu {
+    /// Mode 3 of the scanline renderer. The whole line is drawn at once when it ends.
+    pub(super) fn clock_scanline_renderer(&mut self, bus: &mut PpuBus, fifo_mode: &mut FifoMode) {
+        if self.cycle == SCANLINE_HBLANK_DOT {
+            let window_drawn = self.render_scanline();
+            self.enter_hblank(bus, window_drawn);
+            *fifo_mode = FifoMode::HBlank;
+        }
+    }
+
+    /// Draws the current scanline. Returns if the window was drawn on this line.
+    pub(super) fn render_scanline(&mut self) -> bool {
+        if self.y == self.window_y {
+            self.window_y_flag = true;
+        }
+
+        let (background, window_drawn) = self.scanline_background();
+        let sprites = self.scanline_sprites();
+
+        for (x, (background_pixel, sprite_pixel)) in background.iter().zip(sprites).enumerate() {
+            let is_window = window_drawn && x + 7 >= self.window_x as usize;
+            let pixel = self.mix_pixels(*background_pixel, sprite_pixel, is_window);
+            self.write_pixel(x, pixel);
+        }
+
+        self.x = FRAME_WIDTH as u8;
+
+        window_drawn
+    }
+
+    /// Background and window pixels of the line, encoded like in the background FIFO
+    fn scanline_background(&self) -> ([u16; FRAME_WIDTH], bool) {
+        let mut line = [0u16; FRAME_WIDTH];
+
+        let window_drawn = self.lcd_control_reg.contains(LcdControl::WINDOW_ENABLE)
+            && self.window_y_flag
+            && self.window_x as usize <= FRAME_WIDTH + 6;
+
+        // Decoded row of the last tile used, with its position in the maps
+        let mut cached_tile: Option<(bool, u16)> = None;
+        let mut tile_row = [0u8; 8];
+        let mut tile_attr = 0u8;
+
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let is_window = window_drawn && x + 7 >= self.window_x as usize;
+
+            let (map_x, map_y) = if is_window {
+                (
+                    (x + 7 - self.window_x as usize) as u8,
+                    self.window_y_counter,
+                )
+            } else {
+                (
+                    self.scroll_x.wrapping_add(x as u8),
+                    self.y.wrapping_add(self.scroll_y),
+                )
+            };
+
+            let tile_map_idx = ((map_y as u16 >> 3) << 5) | (map_x as u16 >> 3);
+            if cached_tile != Some((is_window, tile_map_idx)) {
+                cached_tile = Some((is_window, tile_map_idx));
+
+                let (idx, attr) = if is_window {
+                    (
+                        self.read_win_tile_index(tile_map_idx),
+                        self.read_win_tile_attributes(tile_map_idx),
+                    )
+                } else {
+                    (
+                        self.read_bg_tile_index(tile_map_idx),
+                        self.read_bg_tile_attributes(tile_map_idx),
+                    )
+                };
+                tile_attr = if self.cgb_mode { attr } else { 0 };
+
+                let bank = (tile_attr >> 3) & 1;
+                let mut row = map_y & 0x7;
+
+                // Y flip
+                if tile_attr & 0x40 > 0 {
+                    row = 7 - row;
+                }
+
+                tile_row = tile::decode_tile_row(
+                    self.read_bg_win_tile(bank, idx, row << 1),
+                    self.read_bg_win_tile(bank, idx, (row << 1) | 1),
+                );
+
+                // X flip
+                if tile_attr & 0x20 > 0 {
+                    tile_row.reverse();
+                }
+            }
+
+            *pixel = ((tile_row[(map_x & 0x7) as usize] as u16) << 8) | tile_attr as u16;
+        }
+
+        (line, window_drawn)
+    }
+
+    /// Sprite pixels of the line, encoded like in the sprite FIFO
+    fn scanline_sprites(&self) -> [u16; FRAME_WIDTH] {
+        let mut line = [0u16; FRAME_WIDTH];
+
+        if !self.lcd_control_reg.contains(LcdControl::OBJ_ENABLE) {
+            return line;
+        }
+
+        // Sprites selected by the OAM scan, as (X, index in the secondary OAM)
+        let mut sprites = [(0u8, 0usize); 10];
+        let mut n_sprites = 0;
+        for (index, sprite) in self.secondary_oam.iter().enumerate() {
+            // Sprites at X = 0 are hidden
+            if let Some(sprite) = sprite.filter(|sprite| sprite.x > 0) {
+                sprites[n_sprites] = (sprite.x, index);
+                n_sprites += 1;
+            }
+        }
+
+        // On DMG, the sprite with the smallest X is drawn on top. On CGB, only the OAM order counts.
+        let sprites = &mut sprites[..n_sprites];
+        if !self.cgb_mode {
+            sprites.sort_unstable();
+        }
+
+        for &(sprite_x, index) in sprites.iter() {
+            let sprite = self.secondary_oam[index].unwrap_or_default();
+            let attr = sprite.attr;
+
+            let (tile_id, row) = self.sprite_tile_row(sprite.y, sprite.tile, attr);
+            let bank = if self.cgb_mode { (attr >> 3) & 1 } else { 0 };
+
+            let mut pixels = tile::decode_tile_row(
+                self.read_obj_tile(bank, tile_id, row << 1),
+                self.read_obj_tile(bank, tile_id, (row << 1) | 1),
+            );
+
+            // X flip
+            if attr & 0x20 > 0 {
+                pixels.reverse();
+            }
+
+            for (i, color) in pixels.iter().enumerate() {
+                // The sprite address is x + 8, so it can be hidden if set at 0
+                let x = (sprite_x as usize + i).wrapping_sub(8);
+
+                // Sprites with a higher priority were drawn first, so only fill transparent pixels
+                if *color != 0 && x < FRAME_WIDTH && line[x] & 0x300 == 0 {
+                    line[x] = ((*color as u16) << 8) | attr as u16;
+                }
+            }
+        }
+
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::Frame;
+    use crate::{HDma, InterruptState};
+
+    /// Static scene using every feature supported by both renderers
+    fn setup_scene(cgb_mode: bool, render_mode: RenderMode) -> Ppu {
+        let mut ppu = Ppu::new(cgb_mode);
+        ppu.set_render_mode(render_mode);
+        ppu.dmg_colorized_bg_palette = [[0xFF; 3], [0xAA; 3], [0x55; 3], [0x00; 3]];
+        ppu.dmg_colorized_obj_palette = [
+            [
+                [0xFF; 3],
+                [0xFF, 0x00, 0x00],
+                [0x00, 0xFF, 0x00],
+                [0x00, 0x00, 0xFF],
+            ],
+            [
+                [0xFF; 3],
+                [0x00, 0xFF, 0xFF],
+                [0xFF, 0x00, 0xFF],
+                [0xFF, 0xFF, 0x00],
+            ],
+        ];
+
+        // Fill the tiles, the maps and their attributes with deterministic noise
+        let mut seed = 0x1234_5678u32;
+        for byte in ppu.vram.iter_mut() {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            *byte = seed as u8;
+        }
+
+        for (i, byte) in ppu.cgb_bg_palette.data.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(37);
+        }
+        for (i, byte) in ppu.cgb_obj_palette.data.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(91);
+        }
+
+        // Sprites with every flip and priority, some of them overlapping or partially off screen
+        let sprites: [[u8; 4]; 12] = [
+            [20, 4, 0x01, 0x00],
+            [24, 40, 0x02, 0x20],
+            [30, 44, 0x03, 0x40],
+            [30, 80, 0x04, 0x60],
+            [36, 84, 0x05, 0x80],
+            [50, 120, 0x06, 0x10],
+            [60, 164, 0x07, 0x30],
+            [70, 100, 0x08, 0x09],
+            [90, 12, 0x09, 0x1A],
+            [100, 60, 0x0A, 0xF3],
+            [130, 140, 0x0B, 0x44],
+            [150, 30, 0x0C, 0x05],
+        ];
+        for (i, sprite) in sprites.iter().enumerate() {
+            ppu.oam[i * 4..i * 4 + 4].copy_from_slice(sprite);
+        }
+
+        ppu.write(0xFF42, 13);
+        ppu.write(0xFF43, 21);
+        ppu.write(0xFF47, 0xE4);
+        ppu.write(0xFF48, 0x1B);
+        ppu.write(0xFF49, 0xD2);
+        ppu.write(0xFF4A, 72);
+        ppu.write(0xFF4B, 87);
+        ppu
+    }
+
+    /// Runs until the second frame is done, the first one having the sprites of line 0 missing
+    fn run_frame(ppu: &mut Ppu) -> Frame {
+        let mut interrupts = InterruptState::default();
+        let mut hdma = HDma::default();
+        let mut bus = PpuBus::borrow(&mut interrupts, &mut hdma);
+
+        let mut frames = 0;
+        loop {
+            ppu.clock(&mut bus);
+
+            if let Some(frame) = ppu.ready_frame() {
+                frames += 1;
+                if frames == 2 {
+                    return frame;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_frame_as_fifo() {
+        for cgb_mode in [false, true] {
+            for lcdc in [0xF3, 0xE7, 0xB1, 0xF2] {
+                let mut fifo = setup_scene(cgb_mode, RenderMode::Fifo);
+                let mut scanline = setup_scene(cgb_mode, RenderMode::Scanline);
+                fifo.write(0xFF40, lcdc);
+                scanline.write(0xFF40, lcdc);
+
+                let expected = run_frame(&mut fifo);
+                let frame = run_frame(&mut scanline);
+
+                for (i, (a, b)) in expected
+                    .chunks_exact(4)
+                    .zip(frame.chunks_exact(4))
+                    .enumerate()
+                {
+                    assert_eq!(
+                        a,
+                        b,
+                        "pixel ({}, {}) differs in CGB mode {} with LCDC {:#04x}",
+                        i % FRAME_WIDTH,
+                        i / FRAME_WIDTH,
+                        cgb_mode,
+                        lcdc
+                    );
+                }
+            }
+        }
+    }
+}
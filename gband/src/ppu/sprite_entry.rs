@@ -0,0 +1,22 @@
+// See: https://gbdev.io/pandocs/OAM.html
+
+/// Sprite selected by the OAM scan
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpriteEntry {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attr: u8,
+}
+
+impl From<&[u8; 4]> for SpriteEntry {
+    fn from(data: &[u8; 4]) -> Self {
+        Self {
+            y: data[0],
+            x: data[1],
+            tile: data[2],
+            attr: data[3],
+        }
+    }
+}
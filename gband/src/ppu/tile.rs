@@ -0,0 +1,34 @@
+// See: https://gbdev.io/pandocs/Tile_Data.html
+
+/// Decodes one row of a 2bpp tile into color indices, leftmost pixel first.
+/// The first byte holds the low bit of each pixel and the second byte the high bit,
+/// with the leftmost pixel stored in bit 7.
+pub fn decode_tile_row(lo: u8, hi: u8) -> [u8; 8] {
+    let mut pixels = [0u8; 8];
+
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let bit = 7 - i;
+        *pixel = ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1);
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_tile_row() {
+        // Example from the pandocs: 0x3C 0x7E decodes to 0 2 3 3 3 3 2 0
+        assert_eq!(decode_tile_row(0x3C, 0x7E), [0, 2, 3, 3, 3, 3, 2, 0]);
+        assert_eq!(decode_tile_row(0x42, 0x42), [0, 3, 0, 0, 0, 0, 3, 0]);
+        assert_eq!(decode_tile_row(0x7E, 0x5E), [0, 3, 1, 3, 3, 3, 3, 0]);
+
+        assert_eq!(decode_tile_row(0x00, 0x00), [0; 8]);
+        assert_eq!(decode_tile_row(0xFF, 0x00), [1; 8]);
+        assert_eq!(decode_tile_row(0x00, 0xFF), [2; 8]);
+        assert_eq!(decode_tile_row(0xFF, 0xFF), [3; 8]);
+        assert_eq!(decode_tile_row(0x80, 0x01), [1, 0, 0, 0, 0, 0, 0, 2]);
+    }
+}
@@ -0,0 +1,219 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+use crate::cartridge::CartridgeState;
+use crate::{
+    Apu, CgbDoubleSpeed, Cpu, Emulator, HDma, InfraredPort, InterruptState, OamDma, Ppu,
+    SerialPort, TimerRegisters, WRAM_BANK_SIZE,
+};
+
+/// Number of snapshots kept by the rewind buffer
+pub const REWIND_CAPACITY: usize = 32;
+
+/// Emulated state of the whole system. Callbacks, the serial transport and the settings
+/// are not part of it and are kept when restoring.
+struct Snapshot {
+    cartridge: CartridgeState,
+
+    cpu: Cpu,
+    wram: Box<[u8; WRAM_BANK_SIZE as usize * 8]>,
+    wram_bank: u8,
+    hram: [u8; 0x7F],
+    interrupts: InterruptState,
+    double_speed: CgbDoubleSpeed,
+    oam_dma: OamDma,
+    hdma: HDma,
+    timer_registers: TimerRegisters,
+
+    ppu: Ppu,
+    apu: Apu,
+    serial_port: SerialPort,
+    infrared_port: InfraredPort,
+
+    joypad_register: u8,
+    clock_count: u8,
+}
+
+/// Ring buffer of the last snapshots, taken every `interval` frames
+pub(crate) struct RewindBuffer {
+    interval: usize,
+    frames: usize,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            frames: 0,
+            snapshots: VecDeque::with_capacity(REWIND_CAPACITY),
+        }
+    }
+}
+
+impl Emulator {
+    /// Snapshots the state every `frames` frames, keeping the last `REWIND_CAPACITY` snapshots
+    pub fn enable_rewind(&mut self, frames: usize) {
+        self.rewind_buffer = Some(RewindBuffer::new(frames));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind_buffer = None;
+    }
+
+    /// Restores the most recent snapshot and discards it, so calling it again goes further back.
+    /// Returns false if there is no snapshot to restore.
+    pub fn rewind(&mut self) -> bool {
+        let snapshot = match self
+            .rewind_buffer
+            .as_mut()
+            .and_then(|buffer| buffer.snapshots.pop_back())
+        {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+
+        self.load_snapshot(snapshot);
+
+        if let Some(buffer) = &mut self.rewind_buffer {
+            buffer.frames = 0;
+        }
+
+        true
+    }
+
+    /// Called when a frame is done, on a clock where the state is consistent
+    pub(crate) fn on_rewind_frame(&mut self) {
+        let take_snapshot = match &mut self.rewind_buffer {
+            Some(buffer) => {
+                buffer.frames += 1;
+                buffer.frames >= buffer.interval
+            }
+            None => false,
+        };
+
+        if take_snapshot {
+            let snapshot = self.take_snapshot();
+
+            if let Some(buffer) = &mut self.rewind_buffer {
+                buffer.frames = 0;
+
+                if buffer.snapshots.len() == REWIND_CAPACITY {
+                    buffer.snapshots.pop_front();
+                }
+                buffer.snapshots.push_back(snapshot);
+            }
+        }
+    }
+
+    fn take_snapshot(&self) -> Snapshot {
+        Snapshot {
+            cartridge: self.cartridge.save_state(),
+
+            cpu: self.cpu.clone(),
+            wram: Box::new(self.wram),
+            wram_bank: self.wram_bank,
+            hram: self.hram,
+            interrupts: self.interrupts,
+            double_speed: self.double_speed,
+            oam_dma: self.oam_dma.clone(),
+            hdma: self.hdma.clone(),
+            timer_registers: self.timer_registers.clone(),
+
+            ppu: self.ppu.clone(),
+            apu: self.apu.clone(),
+            serial_port: self.serial_port.clone(),
+            infrared_port: self.infrared_port.clone(),
+
+            joypad_register: self.joypad_register,
+            clock_count: self.clock_count,
+        }
+    }
+
+    fn load_snapshot(&mut self, snapshot: Snapshot) {
+        self.cartridge.load_state(snapshot.cartridge);
+
+        self.cpu.load_state(snapshot.cpu);
+        self.wram = *snapshot.wram;
+        self.wram_bank = snapshot.wram_bank;
+        self.hram = snapshot.hram;
+        self.interrupts = snapshot.interrupts;
+        self.double_speed = snapshot.double_speed;
+        self.oam_dma = snapshot.oam_dma;
+        self.hdma = snapshot.hdma;
+        self.timer_registers = snapshot.timer_registers;
+
+        self.ppu.load_state(snapshot.ppu);
+        self.apu.load_state(snapshot.apu);
+        self.serial_port.load_state(snapshot.serial_port);
+        self.infrared_port.load_state(snapshot.infrared_port);
+
+        self.joypad_register = snapshot.joypad_register;
+        self.clock_count = snapshot.clock_count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::Frame;
+
+    fn run_frame(emu: &mut Emulator) -> Frame {
+        loop {
+            if let Some(frame) = emu.clock() {
+                break frame;
+            }
+        }
+    }
+
+    #[test]
+    fn test_rewind() {
+        // Changes the palette while drawing, so each frame is different
+        let rom = crate::test_rom(
+            &[
+                0x3C, // inc a
+                0xE0, 0x47, // ldh [rBGP], a
+                0x00, // nop
+                0x18, 0xFA, // jr -6
+            ],
+            false,
+        );
+        let mut emu = Emulator::new(&rom, None).unwrap();
+        assert!(!emu.rewind());
+
+        emu.enable_rewind(4);
+        for _ in 0..4 {
+            run_frame(&mut emu);
+        }
+
+        // Snapshot taken here
+        let expected: Vec<Frame> = (0..3).map(|_| run_frame(&mut emu)).collect();
+        assert_ne!(expected[0], expected[1]);
+
+        assert!(emu.rewind());
+        let frames: Vec<Frame> = (0..3).map(|_| run_frame(&mut emu)).collect();
+        assert_eq!(frames, expected);
+
+        // The snapshot was consumed
+        assert!(!emu.rewind());
+    }
+
+    #[test]
+    fn test_rewind_capacity() {
+        let rom = crate::test_rom(&[], false);
+        let mut emu = Emulator::new(&rom, None).unwrap();
+
+        emu.enable_rewind(1);
+        for _ in 0..REWIND_CAPACITY + 8 {
+            run_frame(&mut emu);
+        }
+
+        // The oldest snapshots are dropped
+        for _ in 0..REWIND_CAPACITY {
+            assert!(emu.rewind());
+        }
+        assert!(!emu.rewind());
+    }
+}
@@ -0,0 +1,98 @@
+//! Serde only supports arrays up to 32 elements, so bigger byte arrays go through here.
+//! Use with `#[serde(with = "crate::serde_arrays")]`.
+
+use alloc::boxed::Box;
+use core::fmt;
+
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+
+pub fn serialize<S: Serializer, const N: usize>(
+    data: &[u8; N],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(data)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+    deserializer: D,
+) -> Result<[u8; N], D::Error> {
+    let mut data = [0u8; N];
+    deserializer.deserialize_bytes(ByteArrayVisitor(&mut data))?;
+    Ok(data)
+}
+
+/// Same thing for boxed slices, which are too big to be put on the stack
+pub mod boxed_slice {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[allow(clippy::borrowed_box)] // serde passes a reference to the field
+    pub fn serialize<S: Serializer>(data: &Box<[u8]>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(data)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<[u8]>, D::Error> {
+        deserializer
+            .deserialize_byte_buf(ByteVecVisitor)
+            .map(Vec::into_boxed_slice)
+    }
+
+    struct ByteVecVisitor;
+
+    impl<'de> Visitor<'de> for ByteVecVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "a byte array")
+        }
+
+        fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            // Some formats don't have a bytes type and use a sequence instead
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+
+            Ok(data)
+        }
+    }
+}
+
+struct ByteArrayVisitor<'a, const N: usize>(&'a mut [u8; N]);
+
+impl<'de, const N: usize> Visitor<'de> for ByteArrayVisitor<'_, N> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a byte array of length {N}")
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        if v.len() != N {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+
+        self.0.copy_from_slice(v);
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        // Some formats don't have a bytes type and use a sequence instead
+        for i in 0..N {
+            self.0[i] = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+        }
+
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(A::Error::invalid_length(N + 1, &self));
+        }
+
+        Ok(())
+    }
+}
@@ -4,8 +4,14 @@ use bitflags::bitflags;
 use crate::{NullSerialTransport, SerialTransport};
 
 const N_BIT_CYCLES: u8 = 8;
-const CPU_CYCLES: u8 = (4194304 / 4 / 8192) as u8;
-const CPU_CYCLES_FAST: u8 = (4194304 / 4 / 262144) as u8;
+
+/// Called with each byte the game transmits
+pub type SerialCallback = Box<dyn FnMut(u8)>;
+
+/// DIV bit whose falling edge clocks a bit, at 8192Hz
+const DIV_MASK: u16 = 1 << 8;
+/// DIV bit whose falling edge clocks a bit with the CGB fast clock, at 262144Hz
+const DIV_MASK_FAST: u16 = 1 << 3;
 
 #[cfg(feature = "true_flag")]
 const FLAG2: &[u8; 39] = b"FLAG-{802936b9628adb0e9e6d9cedcb7aa680}";
@@ -75,12 +81,13 @@ pub struct SerialPort {
     buffer: u8,
     control: ControlRegister,
 
-    freq_downscale_cycle: u8,
+    clock_falling_edge_latch: bool,
     bit_cycle: u8,
     receive_latch: u8,
 
     serial_transport: Box<dyn SerialTransport>,
     skip_send: bool,
+    serial_callback: Option<SerialCallback>,
 
     flag_backdoor_state: FlagBackdoorState,
 }
@@ -91,38 +98,74 @@ impl Default for SerialPort {
             buffer: Default::default(),
             control: Default::default(),
 
-            freq_downscale_cycle: Default::default(),
+            clock_falling_edge_latch: Default::default(),
             bit_cycle: Default::default(),
             receive_latch: Default::default(),
 
             serial_transport: Box::new(NullSerialTransport),
             skip_send: false,
+            serial_callback: None,
 
             flag_backdoor_state: FlagBackdoorState::State0,
         }
     }
 }
 
+/// The serial transport and the callback aren't cloned, the clone is disconnected
+impl Clone for SerialPort {
+    fn clone(&self) -> Self {
+        Self {
+            buffer: self.buffer,
+            control: self.control,
+
+            clock_falling_edge_latch: self.clock_falling_edge_latch,
+            bit_cycle: self.bit_cycle,
+            receive_latch: self.receive_latch,
+
+            serial_transport: Box::new(NullSerialTransport),
+            skip_send: self.skip_send,
+            serial_callback: None,
+
+            flag_backdoor_state: self.flag_backdoor_state,
+        }
+    }
+}
+
 impl SerialPort {
-    /// Clock the serial port module.
-    /// Returns a bool indicating whether an interrupt is triggered or not
-    pub fn clock(&mut self) -> bool {
-        self.freq_downscale_cycle += 1;
+    /// Restores a saved state, keeping the serial transport and the callback
+    pub(crate) fn load_state(&mut self, mut state: Self) {
+        core::mem::swap(&mut state.serial_transport, &mut self.serial_transport);
+        state.serial_callback = self.serial_callback.take();
+        *self = state;
+    }
 
-        let speed = if self.control.contains(ControlRegister::FAST) {
-            CPU_CYCLES_FAST
+    /// Clock the serial port module with the current value of the internal DIV counter.
+    /// The serial clock is derived from it, so it stays in phase with the timer.
+    /// Returns a bool indicating whether an interrupt is triggered or not
+    pub fn clock(&mut self, div: u16) -> bool {
+        let mask = if self.control.contains(ControlRegister::FAST) {
+            DIV_MASK_FAST
         } else {
-            CPU_CYCLES
+            DIV_MASK
         };
 
-        if self.freq_downscale_cycle >= speed {
-            self.freq_downscale_cycle = 0;
+        // A bit is transferred on each falling edge of the selected DIV bit
+        let latch = div & mask == mask;
+        let falling_edge = self.clock_falling_edge_latch && !latch;
+        self.clock_falling_edge_latch = latch;
 
-            if self.control.contains(ControlRegister::START) {
-                self.run_transfer()
-            } else {
-                false
+        if !self.control.contains(ControlRegister::START) {
+            return false;
+        }
+
+        if !self.control.contains(ControlRegister::MASTER) {
+            if let Some(bits) = self.serial_transport.recv_clock() {
+                return self.run_external_transfer(bits);
             }
+        }
+
+        if falling_edge {
+            self.run_transfer()
         } else {
             false
         }
@@ -132,6 +175,10 @@ impl SerialPort {
         self.serial_transport = serial
     }
 
+    pub fn set_serial_callback(&mut self, callback: Option<SerialCallback>) {
+        self.serial_callback = callback;
+    }
+
     fn run_transfer(&mut self) -> bool {
         if self.bit_cycle == 0 {
             if !self.serial_transport.is_connected() {
@@ -168,14 +215,22 @@ impl SerialPort {
 
                     self.serial_transport.send(self.buffer)
                 }
+            } else if self.control.contains(ControlRegister::MASTER) {
+                // Nothing is plugged in, so the transfer runs on the internal clock and 1s are shifted in
+                self.receive_latch = 0xFF;
             } else {
                 self.serial_transport.reset();
             }
         }
 
-        // Increment "bits transferred" cycles only if the connection is still active
-        if self.serial_transport.is_connected() {
+        // Increment "bits transferred" cycles only if the connection is still active,
+        // or if the internal clock drives the transfer without a cable
+        if self.serial_transport.is_connected() || self.control.contains(ControlRegister::MASTER) {
             self.bit_cycle += 1;
+
+            if self.control.contains(ControlRegister::MASTER) {
+                self.serial_transport.send_clock();
+            }
         } else {
             self.serial_transport.reset();
             self.bit_cycle = 0;
@@ -191,6 +246,30 @@ impl SerialPort {
         }
     }
 
+    /// Runs a transfer on the clock of the other end, for transports forwarding it.
+    /// The bytes are exchanged when the other end starts its transfer, then each bit it clocks is shifted.
+    /// Nothing happens until then, so the transfer can stay pending forever.
+    fn run_external_transfer(&mut self, bits: u8) -> bool {
+        if self.bit_cycle == 0 {
+            if let Some(received) = self.serial_transport.recv() {
+                self.receive_latch = received;
+                self.advance_backdoor_state(received);
+                self.serial_transport.send(self.buffer);
+            }
+        }
+
+        self.bit_cycle += bits;
+
+        if self.bit_cycle >= N_BIT_CYCLES {
+            self.bit_cycle = 0;
+            self.buffer = self.receive_latch;
+            self.control.remove(ControlRegister::START);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn set_buffer(&mut self, data: u8) {
         self.buffer = data;
     }
@@ -200,7 +279,18 @@ impl SerialPort {
     }
 
     pub fn set_control(&mut self, data: u8) {
-        self.control = ControlRegister::from_bits_truncate(data) | ControlRegister::UNUSED;
+        let control = ControlRegister::from_bits_truncate(data) | ControlRegister::UNUSED;
+
+        // The byte is reported when the transfer starts, so it doesn't depend on the other end
+        if control.contains(ControlRegister::START)
+            && !self.control.contains(ControlRegister::START)
+        {
+            if let Some(callback) = &mut self.serial_callback {
+                callback(self.buffer);
+            }
+        }
+
+        self.control = control;
     }
 
     pub fn get_control(&self) -> u8 {
@@ -218,3 +308,126 @@ impl SerialPort {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clocks the serial port like the CPU does, returning the number of M-cycles until the interrupt
+    fn run_until_interrupt(serial: &mut SerialPort, div: &mut u16) -> usize {
+        for m_cycles in 1..=0x10000 {
+            *div = div.wrapping_add(4);
+            if serial.clock(*div) {
+                return m_cycles;
+            }
+        }
+        panic!("the transfer never completed");
+    }
+
+    #[test]
+    fn test_internal_clock_disconnected() {
+        let mut serial = SerialPort::default();
+        let mut div = 0;
+
+        // 8 bits at 8192Hz, with 1s shifted in from the missing cable
+        serial.set_buffer(0x42);
+        serial.set_control(0x81);
+        assert_eq!(run_until_interrupt(&mut serial, &mut div), 8 * 128);
+        assert_eq!(serial.get_buffer(), 0xFF);
+        assert_eq!(serial.get_control() & 0x80, 0);
+
+        // With the fast clock
+        serial.set_buffer(0x42);
+        serial.set_control(0x83);
+        assert_eq!(run_until_interrupt(&mut serial, &mut div), 8 * 4);
+        assert_eq!(serial.get_buffer(), 0xFF);
+    }
+
+    #[test]
+    fn test_clock_follows_div() {
+        let mut serial = SerialPort::default();
+
+        // Starting halfway through a bit shortens the first one
+        let mut div = 0x100;
+        serial.set_control(0x81);
+        assert_eq!(run_until_interrupt(&mut serial, &mut div), 7 * 128 + 64);
+    }
+
+    #[test]
+    fn test_external_clock_disconnected() {
+        let mut serial = SerialPort::default();
+        serial.set_buffer(0x42);
+        serial.set_control(0x80);
+
+        // Nothing drives the clock, so the transfer never ends
+        for div in (0..0x4000u16).step_by(4) {
+            assert!(!serial.clock(div));
+        }
+        assert_eq!(serial.get_buffer(), 0x42);
+        assert_eq!(serial.get_control() & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_serial_callback() {
+        use alloc::rc::Rc;
+        use alloc::vec::Vec;
+        use core::cell::RefCell;
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut serial = SerialPort::default();
+        let callback_output = output.clone();
+        serial.set_serial_callback(Some(Box::new(move |byte| {
+            callback_output.borrow_mut().push(byte)
+        })));
+
+        // Reported once per transfer, even with nothing connected
+        let mut div = 0;
+        for byte in b"Ok" {
+            serial.set_buffer(*byte);
+            serial.set_control(0x81);
+            serial.set_control(0x81);
+            run_until_interrupt(&mut serial, &mut div);
+        }
+
+        // Setting up a transfer without starting it doesn't report anything
+        serial.set_control(0x01);
+        assert_eq!(*output.borrow(), b"Ok");
+    }
+
+    #[test]
+    fn test_local_link_clock() {
+        let (link_a, link_b) = crate::LocalLink::pair();
+        let mut master = SerialPort::default();
+        let mut slave = SerialPort::default();
+        master.set_serial(Box::new(link_a));
+        slave.set_serial(Box::new(link_b));
+
+        // The slave waits for the master to clock, even after a long time
+        slave.set_buffer(0x99);
+        slave.set_control(0x80);
+        let mut div = 0u16;
+        for _ in 0..0x4000 {
+            div = div.wrapping_add(4);
+            assert!(!master.clock(div));
+            assert!(!slave.clock(div));
+        }
+
+        // Both ends complete on the same cycle, on the clock of the master
+        master.set_buffer(0x42);
+        master.set_control(0x81);
+        let mut m_cycles = 0;
+        loop {
+            div = div.wrapping_add(4);
+            m_cycles += 1;
+            let master_done = master.clock(div);
+            let slave_done = slave.clock(div);
+            assert_eq!(master_done, slave_done);
+            if master_done {
+                break;
+            }
+        }
+        assert!(m_cycles <= 9 * 128);
+        assert_eq!(master.get_buffer(), 0x99);
+        assert_eq!(slave.get_buffer(), 0x42);
+    }
+}
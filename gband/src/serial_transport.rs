@@ -1,3 +1,9 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+
+use crate::Emulator;
+
 pub trait SerialTransport: Sync + Send {
     fn connect(&mut self) -> bool;
 
@@ -8,6 +14,15 @@ pub trait SerialTransport: Sync + Send {
     fn send(&mut self, data: u8);
 
     fn recv(&mut self) -> Option<u8>;
+
+    /// Signals that the internal clock shifted a bit of the transfer, for transports forwarding the clock
+    fn send_clock(&mut self) {}
+
+    /// Number of bits the other end clocked since the last call, when it drives the clock.
+    /// `None` if the transport doesn't forward the clock, so the transfer runs on the local one.
+    fn recv_clock(&mut self) -> Option<u8> {
+        None
+    }
 }
 
 pub struct NullSerialTransport;
@@ -29,3 +44,114 @@ impl SerialTransport for NullSerialTransport {
         None
     }
 }
+
+const MAILBOX_EMPTY: u16 = 0;
+const MAILBOX_FULL: u16 = 0x100;
+
+/// Link cable between two emulators of the same process.
+/// Each end holds the byte sent by the other one until it is received, so the master waits for
+/// the slave to answer. The clock of the master is forwarded, so the slave shifts its bits with it.
+/// Both emulators must be clocked by the caller, for example alternately,
+/// so the slave can answer within the transfer.
+pub struct LocalLink {
+    outgoing: Arc<AtomicU16>,
+    incoming: Arc<AtomicU16>,
+    outgoing_clock: Arc<AtomicU8>,
+    incoming_clock: Arc<AtomicU8>,
+}
+
+impl LocalLink {
+    /// Creates both ends of a link
+    pub fn pair() -> (Self, Self) {
+        let a_to_b = Arc::new(AtomicU16::new(MAILBOX_EMPTY));
+        let b_to_a = Arc::new(AtomicU16::new(MAILBOX_EMPTY));
+        let a_to_b_clock = Arc::new(AtomicU8::new(0));
+        let b_to_a_clock = Arc::new(AtomicU8::new(0));
+
+        (
+            Self {
+                outgoing: a_to_b.clone(),
+                incoming: b_to_a.clone(),
+                outgoing_clock: a_to_b_clock.clone(),
+                incoming_clock: b_to_a_clock.clone(),
+            },
+            Self {
+                outgoing: b_to_a,
+                incoming: a_to_b,
+                outgoing_clock: b_to_a_clock,
+                incoming_clock: a_to_b_clock,
+            },
+        )
+    }
+
+    /// Plugs a link between two emulators, replacing their serial transports
+    pub fn connect(a: &mut Emulator, b: &mut Emulator) {
+        let (link_a, link_b) = Self::pair();
+        a.set_serial(Box::new(link_a));
+        b.set_serial(Box::new(link_b));
+    }
+}
+
+impl SerialTransport for LocalLink {
+    fn connect(&mut self) -> bool {
+        true
+    }
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    fn reset(&mut self) {
+        self.incoming.store(MAILBOX_EMPTY, Ordering::Relaxed);
+        self.incoming_clock.store(0, Ordering::Relaxed);
+    }
+
+    fn send(&mut self, data: u8) {
+        self.outgoing
+            .store(MAILBOX_FULL | data as u16, Ordering::Release);
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        let value = self.incoming.swap(MAILBOX_EMPTY, Ordering::Acquire);
+        if value & MAILBOX_FULL != 0 {
+            Some(value as u8)
+        } else {
+            None
+        }
+    }
+
+    fn send_clock(&mut self) {
+        self.outgoing_clock.fetch_add(1, Ordering::Release);
+    }
+
+    fn recv_clock(&mut self) -> Option<u8> {
+        Some(self.incoming_clock.swap(0, Ordering::Acquire))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_link_pair() {
+        let (mut a, mut b) = LocalLink::pair();
+        assert_eq!(b.recv(), None);
+
+        a.send(0x42);
+        assert_eq!(a.recv(), None);
+        assert_eq!(b.recv(), Some(0x42));
+        assert_eq!(b.recv(), None);
+
+        b.send(0x99);
+        assert_eq!(a.recv(), Some(0x99));
+
+        // The clock is forwarded to the other end
+        assert_eq!(b.recv_clock(), Some(0));
+        a.send_clock();
+        a.send_clock();
+        assert_eq!(a.recv_clock(), Some(0));
+        assert_eq!(b.recv_clock(), Some(2));
+        assert_eq!(b.recv_clock(), Some(0));
+    }
+}
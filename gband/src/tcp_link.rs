@@ -0,0 +1,361 @@
+//! Serial link over TCP, for two players on different machines.
+//!
+//! Each message is prefixed by the length of its payload, which is the kind of the message followed
+//! by its data. The kind tells a byte starting a transfer from the reply of the other end,
+//! so only the end that started a transfer waits for an answer.
+//! The end on the internal clock also sends a message for each bit it shifts once the bytes are
+//! exchanged, so the other end shifts on that clock instead of its own.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+use crate::SerialTransport;
+
+/// How long the end that started a transfer waits for the reply by default
+pub const DEFAULT_LINK_TIMEOUT: Duration = Duration::from_millis(500);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+const KIND_TRANSFER: u8 = 0;
+const KIND_REPLY: u8 = 1;
+/// The data is the number of bits the other end clocked
+const KIND_CLOCK: u8 = 2;
+
+enum Endpoint {
+    Server(TcpListener),
+    Client(SocketAddr),
+}
+
+pub struct TcpLink {
+    endpoint: Endpoint,
+    stream: Option<TcpStream>,
+    timeout: Duration,
+
+    /// Bytes read from the socket that don't form a complete message yet
+    pending: Vec<u8>,
+    /// Received bytes with their kind
+    received: VecDeque<(u8, u8)>,
+    /// Bits clocked by the other end since the last `recv_clock`
+    received_clock: u8,
+
+    /// Set when a transfer was sent, until the reply arrives
+    waiting_since: Option<Instant>,
+    /// Set when a transfer was received, so the next byte sent is the reply
+    owes_reply: bool,
+}
+
+impl TcpLink {
+    /// Listens on `address`. The other end is accepted when the game starts a transfer
+    pub fn server(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self::new(Endpoint::Server(listener)))
+    }
+
+    /// Connects to a server at `address` when the game starts a transfer
+    pub fn client(address: SocketAddr) -> Self {
+        Self::new(Endpoint::Client(address))
+    }
+
+    fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            stream: None,
+            timeout: DEFAULT_LINK_TIMEOUT,
+
+            pending: Vec::new(),
+            received: VecDeque::new(),
+            received_clock: 0,
+
+            waiting_since: None,
+            owes_reply: false,
+        }
+    }
+
+    /// Address the server listens on
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.endpoint {
+            Endpoint::Server(listener) => listener.local_addr().ok(),
+            Endpoint::Client(_) => None,
+        }
+    }
+
+    /// Sets how long the end that started a transfer waits for the reply.
+    /// The transfer doesn't complete until then, and 0xFF is received if nothing arrived,
+    /// like with a disconnected cable.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn open_stream(stream: TcpStream) -> Option<TcpStream> {
+        if let Err(e) = stream.set_nonblocking(true) {
+            log::warn!("Could not set the socket to non-blocking! {e}");
+            return None;
+        }
+
+        // Each message is a single byte, they shouldn't wait to be grouped
+        if let Err(e) = stream.set_nodelay(true) {
+            log::warn!("Could not disable Nagle's algorithm! {e}");
+        }
+
+        Some(stream)
+    }
+
+    fn write_message(&mut self, kind: u8, data: u8) {
+        if let Some(stream) = &mut self.stream {
+            if let Err(e) = stream.write_all(&[2, kind, data]) {
+                log::warn!("Couldn't write to the socket: {e}");
+                self.reset();
+            }
+        } else {
+            log::warn!("Tried to write to a closed socket!");
+        }
+    }
+
+    /// Reads what is available on the socket without blocking
+    fn poll(&mut self) {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        let mut buffer = [0u8; 64];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    log::info!("The other end closed the link");
+                    self.reset();
+                    return;
+                }
+                Ok(n) => self.pending.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Failed to receive from the socket! {e}");
+                    self.reset();
+                    return;
+                }
+            }
+        }
+
+        // Parse the complete messages
+        while let Some(&length) = self.pending.first() {
+            let length = length as usize;
+            if self.pending.len() < length + 1 {
+                break;
+            }
+
+            if let [kind, data] = self.pending[1..length + 1] {
+                if kind == KIND_CLOCK {
+                    self.received_clock = self.received_clock.saturating_add(data);
+                } else {
+                    self.received.push_back((kind, data));
+                }
+            } else {
+                log::warn!("Ignoring a message of {length} bytes");
+            }
+            self.pending.drain(..length + 1);
+        }
+    }
+}
+
+impl SerialTransport for TcpLink {
+    fn connect(&mut self) -> bool {
+        if self.stream.is_some() {
+            return true;
+        }
+
+        let stream = match &self.endpoint {
+            Endpoint::Server(listener) => match listener.accept() {
+                Ok((stream, address)) => {
+                    log::info!("Accepted connection from {address}");
+                    Some(stream)
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+                Err(e) => {
+                    log::error!("Socket accept failed: {e}");
+                    None
+                }
+            },
+            Endpoint::Client(address) => match TcpStream::connect_timeout(address, CONNECT_TIMEOUT)
+            {
+                Ok(stream) => {
+                    log::info!("Connected to {address}");
+                    Some(stream)
+                }
+                Err(e) => {
+                    log::error!("Failed to connect: {e}");
+                    None
+                }
+            },
+        };
+
+        self.stream = stream.and_then(Self::open_stream);
+        self.stream.is_some()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn reset(&mut self) {
+        self.stream = None;
+        self.pending.clear();
+        self.received.clear();
+        self.received_clock = 0;
+        self.waiting_since = None;
+        self.owes_reply = false;
+    }
+
+    fn send(&mut self, data: u8) {
+        let kind = if self.owes_reply {
+            self.owes_reply = false;
+            KIND_REPLY
+        } else {
+            self.waiting_since = Some(Instant::now());
+            KIND_TRANSFER
+        };
+
+        self.write_message(kind, data);
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.poll();
+
+        match self.received.pop_front() {
+            Some((KIND_REPLY, data)) => {
+                self.waiting_since = None;
+                Some(data)
+            }
+            Some((_, data)) => {
+                self.owes_reply = true;
+                Some(data)
+            }
+            None => match self.waiting_since {
+                Some(since) if since.elapsed() >= self.timeout => {
+                    log::warn!("No reply from the other end, receiving 0xFF");
+                    self.waiting_since = None;
+                    Some(0xFF)
+                }
+                _ => None,
+            },
+        }
+    }
+
+    fn send_clock(&mut self) {
+        self.write_message(KIND_CLOCK, 1);
+    }
+
+    /// Until the link is up, the transfer runs on the local clock so the link gets connected
+    fn recv_clock(&mut self) -> Option<u8> {
+        self.stream.as_ref()?;
+        self.poll();
+
+        Some(core::mem::take(&mut self.received_clock))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+
+    /// Receives the next byte, failing after a second
+    fn recv_blocking(link: &mut TcpLink) -> u8 {
+        let start = Instant::now();
+        loop {
+            if let Some(data) = link.recv() {
+                return data;
+            }
+            assert!(start.elapsed() < Duration::from_secs(1), "nothing received");
+            std::thread::yield_now();
+        }
+    }
+
+    fn connected_pair() -> (TcpLink, TcpLink) {
+        let mut server = TcpLink::server("127.0.0.1:0").unwrap();
+        let mut client = TcpLink::client(server.local_addr().unwrap());
+
+        assert!(client.connect());
+        let start = Instant::now();
+        while !server.connect() {
+            assert!(start.elapsed() < Duration::from_secs(1), "never accepted");
+        }
+
+        (server, client)
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let (mut server, mut client) = connected_pair();
+        assert_eq!(server.recv(), None);
+
+        // Either end can start a transfer
+        for i in 0..16u8 {
+            client.send(i);
+            assert_eq!(recv_blocking(&mut server), i);
+            server.send(!i);
+            assert_eq!(recv_blocking(&mut client), !i);
+
+            server.send(i << 4);
+            assert_eq!(recv_blocking(&mut client), i << 4);
+            client.send(i);
+            assert_eq!(recv_blocking(&mut server), i);
+        }
+    }
+
+    #[test]
+    fn test_reply_timeout() {
+        let (mut server, mut client) = connected_pair();
+        client.set_timeout(Duration::from_millis(50));
+
+        // Only the end that started the transfer gives up
+        let start = Instant::now();
+        client.send(0x12);
+        assert_eq!(recv_blocking(&mut client), 0xFF);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(recv_blocking(&mut server), 0x12);
+        assert_eq!(server.recv(), None);
+    }
+
+    #[test]
+    fn test_clock_forwarding() {
+        let (server, client) = connected_pair();
+        let mut master = crate::SerialPort::default();
+        let mut slave = crate::SerialPort::default();
+        master.set_serial(Box::new(server));
+        slave.set_serial(Box::new(client));
+
+        // The slave waits for the clock of the master instead of shifting on its own
+        slave.set_buffer(0x99);
+        slave.set_control(0x80);
+        let mut div = 0u16;
+        for _ in 0..0x4000 {
+            div = div.wrapping_add(4);
+            assert!(!slave.clock(div));
+        }
+
+        master.set_buffer(0x42);
+        master.set_control(0x81);
+        let (mut master_done, mut slave_done) = (false, false);
+        let start = Instant::now();
+        while !(master_done && slave_done) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "the transfer never completed"
+            );
+            div = div.wrapping_add(4);
+            master_done |= master.clock(div);
+            slave_done |= slave.clock(div);
+
+            // The last bit is shifted by the master before the slave can get its clock
+            assert!(master_done || !slave_done);
+        }
+        assert_eq!(master.get_buffer(), 0x99);
+        assert_eq!(slave.get_buffer(), 0x42);
+    }
+}
@@ -1,6 +1,7 @@
 use bitflags::bitflags;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimerRegisters {
     div: u16,
     counter: u8,
@@ -8,33 +9,37 @@ pub struct TimerRegisters {
     control: TimerControl,
 
     tac_falling_edge_latch: bool,
-    interrupt_cycle_countdown: u8,
+    reload: TimaReload,
+}
+
+/// State of the reload of TIMA with TMA after an overflow
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum TimaReload {
+    #[default]
+    Idle,
+    /// TIMA overflowed and reads 0 until the next M-cycle, writing it cancels the reload
+    Pending,
+    /// TMA is loaded in TIMA during this M-cycle. Writes to TIMA are ignored
+    /// and writes to TMA go through to TIMA.
+    Reloading,
 }
 
 impl TimerRegisters {
     pub fn clock(&mut self) -> bool {
-        // Accuracy shennanigans
-        // If TIMA is written on this specific cycle, the interrupt is not fired.
-        let result = self.interrupt_cycle_countdown == 2 && self.counter == 0;
-
-        match self.interrupt_cycle_countdown {
-            // This is the cycle the interrupt is thrown
-            2 => {
-                self.interrupt_cycle_countdown = 1;
-
-                if self.counter == 0 {
-                    self.counter = self.modulo;
-                }
+        // The reload and the interrupt happen one M-cycle after the overflow
+        let result = match self.reload {
+            TimaReload::Pending => {
+                self.counter = self.modulo;
+                self.reload = TimaReload::Reloading;
+                true
             }
-            // This is the cycle the counter get reset to the modulo
-            // Note that the counter itself gets reset in the emulator the previous cycle,
-            //      however this is because we emulate the cycles sequentially, not concurrently,
-            //      so the actual electrical circuit is hard to represent.
-            1 => {
-                self.interrupt_cycle_countdown = 0;
+            TimaReload::Reloading => {
+                self.reload = TimaReload::Idle;
+                false
             }
-            _ => {}
-        }
+            TimaReload::Idle => false,
+        };
 
         // This is incremented 4 times per CPU clock.
         // Since we don't emulate the CPU sub-cycle, we can approximate it this way.
@@ -49,23 +54,32 @@ impl TimerRegisters {
             0xFF04 => self.reset_div(),
 
             // TIMA counter
-            0xFF05 => {
-                if self.interrupt_cycle_countdown != 1 {
-                    // On this specific cycle, the value gets overwritten by TMA, so the write is ignored
+            0xFF05 => match self.reload {
+                // On this specific cycle, the value gets overwritten by TMA, so the write is ignored
+                TimaReload::Reloading => {}
+                // Writing before the reload cancels it, along with the interrupt
+                TimaReload::Pending => {
+                    self.reload = TimaReload::Idle;
                     self.counter = data;
                 }
-            }
+                TimaReload::Idle => self.counter = data,
+            },
 
             // TMA modulo
             0xFF06 => {
                 self.modulo = data;
-                if self.interrupt_cycle_countdown == 1 {
+                if self.reload == TimaReload::Reloading {
                     self.counter = data;
                 }
             }
 
             // Control
-            0xFF07 => self.control.bits = data & 0x07,
+            0xFF07 => {
+                self.control.bits = data & 0x07;
+
+                // Disabling the timer or selecting another bit can also make the input fall
+                self.set_div(self.div);
+            }
             _ => {}
         }
     }
@@ -87,6 +101,11 @@ impl TimerRegisters {
         }
     }
 
+    /// Internal 16 bits counter, of which DIV is the upper byte
+    pub fn div(&self) -> u16 {
+        self.div
+    }
+
     pub fn reset_div(&mut self) {
         self.set_div(0);
     }
@@ -103,10 +122,9 @@ impl TimerRegisters {
         let latch = self.control.contains(TimerControl::ENABLED) && self.div & mask == mask;
 
         if !latch & self.tac_falling_edge_latch {
-            // Interrupt is delayed one cycle, during which TIMA is 0.
-            // We use 2 because there's another behavior to emulate another cycle later.
+            // The reload and the interrupt are delayed one cycle, during which TIMA is 0
             if self.counter == 0xFF {
-                self.interrupt_cycle_countdown = 2;
+                self.reload = TimaReload::Pending;
             };
 
             self.counter = self.counter.wrapping_add(1);
@@ -118,6 +136,7 @@ impl TimerRegisters {
 
 bitflags! {
     #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct TimerControl: u8 {
         const CLOCK_1024 = 0x00;
         const CLOCK_16 = 0x01;
@@ -139,3 +158,142 @@ impl TimerControl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(timer: &mut TimerRegisters, m_cycles: usize) {
+        for _ in 0..m_cycles {
+            timer.clock();
+        }
+    }
+
+    #[test]
+    fn test_tima_frequency() {
+        let mut timer = TimerRegisters::default();
+
+        // Every 4 M-cycles
+        timer.write(0xFF07, 0x05);
+        clock(&mut timer, 4 * 10);
+        assert_eq!(timer.read(0xFF05), 10);
+
+        // DIV is the upper byte of the counter
+        assert_eq!(timer.read(0xFF04), 0);
+        clock(&mut timer, 64 - 40);
+        assert_eq!(timer.read(0xFF04), 1);
+    }
+
+    #[test]
+    fn test_div_write() {
+        let mut timer = TimerRegisters::default();
+        timer.write(0xFF07, 0x05);
+
+        // The selected bit is still low, so the reset doesn't increment TIMA
+        clock(&mut timer, 1);
+        timer.write(0xFF04, 0x12);
+        assert_eq!(timer.read(0xFF05), 0);
+
+        // It is now high, and falls with the reset
+        clock(&mut timer, 2);
+        timer.write(0xFF04, 0x12);
+        assert_eq!(timer.read(0xFF05), 1);
+        assert_eq!(timer.read(0xFF04), 0);
+
+        // The counter restarts from 0
+        clock(&mut timer, 3);
+        assert_eq!(timer.read(0xFF05), 1);
+        clock(&mut timer, 1);
+        assert_eq!(timer.read(0xFF05), 2);
+    }
+
+    #[test]
+    fn test_rapid_toggle() {
+        let mut timer = TimerRegisters::default();
+        timer.write(0xFF07, 0x05);
+        clock(&mut timer, 2);
+
+        // Disabling the timer while the selected bit is high increments TIMA
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 1);
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 2);
+
+        // So does selecting a bit that is low
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x04);
+        assert_eq!(timer.read(0xFF05), 3);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut timer = TimerRegisters::default();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0x05);
+
+        // TIMA stays at 0 for a cycle before being reloaded with TMA and the interrupt is requested
+        clock(&mut timer, 4);
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert!(timer.clock());
+        assert_eq!(timer.read(0xFF05), 0xAB);
+    }
+
+    fn overflow_timer() -> TimerRegisters {
+        let mut timer = TimerRegisters::default();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0x05);
+        clock(&mut timer, 4);
+        assert_eq!(timer.read(0xFF05), 0x00);
+        timer
+    }
+
+    #[test]
+    fn test_tima_write_before_reload() {
+        // Writing TIMA while it reads 0 cancels the reload and the interrupt, even if the value is 0
+        let mut timer = overflow_timer();
+        timer.write(0xFF05, 0x00);
+        assert!(!timer.clock());
+        assert_eq!(timer.read(0xFF05), 0x00);
+
+        let mut timer = overflow_timer();
+        timer.write(0xFF05, 0x12);
+        assert!(!timer.clock());
+        assert_eq!(timer.read(0xFF05), 0x12);
+    }
+
+    #[test]
+    fn test_tima_write_reloading() {
+        // Writing TIMA on the reload cycle is ignored
+        let mut timer = overflow_timer();
+        assert!(timer.clock());
+        timer.write(0xFF05, 0x12);
+        assert_eq!(timer.read(0xFF05), 0xAB);
+
+        // It works again on the next cycle
+        timer.clock();
+        timer.write(0xFF05, 0x12);
+        assert_eq!(timer.read(0xFF05), 0x12);
+    }
+
+    #[test]
+    fn test_tma_write_reloading() {
+        // Writing TMA on the reload cycle also loads it in TIMA
+        let mut timer = overflow_timer();
+        assert!(timer.clock());
+        timer.write(0xFF06, 0x34);
+        assert_eq!(timer.read(0xFF05), 0x34);
+
+        // But not before the reload or after it
+        let mut timer = overflow_timer();
+        timer.write(0xFF06, 0x56);
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert!(timer.clock());
+        assert_eq!(timer.read(0xFF05), 0x56);
+        timer.clock();
+        timer.write(0xFF06, 0x78);
+        assert_eq!(timer.read(0xFF05), 0x56);
+    }
+}
@@ -26,5 +26,5 @@ fn cgb_acid2() {
         }
     };
 
-    assert_eq!(frame.as_slice(), &cgb_acid2_image);
+    assert_eq!(&frame[..], &cgb_acid2_image);
 }
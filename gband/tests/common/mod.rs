@@ -0,0 +1,68 @@
+//! Harness shared by the test ROM suites that aren't distributed with the repository
+
+use std::path::PathBuf;
+
+/// Runs `rom` a frame at a time until `result` returns the result of the test, or `None` once
+/// `max_frames` frames are done. `setup` is called before the first frame.
+pub fn run_rom<T>(
+    rom: &[u8],
+    max_frames: usize,
+    setup: impl FnOnce(&mut gband::Emulator),
+    mut result: impl FnMut(&mut gband::Emulator) -> Option<T>,
+) -> Option<T> {
+    let mut emulator = gband::Emulator::new(rom, None).expect("Invalid Rom!");
+    emulator.set_rendering_enabled(false);
+    setup(&mut emulator);
+
+    for _ in 0..max_frames {
+        emulator.run_frame();
+
+        if let Some(result) = result(&mut emulator) {
+            return Some(result);
+        }
+    }
+
+    None
+}
+
+/// Runs every ROM in the directory set in the `env_var` environment variable, printing their
+/// results, and fails if any of them doesn't pass. Nothing is run if the variable isn't set.
+/// `run_test` returns whether a ROM passed and its text output, or `None` on timeout.
+pub fn run_rom_dir(env_var: &str, run_test: impl Fn(&[u8]) -> Option<(bool, String)>) {
+    let dir = match std::env::var_os(env_var) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            println!("{env_var} isn't set, skipping");
+            return;
+        }
+    };
+
+    let mut roms: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|_| panic!("{env_var} should be a directory"))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gb"))
+        .collect();
+    roms.sort();
+    assert!(!roms.is_empty(), "no ROM found in {}", dir.display());
+
+    let mut failed = Vec::new();
+    for path in &roms {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let rom = std::fs::read(path).unwrap();
+
+        match run_test(&rom) {
+            Some((true, _)) => println!("PASS    {name}"),
+            Some((false, text)) => {
+                println!("FAIL    {name}\n{}", text.trim_end());
+                failed.push(name);
+            }
+            None => {
+                println!("TIMEOUT {name}");
+                failed.push(name);
+            }
+        }
+    }
+
+    println!("{}/{} passed", roms.len() - failed.len(), roms.len());
+    assert!(failed.is_empty(), "failed: {failed:?}");
+}
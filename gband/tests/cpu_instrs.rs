@@ -0,0 +1,42 @@
+//! Runs blargg's cpu_instrs test ROMs and reports which ones pass, from the text they print
+//! on the serial port. The ROMs aren't distributed with the repository: set `CPU_INSTRS_ROMS`
+//! to the directory containing the individual ROMs (`01-special.gb` to `11-op a,(hl).gb`).
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The longest test takes less than 30 seconds, this leaves plenty of margin
+const MAX_FRAMES: usize = 60 * 60;
+
+/// Runs a test ROM until it prints its result, returning whether it passed and the text output
+fn run_test(rom: &[u8]) -> Option<(bool, String)> {
+    let output = Rc::new(RefCell::new(String::new()));
+    let callback_output = output.clone();
+
+    common::run_rom(
+        rom,
+        MAX_FRAMES,
+        |emulator| {
+            emulator.set_serial_callback(Box::new(move |byte| {
+                callback_output.borrow_mut().push(char::from(byte))
+            }))
+        },
+        |_| {
+            let output = output.borrow();
+            if output.contains("Passed") {
+                Some((true, output.clone()))
+            } else if output.contains("Failed") {
+                Some((false, output.clone()))
+            } else {
+                None
+            }
+        },
+    )
+}
+
+#[test]
+fn cpu_instrs() {
+    common::run_rom_dir("CPU_INSTRS_ROMS", run_test);
+}
@@ -26,5 +26,5 @@ fn dmg_acid2() {
         }
     };
 
-    assert_eq!(frame.as_slice(), &dmg_acid2_image);
+    assert_eq!(&frame[..], &dmg_acid2_image);
 }
@@ -0,0 +1,50 @@
+//! Runs blargg's dmg_sound test ROMs and reports which ones pass.
+//! The ROMs aren't distributed with the repository: set `DMG_SOUND_ROMS` to the directory
+//! containing the individual ROMs (`01-registers.gb` to `12-wave write while on.gb`).
+
+mod common;
+
+/// The tests take a few seconds each, this leaves plenty of margin
+const MAX_FRAMES: usize = 60 * 60;
+
+/// Written at 0xA001 once the result in cartridge RAM is valid
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+/// Status written at 0xA000 while the test is running
+const RUNNING: u8 = 0x80;
+
+/// Runs a test ROM until it reports its result in cartridge RAM, as the status code and the text output
+fn run_test(rom: &[u8]) -> Option<(u8, String)> {
+    common::run_rom(
+        rom,
+        MAX_FRAMES,
+        // 09, 10 and 12 test the wave RAM quirks
+        |emulator| emulator.set_accuracy(gband::Accuracy::WAVE_RAM_ACCESS),
+        |emulator| {
+            let signature = [
+                emulator.peek(0xA001),
+                emulator.peek(0xA002),
+                emulator.peek(0xA003),
+            ];
+            let status = emulator.peek(0xA000);
+            if signature != SIGNATURE || status == RUNNING {
+                return None;
+            }
+
+            let text = (0xA004..0xC000)
+                .map(|addr| emulator.peek(addr))
+                .take_while(|&c| c != 0)
+                .map(char::from)
+                .collect();
+
+            Some((status, text))
+        },
+    )
+}
+
+#[test]
+fn dmg_sound() {
+    common::run_rom_dir("DMG_SOUND_ROMS", |rom| {
+        run_test(rom).map(|(status, text)| (status == 0, format!("status {status}\n{text}")))
+    });
+}